@@ -115,6 +115,8 @@ pub enum ParseReqError {
 pub enum StatusCode {
 	Ok200,
 
+	BadRequest400,
+	Forbidden403,
 	NotFound404,
 
 	InternalServerError500,
@@ -125,6 +127,8 @@ impl StatusCode {
 		use StatusCode::*;
 		match self {
 			Ok200                  => "200 OK",
+			BadRequest400          => "400 Bad Request",
+			Forbidden403           => "403 Forbidden",
 			NotFound404            => "404 Not Found",
 			InternalServerError500 => "500 Internal Server Error",
 		}
@@ -269,6 +273,21 @@ impl Response {
 		}
 	}
 
+	pub fn generate_error_response(req: &Request, code: StatusCode, content_type: &str, body: Vec<u8>) -> Response {
+		let mut headers = BTreeMap::new();
+		add_content_type(&mut headers, content_type);
+		add_content_length(&mut headers, &body);
+
+		Response {
+			header: ResponseHeader {
+				version: req.header.version,
+				code,
+				headers,
+			},
+			body,
+		}
+	}
+
 	pub fn generate_internal_server_error(req: &Request) -> Response {
 		const TEXT: &'static str = "Server Error";
 
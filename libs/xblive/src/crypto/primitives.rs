@@ -6,6 +6,7 @@ use block_modes::cipher::generic_array::typenum::{U24, U8};
 use bytes::BufMut;
 
 use crypto::{
+    digest::Digest,
     hmac::Hmac,
     mac::Mac,
     md5::Md5,
@@ -149,6 +150,18 @@ pub fn sha1_hmac(key: &[u8], data: &[&[u8]]) -> Sha1Digest {
     digest
 }
 
+pub fn sha1(data: &[&[u8]]) -> Sha1Digest {
+    let mut sha1 = Sha1::new();
+    for data in data {
+        sha1.input(data);
+    }
+
+    let mut digest = Sha1Digest(Default::default());
+    sha1.result(&mut digest.0);
+
+    digest
+}
+
 pub const MD5_DIGEST_LEN: usize = 16;
 #[derive(Clone, Copy, Debug)]
 pub struct Md5Digest(pub [u8;MD5_DIGEST_LEN]);
@@ -318,6 +331,14 @@ mod tests {
             .is_some())
     }
 
+    #[test]
+    fn sha1_digest() {
+        assert_eq!(
+            sha1(&[b"a", b"bc"]),
+            Sha1Digest(hex!["a9 99 3e 36 47 06 81 6a ba 3e 25 71 78 50 c2 6c 9c d0 d8 9d"])
+        );
+    }
+
     #[test]
     fn rc4_md5_hmac_encrypt() {
         let input: [u8;28] = [
@@ -1,2 +1,10 @@
+pub mod autoupdate;
+pub mod content;
+pub mod feedback;
 pub mod matchmaking;
+pub mod messaging;
 pub mod presence;
+pub mod stats;
+pub mod storage;
+pub mod string;
+pub mod team;
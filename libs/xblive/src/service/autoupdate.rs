@@ -0,0 +1,195 @@
+use bytes::BufMut;
+
+use nom::number::complete::{le_u32, le_u64, le_u8};
+
+use xbox_sys::codec::{BufPut, Decode};
+use xbox_sys::status::HResult;
+
+use crate::crypto::primitives::Sha1Digest;
+
+/// PROVISIONAL: there's no public reference for the auto-update service's
+/// content type, its /xupdate/ endpoints or the layout of their bodies.  All
+/// of them are this server's own, so consoles only reach them where the
+/// client side follows the same convention.  Replace them once the real ones
+/// are known.
+pub const CONTENT_TYPE: &'static str = "xon/3";
+
+// PROVISIONAL: the service's own failure codes aren't known either, so each
+// of these is the generic HRESULT closest to it.
+pub const E_INVALID_TITLE_ID: HResult = HResult::E_INVALIDARG;
+pub const E_UPDATE_NOT_FOUND: HResult = HResult::E_FILE_NOT_FOUND;
+pub const E_INVALID_RANGE:    HResult = HResult::E_INVALIDARG;
+
+/// Title id the dashboard reports itself as.
+pub const DASHBOARD_TITLE_ID: u32 = 0xFFFE_0000;
+
+/// Most package bytes a single download returns.
+pub const MAX_CHUNK_LEN: u32 = 0x1_0000;
+
+/// Body of /xupdate/xupdatecheck.srf, answered with an UpdateCheckReply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UpdateCheckRequest {
+	pub title_id: u32,
+	pub title_version: u32,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for UpdateCheckRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		buf.put_u32_le(self.title_version);
+	}
+}
+
+impl Decode for UpdateCheckRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, title_version) = le_u32(input)?;
+
+		Ok((input, UpdateCheckRequest {
+			title_id,
+			title_version,
+		}))
+	}
+}
+
+/// An update a console has to install before going online with the title.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UpdateInfo {
+	pub version: u32,
+	pub package_size: u64,
+	pub hash: Sha1Digest,
+}
+
+/// update is None when the console's version is current.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UpdateCheckReply {
+	pub update: Option<UpdateInfo>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for UpdateCheckReply {
+	fn put(&self, buf: &mut AnyBufMut) {
+		match &self.update {
+			None => buf.put_u8(0),
+			Some(update) => {
+				buf.put_u8(1);
+				buf.put_u32_le(update.version);
+				buf.put_u64_le(update.package_size);
+				update.hash.put(buf);
+			}
+		}
+	}
+}
+
+impl Decode for UpdateCheckReply {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, has_update) = le_u8(input)?;
+
+		if has_update == 0 {
+			return Ok((input, UpdateCheckReply {
+				update: None,
+			}))
+		}
+
+		let (input, version) = le_u32(input)?;
+		let (input, package_size) = le_u64(input)?;
+		let (input, hash) = Sha1Digest::decode(input)?;
+
+		Ok((input, UpdateCheckReply {
+			update: Some(UpdateInfo {
+				version,
+				package_size,
+				hash,
+			}),
+		}))
+	}
+}
+
+/// Body of /xupdate/xupdatedownload.srf, answered with the requested bytes
+/// of the update package, at most MAX_CHUNK_LEN of them.  A reply shorter
+/// than len means the end of the package was reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UpdateDownloadRequest {
+	pub title_id: u32,
+	pub version: u32,
+	pub offset: u64,
+	pub len: u32,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for UpdateDownloadRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		buf.put_u32_le(self.version);
+		buf.put_u64_le(self.offset);
+		buf.put_u32_le(self.len);
+	}
+}
+
+impl Decode for UpdateDownloadRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, version) = le_u32(input)?;
+		let (input, offset) = le_u64(input)?;
+		let (input, len) = le_u32(input)?;
+
+		Ok((input, UpdateDownloadRequest {
+			title_id,
+			version,
+			offset,
+			len,
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use hex_literal::hex;
+
+	use xbox_sys::codec::test_codec;
+
+	use super::*;
+
+	#[test]
+	fn check_codec() {
+		test_codec(&hex!["0b00414c 02000100"], UpdateCheckRequest {
+			title_id: 0x4C41000B,
+			title_version: 0x0001_0002,
+		});
+
+		test_codec(&hex!["00"], UpdateCheckReply {
+			update: None,
+		});
+
+		test_codec(&hex!["
+			01
+			03000100
+			0300000000000000
+			a9993e364706816aba3e25717850c26c9cd0d89d
+		"], UpdateCheckReply {
+			update: Some(UpdateInfo {
+				version: 0x0001_0003,
+				package_size: 3,
+				hash: Sha1Digest(hex!["a9993e364706816aba3e25717850c26c9cd0d89d"]),
+			}),
+		});
+	}
+
+	#[test]
+	fn download_codec() {
+		test_codec(&hex!["
+			0000feff
+			03000100
+			0000010000000000
+			00000100
+		"], UpdateDownloadRequest {
+			title_id: DASHBOARD_TITLE_ID,
+			version: 0x0001_0003,
+			offset: 0x1_0000,
+			len: MAX_CHUNK_LEN,
+		});
+	}
+
+	#[test]
+	fn truncated_reply_is_rejected() {
+		assert!(UpdateCheckReply::decode(&hex!["01 03000100 03000000"]).is_err());
+	}
+}
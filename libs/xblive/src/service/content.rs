@@ -0,0 +1,359 @@
+use bytes::BufMut;
+
+use nom::multi::count;
+use nom::number::complete::{le_u16, le_u32, le_u64};
+
+use xbox_sys::account::Xuid;
+use xbox_sys::codec::{BufPut, Decode, parse_nul_terminated_ascii, put_nul_terminated_ascii};
+use xbox_sys::status::HResult;
+
+use crate::crypto::primitives::Sha1Digest;
+
+/// PROVISIONAL: there's no public reference for the content service's
+/// content type, its /xcontent/ endpoints or the layout of their bodies.  All
+/// of them are this server's own, so consoles only reach them where the
+/// client side follows the same convention.  Replace them once the real ones
+/// are known.
+pub const CONTENT_TYPE: &'static str = "xon/4";
+
+// PROVISIONAL: the service's own failure codes aren't known either, so each
+// of these is the generic HRESULT closest to it.
+pub const E_INVALID_TITLE_ID: HResult = HResult::E_INVALIDARG;
+pub const E_OFFER_NOT_FOUND:  HResult = HResult::E_FILE_NOT_FOUND;
+pub const E_FILE_NOT_FOUND:   HResult = HResult::E_FILE_NOT_FOUND;
+pub const E_INVALID_RANGE:    HResult = HResult::E_INVALIDARG;
+
+/// Most offers a single enumeration returns.
+pub const MAX_ENUM_RESULTS: u16 = 100;
+
+/// Most package bytes a single download returns.
+pub const MAX_CHUNK_LEN: u32 = 0x1_0000;
+
+/// Body of /xcontent/xcontentenum.srf, answered with a ContentEnumerateReply.
+/// Lists the title's offers that have every one of required_flags set, in
+/// offer id order, skipping the first start.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentEnumerateRequest {
+	pub title_id: u32,
+	pub user_id: Xuid,
+	pub required_flags: u32,
+	pub start: u32,
+	pub max_results: u16,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for ContentEnumerateRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		self.user_id.put(buf);
+		buf.put_u32_le(self.required_flags);
+		buf.put_u32_le(self.start);
+		buf.put_u16_le(self.max_results);
+	}
+}
+
+impl Decode for ContentEnumerateRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, user_id) = Xuid::decode(input)?;
+		let (input, required_flags) = le_u32(input)?;
+		let (input, start) = le_u32(input)?;
+		let (input, max_results) = le_u16(input)?;
+
+		Ok((input, ContentEnumerateRequest {
+			title_id,
+			user_id,
+			required_flags,
+			start,
+			max_results,
+		}))
+	}
+}
+
+/// An offer as listed in an enumeration.  package_size is the total of
+/// every file in the offer's package.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContentOffer {
+	pub offer_id: u64,
+	pub flags: u32,
+	pub package_size: u64,
+	pub name: String,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for ContentOffer {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u64_le(self.offer_id);
+		buf.put_u32_le(self.flags);
+		buf.put_u64_le(self.package_size);
+		put_nul_terminated_ascii(&self.name, buf);
+	}
+}
+
+impl Decode for ContentOffer {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, offer_id) = le_u64(input)?;
+		let (input, flags) = le_u32(input)?;
+		let (input, package_size) = le_u64(input)?;
+		let (input, name) = parse_nul_terminated_ascii(input)?;
+
+		Ok((input, ContentOffer {
+			offer_id,
+			flags,
+			package_size,
+			name: name.to_owned(),
+		}))
+	}
+}
+
+/// total_offers counts every offer matching the enumeration, not just the
+/// ones returned.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContentEnumerateReply {
+	pub total_offers: u32,
+	pub offers: Vec<ContentOffer>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for ContentEnumerateReply {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.total_offers);
+		buf.put_u16_le(self.offers.len() as u16);
+		for offer in &self.offers {
+			offer.put(buf);
+		}
+	}
+}
+
+impl Decode for ContentEnumerateReply {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, total_offers) = le_u32(input)?;
+		let (input, num_offers) = le_u16(input)?;
+		let (input, offers) = count(ContentOffer::decode, num_offers as usize)(input)?;
+
+		Ok((input, ContentEnumerateReply {
+			total_offers,
+			offers,
+		}))
+	}
+}
+
+/// Body of /xcontent/xcontentlocation.srf, answered with a
+/// ContentLocationReply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentLocationRequest {
+	pub title_id: u32,
+	pub offer_id: u64,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for ContentLocationRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		buf.put_u64_le(self.offer_id);
+	}
+}
+
+impl Decode for ContentLocationRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, offer_id) = le_u64(input)?;
+
+		Ok((input, ContentLocationRequest {
+			title_id,
+			offer_id,
+		}))
+	}
+}
+
+/// One file of an offer's package.  Files are downloaded by their index in
+/// ContentLocationReply::files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContentFile {
+	pub name: String,
+	pub size: u64,
+	pub hash: Sha1Digest,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for ContentFile {
+	fn put(&self, buf: &mut AnyBufMut) {
+		put_nul_terminated_ascii(&self.name, buf);
+		buf.put_u64_le(self.size);
+		self.hash.put(buf);
+	}
+}
+
+impl Decode for ContentFile {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, name) = parse_nul_terminated_ascii(input)?;
+		let (input, size) = le_u64(input)?;
+		let (input, hash) = Sha1Digest::decode(input)?;
+
+		Ok((input, ContentFile {
+			name: name.to_owned(),
+			size,
+			hash,
+		}))
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContentLocationReply {
+	pub offer_id: u64,
+	pub files: Vec<ContentFile>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for ContentLocationReply {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u64_le(self.offer_id);
+		buf.put_u16_le(self.files.len() as u16);
+		for file in &self.files {
+			file.put(buf);
+		}
+	}
+}
+
+impl Decode for ContentLocationReply {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, offer_id) = le_u64(input)?;
+		let (input, num_files) = le_u16(input)?;
+		let (input, files) = count(ContentFile::decode, num_files as usize)(input)?;
+
+		Ok((input, ContentLocationReply {
+			offer_id,
+			files,
+		}))
+	}
+}
+
+/// Body of /xcontent/xcontentdownload.srf, answered with the requested
+/// bytes of the file, at most MAX_CHUNK_LEN of them.  A reply shorter than
+/// len means the end of the file was reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentDownloadRequest {
+	pub title_id: u32,
+	pub offer_id: u64,
+	pub file_index: u16,
+	pub offset: u64,
+	pub len: u32,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for ContentDownloadRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		buf.put_u64_le(self.offer_id);
+		buf.put_u16_le(self.file_index);
+		buf.put_u64_le(self.offset);
+		buf.put_u32_le(self.len);
+	}
+}
+
+impl Decode for ContentDownloadRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, offer_id) = le_u64(input)?;
+		let (input, file_index) = le_u16(input)?;
+		let (input, offset) = le_u64(input)?;
+		let (input, len) = le_u32(input)?;
+
+		Ok((input, ContentDownloadRequest {
+			title_id,
+			offer_id,
+			file_index,
+			offset,
+			len,
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use hex_literal::hex;
+
+	use xbox_sys::codec::test_codec;
+
+	use super::*;
+
+	#[test]
+	fn enumerate_codec() {
+		test_codec(&hex!["
+			0b00414c
+			3292446c90740900
+			01000000
+			00000000
+			6400
+		"], ContentEnumerateRequest {
+			title_id: 0x4C41000B,
+			user_id: Xuid(0x000974906c449232),
+			required_flags: 1,
+			start: 0,
+			max_results: MAX_ENUM_RESULTS,
+		});
+
+		test_codec(&hex!["
+			03000000
+			0100
+			0100000001000000
+			01000000
+			0010000000000000
+			4d6170205061636b00
+		"], ContentEnumerateReply {
+			total_offers: 3,
+			offers: vec![
+				ContentOffer {
+					offer_id: 0x0000_0001_0000_0001,
+					flags: 1,
+					package_size: 0x1000,
+					name: "Map Pack".to_owned(),
+				},
+			],
+		});
+	}
+
+	#[test]
+	fn location_codec() {
+		test_codec(&hex!["
+			0b00414c
+			0100000001000000
+		"], ContentLocationRequest {
+			title_id: 0x4C41000B,
+			offer_id: 0x0000_0001_0000_0001,
+		});
+
+		test_codec(&hex!["
+			0100000001000000
+			0100
+			6d6170732e786278 00
+			0300000000000000
+			a9993e364706816aba3e25717850c26c9cd0d89d
+		"], ContentLocationReply {
+			offer_id: 0x0000_0001_0000_0001,
+			files: vec![
+				ContentFile {
+					name: "maps.xbx".to_owned(),
+					size: 3,
+					hash: Sha1Digest(hex!["a9993e364706816aba3e25717850c26c9cd0d89d"]),
+				},
+			],
+		});
+	}
+
+	#[test]
+	fn download_codec() {
+		test_codec(&hex!["
+			0b00414c
+			0100000001000000
+			0200
+			0000010000000000
+			00000100
+		"], ContentDownloadRequest {
+			title_id: 0x4C41000B,
+			offer_id: 0x0000_0001_0000_0001,
+			file_index: 2,
+			offset: 0x1_0000,
+			len: MAX_CHUNK_LEN,
+		});
+	}
+
+	#[test]
+	fn truncated_requests_are_rejected() {
+		assert!(ContentDownloadRequest::decode(&hex!["0b00414c 0100000001000000 0200"]).is_err());
+		assert!(ContentOffer::decode(&hex!["0100000001000000 01000000 0010000000000000 4d6170"]).is_err());
+	}
+}
@@ -0,0 +1,130 @@
+use bytes::BufMut;
+
+use nom::number::complete::le_u32;
+
+use xbox_sys::account::Xuid;
+use xbox_sys::codec::{BufPut, Decode, parse_nul_terminated_ascii, put_nul_terminated_ascii};
+use xbox_sys::status::HResult;
+
+/// PROVISIONAL: there's no public reference for the feedback service's
+/// content type, its /xfeedback/ endpoint or the layout of its body.  All of
+/// them are this server's own, so consoles only reach them where the client
+/// side follows the same convention.  Replace them once the real ones are
+/// known.
+pub const CONTENT_TYPE: &'static str = "xon/8";
+
+// PROVISIONAL: the service's own failure codes aren't known either, so each
+// of these is the generic HRESULT closest to it.
+pub const E_INVALID_TITLE_ID:      HResult = HResult::E_INVALIDARG;
+pub const E_INVALID_USER:          HResult = HResult::E_ACCESSDENIED;
+pub const E_INVALID_TARGET:        HResult = HResult::E_INVALIDARG;
+pub const E_INVALID_FEEDBACK_TYPE: HResult = HResult::E_INVALIDARG;
+pub const E_TEXT_TOO_LONG:         HResult = HResult::E_INVALIDARG;
+
+/// Longest comment, in bytes, a player can attach to feedback.
+pub const MAX_TEXT_LEN: usize = 256;
+
+pub const FEEDBACK_NEG_NICKNAME:         u32 = 0;
+pub const FEEDBACK_NEG_GAMEPLAY:         u32 = 1;
+pub const FEEDBACK_NEG_SCREAMING:        u32 = 2;
+pub const FEEDBACK_NEG_HARASSMENT:       u32 = 3;
+pub const FEEDBACK_NEG_LEWDNESS:         u32 = 4;
+pub const FEEDBACK_POS_ATTITUDE:         u32 = 5;
+pub const FEEDBACK_POS_SESSION:          u32 = 6;
+pub const FEEDBACK_NEG_STATS_ATTACHMENT: u32 = 7;
+pub const FEEDBACK_NEG_STATS_TEXT:       u32 = 8;
+pub const FEEDBACK_NEG_STATS_CHEATING:   u32 = 9;
+
+/// The number of feedback types, every one below this is valid.
+pub const NUM_FEEDBACK_TYPES: u32 = 10;
+
+/// Compliments, as opposed to complaints.
+pub fn is_positive_feedback(feedback_type: u32) -> bool {
+	feedback_type == FEEDBACK_POS_ATTITUDE || feedback_type == FEEDBACK_POS_SESSION
+}
+
+/// Body of /xfeedback/xfeedbacksubmit.srf, answered with an empty body.
+/// text is empty when the player didn't write anything.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeedbackRequest {
+	pub title_id: u32,
+	pub reporter: Xuid,
+	pub target: Xuid,
+	pub feedback_type: u32,
+	pub text: String,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for FeedbackRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		self.reporter.put(buf);
+		self.target.put(buf);
+		buf.put_u32_le(self.feedback_type);
+		put_nul_terminated_ascii(&self.text, buf);
+	}
+}
+
+impl Decode for FeedbackRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, reporter) = Xuid::decode(input)?;
+		let (input, target) = Xuid::decode(input)?;
+		let (input, feedback_type) = le_u32(input)?;
+		let (input, text) = parse_nul_terminated_ascii(input)?;
+
+		Ok((input, FeedbackRequest {
+			title_id,
+			reporter,
+			target,
+			feedback_type,
+			text: text.to_owned(),
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use hex_literal::hex;
+
+	use xbox_sys::codec::test_codec;
+
+	use super::*;
+
+	#[test]
+	fn feedback_codec() {
+		test_codec(&hex!["
+			0b00414c
+			3292446c90740900
+			0100000000000900
+			03000000
+			7370616d00
+		"], FeedbackRequest {
+			title_id: 0x4C41000B,
+			reporter: Xuid(0x000974906c449232),
+			target: Xuid(0x0009_0000_0000_0001),
+			feedback_type: FEEDBACK_NEG_HARASSMENT,
+			text: "spam".to_owned(),
+		});
+
+		test_codec(&hex!["
+			0b00414c
+			3292446c90740900
+			0100000000000900
+			05000000
+			00
+		"], FeedbackRequest {
+			title_id: 0x4C41000B,
+			reporter: Xuid(0x000974906c449232),
+			target: Xuid(0x0009_0000_0000_0001),
+			feedback_type: FEEDBACK_POS_ATTITUDE,
+			text: String::new(),
+		});
+	}
+
+	#[test]
+	fn unterminated_text_is_rejected() {
+		assert!(FeedbackRequest::decode(&hex!["
+			0b00414c 3292446c90740900 0100000000000900 03000000 7370616d
+		"]).is_err());
+	}
+}
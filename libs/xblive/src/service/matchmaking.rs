@@ -351,6 +351,49 @@ impl Decode for SessionHeader {
 	}
 }
 
+/// Sent by a host to /xmatch/xmatchhostdelete.srf when it stops hosting a
+/// session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionDelete {
+	pub message_length: u32,
+	pub session_id: KeyId,
+	pub title_id: u32,
+}
+
+impl SessionDelete {
+	pub const ENCODED_LEN: usize = 4 + 8 + 4;
+
+	pub fn generate(session_id: KeyId, title_id: u32) -> Self {
+		SessionDelete {
+			message_length: Self::ENCODED_LEN as u32,
+			session_id,
+			title_id,
+		}
+	}
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for SessionDelete {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.message_length);
+		self.session_id.put(buf);
+		buf.put_u32_le(self.title_id);
+	}
+}
+
+impl Decode for SessionDelete {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, message_length) = le_u32(input)?;
+		let (input, session_id) = KeyId::decode(input)?;
+		let (input, title_id) = le_u32(input)?;
+
+		Ok((input, SessionDelete {
+			message_length,
+			session_id,
+			title_id,
+		}))
+	}
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct SearchResults {
 	pub header: SearchResultsHeader,
@@ -765,6 +808,18 @@ mod tests {
 		})
 	}
 
+	#[test]
+	fn session_delete_codec() {
+		test_codec(&hex!["
+			10 00 00 00
+			01 23 45 67 89 ab cd ef
+			0b 00 41 4c
+		"], SessionDelete::generate(
+			KeyId(hex!["01 23 45 67 89 ab cd ef"]),
+			0x4c41000b,
+		))
+	}
+
 	#[test]
 	fn session_header_codec() {
 		test_codec(&hex!["
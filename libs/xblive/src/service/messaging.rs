@@ -0,0 +1,479 @@
+use bytes::BufMut;
+
+use nom::bytes::complete::take;
+use nom::multi::count;
+use nom::number::complete::{le_u16, le_u32, le_u64};
+
+use xbox_sys::account::Xuid;
+use xbox_sys::codec::{BufPut, Decode, parse_nul_terminated_ascii, put_nul_terminated_ascii};
+use xbox_sys::status::HResult;
+
+/// PROVISIONAL: there's no public reference for the messaging service's
+/// content type, its /xmsg/ endpoints or the layout of their bodies.  All of
+/// them are this server's own, so consoles only reach them where the client
+/// side follows the same convention.  Replace them once the real ones are
+/// known.
+pub const CONTENT_TYPE: &'static str = "xon/12";
+
+// PROVISIONAL: the service's own failure codes aren't known either, so each
+// of these is the generic HRESULT closest to it.
+pub const E_INVALID_TITLE_ID:     HResult = HResult::E_INVALIDARG;
+pub const E_INVALID_USER:         HResult = HResult::E_ACCESSDENIED;
+pub const E_INVALID_RECIPIENT:    HResult = HResult::E_INVALIDARG;
+pub const E_BLOCKED:              HResult = HResult::E_ACCESSDENIED;
+pub const E_NOT_BUDDIES:          HResult = HResult::E_ACCESSDENIED;
+pub const E_INBOX_FULL:           HResult = HResult::E_DISK_FULL;
+pub const E_MESSAGE_NOT_FOUND:    HResult = HResult::E_FILE_NOT_FOUND;
+pub const E_TOO_MANY_RECIPIENTS:  HResult = HResult::E_INVALIDARG;
+pub const E_TEXT_TOO_LONG:        HResult = HResult::E_INVALIDARG;
+pub const E_ATTACHMENT_TOO_LARGE: HResult = HResult::E_INVALIDARG;
+
+/// Most users a single message can be sent to.
+pub const MAX_RECIPIENTS: u16 = 16;
+
+/// Longest message text, in bytes, not counting the nul.
+pub const MAX_TEXT_LEN: usize = 256;
+
+/// Largest attachment, such as a voice message, a message can carry.
+pub const MAX_ATTACHMENT_LEN: usize = 0x1_0000;
+
+/// Most messages an inbox holds before it stops accepting new ones.
+pub const MAX_INBOX_MESSAGES: u16 = 100;
+
+/// Set by the service once the recipient has read the message.
+pub const MESSAGE_FLAG_READ:           u32 = 0x0000_0001;
+/// Set by the service when the message carries an attachment.
+pub const MESSAGE_FLAG_HAS_ATTACHMENT: u32 = 0x0000_0002;
+/// The flags a sender sets, which mean whatever the title wants them to.
+pub const MESSAGE_FLAGS_TITLE_MASK:    u32 = 0xFFFF_0000;
+
+fn put_data<AnyBufMut: BufMut>(data: &[u8], buf: &mut AnyBufMut) {
+	buf.put_u32_le(data.len() as u32);
+	buf.put_slice(data);
+}
+
+fn decode_data(input: &[u8]) -> nom::IResult<&[u8], Vec<u8>> {
+	let (input, len) = le_u32(input)?;
+	let (input, data) = take(len)(input)?;
+	Ok((input, data.to_vec()))
+}
+
+/// Body of /xmsg/xmsgsend.srf, answered with a SendMessageReply.  Each
+/// recipient gets their own copy of the message.  expire_secs shortens how
+/// long the message is kept, 0 keeps it for as long as the service allows.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SendMessageRequest {
+	pub title_id: u32,
+	pub sender: Xuid,
+	pub flags: u32,
+	pub expire_secs: u32,
+	pub text: String,
+	pub recipients: Vec<Xuid>,
+	pub attachment: Vec<u8>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for SendMessageRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		self.sender.put(buf);
+		buf.put_u32_le(self.flags);
+		buf.put_u32_le(self.expire_secs);
+		put_nul_terminated_ascii(&self.text, buf);
+		buf.put_u16_le(self.recipients.len() as u16);
+		for recipient in &self.recipients {
+			recipient.put(buf);
+		}
+		put_data(&self.attachment, buf);
+	}
+}
+
+impl Decode for SendMessageRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, sender) = Xuid::decode(input)?;
+		let (input, flags) = le_u32(input)?;
+		let (input, expire_secs) = le_u32(input)?;
+		let (input, text) = parse_nul_terminated_ascii(input)?;
+		let (input, num_recipients) = le_u16(input)?;
+		let (input, recipients) = count(Xuid::decode, num_recipients as usize)(input)?;
+		let (input, attachment) = decode_data(input)?;
+
+		Ok((input, SendMessageRequest {
+			title_id,
+			sender,
+			flags,
+			expire_secs,
+			text: text.to_owned(),
+			recipients,
+			attachment,
+		}))
+	}
+}
+
+/// How sending to one recipient went.  message_id is 0 unless hr is
+/// SUCCESS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecipientResult {
+	pub recipient: Xuid,
+	pub hr: HResult,
+	pub message_id: u64,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for RecipientResult {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.recipient.put(buf);
+		self.hr.put(buf);
+		buf.put_u64_le(self.message_id);
+	}
+}
+
+impl Decode for RecipientResult {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, recipient) = Xuid::decode(input)?;
+		let (input, hr) = HResult::decode(input)?;
+		let (input, message_id) = le_u64(input)?;
+
+		Ok((input, RecipientResult {
+			recipient,
+			hr,
+			message_id,
+		}))
+	}
+}
+
+/// One result per recipient, in the order they were given.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SendMessageReply {
+	pub results: Vec<RecipientResult>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for SendMessageReply {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u16_le(self.results.len() as u16);
+		for result in &self.results {
+			result.put(buf);
+		}
+	}
+}
+
+impl Decode for SendMessageReply {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, num_results) = le_u16(input)?;
+		let (input, results) = count(RecipientResult::decode, num_results as usize)(input)?;
+
+		Ok((input, SendMessageReply {
+			results,
+		}))
+	}
+}
+
+/// Body of /xmsg/xmsgenum.srf, answered with an EnumerateMessagesReply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EnumerateMessagesRequest {
+	pub title_id: u32,
+	pub user: Xuid,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for EnumerateMessagesRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		self.user.put(buf);
+	}
+}
+
+impl Decode for EnumerateMessagesRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, user) = Xuid::decode(input)?;
+
+		Ok((input, EnumerateMessagesRequest {
+			title_id,
+			user,
+		}))
+	}
+}
+
+/// A message as listed in an inbox, without its text or attachment.  Times
+/// are seconds since the Unix epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageSummary {
+	pub message_id: u64,
+	pub sender: Xuid,
+	/// The title the message was sent from.
+	pub title_id: u32,
+	pub flags: u32,
+	pub sent: u64,
+	pub expires: u64,
+	pub attachment_len: u32,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for MessageSummary {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u64_le(self.message_id);
+		self.sender.put(buf);
+		buf.put_u32_le(self.title_id);
+		buf.put_u32_le(self.flags);
+		buf.put_u64_le(self.sent);
+		buf.put_u64_le(self.expires);
+		buf.put_u32_le(self.attachment_len);
+	}
+}
+
+impl Decode for MessageSummary {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, message_id) = le_u64(input)?;
+		let (input, sender) = Xuid::decode(input)?;
+		let (input, title_id) = le_u32(input)?;
+		let (input, flags) = le_u32(input)?;
+		let (input, sent) = le_u64(input)?;
+		let (input, expires) = le_u64(input)?;
+		let (input, attachment_len) = le_u32(input)?;
+
+		Ok((input, MessageSummary {
+			message_id,
+			sender,
+			title_id,
+			flags,
+			sent,
+			expires,
+			attachment_len,
+		}))
+	}
+}
+
+/// The inbox, newest message first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnumerateMessagesReply {
+	pub messages: Vec<MessageSummary>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for EnumerateMessagesReply {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u16_le(self.messages.len() as u16);
+		for message in &self.messages {
+			message.put(buf);
+		}
+	}
+}
+
+impl Decode for EnumerateMessagesReply {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, num_messages) = le_u16(input)?;
+		let (input, messages) = count(MessageSummary::decode, num_messages as usize)(input)?;
+
+		Ok((input, EnumerateMessagesReply {
+			messages,
+		}))
+	}
+}
+
+/// Body of /xmsg/xmsgdetails.srf, answered with a MessageDetailsReply, of
+/// /xmsg/xmsgattachment.srf, answered with the attachment's bytes, and of
+/// /xmsg/xmsgmarkread.srf and /xmsg/xmsgdelete.srf, answered with an empty
+/// body.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageRequest {
+	pub title_id: u32,
+	pub user: Xuid,
+	pub message_id: u64,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for MessageRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		self.user.put(buf);
+		buf.put_u64_le(self.message_id);
+	}
+}
+
+impl Decode for MessageRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, user) = Xuid::decode(input)?;
+		let (input, message_id) = le_u64(input)?;
+
+		Ok((input, MessageRequest {
+			title_id,
+			user,
+			message_id,
+		}))
+	}
+}
+
+/// Reading a message's details doesn't mark it read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MessageDetailsReply {
+	pub summary: MessageSummary,
+	pub text: String,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for MessageDetailsReply {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.summary.put(buf);
+		put_nul_terminated_ascii(&self.text, buf);
+	}
+}
+
+impl Decode for MessageDetailsReply {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, summary) = MessageSummary::decode(input)?;
+		let (input, text) = parse_nul_terminated_ascii(input)?;
+
+		Ok((input, MessageDetailsReply {
+			summary,
+			text: text.to_owned(),
+		}))
+	}
+}
+
+/// Data of the MESSAGE pulse event telling a console one of its users has a
+/// new message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageNotification {
+	pub message_id: u64,
+	pub sender: Xuid,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for MessageNotification {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u64_le(self.message_id);
+		self.sender.put(buf);
+	}
+}
+
+impl Decode for MessageNotification {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, message_id) = le_u64(input)?;
+		let (input, sender) = Xuid::decode(input)?;
+
+		Ok((input, MessageNotification {
+			message_id,
+			sender,
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use hex_literal::hex;
+
+	use xbox_sys::codec::test_codec;
+
+	use super::*;
+
+	const SUMMARY: MessageSummary = MessageSummary {
+		message_id: 7,
+		sender: Xuid(0x000974906c449232),
+		title_id: 0x4C41000B,
+		flags: MESSAGE_FLAG_READ | MESSAGE_FLAG_HAS_ATTACHMENT,
+		sent: 1_700_000_000,
+		expires: 1_702_592_000,
+		attachment_len: 3,
+	};
+
+	const SUMMARY_BYTES: [u8; 44] = hex!["
+		0700000000000000
+		3292446c90740900
+		0b00414c
+		03000000
+		00f1536500000000
+		007e7b6500000000
+		03000000
+	"];
+
+	#[test]
+	fn send_codec() {
+		test_codec(&hex!["
+			0b00414c
+			3292446c90740900
+			00000100
+			00000000
+			676700
+			0200
+			0100000000000900
+			0200000000000900
+			03000000 010203
+		"], SendMessageRequest {
+			title_id: 0x4C41000B,
+			sender: Xuid(0x000974906c449232),
+			flags: 0x0001_0000,
+			expire_secs: 0,
+			text: "gg".to_owned(),
+			recipients: vec![Xuid(0x0009_0000_0000_0001), Xuid(0x0009_0000_0000_0002)],
+			attachment: vec![1, 2, 3],
+		});
+
+		test_codec(&hex!["
+			0200
+			0100000000000900 00000000 0700000000000000
+			0200000000000900 05000780 0000000000000000
+		"], SendMessageReply {
+			results: vec![
+				RecipientResult {
+					recipient: Xuid(0x0009_0000_0000_0001),
+					hr: HResult::SUCCESS,
+					message_id: 7,
+				},
+				RecipientResult {
+					recipient: Xuid(0x0009_0000_0000_0002),
+					hr: E_BLOCKED,
+					message_id: 0,
+				},
+			],
+		});
+	}
+
+	#[test]
+	fn enumerate_codec() {
+		test_codec(&hex!["
+			0b00414c
+			3292446c90740900
+		"], EnumerateMessagesRequest {
+			title_id: 0x4C41000B,
+			user: Xuid(0x000974906c449232),
+		});
+
+		let mut reply = hex!["0100"].to_vec();
+		reply.extend_from_slice(&SUMMARY_BYTES);
+
+		test_codec(&reply, EnumerateMessagesReply {
+			messages: vec![SUMMARY],
+		});
+	}
+
+	#[test]
+	fn message_codec() {
+		test_codec(&hex!["
+			0b00414c
+			3292446c90740900
+			0700000000000000
+		"], MessageRequest {
+			title_id: 0x4C41000B,
+			user: Xuid(0x000974906c449232),
+			message_id: 7,
+		});
+
+		let mut reply = SUMMARY_BYTES.to_vec();
+		reply.extend_from_slice(&hex!["676700"]);
+
+		test_codec(&reply, MessageDetailsReply {
+			summary: SUMMARY,
+			text: "gg".to_owned(),
+		});
+
+		test_codec(&hex!["
+			0700000000000000
+			3292446c90740900
+		"], MessageNotification {
+			message_id: 7,
+			sender: Xuid(0x000974906c449232),
+		});
+	}
+
+	#[test]
+	fn truncated_attachment_is_rejected() {
+		assert!(SendMessageRequest::decode(&hex!["
+			0b00414c 3292446c90740900 00000000 00000000 00 0000 03000000 0102
+		"]).is_err());
+	}
+}
@@ -1,6 +1,8 @@
 use bytes::BufMut;
 
-use nom::number::complete::{le_u16, le_u32, le_u64};
+use nom::bytes::complete::take;
+use nom::multi::count;
+use nom::number::complete::{le_u8, le_u16, le_u32, le_u64};
 
 use std::mem::size_of;
 use std::num::NonZeroUsize;
@@ -19,9 +21,50 @@ use crate::ver::LibraryVersion;
 
 const NUL_TERMINIATOR_LEN: usize = 1;
 
-pub const ALIVE_MSG_TYPE:       u32 = 1001;
-pub const ALIVE_2_MSG_TYPE:     u32 = 1025;
-pub const ALIVE_REPLY_MSG_TYPE: u32 = 1101;
+pub const ALIVE_MSG_TYPE:         u32 = 1001;
+pub const SYNC_MSG_TYPE:          u32 = 1002;
+pub const ADD_MSG_TYPE:           u32 = 1003;
+pub const DELETE_MSG_TYPE:        u32 = 1004;
+pub const ACCEPT_MSG_TYPE:        u32 = 1005;
+pub const REJECT_MSG_TYPE:        u32 = 1006;
+pub const BLOCK_MSG_TYPE:         u32 = 1007;
+pub const UNBLOCK_MSG_TYPE:       u32 = 1008;
+pub const INVITE_MSG_TYPE:        u32 = 1009;
+pub const CANCEL_MSG_TYPE:        u32 = 1010;
+pub const INVITE_ANSWER_MSG_TYPE: u32 = 1011;
+pub const DEAD_USER_MSG_TYPE:     u32 = 1015;
+/// Provisional: no capture or documentation of a peek has been found, so
+/// this is only the next free number after DEAD_USER_MSG_TYPE.
+pub const PEEK_MSG_TYPE:          u32 = 1016;
+pub const ALIVE_2_MSG_TYPE:       u32 = 1025;
+pub const ALIVE_REPLY_MSG_TYPE:   u32 = 1101;
+/// Provisional and unsourced like PEEK_MSG_TYPE.  This was 1102, following
+/// ALIVE_REPLY_MSG_TYPE, until it was moved to REPLY_MSG_TYPE_OFFSET above
+/// the peek so that it agrees with the failure replies.
+pub const PEEK_REPLY_MSG_TYPE:    u32 = 1116;
+
+/// Replies other than the AliveReply are numbered this far above the request
+/// they answer.
+pub const REPLY_MSG_TYPE_OFFSET: u32 = 100;
+
+/// Most buddies a single user can have, counting requests either way.
+pub const MAX_BUDDIES: usize = 100;
+
+/// Most users a single user can block.
+pub const MAX_BLOCKS: usize = 250;
+
+/// The invitee won't be joining.
+pub const INVITE_ANSWER_DECLINE: u16 = 0;
+/// The invitee agreed to play, and will join once they're ready.
+pub const INVITE_ANSWER_ACCEPT:  u16 = 1;
+/// The invitee is joining the host's session right away.
+pub const INVITE_ANSWER_JOIN:    u16 = 2;
+
+pub const BUDDY_STATUS_OK:      u8 = 0;
+/// The user asked to be buddies and is waiting on an answer.
+pub const BUDDY_STATUS_PENDING: u8 = 1;
+/// The buddy asked to be buddies with the user.
+pub const BUDDY_STATUS_REQUEST: u8 = 2;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Message {
@@ -30,6 +73,16 @@ pub struct Message {
 }
 
 impl Message {
+	/// A Failure reply to req carrying hr.
+	pub fn failure_reply_to_req(hr: HResult, req: &Message) -> Message {
+		let kind = MessageKind::Failure {
+			msg_type: req.header.msg_type.wrapping_add(REPLY_MSG_TYPE_OFFSET),
+			hr,
+		};
+
+		Message::reply_from_kind_and_req(kind, req)
+	}
+
 	pub fn reply_from_kind_and_req(kind: MessageKind, req: &Message) -> Message {
 		assert!(kind.is_reply());
 		assert!(req.kind.is_request());
@@ -72,13 +125,14 @@ impl Decode for Message {
 		let kind = match header.msg_type {
 			ALIVE_MSG_TYPE => {
 				let (input, body) = Alive::decode(body)?;
-				let (_, acct_name) = parse_nul_terminated_ascii(input)?;
-				if body.nickname_len != 0 || body.title_stuff_len != 0 {
-					todo!("{:?}", body);
-				}
+				let (input, acct_name) = parse_nul_terminated_ascii(input)?;
+				let (input, nickname) = take(body.nickname_len)(input)?;
+				let (_, title_stuff) = take(body.title_stuff_len)(input)?;
 				MessageKind::Alive {
 					body,
 					acct_name: acct_name.to_owned(),
+					nickname: nickname.to_vec(),
+					title_stuff: title_stuff.to_vec(),
 				}
 			}
 			ALIVE_2_MSG_TYPE => {
@@ -89,8 +143,85 @@ impl Decode for Message {
 					acct_name: acct_name.to_owned(),
 				}
 			}
-			_ => {
-				todo!("{:?} {:02x?}", header, body)
+			DEAD_USER_MSG_TYPE => {
+				let (_, body) = DeadUser::decode(body)?;
+				MessageKind::DeadUser(body)
+			}
+			SYNC_MSG_TYPE => {
+				let (_, body) = ListSync::decode(body)?;
+				MessageKind::ListSync(body)
+			}
+			ADD_MSG_TYPE => {
+				let (_, body) = BuddyRequest::decode(body)?;
+				MessageKind::Add(body)
+			}
+			DELETE_MSG_TYPE => {
+				let (_, body) = BuddyRequest::decode(body)?;
+				MessageKind::Delete(body)
+			}
+			ACCEPT_MSG_TYPE => {
+				let (_, body) = BuddyRequest::decode(body)?;
+				MessageKind::Accept(body)
+			}
+			REJECT_MSG_TYPE => {
+				let (_, body) = BuddyRequest::decode(body)?;
+				MessageKind::Reject(body)
+			}
+			BLOCK_MSG_TYPE => {
+				let (_, body) = BlockRequest::decode(body)?;
+				MessageKind::Block(body)
+			}
+			UNBLOCK_MSG_TYPE => {
+				let (_, body) = BlockRequest::decode(body)?;
+				MessageKind::Unblock(body)
+			}
+			INVITE_MSG_TYPE => {
+				let (input, body) = Invite::decode(body)?;
+				let (_, invitees) = count(Xuid::decode, body.num_invitees as usize)(input)?;
+				MessageKind::Invite {
+					body,
+					invitees,
+				}
+			}
+			CANCEL_MSG_TYPE => {
+				let (input, body) = InviteCancel::decode(body)?;
+				let (_, invitees) = count(Xuid::decode, body.num_invitees as usize)(input)?;
+				MessageKind::Cancel {
+					body,
+					invitees,
+				}
+			}
+			INVITE_ANSWER_MSG_TYPE => {
+				let (_, body) = InviteAnswer::decode(body)?;
+				MessageKind::InviteAnswer(body)
+			}
+			PEEK_MSG_TYPE => {
+				let (_, body) = Peek::decode(body)?;
+				MessageKind::Peek(body)
+			}
+			PEEK_REPLY_MSG_TYPE => {
+				let (input, body) = PeekReply::decode(body)?;
+				let (_, buddies) = count(PeekBuddy::decode, body.buddies_sent as usize)(input)?;
+				MessageKind::PeekReply {
+					body,
+					buddies,
+				}
+			}
+			ALIVE_REPLY_MSG_TYPE => {
+				let (input, body) = AliveReply::decode(body)?;
+				let (input, buddies) = count(ReplyBuddy::decode, body.buddies_sent as usize)(input)?;
+				let (_, blocks) = count(Xuid::decode, body.blocks_sent as usize)(input)?;
+				MessageKind::AliveReply {
+					body,
+					buddies,
+					blocks,
+				}
+			}
+			msg_type => {
+				MessageKind::Unknown {
+					msg_type,
+					body: body.to_vec(),
+				}
 			}
 		};
 
@@ -103,39 +234,106 @@ impl Decode for Message {
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum MessageKind {
-	Alive{body: Alive, acct_name: String},
+	/// The nickname and title stuff are opaque to the server, they're only
+	/// ever handed back to other consoles.
+	Alive{body: Alive, acct_name: String, nickname: Vec<u8>, title_stuff: Vec<u8>},
 	Alive2{body: Alive2, acct_name: String},
-	AliveReply(AliveReply),
+	DeadUser(DeadUser),
+	ListSync(ListSync),
+	Add(BuddyRequest),
+	Delete(BuddyRequest),
+	Accept(BuddyRequest),
+	Reject(BuddyRequest),
+	Block(BlockRequest),
+	Unblock(BlockRequest),
+	Invite{body: Invite, invitees: Vec<Xuid>},
+	Cancel{body: InviteCancel, invitees: Vec<Xuid>},
+	InviteAnswer(InviteAnswer),
+	Peek(Peek),
+	/// The buddies and blocks are the user's whole buddy and block lists,
+	/// each only sent when the console's copy is out of date.
+	AliveReply{body: AliveReply, buddies: Vec<ReplyBuddy>, blocks: Vec<Xuid>},
+	PeekReply{body: PeekReply, buddies: Vec<PeekBuddy>},
+	/// A request this codec doesn't know how to parse.
+	Unknown{msg_type: u32, body: Vec<u8>},
+	/// A reply to a request that couldn't be carried out, with nothing but
+	/// why.  Never decoded, as it shares its message type with the reply the
+	/// request would normally get.
+	Failure{msg_type: u32, hr: HResult},
 }
 
 impl MessageKind {
 	pub fn msg_type(&self) -> u32 {
 		use MessageKind::*;
 		match self {
-			Alive { body: _, acct_name: _ }  => ALIVE_MSG_TYPE,
+			Alive { .. }                     => ALIVE_MSG_TYPE,
 			Alive2 { body: _, acct_name: _ } => ALIVE_2_MSG_TYPE,
-			AliveReply(_)                    => ALIVE_REPLY_MSG_TYPE,
+			DeadUser(_)                      => DEAD_USER_MSG_TYPE,
+			ListSync(_)                      => SYNC_MSG_TYPE,
+			Add(_)                           => ADD_MSG_TYPE,
+			Delete(_)                        => DELETE_MSG_TYPE,
+			Accept(_)                        => ACCEPT_MSG_TYPE,
+			Reject(_)                        => REJECT_MSG_TYPE,
+			Block(_)                         => BLOCK_MSG_TYPE,
+			Unblock(_)                       => UNBLOCK_MSG_TYPE,
+			Invite { .. }                    => INVITE_MSG_TYPE,
+			Cancel { .. }                    => CANCEL_MSG_TYPE,
+			InviteAnswer(_)                  => INVITE_ANSWER_MSG_TYPE,
+			Peek(_)                          => PEEK_MSG_TYPE,
+			AliveReply { .. }                => ALIVE_REPLY_MSG_TYPE,
+			PeekReply { .. }                 => PEEK_REPLY_MSG_TYPE,
+			Unknown { msg_type, .. }         => *msg_type,
+			Failure { msg_type, .. }         => *msg_type,
 		}
 	}
 
 	pub fn encoded_len(&self) -> usize {
 		match self {
-			MessageKind::Alive { body, acct_name } => {
-				const ALIVE_HEADER_LEN: usize = 0x28;
-				if body.nickname_len != 0 || body.title_stuff_len != 0 {
-					todo!("{:?}", body)
-				}
-				ALIVE_HEADER_LEN + acct_name.as_bytes().len() + NUL_TERMINIATOR_LEN
+			MessageKind::Alive { body: _, acct_name, nickname, title_stuff } => {
+				const ALIVE_HEADER_LEN: usize = 0x26;
+				ALIVE_HEADER_LEN + acct_name.as_bytes().len() + NUL_TERMINIATOR_LEN + nickname.len() + title_stuff.len()
 			}
 			MessageKind::Alive2 { body: _, acct_name } => {
 				size_of::<Alive2>() + acct_name.as_bytes().len() + NUL_TERMINIATOR_LEN
 			}
-			MessageKind::AliveReply(body) => {
+			MessageKind::DeadUser(_) => {
+				size_of::<DeadUser>()
+			}
+			MessageKind::ListSync(_) => {
+				ListSync::ENCODED_LEN
+			}
+			MessageKind::Add(_) | MessageKind::Delete(_) | MessageKind::Accept(_) | MessageKind::Reject(_) => {
+				BuddyRequest::ENCODED_LEN
+			}
+			MessageKind::Block(_) | MessageKind::Unblock(_) => {
+				BlockRequest::ENCODED_LEN
+			}
+			MessageKind::Invite { body: _, invitees } => {
+				Invite::ENCODED_LEN + invitees.len() * size_of::<Xuid>()
+			}
+			MessageKind::Cancel { body: _, invitees } => {
+				InviteCancel::ENCODED_LEN + invitees.len() * size_of::<Xuid>()
+			}
+			MessageKind::InviteAnswer(_) => {
+				InviteAnswer::ENCODED_LEN
+			}
+			MessageKind::Peek(_) => {
+				Peek::ENCODED_LEN
+			}
+			MessageKind::AliveReply { body: _, buddies, blocks } => {
 				const ALIVE_REPLY_HEADER_LEN: usize = 0x10;
-				if body.blocks_sent != 0 || body.buddies_sent != 0 {
-					todo!("{:?}", body)
-				}
 				ALIVE_REPLY_HEADER_LEN
+					+ buddies.iter().map(ReplyBuddy::encoded_len).sum::<usize>()
+					+ blocks.len() * size_of::<Xuid>()
+			}
+			MessageKind::PeekReply { body: _, buddies } => {
+				PeekReply::ENCODED_LEN + buddies.iter().map(PeekBuddy::encoded_len).sum::<usize>()
+			}
+			MessageKind::Unknown { msg_type: _, body } => {
+				body.len()
+			}
+			MessageKind::Failure { .. } => {
+				size_of::<HResult>()
 			}
 		}
 	}
@@ -143,9 +341,30 @@ impl MessageKind {
 	pub fn is_request(&self) -> bool {
 		use MessageKind::*;
 		match self {
-			Alive { body: _, acct_name: _ } => true,
+			Alive { .. } => true,
 			Alive2 { body: _, acct_name: _ } => true,
-			AliveReply(_) => false,
+			DeadUser(_) => true,
+			ListSync(_) => true,
+			Add(_) | Delete(_) | Accept(_) | Reject(_) => true,
+			Block(_) | Unblock(_) => true,
+			Invite { .. } | Cancel { .. } | InviteAnswer(_) => true,
+			Peek(_) => true,
+			Unknown { .. } => true,
+			AliveReply { .. } => false,
+			PeekReply { .. } => false,
+			Failure { .. } => false,
+		}
+	}
+
+	/// Requests the console doesn't expect a presence message in reply to.
+	pub fn expects_reply(&self) -> bool {
+		use MessageKind::*;
+		match self {
+			DeadUser(_) => false,
+			Add(_) | Delete(_) | Accept(_) | Reject(_) => false,
+			Block(_) | Unblock(_) => false,
+			Invite { .. } | Cancel { .. } | InviteAnswer(_) => false,
+			other => other.is_request(),
 		}
 	}
 
@@ -157,19 +376,66 @@ impl MessageKind {
 impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for MessageKind {
 	fn put(&self, buf: &mut AnyBufMut) {
 		match self {
-			Self::Alive{body, acct_name} => {
+			Self::Alive{body, acct_name, nickname, title_stuff} => {
 				body.put(buf);
 				put_nul_terminated_ascii(acct_name, buf);
+				buf.put_slice(nickname);
+				buf.put_slice(title_stuff);
 			}
 			Self::Alive2{body, acct_name} => {
 				body.put(buf);
 				put_nul_terminated_ascii(acct_name, buf);
 			}
-			Self::AliveReply(body) => {
-				if body.blocks_sent != 0 || body.buddies_sent != 0 {
-					todo!("{:?}", body)
+			Self::DeadUser(body) => {
+				body.put(buf);
+			}
+			Self::ListSync(body) => {
+				body.put(buf);
+			}
+			Self::Add(body) | Self::Delete(body) | Self::Accept(body) | Self::Reject(body) => {
+				body.put(buf);
+			}
+			Self::Block(body) | Self::Unblock(body) => {
+				body.put(buf);
+			}
+			Self::Invite{body, invitees} => {
+				body.put(buf);
+				for invitee in invitees {
+					invitee.put(buf);
+				}
+			}
+			Self::Cancel{body, invitees} => {
+				body.put(buf);
+				for invitee in invitees {
+					invitee.put(buf);
+				}
+			}
+			Self::InviteAnswer(body) => {
+				body.put(buf);
+			}
+			Self::Peek(body) => {
+				body.put(buf);
+			}
+			Self::AliveReply{body, buddies, blocks} => {
+				body.put(buf);
+				for buddy in buddies {
+					buddy.put(buf);
 				}
+				for block in blocks {
+					block.put(buf);
+				}
+			}
+			Self::PeekReply{body, buddies} => {
 				body.put(buf);
+				for buddy in buddies {
+					buddy.put(buf);
+				}
+			}
+			Self::Unknown{msg_type: _, body} => {
+				buf.put_slice(body);
+			}
+			Self::Failure{msg_type: _, hr} => {
+				hr.put(buf);
 			}
 		}
 	}
@@ -341,175 +607,591 @@ impl Decode for Alive2 {
 	}
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(C)]
-pub struct AliveReply {
-	pub hr: HResult,
-	pub buddy_list_version: u32,
-	pub buddies_sent: u16,
-	pub block_list_version: u32,
-	pub blocks_sent: u16,
+pub struct DeadUser {
+	pub user_id: Xuid,
 }
 
-impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for AliveReply {
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for DeadUser {
 	fn put(&self, buf: &mut AnyBufMut) {
-		self.hr.put(buf);
-		buf.put_u32_le(self.buddy_list_version);
-		buf.put_u16_le(self.buddies_sent);
-		buf.put_u32_le(self.block_list_version);
-		buf.put_u16_le(self.blocks_sent);
+		self.user_id.put(buf);
 	}
 }
 
-impl Decode for AliveReply {
+impl Decode for DeadUser {
 	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
-		let (input, hr) = HResult::decode(input)?;
-		let (input, buddy_list_version) = le_u32(input)?;
-		let (input, buddies_sent) = le_u16(input)?;
-		let (input, block_list_version) = le_u32(input)?;
-		let (input, blocks_sent) = le_u16(input)?;
+		let (input, user_id) = Xuid::decode(input)?;
 
-		Ok((input, AliveReply {
-			hr,
-			buddy_list_version,
-			buddies_sent,
-			block_list_version,
-			blocks_sent,
+		Ok((input, DeadUser {
+			user_id,
 		}))
 	}
 }
 
-#[cfg(test)]
-mod tests {
-	use hex_literal::hex;
+/// Asks for the user's lists to be sent again in full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct ListSync {
+	pub user_id: Xuid,
+}
 
-	use xbox_sys::codec::test_codec;
-	use xbox_sys::crypto::SYMMETRIC_KEY_LEN;
+impl ListSync {
+	pub const ENCODED_LEN: usize = 8;
+}
 
-	use crate::crypto::primitives::KEY_ID_LENGTH;
-	use crate::net::Eui48;
-	use crate::sg::SG_NONCE_LEN;
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for ListSync {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.user_id.put(buf);
+	}
+}
 
-	use super::*;
+impl Decode for ListSync {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, user_id) = Xuid::decode(input)?;
 
-	#[test]
-	fn alive2_message_codec() {
-		test_codec(
-			&hex!["
-				0104000081000000010000000a000064
-				00010000010000000000000000000000
+		Ok((input, ListSync {
+			user_id,
+		}))
+	}
+}
 
-				3292446c9074090009000a00020fc0a8
-				015bbbbe0050f21f64530a0000640001
-				000001000000000000000000000082bb
-				78b11ec1da0539376321974612e22d12
-				25f8c6f9c6cc00000000000000000000
-				0000000000000000feff000000000000
-				00000000000000000000000000000000
-				0000000000000000
+/// The body of an Add, Delete, Accept or Reject, all of which are the user
+/// doing something to their relationship with a buddy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct BuddyRequest {
+	pub user_id: Xuid,
+	pub buddy_id: Xuid,
+}
 
-				6d6f6e6f6361736100
-			"], Message {
-				header: Header {
-					msg_type: 0x401,
-					msg_len: 0x81,
-					seq_num: 1,
-					sgaddr: SgAddr {
-						ina_sg: InAddr([10, 0, 0, 100]),
-						spi_sg: 0x00000100,
-						xbox_id: Xuid(1),
-						_rsvd_10: [0;4],
-					},
-				},
-				kind: MessageKind::Alive2 {
-					body: Alive2 {
-						user_id: Xuid(0x000974906c449232),
-						acct_name_len: 9,
-						xnaddr: Addr {
-							addr: InAddr([10, 0, 2, 15]),
-							addr_online: InAddr([192, 168, 1, 91]),
-							port_online: 0xbebb,
-							enet: Eui48(hex!["00 50 f2 1f 64 53"]),
-							online: hex!["0a000064 00010000 01000000 00000000 00000000"],
-						},
-						xnkid: KeyId(hex!["82bb78b11ec1da05"]),
-						xnkey: SymmetricKey(hex!["39376321974612e22d1225f8c6f9c6cc"]),
-						buddy_list_version: 0,
-						block_list_version: 0,
-						client_version: LibraryVersion {
-							major: 0,
-							minor: 0,
-							build: 0,
-							qfe: 0,
-						},
-						title_id: 0xfffe0000,
-						title_version: 0,
-						title_region: 0,
-						ip_al: InAddr([0, 0, 0, 0]),
-						port: 0,
-						nonce: SgNonce(hex!["0000000000000000"]),
-						time_init: TimeStamp(0),
-					},
-					acct_name: "monocasa".to_owned(),
-				}
-			}
-		);
-	}
+impl BuddyRequest {
+	pub const ENCODED_LEN: usize = 8 + 8;
+}
 
-	#[test]
-	fn header_codec() {
-		test_codec(
-			&hex!["
-				01040000
-				81000000
-				01000000
-				0a000064
-				00010000
-				0100000000000000
-				00000000
-			"], Header {
-				msg_type: 0x401,
-				msg_len: 0x81,
-				seq_num: 1,
-				sgaddr: SgAddr {
-					ina_sg: InAddr([10, 0, 0, 100]),
-					spi_sg: 0x00000100,
-					xbox_id: Xuid(1),
-					_rsvd_10: [0;4],
-				}
-			}
-		);
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for BuddyRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.user_id.put(buf);
+		self.buddy_id.put(buf);
 	}
+}
 
-	#[test]
-	fn alive_codec() {
-		test_codec(
-			&hex!["
-				32 92 44 6c 90 74 09 00
-				0b 00 41 4c
-				09 00
-				00 00 00 00
-				00 00 00 00
-				05 00 00 00
-				00 00 00 00 00 00 00 00
-				00 00
-				00 00
-			"], Alive {
-				user_id: Xuid(0x000974906c449232),
-				title_id: 0x4c41000b,
-				acct_name_len: 9,
-				buddy_list_version: 0,
-				block_list_version: 0,
-				state: 5,
-				match_session_id: 0,
-				nickname_len: 0,
-				title_stuff_len: 0,
-			}
-		)
+impl Decode for BuddyRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, user_id) = Xuid::decode(input)?;
+		let (input, buddy_id) = Xuid::decode(input)?;
+
+		Ok((input, BuddyRequest {
+			user_id,
+			buddy_id,
+		}))
 	}
+}
 
-	#[test]
-	fn alive_message_codec() {
+/// The body of a Block or Unblock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct BlockRequest {
+	pub user_id: Xuid,
+	pub blocked_id: Xuid,
+}
+
+impl BlockRequest {
+	pub const ENCODED_LEN: usize = 8 + 8;
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for BlockRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.user_id.put(buf);
+		self.blocked_id.put(buf);
+	}
+}
+
+impl Decode for BlockRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, user_id) = Xuid::decode(input)?;
+		let (input, blocked_id) = Xuid::decode(input)?;
+
+		Ok((input, BlockRequest {
+			user_id,
+			blocked_id,
+		}))
+	}
+}
+
+/// The user invites the users following the body into their session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct Invite {
+	pub user_id: Xuid,
+	pub xnkid: KeyId,
+	pub title_id: u32,
+	pub num_invitees: u16,
+}
+
+impl Invite {
+	pub const ENCODED_LEN: usize = 8 + 8 + 4 + 2;
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for Invite {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.user_id.put(buf);
+		self.xnkid.put(buf);
+		buf.put_u32_le(self.title_id);
+		buf.put_u16_le(self.num_invitees);
+	}
+}
+
+impl Decode for Invite {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, user_id) = Xuid::decode(input)?;
+		let (input, xnkid) = KeyId::decode(input)?;
+		let (input, title_id) = le_u32(input)?;
+		let (input, num_invitees) = le_u16(input)?;
+
+		Ok((input, Invite {
+			user_id,
+			xnkid,
+			title_id,
+			num_invitees,
+		}))
+	}
+}
+
+/// The user takes back their invitations into a session from the users
+/// following the body.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct InviteCancel {
+	pub user_id: Xuid,
+	pub xnkid: KeyId,
+	pub num_invitees: u16,
+}
+
+impl InviteCancel {
+	pub const ENCODED_LEN: usize = 8 + 8 + 2;
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for InviteCancel {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.user_id.put(buf);
+		self.xnkid.put(buf);
+		buf.put_u16_le(self.num_invitees);
+	}
+}
+
+impl Decode for InviteCancel {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, user_id) = Xuid::decode(input)?;
+		let (input, xnkid) = KeyId::decode(input)?;
+		let (input, num_invitees) = le_u16(input)?;
+
+		Ok((input, InviteCancel {
+			user_id,
+			xnkid,
+			num_invitees,
+		}))
+	}
+}
+
+/// The user's answer to an invitation from host_id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct InviteAnswer {
+	pub user_id: Xuid,
+	pub host_id: Xuid,
+	pub xnkid: KeyId,
+	pub answer: u16,
+}
+
+impl InviteAnswer {
+	pub const ENCODED_LEN: usize = 8 + 8 + 8 + 2;
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for InviteAnswer {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.user_id.put(buf);
+		self.host_id.put(buf);
+		self.xnkid.put(buf);
+		buf.put_u16_le(self.answer);
+	}
+}
+
+impl Decode for InviteAnswer {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, user_id) = Xuid::decode(input)?;
+		let (input, host_id) = Xuid::decode(input)?;
+		let (input, xnkid) = KeyId::decode(input)?;
+		let (input, answer) = le_u16(input)?;
+
+		Ok((input, InviteAnswer {
+			user_id,
+			host_id,
+			xnkid,
+			answer,
+		}))
+	}
+}
+
+/// The data of the GAME_INVITE pulse event telling an invitee's console
+/// about an invitation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct InviteNotice {
+	pub host_id: Xuid,
+	pub xnkid: KeyId,
+	pub title_id: u32,
+}
+
+impl InviteNotice {
+	pub const ENCODED_LEN: usize = 8 + 8 + 4;
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for InviteNotice {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.host_id.put(buf);
+		self.xnkid.put(buf);
+		buf.put_u32_le(self.title_id);
+	}
+}
+
+impl Decode for InviteNotice {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, host_id) = Xuid::decode(input)?;
+		let (input, xnkid) = KeyId::decode(input)?;
+		let (input, title_id) = le_u32(input)?;
+
+		Ok((input, InviteNotice {
+			host_id,
+			xnkid,
+			title_id,
+		}))
+	}
+}
+
+/// One entry of the buddy list following an AliveReply.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplyBuddy {
+	pub buddy_id: Xuid,
+	pub status: u8,
+	pub acct_name: String,
+}
+
+impl ReplyBuddy {
+	const HEADER_LEN: usize = 8 + 2 + 1;
+
+	pub fn encoded_len(&self) -> usize {
+		Self::HEADER_LEN + self.acct_name.len() + NUL_TERMINIATOR_LEN
+	}
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for ReplyBuddy {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.buddy_id.put(buf);
+		buf.put_u16_le((self.acct_name.len() + NUL_TERMINIATOR_LEN) as u16);
+		buf.put_u8(self.status);
+		put_nul_terminated_ascii(&self.acct_name, buf);
+	}
+}
+
+impl Decode for ReplyBuddy {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, buddy_id) = Xuid::decode(input)?;
+		let (input, acct_name_len) = le_u16(input)?;
+		let (input, status) = le_u8(input)?;
+		let (input, acct_name) = take(acct_name_len)(input)?;
+		let (_, acct_name) = parse_nul_terminated_ascii(acct_name)?;
+
+		Ok((input, ReplyBuddy {
+			buddy_id,
+			status,
+			acct_name: acct_name.to_owned(),
+		}))
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct AliveReply {
+	pub hr: HResult,
+	pub buddy_list_version: u32,
+	pub buddies_sent: u16,
+	pub block_list_version: u32,
+	pub blocks_sent: u16,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for AliveReply {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.hr.put(buf);
+		buf.put_u32_le(self.buddy_list_version);
+		buf.put_u16_le(self.buddies_sent);
+		buf.put_u32_le(self.block_list_version);
+		buf.put_u16_le(self.blocks_sent);
+	}
+}
+
+impl Decode for AliveReply {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, hr) = HResult::decode(input)?;
+		let (input, buddy_list_version) = le_u32(input)?;
+		let (input, buddies_sent) = le_u16(input)?;
+		let (input, block_list_version) = le_u32(input)?;
+		let (input, blocks_sent) = le_u16(input)?;
+
+		Ok((input, AliveReply {
+			hr,
+			buddy_list_version,
+			buddies_sent,
+			block_list_version,
+			blocks_sent,
+		}))
+	}
+}
+
+/// Asks what the user's buddies are up to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct Peek {
+	pub user_id: Xuid,
+}
+
+impl Peek {
+	pub const ENCODED_LEN: usize = 8;
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for Peek {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.user_id.put(buf);
+	}
+}
+
+impl Decode for Peek {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, user_id) = Xuid::decode(input)?;
+
+		Ok((input, Peek {
+			user_id,
+		}))
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct PeekReply {
+	pub hr: HResult,
+	pub buddies_sent: u16,
+}
+
+impl PeekReply {
+	pub const ENCODED_LEN: usize = 4 + 2;
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for PeekReply {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.hr.put(buf);
+		buf.put_u16_le(self.buddies_sent);
+	}
+}
+
+impl Decode for PeekReply {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, hr) = HResult::decode(input)?;
+		let (input, buddies_sent) = le_u16(input)?;
+
+		Ok((input, PeekReply {
+			hr,
+			buddies_sent,
+		}))
+	}
+}
+
+/// One buddy's presence following a PeekReply.  The nickname and title stuff
+/// are what the buddy's console last sent in an Alive, and a state of zero
+/// means the buddy is offline.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeekBuddy {
+	pub buddy_id: Xuid,
+	pub state: u32,
+	pub title_id: u32,
+	pub match_session_id: u64,
+	pub nickname: Vec<u8>,
+	pub title_stuff: Vec<u8>,
+}
+
+impl PeekBuddy {
+	const HEADER_LEN: usize = 8 + 4 + 4 + 8 + 2 + 2;
+
+	pub fn encoded_len(&self) -> usize {
+		Self::HEADER_LEN + self.nickname.len() + self.title_stuff.len()
+	}
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for PeekBuddy {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.buddy_id.put(buf);
+		buf.put_u32_le(self.state);
+		buf.put_u32_le(self.title_id);
+		buf.put_u64_le(self.match_session_id);
+		buf.put_u16_le(self.nickname.len() as u16);
+		buf.put_u16_le(self.title_stuff.len() as u16);
+		buf.put_slice(&self.nickname);
+		buf.put_slice(&self.title_stuff);
+	}
+}
+
+impl Decode for PeekBuddy {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, buddy_id) = Xuid::decode(input)?;
+		let (input, state) = le_u32(input)?;
+		let (input, title_id) = le_u32(input)?;
+		let (input, match_session_id) = le_u64(input)?;
+		let (input, nickname_len) = le_u16(input)?;
+		let (input, title_stuff_len) = le_u16(input)?;
+		let (input, nickname) = take(nickname_len)(input)?;
+		let (input, title_stuff) = take(title_stuff_len)(input)?;
+
+		Ok((input, PeekBuddy {
+			buddy_id,
+			state,
+			title_id,
+			match_session_id,
+			nickname: nickname.to_vec(),
+			title_stuff: title_stuff.to_vec(),
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use hex_literal::hex;
+
+	use xbox_sys::codec::test_codec;
+	use xbox_sys::crypto::SYMMETRIC_KEY_LEN;
+
+	use crate::crypto::primitives::KEY_ID_LENGTH;
+	use crate::net::Eui48;
+	use crate::sg::SG_NONCE_LEN;
+
+	use super::*;
+
+	#[test]
+	fn alive2_message_codec() {
+		test_codec(
+			&hex!["
+				0104000081000000010000000a000064
+				00010000010000000000000000000000
+
+				3292446c9074090009000a00020fc0a8
+				015bbbbe0050f21f64530a0000640001
+				000001000000000000000000000082bb
+				78b11ec1da0539376321974612e22d12
+				25f8c6f9c6cc00000000000000000000
+				0000000000000000feff000000000000
+				00000000000000000000000000000000
+				0000000000000000
+
+				6d6f6e6f6361736100
+			"], Message {
+				header: Header {
+					msg_type: 0x401,
+					msg_len: 0x81,
+					seq_num: 1,
+					sgaddr: SgAddr {
+						ina_sg: InAddr([10, 0, 0, 100]),
+						spi_sg: 0x00000100,
+						xbox_id: Xuid(1),
+						_rsvd_10: [0;4],
+					},
+				},
+				kind: MessageKind::Alive2 {
+					body: Alive2 {
+						user_id: Xuid(0x000974906c449232),
+						acct_name_len: 9,
+						xnaddr: Addr {
+							addr: InAddr([10, 0, 2, 15]),
+							addr_online: InAddr([192, 168, 1, 91]),
+							port_online: 0xbebb,
+							enet: Eui48(hex!["00 50 f2 1f 64 53"]),
+							online: hex!["0a000064 00010000 01000000 00000000 00000000"],
+						},
+						xnkid: KeyId(hex!["82bb78b11ec1da05"]),
+						xnkey: SymmetricKey(hex!["39376321974612e22d1225f8c6f9c6cc"]),
+						buddy_list_version: 0,
+						block_list_version: 0,
+						client_version: LibraryVersion {
+							major: 0,
+							minor: 0,
+							build: 0,
+							qfe: 0,
+						},
+						title_id: 0xfffe0000,
+						title_version: 0,
+						title_region: 0,
+						ip_al: InAddr([0, 0, 0, 0]),
+						port: 0,
+						nonce: SgNonce(hex!["0000000000000000"]),
+						time_init: TimeStamp(0),
+					},
+					acct_name: "monocasa".to_owned(),
+				}
+			}
+		);
+	}
+
+	#[test]
+	fn header_codec() {
+		test_codec(
+			&hex!["
+				01040000
+				81000000
+				01000000
+				0a000064
+				00010000
+				0100000000000000
+				00000000
+			"], Header {
+				msg_type: 0x401,
+				msg_len: 0x81,
+				seq_num: 1,
+				sgaddr: SgAddr {
+					ina_sg: InAddr([10, 0, 0, 100]),
+					spi_sg: 0x00000100,
+					xbox_id: Xuid(1),
+					_rsvd_10: [0;4],
+				}
+			}
+		);
+	}
+
+	#[test]
+	fn alive_codec() {
+		test_codec(
+			&hex!["
+				32 92 44 6c 90 74 09 00
+				0b 00 41 4c
+				09 00
+				00 00 00 00
+				00 00 00 00
+				05 00 00 00
+				00 00 00 00 00 00 00 00
+				00 00
+				00 00
+			"], Alive {
+				user_id: Xuid(0x000974906c449232),
+				title_id: 0x4c41000b,
+				acct_name_len: 9,
+				buddy_list_version: 0,
+				block_list_version: 0,
+				state: 5,
+				match_session_id: 0,
+				nickname_len: 0,
+				title_stuff_len: 0,
+			}
+		)
+	}
+
+	#[test]
+	fn alive_message_codec() {
 		test_codec(
 			&hex!["
 				e90300002f000000010000000a000064
@@ -543,11 +1225,61 @@ mod tests {
 						title_stuff_len: 0,
 					},
 					acct_name: "monocasa".to_owned(),
+					nickname: vec![],
+					title_stuff: vec![],
 				}
 			}
 		)
 	}
 
+	#[test]
+	fn alive_message_with_nickname_and_title_stuff_codec() {
+		let message = Message {
+			header: Header {
+				msg_type: ALIVE_MSG_TYPE,
+				msg_len: 0x34,
+				seq_num: 3,
+				sgaddr: SgAddr {
+					ina_sg: InAddr([10, 0, 0, 100]),
+					spi_sg: 256,
+					xbox_id: Xuid(1),
+					_rsvd_10: [0;4]
+				}
+			},
+			kind: MessageKind::Alive {
+				body: Alive {
+					user_id: Xuid(0x000974906c449232),
+					title_id: 0x4c41000b,
+					acct_name_len: 9,
+					buddy_list_version: 2,
+					block_list_version: 1,
+					state: 3,
+					match_session_id: 0x1122334455667788,
+					nickname_len: 3,
+					title_stuff_len: 2,
+				},
+				acct_name: "monocasa".to_owned(),
+				nickname: b"mc\0".to_vec(),
+				title_stuff: vec![0x07, 0x01],
+			}
+		};
+
+		test_codec(
+			&hex!["
+				e903000034000000030000000a000064
+				00010000010000000000000000000000
+
+				3292446c907409000b00414c09000200
+				00000100000003000000887766554433
+				221103000200
+				6d6f6e6f6361736100
+				6d6300
+				0701
+			"], message.clone());
+
+		assert_eq!(message.kind.encoded_len(), message.header.msg_len as usize);
+	}
+
 	#[test]
 	fn alive2_codec() {
 		test_codec(
@@ -623,6 +1355,33 @@ mod tests {
 			})
 	}
 
+	#[test]
+	fn dead_user_message_codec() {
+		test_codec(
+			&hex!["
+				f7030000080000000200000006070809
+				630000002a0000000000000000000000
+
+				3292446c90740900
+			"], Message {
+				header: Header {
+					msg_type: DEAD_USER_MSG_TYPE,
+					msg_len: 8,
+					seq_num: 2,
+					sgaddr: SgAddr {
+						ina_sg: InAddr([6, 7, 8, 9]),
+						spi_sg: 99,
+						xbox_id: Xuid(42),
+						_rsvd_10: [0;4],
+					}
+				},
+				kind: MessageKind::DeadUser(DeadUser {
+					user_id: Xuid(0x000974906c449232),
+				}),
+			}
+		)
+	}
+
 	const EMPTY_ALIVE_2_MSG: Alive2 = Alive2 {
 		user_id: Xuid(0),
 		acct_name_len: 0,
@@ -654,13 +1413,17 @@ mod tests {
 
 	#[test]
 	fn alive_reply_encode() {
-		let kind = MessageKind::AliveReply(AliveReply {
-			hr: HResult(0x65544656),
-			buddy_list_version: 0x47632563,
-			buddies_sent: 0,
-			block_list_version: 0x67081776,
-			blocks_sent: 0,
-		});
+		let kind = MessageKind::AliveReply {
+			body: AliveReply {
+				hr: HResult(0x65544656),
+				buddy_list_version: 0x47632563,
+				buddies_sent: 0,
+				block_list_version: 0x67081776,
+				blocks_sent: 0,
+			},
+			buddies: vec![],
+			blocks: vec![],
+		};
 
 		let req = Message {
 			header: Header {
@@ -697,4 +1460,494 @@ mod tests {
 			0000
 		"])
 	}
+
+	#[test]
+	fn add_message_codec() {
+		test_codec(
+			&hex!["
+				eb030000100000000400000006070809
+				630000002a0000000000000000000000
+
+				3292446c90740900
+				0200000000000900
+			"], Message {
+				header: Header {
+					msg_type: ADD_MSG_TYPE,
+					msg_len: 16,
+					seq_num: 4,
+					sgaddr: SgAddr {
+						ina_sg: InAddr([6, 7, 8, 9]),
+						spi_sg: 99,
+						xbox_id: Xuid(42),
+						_rsvd_10: [0;4],
+					}
+				},
+				kind: MessageKind::Add(BuddyRequest {
+					user_id: Xuid(0x000974906c449232),
+					buddy_id: Xuid(0x0009000000000002),
+				}),
+			}
+		)
+	}
+
+	#[test]
+	fn sync_message_codec() {
+		test_codec(
+			&hex!["
+				ea030000080000000500000006070809
+				630000002a0000000000000000000000
+
+				3292446c90740900
+			"], Message {
+				header: Header {
+					msg_type: SYNC_MSG_TYPE,
+					msg_len: 8,
+					seq_num: 5,
+					sgaddr: SgAddr {
+						ina_sg: InAddr([6, 7, 8, 9]),
+						spi_sg: 99,
+						xbox_id: Xuid(42),
+						_rsvd_10: [0;4],
+					}
+				},
+				kind: MessageKind::ListSync(ListSync {
+					user_id: Xuid(0x000974906c449232),
+				}),
+			}
+		)
+	}
+
+	#[test]
+	fn alive_reply_with_buddies_message_codec() {
+		let message = Message {
+			header: Header {
+				msg_type: ALIVE_REPLY_MSG_TYPE,
+				msg_len: 0x2e,
+				seq_num: 1,
+				sgaddr: SgAddr {
+					ina_sg: InAddr([6, 7, 8, 9]),
+					spi_sg: 99,
+					xbox_id: Xuid(42),
+					_rsvd_10: [0;4],
+				}
+			},
+			kind: MessageKind::AliveReply {
+				body: AliveReply {
+					hr: HResult::SUCCESS,
+					buddy_list_version: 3,
+					buddies_sent: 2,
+					block_list_version: 0,
+					blocks_sent: 0,
+				},
+				buddies: vec![
+					ReplyBuddy {
+						buddy_id: Xuid(0x0009000000000002),
+						status: BUDDY_STATUS_OK,
+						acct_name: "duck".to_owned(),
+					},
+					ReplyBuddy {
+						buddy_id: Xuid(0x0009000000000003),
+						status: BUDDY_STATUS_REQUEST,
+						acct_name: "ox".to_owned(),
+					},
+				],
+				blocks: vec![],
+			}
+		};
+
+		test_codec(
+			&hex!["
+				4d0400002e0000000100000006070809
+				630000002a0000000000000000000000
+
+				00000000
+				03000000
+				0200
+				00000000
+				0000
+
+				0200000000000900 0500 00 6475636b00
+				0300000000000900 0300 02 6f7800
+			"], message.clone());
+
+		assert_eq!(message.kind.encoded_len(), message.header.msg_len as usize);
+	}
+
+	#[test]
+	fn block_message_codec() {
+		test_codec(
+			&hex!["
+				ef030000100000000500000006070809
+				630000002a0000000000000000000000
+
+				3292446c90740900
+				0300000000000900
+			"], Message {
+				header: Header {
+					msg_type: BLOCK_MSG_TYPE,
+					msg_len: 16,
+					seq_num: 5,
+					sgaddr: SgAddr {
+						ina_sg: InAddr([6, 7, 8, 9]),
+						spi_sg: 99,
+						xbox_id: Xuid(42),
+						_rsvd_10: [0;4],
+					}
+				},
+				kind: MessageKind::Block(BlockRequest {
+					user_id: Xuid(0x000974906c449232),
+					blocked_id: Xuid(0x0009000000000003),
+				}),
+			}
+		)
+	}
+
+	#[test]
+	fn alive_reply_with_buddies_and_blocks_message_codec() {
+		let message = Message {
+			header: Header {
+				msg_type: ALIVE_REPLY_MSG_TYPE,
+				msg_len: 0x30,
+				seq_num: 1,
+				sgaddr: SgAddr {
+					ina_sg: InAddr([6, 7, 8, 9]),
+					spi_sg: 99,
+					xbox_id: Xuid(42),
+					_rsvd_10: [0;4],
+				}
+			},
+			kind: MessageKind::AliveReply {
+				body: AliveReply {
+					hr: HResult::SUCCESS,
+					buddy_list_version: 3,
+					buddies_sent: 1,
+					block_list_version: 7,
+					blocks_sent: 2,
+				},
+				buddies: vec![
+					ReplyBuddy {
+						buddy_id: Xuid(0x0009000000000002),
+						status: BUDDY_STATUS_PENDING,
+						acct_name: "duck".to_owned(),
+					},
+				],
+				blocks: vec![
+					Xuid(0x0009000000000004),
+					Xuid(0x0009000000000005),
+				],
+			}
+		};
+
+		test_codec(
+			&hex!["
+				4d040000300000000100000006070809
+				630000002a0000000000000000000000
+
+				00000000
+				03000000
+				0100
+				07000000
+				0200
+
+				0200000000000900 0500 01 6475636b00
+
+				0400000000000900
+				0500000000000900
+			"], message.clone());
+
+		assert_eq!(message.kind.encoded_len(), message.header.msg_len as usize);
+	}
+
+	#[test]
+	fn invite_message_codec() {
+		test_codec(
+			&hex!["
+				f1030000260000000600000006070809
+				630000002a0000000000000000000000
+
+				3292446c90740900
+				1122334455667788
+				0b00414c
+				0200
+				0200000000000900
+				0300000000000900
+			"], Message {
+				header: Header {
+					msg_type: INVITE_MSG_TYPE,
+					msg_len: 0x26,
+					seq_num: 6,
+					sgaddr: SgAddr {
+						ina_sg: InAddr([6, 7, 8, 9]),
+						spi_sg: 99,
+						xbox_id: Xuid(42),
+						_rsvd_10: [0;4],
+					}
+				},
+				kind: MessageKind::Invite {
+					body: Invite {
+						user_id: Xuid(0x000974906c449232),
+						xnkid: KeyId([0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88]),
+						title_id: 0x4C41000B,
+						num_invitees: 2,
+					},
+					invitees: vec![
+						Xuid(0x0009000000000002),
+						Xuid(0x0009000000000003),
+					],
+				},
+			}
+		)
+	}
+
+	#[test]
+	fn invite_answer_message_codec() {
+		test_codec(
+			&hex!["
+				f30300001a0000000700000006070809
+				630000002a0000000000000000000000
+
+				0200000000000900
+				3292446c90740900
+				1122334455667788
+				0200
+			"], Message {
+				header: Header {
+					msg_type: INVITE_ANSWER_MSG_TYPE,
+					msg_len: 0x1a,
+					seq_num: 7,
+					sgaddr: SgAddr {
+						ina_sg: InAddr([6, 7, 8, 9]),
+						spi_sg: 99,
+						xbox_id: Xuid(42),
+						_rsvd_10: [0;4],
+					}
+				},
+				kind: MessageKind::InviteAnswer(InviteAnswer {
+					user_id: Xuid(0x0009000000000002),
+					host_id: Xuid(0x000974906c449232),
+					xnkid: KeyId([0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88]),
+					answer: INVITE_ANSWER_JOIN,
+				}),
+			}
+		)
+	}
+
+	#[test]
+	fn invite_notice_codec() {
+		test_codec(
+			&hex!["
+				3292446c90740900
+				1122334455667788
+				0b00414c
+			"], InviteNotice {
+				host_id: Xuid(0x000974906c449232),
+				xnkid: KeyId([0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88]),
+				title_id: 0x4C41000B,
+			}
+		)
+	}
+
+	#[test]
+	fn peek_reply_message_codec() {
+		let message = Message {
+			header: Header {
+				msg_type: PEEK_REPLY_MSG_TYPE,
+				msg_len: 0x43,
+				seq_num: 8,
+				sgaddr: SgAddr {
+					ina_sg: InAddr([6, 7, 8, 9]),
+					spi_sg: 99,
+					xbox_id: Xuid(42),
+					_rsvd_10: [0;4],
+				}
+			},
+			kind: MessageKind::PeekReply {
+				body: PeekReply {
+					hr: HResult::SUCCESS,
+					buddies_sent: 2,
+				},
+				buddies: vec![
+					PeekBuddy {
+						buddy_id: Xuid(0x0009000000000002),
+						state: 3,
+						title_id: 0x4C41000B,
+						match_session_id: 0x1122334455667788,
+						nickname: b"mc".to_vec(),
+						title_stuff: vec![0xAA, 0xBB, 0xCC],
+					},
+					PeekBuddy {
+						buddy_id: Xuid(0x0009000000000003),
+						state: 0,
+						title_id: 0,
+						match_session_id: 0,
+						nickname: vec![],
+						title_stuff: vec![],
+					},
+				],
+			}
+		};
+
+		test_codec(
+			&hex!["
+				5c040000430000000800000006070809
+				630000002a0000000000000000000000
+
+				00000000
+				0200
+
+				0200000000000900
+				03000000
+				0b00414c
+				8877665544332211
+				0200 0300
+				6d63 aabbcc
+
+				0300000000000900
+				00000000
+				00000000
+				0000000000000000
+				0000 0000
+			"], message.clone());
+
+		assert_eq!(message.kind.encoded_len(), message.header.msg_len as usize);
+	}
+
+	#[test]
+	fn unknown_message_codec() {
+		test_codec(
+			&hex!["
+				fc030000040000000900000006070809
+				630000002a0000000000000000000000
+
+				01020304
+			"], Message {
+				header: Header {
+					msg_type: 1020,
+					msg_len: 4,
+					seq_num: 9,
+					sgaddr: SgAddr {
+						ina_sg: InAddr([6, 7, 8, 9]),
+						spi_sg: 99,
+						xbox_id: Xuid(42),
+						_rsvd_10: [0;4],
+					}
+				},
+				kind: MessageKind::Unknown {
+					msg_type: 1020,
+					body: vec![1, 2, 3, 4],
+				},
+			}
+		)
+	}
+
+	#[test]
+	fn failure_reply_encode() {
+		let (_, req) = Message::decode(&hex!["
+			fc030000040000000900000006070809
+			630000002a0000000000000000000000
+
+			01020304
+		"]).unwrap();
+
+		let mut buf = vec![];
+		Message::failure_reply_to_req(HResult::E_NOTIMPL, &req).put(&mut buf);
+
+		assert_eq!(buf, hex!["
+			60040000040000000900000006070809
+			630000002a0000000000000000000000
+
+			01400080
+		"]);
+	}
+
+	/// Well formed messages of every shape the codec parses, to be cut up
+	/// into malformed ones.
+	fn valid_messages() -> Vec<Vec<u8>> {
+		vec![
+			hex!["
+				e90300002f000000010000000a000064
+				00010000010000000000000000000000
+				3292446c907409000b00414c09000000
+				00000000000005000000000000000000
+				0000000000006d6f6e6f6361736100
+			"].to_vec(),
+			hex!["
+				eb030000100000000400000006070809
+				630000002a0000000000000000000000
+				3292446c90740900
+				0200000000000900
+			"].to_vec(),
+			hex!["
+				f1030000260000000600000006070809
+				630000002a0000000000000000000000
+				3292446c90740900 1122334455667788 0b00414c 0200
+				0200000000000900 0300000000000900
+			"].to_vec(),
+			hex!["
+				4d040000300000000100000006070809
+				630000002a0000000000000000000000
+				00000000 03000000 0100 07000000 0200
+				0200000000000900 0500 01 6475636b00
+				0400000000000900 0500000000000900
+			"].to_vec(),
+			hex!["
+				5c040000430000000800000006070809
+				630000002a0000000000000000000000
+				00000000 0200
+				0200000000000900 03000000 0b00414c 8877665544332211 0200 0300 6d63 aabbcc
+				0300000000000900 00000000 00000000 0000000000000000 0000 0000
+			"].to_vec(),
+		]
+	}
+
+	/// Every valid message with each byte in turn replaced by values likely
+	/// to upset a length or count.
+	fn malformed_corpus() -> Vec<Vec<u8>> {
+		let mut corpus = vec![];
+
+		for valid in valid_messages() {
+			for i in 0..valid.len() {
+				for value in [0x00, 0x01, 0x7f, 0x80, 0xff] {
+					let mut malformed = valid.clone();
+					malformed[i] = value;
+					corpus.push(malformed);
+				}
+			}
+
+			let mut huge_len = valid.clone();
+			huge_len[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+			corpus.push(huge_len);
+
+			let mut trailing = valid.clone();
+			trailing.extend_from_slice(&[0xff; 16]);
+			corpus.push(trailing);
+		}
+
+		corpus
+	}
+
+	#[test]
+	fn truncated_messages_are_rejected() {
+		for valid in valid_messages() {
+			assert!(Message::decode(&valid).is_ok());
+
+			for len in 0..valid.len() {
+				assert!(Message::decode(&valid[..len]).is_err(), "{:02x?}", &valid[..len]);
+			}
+		}
+	}
+
+	#[test]
+	fn malformed_messages_never_panic() {
+		for malformed in malformed_corpus() {
+			// Anything is fine but a panic; plenty of the corpus is still
+			// well formed.
+			if let Ok((_, message)) = Message::decode(&malformed) {
+				if message.kind.is_request() {
+					let mut buf = vec![];
+					Message::failure_reply_to_req(HResult::E_FAIL, &message).put(&mut buf);
+				}
+			}
+		}
+	}
 }
@@ -0,0 +1,518 @@
+use bytes::BufMut;
+
+use nom::bytes::complete::take;
+use nom::multi::count;
+use nom::number::complete::{le_f64, le_i32, le_i64, le_u16, le_u32, le_u8};
+
+use xbox_sys::account::Xuid;
+use xbox_sys::codec::{BufPut, Decode, parse_nul_terminated_ascii, put_nul_terminated_ascii};
+use xbox_sys::status::HResult;
+
+/// PROVISIONAL: there's no public reference for the stats service's content
+/// type, its /xstats/ endpoints or the layout of their bodies.  All of them
+/// are this server's own, so consoles only reach them where the client side
+/// follows the same convention.  Replace them once the real ones are known.
+pub const CONTENT_TYPE: &'static str = "xon/7";
+
+// PROVISIONAL: the service's own failure codes aren't known either, so each
+// of these is the generic HRESULT closest to it.
+pub const E_INVALID_TITLE_ID:    HResult = HResult::E_INVALIDARG;
+pub const E_INVALID_LEADERBOARD: HResult = HResult::E_INVALIDARG;
+pub const E_INVALID_ATTRIBUTE:   HResult = HResult::E_INVALIDARG;
+pub const E_INVALID_USER:        HResult = HResult::E_ACCESSDENIED;
+
+/// Leaderboard id of a reset that clears the user from every leaderboard of
+/// the title.
+pub const ALL_LEADERBOARDS: u32 = 0xFFFF_FFFF;
+
+/// Most rows a single read returns.
+pub const MAX_PAGE_SIZE: u16 = 100;
+
+pub const ENUM_BY_RANK:     u8 = 0;
+pub const ENUM_AROUND_USER: u8 = 1;
+
+const NUL_TERMINATOR_LEN: usize = 1;
+
+const NULL_TYPE:      u8 = 0;
+const LONG_TYPE:      u8 = 1;
+const LONG_LONG_TYPE: u8 = 2;
+const DOUBLE_TYPE:    u8 = 3;
+const STRING_TYPE:    u8 = 4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StatValue {
+	Null,
+	Long(i32),
+	LongLong(i64),
+	Double(f64),
+	String(String),
+}
+
+impl StatValue {
+	pub fn encoded_len(&self) -> usize {
+		1 + match self {
+			StatValue::Null => 0,
+			StatValue::Long(_) => 4,
+			StatValue::LongLong(_) => 8,
+			StatValue::Double(_) => 8,
+			StatValue::String(s) => 2 + s.len() + NUL_TERMINATOR_LEN,
+		}
+	}
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for StatValue {
+	fn put(&self, buf: &mut AnyBufMut) {
+		match self {
+			StatValue::Null => buf.put_u8(NULL_TYPE),
+			StatValue::Long(value) => {
+				buf.put_u8(LONG_TYPE);
+				buf.put_i32_le(*value);
+			}
+			StatValue::LongLong(value) => {
+				buf.put_u8(LONG_LONG_TYPE);
+				buf.put_i64_le(*value);
+			}
+			StatValue::Double(value) => {
+				buf.put_u8(DOUBLE_TYPE);
+				buf.put_f64_le(*value);
+			}
+			StatValue::String(s) => {
+				buf.put_u8(STRING_TYPE);
+				buf.put_u16_le((s.len() + NUL_TERMINATOR_LEN) as u16);
+				put_nul_terminated_ascii(s, buf);
+			}
+		}
+	}
+}
+
+impl Decode for StatValue {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, value_type) = le_u8(input)?;
+
+		match value_type {
+			NULL_TYPE => Ok((input, StatValue::Null)),
+			LONG_TYPE => {
+				let (input, value) = le_i32(input)?;
+				Ok((input, StatValue::Long(value)))
+			}
+			LONG_LONG_TYPE => {
+				let (input, value) = le_i64(input)?;
+				Ok((input, StatValue::LongLong(value)))
+			}
+			DOUBLE_TYPE => {
+				let (input, value) = le_f64(input)?;
+				Ok((input, StatValue::Double(value)))
+			}
+			STRING_TYPE => {
+				let (input, len) = le_u16(input)?;
+				let (input, s) = take(len)(input)?;
+				let (_, s) = parse_nul_terminated_ascii(s)?;
+				Ok((input, StatValue::String(s.to_owned())))
+			}
+			_ => Err(nom::Err::Error(nom::error::Error {
+				input,
+				code: nom::error::ErrorKind::Switch,
+			})),
+		}
+	}
+}
+
+/// A single column of a leaderboard row.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stat {
+	pub attribute_id: u16,
+	pub value: StatValue,
+}
+
+impl Stat {
+	pub fn encoded_len(&self) -> usize {
+		2 + self.value.encoded_len()
+	}
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for Stat {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u16_le(self.attribute_id);
+		self.value.put(buf);
+	}
+}
+
+impl Decode for Stat {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, attribute_id) = le_u16(input)?;
+		let (input, value) = StatValue::decode(input)?;
+
+		Ok((input, Stat {
+			attribute_id,
+			value,
+		}))
+	}
+}
+
+/// Stats for one user on one leaderboard, in a write.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatsWrite {
+	pub user_id: Xuid,
+	pub leaderboard_id: u32,
+	pub stats: Vec<Stat>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for StatsWrite {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.user_id.put(buf);
+		buf.put_u32_le(self.leaderboard_id);
+		buf.put_u16_le(self.stats.len() as u16);
+		for stat in &self.stats {
+			stat.put(buf);
+		}
+	}
+}
+
+impl Decode for StatsWrite {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, user_id) = Xuid::decode(input)?;
+		let (input, leaderboard_id) = le_u32(input)?;
+		let (input, num_stats) = le_u16(input)?;
+		let (input, stats) = count(Stat::decode, num_stats as usize)(input)?;
+
+		Ok((input, StatsWrite {
+			user_id,
+			leaderboard_id,
+			stats,
+		}))
+	}
+}
+
+/// Body of /xstats/xstatswrite.srf, answered with an empty body.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatsWriteRequest {
+	pub title_id: u32,
+	pub writes: Vec<StatsWrite>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for StatsWriteRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		buf.put_u16_le(self.writes.len() as u16);
+		for write in &self.writes {
+			write.put(buf);
+		}
+	}
+}
+
+impl Decode for StatsWriteRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, num_writes) = le_u16(input)?;
+		let (input, writes) = count(StatsWrite::decode, num_writes as usize)(input)?;
+
+		Ok((input, StatsWriteRequest {
+			title_id,
+			writes,
+		}))
+	}
+}
+
+/// Body of /xstats/xstatsenum.srf, answered with StatsRows.  Reads a page of
+/// a leaderboard either from a rank, counting from one, or centred on
+/// pivot_user.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatsEnumRequest {
+	pub title_id: u32,
+	pub leaderboard_id: u32,
+	pub mode: u8,
+	pub rank: u32,
+	pub pivot_user: Xuid,
+	pub page_size: u16,
+	pub attribute_ids: Vec<u16>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for StatsEnumRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		buf.put_u32_le(self.leaderboard_id);
+		buf.put_u8(self.mode);
+		buf.put_u32_le(self.rank);
+		self.pivot_user.put(buf);
+		buf.put_u16_le(self.page_size);
+		buf.put_u16_le(self.attribute_ids.len() as u16);
+		for attribute_id in &self.attribute_ids {
+			buf.put_u16_le(*attribute_id);
+		}
+	}
+}
+
+impl Decode for StatsEnumRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, leaderboard_id) = le_u32(input)?;
+		let (input, mode) = le_u8(input)?;
+		let (input, rank) = le_u32(input)?;
+		let (input, pivot_user) = Xuid::decode(input)?;
+		let (input, page_size) = le_u16(input)?;
+		let (input, num_attributes) = le_u16(input)?;
+		let (input, attribute_ids) = count(le_u16, num_attributes as usize)(input)?;
+
+		Ok((input, StatsEnumRequest {
+			title_id,
+			leaderboard_id,
+			mode,
+			rank,
+			pivot_user,
+			page_size,
+			attribute_ids,
+		}))
+	}
+}
+
+/// Body of /xstats/xstatsget.srf, answered with StatsRows holding a row for
+/// each of the users that has one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatsGetRequest {
+	pub title_id: u32,
+	pub leaderboard_id: u32,
+	pub users: Vec<Xuid>,
+	pub attribute_ids: Vec<u16>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for StatsGetRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		buf.put_u32_le(self.leaderboard_id);
+		buf.put_u16_le(self.users.len() as u16);
+		buf.put_u16_le(self.attribute_ids.len() as u16);
+		for user in &self.users {
+			user.put(buf);
+		}
+		for attribute_id in &self.attribute_ids {
+			buf.put_u16_le(*attribute_id);
+		}
+	}
+}
+
+impl Decode for StatsGetRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, leaderboard_id) = le_u32(input)?;
+		let (input, num_users) = le_u16(input)?;
+		let (input, num_attributes) = le_u16(input)?;
+		let (input, users) = count(Xuid::decode, num_users as usize)(input)?;
+		let (input, attribute_ids) = count(le_u16, num_attributes as usize)(input)?;
+
+		Ok((input, StatsGetRequest {
+			title_id,
+			leaderboard_id,
+			users,
+			attribute_ids,
+		}))
+	}
+}
+
+/// Body of /xstats/xstatsreset.srf, answered with an empty body.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StatsResetRequest {
+	pub title_id: u32,
+	pub user_id: Xuid,
+	pub leaderboard_id: u32,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for StatsResetRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		self.user_id.put(buf);
+		buf.put_u32_le(self.leaderboard_id);
+	}
+}
+
+impl Decode for StatsResetRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, user_id) = Xuid::decode(input)?;
+		let (input, leaderboard_id) = le_u32(input)?;
+
+		Ok((input, StatsResetRequest {
+			title_id,
+			user_id,
+			leaderboard_id,
+		}))
+	}
+}
+
+/// One user's row of a leaderboard.  Ranks count from one; a rank of zero
+/// means the user has no row.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatsRow {
+	pub user_id: Xuid,
+	pub rank: u32,
+	pub rating: i64,
+	pub gamertag: String,
+	pub stats: Vec<Stat>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for StatsRow {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.user_id.put(buf);
+		buf.put_u32_le(self.rank);
+		buf.put_i64_le(self.rating);
+		put_nul_terminated_ascii(&self.gamertag, buf);
+		buf.put_u16_le(self.stats.len() as u16);
+		for stat in &self.stats {
+			stat.put(buf);
+		}
+	}
+}
+
+impl Decode for StatsRow {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, user_id) = Xuid::decode(input)?;
+		let (input, rank) = le_u32(input)?;
+		let (input, rating) = le_i64(input)?;
+		let (input, gamertag) = parse_nul_terminated_ascii(input)?;
+		let (input, num_stats) = le_u16(input)?;
+		let (input, stats) = count(Stat::decode, num_stats as usize)(input)?;
+
+		Ok((input, StatsRow {
+			user_id,
+			rank,
+			rating,
+			gamertag: gamertag.to_owned(),
+			stats,
+		}))
+	}
+}
+
+/// The reply to a read.  total_rows is the size of the whole leaderboard.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatsRows {
+	pub total_rows: u32,
+	pub rows: Vec<StatsRow>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for StatsRows {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.total_rows);
+		buf.put_u16_le(self.rows.len() as u16);
+		for row in &self.rows {
+			row.put(buf);
+		}
+	}
+}
+
+impl Decode for StatsRows {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, total_rows) = le_u32(input)?;
+		let (input, num_rows) = le_u16(input)?;
+		let (input, rows) = count(StatsRow::decode, num_rows as usize)(input)?;
+
+		Ok((input, StatsRows {
+			total_rows,
+			rows,
+		}))
+	}
+}
+
+/// The columns of a row as stored, without the user or rank.
+pub fn put_stats<AnyBufMut: BufMut>(stats: &[Stat], buf: &mut AnyBufMut) {
+	buf.put_u16_le(stats.len() as u16);
+	for stat in stats {
+		stat.put(buf);
+	}
+}
+
+pub fn decode_stats(input: &[u8]) -> nom::IResult<&[u8], Vec<Stat>> {
+	let (input, num_stats) = le_u16(input)?;
+	count(Stat::decode, num_stats as usize)(input)
+}
+
+#[cfg(test)]
+mod tests {
+	use hex_literal::hex;
+
+	use xbox_sys::codec::test_codec;
+
+	use super::*;
+
+	#[test]
+	fn write_request_codec() {
+		test_codec(&hex!["
+			0b00414c
+			0100
+
+			3292446c90740900
+			01000000
+			0400
+			0100 01 2a000000
+			0200 02 0000000001000000
+			0300 03 000000000000f83f
+			0400 04 0400 6d617000
+		"], StatsWriteRequest {
+			title_id: 0x4C41000B,
+			writes: vec![
+				StatsWrite {
+					user_id: Xuid(0x000974906c449232),
+					leaderboard_id: 1,
+					stats: vec![
+						Stat { attribute_id: 1, value: StatValue::Long(42) },
+						Stat { attribute_id: 2, value: StatValue::LongLong(0x1_0000_0000) },
+						Stat { attribute_id: 3, value: StatValue::Double(1.5) },
+						Stat { attribute_id: 4, value: StatValue::String("map".to_owned()) },
+					],
+				},
+			],
+		});
+	}
+
+	#[test]
+	fn enum_request_codec() {
+		test_codec(&hex!["
+			0b00414c
+			01000000
+			01
+			00000000
+			3292446c90740900
+			0a00
+			0200 0100 0300
+		"], StatsEnumRequest {
+			title_id: 0x4C41000B,
+			leaderboard_id: 1,
+			mode: ENUM_AROUND_USER,
+			rank: 0,
+			pivot_user: Xuid(0x000974906c449232),
+			page_size: 10,
+			attribute_ids: vec![1, 3],
+		});
+	}
+
+	#[test]
+	fn rows_codec() {
+		test_codec(&hex!["
+			07000000
+			0100
+
+			3292446c90740900
+			03000000
+			6400000000000000
+			6d6f6e6f6361736100
+			0100
+			0100 00
+		"], StatsRows {
+			total_rows: 7,
+			rows: vec![
+				StatsRow {
+					user_id: Xuid(0x000974906c449232),
+					rank: 3,
+					rating: 100,
+					gamertag: "monocasa".to_owned(),
+					stats: vec![
+						Stat { attribute_id: 1, value: StatValue::Null },
+					],
+				},
+			],
+		});
+	}
+
+	#[test]
+	fn unknown_value_type_is_rejected() {
+		assert!(Stat::decode(&hex!["0100 09 00000000"]).is_err());
+	}
+}
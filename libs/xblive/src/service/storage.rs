@@ -0,0 +1,373 @@
+use bytes::BufMut;
+
+use nom::bytes::complete::take;
+use nom::multi::count;
+use nom::number::complete::{le_u16, le_u32, le_u64};
+
+use xbox_sys::account::Xuid;
+use xbox_sys::codec::{BufPut, Decode, parse_nul_terminated_ascii, put_nul_terminated_ascii};
+use xbox_sys::status::HResult;
+
+use crate::crypto::primitives::Sha1Digest;
+
+/// PROVISIONAL: there's no public reference for the storage service's
+/// content type, its /xstorage/ endpoints or the layout of their bodies.  All
+/// of them are this server's own, so consoles only reach them where the
+/// client side follows the same convention.  Replace them once the real ones
+/// are known.
+pub const CONTENT_TYPE: &'static str = "xon/f";
+
+// PROVISIONAL: the service's own failure codes aren't known either, so each
+// of these is the generic HRESULT closest to it.
+pub const E_INVALID_TITLE_ID: HResult = HResult::E_INVALIDARG;
+pub const E_ACCESS_DENIED:    HResult = HResult::E_ACCESSDENIED;
+pub const E_FILE_NOT_FOUND:   HResult = HResult::E_FILE_NOT_FOUND;
+pub const E_QUOTA_EXCEEDED:   HResult = HResult::E_DISK_FULL;
+pub const E_INVALID_PATH:     HResult = HResult::E_INVALIDARG;
+
+/// Longest path a file can be stored under, not counting the nul.
+pub const MAX_PATH_LEN: usize = 128;
+
+/// Most files a single enumeration returns.
+pub const MAX_ENUM_RESULTS: u16 = 100;
+
+/// Lets users other than the owner download the file and see it when
+/// enumerating the owner's files.
+pub const FILE_FLAG_SHARED: u32 = 0x0000_0001;
+
+/// What's known about a stored file, without its contents.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StorageFile {
+	pub owner: Xuid,
+	pub path: String,
+	pub flags: u32,
+	pub size: u32,
+	pub hash: Sha1Digest,
+	/// Seconds since the Unix epoch.
+	pub modified: u64,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for StorageFile {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.owner.put(buf);
+		put_nul_terminated_ascii(&self.path, buf);
+		buf.put_u32_le(self.flags);
+		buf.put_u32_le(self.size);
+		self.hash.put(buf);
+		buf.put_u64_le(self.modified);
+	}
+}
+
+impl Decode for StorageFile {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, owner) = Xuid::decode(input)?;
+		let (input, path) = parse_nul_terminated_ascii(input)?;
+		let (input, flags) = le_u32(input)?;
+		let (input, size) = le_u32(input)?;
+		let (input, hash) = Sha1Digest::decode(input)?;
+		let (input, modified) = le_u64(input)?;
+
+		Ok((input, StorageFile {
+			owner,
+			path: path.to_owned(),
+			flags,
+			size,
+			hash,
+			modified,
+		}))
+	}
+}
+
+fn put_data<AnyBufMut: BufMut>(data: &[u8], buf: &mut AnyBufMut) {
+	buf.put_u32_le(data.len() as u32);
+	buf.put_slice(data);
+}
+
+fn decode_data(input: &[u8]) -> nom::IResult<&[u8], Vec<u8>> {
+	let (input, len) = le_u32(input)?;
+	let (input, data) = take(len)(input)?;
+	Ok((input, data.to_vec()))
+}
+
+/// Body of /xstorage/upload.srf, answered with the StorageFile as stored.
+/// Replaces any file already stored under the same path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UploadRequest {
+	pub title_id: u32,
+	pub owner: Xuid,
+	pub path: String,
+	pub flags: u32,
+	pub data: Vec<u8>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for UploadRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		self.owner.put(buf);
+		put_nul_terminated_ascii(&self.path, buf);
+		buf.put_u32_le(self.flags);
+		put_data(&self.data, buf);
+	}
+}
+
+impl Decode for UploadRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, owner) = Xuid::decode(input)?;
+		let (input, path) = parse_nul_terminated_ascii(input)?;
+		let (input, flags) = le_u32(input)?;
+		let (input, data) = decode_data(input)?;
+
+		Ok((input, UploadRequest {
+			title_id,
+			owner,
+			path: path.to_owned(),
+			flags,
+			data,
+		}))
+	}
+}
+
+/// Body of /xstorage/download.srf, answered with a DownloadReply, and of
+/// /xstorage/delete.srf, answered with an empty body.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileRequest {
+	pub title_id: u32,
+	/// The user making the request, who has to be signed in.
+	pub user_id: Xuid,
+	pub owner: Xuid,
+	pub path: String,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for FileRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		self.user_id.put(buf);
+		self.owner.put(buf);
+		put_nul_terminated_ascii(&self.path, buf);
+	}
+}
+
+impl Decode for FileRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, user_id) = Xuid::decode(input)?;
+		let (input, owner) = Xuid::decode(input)?;
+		let (input, path) = parse_nul_terminated_ascii(input)?;
+
+		Ok((input, FileRequest {
+			title_id,
+			user_id,
+			owner,
+			path: path.to_owned(),
+		}))
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DownloadReply {
+	pub file: StorageFile,
+	pub data: Vec<u8>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for DownloadReply {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.file.put(buf);
+		put_data(&self.data, buf);
+	}
+}
+
+impl Decode for DownloadReply {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, file) = StorageFile::decode(input)?;
+		let (input, data) = decode_data(input)?;
+
+		Ok((input, DownloadReply {
+			file,
+			data,
+		}))
+	}
+}
+
+/// Body of /xstorage/enumerate.srf, answered with an EnumerateReply.  Lists
+/// owner's files whose paths start with prefix, in path order, skipping the
+/// first start.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnumerateRequest {
+	pub title_id: u32,
+	pub user_id: Xuid,
+	pub owner: Xuid,
+	pub prefix: String,
+	pub start: u32,
+	pub max_results: u16,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for EnumerateRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		self.user_id.put(buf);
+		self.owner.put(buf);
+		put_nul_terminated_ascii(&self.prefix, buf);
+		buf.put_u32_le(self.start);
+		buf.put_u16_le(self.max_results);
+	}
+}
+
+impl Decode for EnumerateRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, user_id) = Xuid::decode(input)?;
+		let (input, owner) = Xuid::decode(input)?;
+		let (input, prefix) = parse_nul_terminated_ascii(input)?;
+		let (input, start) = le_u32(input)?;
+		let (input, max_results) = le_u16(input)?;
+
+		Ok((input, EnumerateRequest {
+			title_id,
+			user_id,
+			owner,
+			prefix: prefix.to_owned(),
+			start,
+			max_results,
+		}))
+	}
+}
+
+/// total_files counts every file the enumeration could see, not just the
+/// ones returned.  The quota figures are the owner's, in bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnumerateReply {
+	pub total_files: u32,
+	pub quota_used: u64,
+	pub quota: u64,
+	pub files: Vec<StorageFile>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for EnumerateReply {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.total_files);
+		buf.put_u64_le(self.quota_used);
+		buf.put_u64_le(self.quota);
+		buf.put_u16_le(self.files.len() as u16);
+		for file in &self.files {
+			file.put(buf);
+		}
+	}
+}
+
+impl Decode for EnumerateReply {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, total_files) = le_u32(input)?;
+		let (input, quota_used) = le_u64(input)?;
+		let (input, quota) = le_u64(input)?;
+		let (input, num_files) = le_u16(input)?;
+		let (input, files) = count(StorageFile::decode, num_files as usize)(input)?;
+
+		Ok((input, EnumerateReply {
+			total_files,
+			quota_used,
+			quota,
+			files,
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use hex_literal::hex;
+
+	use xbox_sys::codec::test_codec;
+
+	use super::*;
+
+	#[test]
+	fn upload_request_codec() {
+		test_codec(&hex!["
+			0b00414c
+			3292446c90740900
+			7265706c6179732f3100
+			01000000
+			03000000 010203
+		"], UploadRequest {
+			title_id: 0x4C41000B,
+			owner: Xuid(0x000974906c449232),
+			path: "replays/1".to_owned(),
+			flags: FILE_FLAG_SHARED,
+			data: vec![1, 2, 3],
+		});
+	}
+
+	#[test]
+	fn file_request_codec() {
+		test_codec(&hex!["
+			0b00414c
+			3292446c90740900
+			0100000000000900
+			6d617000
+		"], FileRequest {
+			title_id: 0x4C41000B,
+			user_id: Xuid(0x000974906c449232),
+			owner: Xuid(0x0009000000000001),
+			path: "map".to_owned(),
+		});
+	}
+
+	#[test]
+	fn download_reply_codec() {
+		test_codec(&hex!["
+			3292446c90740900
+			6d617000
+			00000000
+			03000000
+			a9993e364706816aba3e25717850c26c9cd0d89d
+			00f1536500000000
+			03000000 616263
+		"], DownloadReply {
+			file: StorageFile {
+				owner: Xuid(0x000974906c449232),
+				path: "map".to_owned(),
+				flags: 0,
+				size: 3,
+				hash: Sha1Digest(hex!["a9993e364706816aba3e25717850c26c9cd0d89d"]),
+				modified: 1_700_000_000,
+			},
+			data: b"abc".to_vec(),
+		});
+	}
+
+	#[test]
+	fn enumerate_codec() {
+		test_codec(&hex!["
+			0b00414c
+			3292446c90740900
+			3292446c90740900
+			7265706c6179732f00
+			00000000
+			6400
+		"], EnumerateRequest {
+			title_id: 0x4C41000B,
+			user_id: Xuid(0x000974906c449232),
+			owner: Xuid(0x000974906c449232),
+			prefix: "replays/".to_owned(),
+			start: 0,
+			max_results: MAX_ENUM_RESULTS,
+		});
+
+		test_codec(&hex!["
+			02000000
+			0300000000000000
+			0000100000000000
+			0000
+		"], EnumerateReply {
+			total_files: 2,
+			quota_used: 3,
+			quota: 0x10_0000,
+			files: vec![],
+		});
+	}
+
+	#[test]
+	fn truncated_data_is_rejected() {
+		assert!(UploadRequest::decode(&hex!["0b00414c 3292446c90740900 6100 00000000 ffffffff 01"]).is_err());
+	}
+}
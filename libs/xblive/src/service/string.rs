@@ -0,0 +1,198 @@
+use bytes::BufMut;
+
+use nom::multi::count;
+use nom::number::complete::{le_u16, le_u32};
+
+use xbox_sys::codec::{BufPut, Decode};
+use xbox_sys::status::HResult;
+
+/// PROVISIONAL: there's no public reference for the string service's content
+/// type, its /xstring/ endpoints or the layout of their bodies.  All of them
+/// are this server's own, so consoles only reach them where the client side
+/// follows the same convention.  Replace them once the real ones are known.
+pub const CONTENT_TYPE: &'static str = "xon/2";
+
+// PROVISIONAL: the service's own failure codes aren't known either, so each
+// of these is the generic HRESULT closest to it.
+pub const E_INVALID_TITLE_ID: HResult = HResult::E_INVALIDARG;
+pub const E_INVALID_LANGUAGE: HResult = HResult::E_INVALIDARG;
+pub const E_TOO_MANY_STRINGS: HResult = HResult::E_INVALIDARG;
+
+/// Most strings a single lookup can ask for.
+pub const MAX_STRING_IDS: u16 = 100;
+
+pub const LANGUAGE_ENGLISH:    u32 = 1;
+pub const LANGUAGE_JAPANESE:   u32 = 2;
+pub const LANGUAGE_GERMAN:     u32 = 3;
+pub const LANGUAGE_FRENCH:     u32 = 4;
+pub const LANGUAGE_SPANISH:    u32 = 5;
+pub const LANGUAGE_ITALIAN:    u32 = 6;
+pub const LANGUAGE_KOREAN:     u32 = 7;
+pub const LANGUAGE_TCHINESE:   u32 = 8;
+pub const LANGUAGE_PORTUGUESE: u32 = 9;
+
+/// Strings are UTF-16, prefixed with their length in code units.
+fn put_utf16<AnyBufMut: BufMut>(s: &str, buf: &mut AnyBufMut) {
+	let units: Vec<u16> = s.encode_utf16().collect();
+
+	buf.put_u16_le(units.len() as u16);
+	for unit in units {
+		buf.put_u16_le(unit);
+	}
+}
+
+fn parse_utf16<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], String> {
+	let (input, len) = le_u16(input)?;
+	let (rem, units) = count(le_u16, len as usize)(input)?;
+
+	match String::from_utf16(&units) {
+		Ok(s) => Ok((rem, s)),
+		Err(_) => Err(nom::Err::Error(nom::error::Error {
+			input,
+			code: nom::error::ErrorKind::Char,
+		})),
+	}
+}
+
+/// Body of /xstring/xstringlookup.srf, answered with a StringLookupReply.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StringLookupRequest {
+	pub title_id: u32,
+	pub language: u32,
+	pub string_ids: Vec<u32>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for StringLookupRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		buf.put_u32_le(self.language);
+		buf.put_u16_le(self.string_ids.len() as u16);
+		for string_id in &self.string_ids {
+			buf.put_u32_le(*string_id);
+		}
+	}
+}
+
+impl Decode for StringLookupRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, language) = le_u32(input)?;
+		let (input, num_string_ids) = le_u16(input)?;
+		let (input, string_ids) = count(le_u32, num_string_ids as usize)(input)?;
+
+		Ok((input, StringLookupRequest {
+			title_id,
+			language,
+			string_ids,
+		}))
+	}
+}
+
+/// language is the language the string was found in, which is not the one
+/// asked for when the lookup fell back to another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalizedString {
+	pub string_id: u32,
+	pub language: u32,
+	pub text: String,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for LocalizedString {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.string_id);
+		buf.put_u32_le(self.language);
+		put_utf16(&self.text, buf);
+	}
+}
+
+impl Decode for LocalizedString {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, string_id) = le_u32(input)?;
+		let (input, language) = le_u32(input)?;
+		let (input, text) = parse_utf16(input)?;
+
+		Ok((input, LocalizedString {
+			string_id,
+			language,
+			text,
+		}))
+	}
+}
+
+/// Strings are in the order they were asked for.  Ids with no string in any
+/// language are left out.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StringLookupReply {
+	pub strings: Vec<LocalizedString>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for StringLookupReply {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u16_le(self.strings.len() as u16);
+		for string in &self.strings {
+			string.put(buf);
+		}
+	}
+}
+
+impl Decode for StringLookupReply {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, num_strings) = le_u16(input)?;
+		let (input, strings) = count(LocalizedString::decode, num_strings as usize)(input)?;
+
+		Ok((input, StringLookupReply {
+			strings,
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use hex_literal::hex;
+
+	use xbox_sys::codec::test_codec;
+
+	use super::*;
+
+	#[test]
+	fn lookup_codec() {
+		test_codec(&hex!["
+			0b00414c
+			03000000
+			0200
+			01000000
+			02000000
+		"], StringLookupRequest {
+			title_id: 0x4C41000B,
+			language: LANGUAGE_GERMAN,
+			string_ids: vec![1, 2],
+		});
+
+		test_codec(&hex!["
+			0200
+			01000000 03000000 0300 4800e4006900
+			02000000 01000000 0200 48006900
+		"], StringLookupReply {
+			strings: vec![
+				LocalizedString {
+					string_id: 1,
+					language: LANGUAGE_GERMAN,
+					text: "Häi".to_owned(),
+				},
+				LocalizedString {
+					string_id: 2,
+					language: LANGUAGE_ENGLISH,
+					text: "Hi".to_owned(),
+				},
+			],
+		});
+	}
+
+	#[test]
+	fn bad_strings_are_rejected() {
+		// Truncated
+		assert!(LocalizedString::decode(&hex!["01000000 01000000 0200 4800"]).is_err());
+		// Unpaired surrogate
+		assert!(LocalizedString::decode(&hex!["01000000 01000000 0100 00d8"]).is_err());
+	}
+}
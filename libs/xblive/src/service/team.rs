@@ -0,0 +1,680 @@
+use bytes::BufMut;
+
+use nom::bytes::complete::take;
+use nom::multi::count;
+use nom::number::complete::{le_u16, le_u32, le_u64};
+
+use xbox_sys::account::Xuid;
+use xbox_sys::codec::{BufPut, Decode, parse_nul_terminated_ascii, put_nul_terminated_ascii};
+use xbox_sys::status::HResult;
+
+/// PROVISIONAL: there's no public reference for the teams service's content
+/// type, its /xteam/ endpoints or the layout of their bodies.  All of them
+/// are this server's own, so consoles only reach them where the client side
+/// follows the same convention.  Replace them once the real ones are known.
+pub const CONTENT_TYPE: &'static str = "xon/13";
+
+// PROVISIONAL: the service's own failure codes aren't known either, so each
+// of these is the generic HRESULT closest to it.
+pub const E_INVALID_TITLE_ID:     HResult = HResult::E_INVALIDARG;
+pub const E_INVALID_USER:         HResult = HResult::E_ACCESSDENIED;
+pub const E_TEAM_NOT_FOUND:       HResult = HResult::E_FILE_NOT_FOUND;
+pub const E_NAME_TAKEN:           HResult = HResult::E_ALREADY_EXISTS;
+pub const E_INVALID_NAME:         HResult = HResult::E_INVALIDARG;
+pub const E_NOT_A_MEMBER:         HResult = HResult::E_ACCESSDENIED;
+pub const E_ALREADY_MEMBER:       HResult = HResult::E_ALREADY_EXISTS;
+pub const E_PERMISSION_DENIED:    HResult = HResult::E_ACCESSDENIED;
+pub const E_NO_INVITATION:        HResult = HResult::E_ACCESSDENIED;
+pub const E_TEAM_FULL:            HResult = HResult::E_ACCESSDENIED;
+pub const E_TOO_MANY_TEAMS:       HResult = HResult::E_ACCESSDENIED;
+pub const E_TOO_MANY_PROPERTIES:  HResult = HResult::E_INVALIDARG;
+pub const E_PROPERTY_TOO_LARGE:   HResult = HResult::E_INVALIDARG;
+pub const E_INVALID_ROLE:         HResult = HResult::E_INVALIDARG;
+pub const E_OWNER_CANNOT_LEAVE:   HResult = HResult::E_ACCESSDENIED;
+
+/// Longest team name, in bytes, not counting the nul.
+pub const MAX_NAME_LEN: usize = 15;
+
+/// Most members a team can have.
+pub const MAX_MEMBERS: u16 = 100;
+
+/// Most teams of a single title a user can belong to.
+pub const MAX_TEAMS_PER_USER: u16 = 8;
+
+/// Most properties a team can have.
+pub const MAX_PROPERTIES: u16 = 16;
+
+/// Largest value a single team property can hold.
+pub const MAX_PROPERTY_LEN: usize = 0x100;
+
+/// Roles are ordered: each can do everything the ones below it can.
+pub const TEAM_ROLE_MEMBER:  u32 = 0;
+/// Can invite users, remove members and change the team's properties.
+pub const TEAM_ROLE_OFFICER: u32 = 1;
+/// Can also change members' roles and delete the team.  Every team has
+/// exactly one.
+pub const TEAM_ROLE_OWNER:   u32 = 2;
+
+/// Property ids with a meaning shared by every title.  Titles are free to use
+/// any other id however they like.
+pub const TEAM_PROPERTY_DESCRIPTION: u32 = 1;
+pub const TEAM_PROPERTY_MOTTO:       u32 = 2;
+pub const TEAM_PROPERTY_URL:         u32 = 3;
+
+fn put_data<AnyBufMut: BufMut>(data: &[u8], buf: &mut AnyBufMut) {
+	buf.put_u16_le(data.len() as u16);
+	buf.put_slice(data);
+}
+
+fn decode_data(input: &[u8]) -> nom::IResult<&[u8], Vec<u8>> {
+	let (input, len) = le_u16(input)?;
+	let (input, data) = take(len)(input)?;
+	Ok((input, data.to_vec()))
+}
+
+fn put_list<AnyBufMut: BufMut, T: BufPut<AnyBufMut>>(items: &[T], buf: &mut AnyBufMut) {
+	buf.put_u16_le(items.len() as u16);
+	for item in items {
+		item.put(buf);
+	}
+}
+
+fn decode_list<T: Decode>(input: &[u8]) -> nom::IResult<&[u8], Vec<T>> {
+	let (input, num_items) = le_u16(input)?;
+	count(T::decode, num_items as usize)(input)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TeamProperty {
+	pub id: u32,
+	/// An empty value removes the property when the team is updated.
+	pub data: Vec<u8>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for TeamProperty {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.id);
+		put_data(&self.data, buf);
+	}
+}
+
+impl Decode for TeamProperty {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, id) = le_u32(input)?;
+		let (input, data) = decode_data(input)?;
+
+		Ok((input, TeamProperty {
+			id,
+			data,
+		}))
+	}
+}
+
+/// Body of /xteam/xteamcreate.srf, answered with a CreateTeamReply.  The
+/// user creating the team becomes its owner.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CreateTeamRequest {
+	pub title_id: u32,
+	pub owner: Xuid,
+	pub name: String,
+	pub properties: Vec<TeamProperty>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for CreateTeamRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		self.owner.put(buf);
+		put_nul_terminated_ascii(&self.name, buf);
+		put_list(&self.properties, buf);
+	}
+}
+
+impl Decode for CreateTeamRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, owner) = Xuid::decode(input)?;
+		let (input, name) = parse_nul_terminated_ascii(input)?;
+		let (input, properties) = decode_list(input)?;
+
+		Ok((input, CreateTeamRequest {
+			title_id,
+			owner,
+			name: name.to_owned(),
+			properties,
+		}))
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CreateTeamReply {
+	pub team_id: u64,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for CreateTeamReply {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u64_le(self.team_id);
+	}
+}
+
+impl Decode for CreateTeamReply {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, team_id) = le_u64(input)?;
+
+		Ok((input, CreateTeamReply {
+			team_id,
+		}))
+	}
+}
+
+/// Body of /xteam/xteamenum.srf, answered with an EnumerateTeamsReply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EnumerateTeamsRequest {
+	pub title_id: u32,
+	pub user: Xuid,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for EnumerateTeamsRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		self.user.put(buf);
+	}
+}
+
+impl Decode for EnumerateTeamsRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, user) = Xuid::decode(input)?;
+
+		Ok((input, EnumerateTeamsRequest {
+			title_id,
+			user,
+		}))
+	}
+}
+
+/// A team the user belongs to, and their role in it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TeamSummary {
+	pub team_id: u64,
+	pub name: String,
+	pub role: u32,
+	pub member_count: u16,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for TeamSummary {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u64_le(self.team_id);
+		put_nul_terminated_ascii(&self.name, buf);
+		buf.put_u32_le(self.role);
+		buf.put_u16_le(self.member_count);
+	}
+}
+
+impl Decode for TeamSummary {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, team_id) = le_u64(input)?;
+		let (input, name) = parse_nul_terminated_ascii(input)?;
+		let (input, role) = le_u32(input)?;
+		let (input, member_count) = le_u16(input)?;
+
+		Ok((input, TeamSummary {
+			team_id,
+			name: name.to_owned(),
+			role,
+			member_count,
+		}))
+	}
+}
+
+/// An invitation waiting for the user to accept or decline it.  sent is in
+/// seconds since the Unix epoch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TeamInvitation {
+	pub team_id: u64,
+	pub name: String,
+	pub inviter: Xuid,
+	pub sent: u64,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for TeamInvitation {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u64_le(self.team_id);
+		put_nul_terminated_ascii(&self.name, buf);
+		self.inviter.put(buf);
+		buf.put_u64_le(self.sent);
+	}
+}
+
+impl Decode for TeamInvitation {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, team_id) = le_u64(input)?;
+		let (input, name) = parse_nul_terminated_ascii(input)?;
+		let (input, inviter) = Xuid::decode(input)?;
+		let (input, sent) = le_u64(input)?;
+
+		Ok((input, TeamInvitation {
+			team_id,
+			name: name.to_owned(),
+			inviter,
+			sent,
+		}))
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnumerateTeamsReply {
+	pub teams: Vec<TeamSummary>,
+	pub invitations: Vec<TeamInvitation>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for EnumerateTeamsReply {
+	fn put(&self, buf: &mut AnyBufMut) {
+		put_list(&self.teams, buf);
+		put_list(&self.invitations, buf);
+	}
+}
+
+impl Decode for EnumerateTeamsReply {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, teams) = decode_list(input)?;
+		let (input, invitations) = decode_list(input)?;
+
+		Ok((input, EnumerateTeamsReply {
+			teams,
+			invitations,
+		}))
+	}
+}
+
+/// Body of requests about a whole team: /xteam/xteamdetails.srf, answered
+/// with a TeamDetailsReply, and /xteam/xteamaccept.srf,
+/// /xteam/xteamdecline.srf and /xteam/xteamdelete.srf, answered with an
+/// empty body.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TeamRequest {
+	pub title_id: u32,
+	pub user: Xuid,
+	pub team_id: u64,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for TeamRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		self.user.put(buf);
+		buf.put_u64_le(self.team_id);
+	}
+}
+
+impl Decode for TeamRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, user) = Xuid::decode(input)?;
+		let (input, team_id) = le_u64(input)?;
+
+		Ok((input, TeamRequest {
+			title_id,
+			user,
+			team_id,
+		}))
+	}
+}
+
+/// joined is in seconds since the Unix epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TeamMember {
+	pub xuid: Xuid,
+	pub role: u32,
+	pub joined: u64,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for TeamMember {
+	fn put(&self, buf: &mut AnyBufMut) {
+		self.xuid.put(buf);
+		buf.put_u32_le(self.role);
+		buf.put_u64_le(self.joined);
+	}
+}
+
+impl Decode for TeamMember {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, xuid) = Xuid::decode(input)?;
+		let (input, role) = le_u32(input)?;
+		let (input, joined) = le_u64(input)?;
+
+		Ok((input, TeamMember {
+			xuid,
+			role,
+			joined,
+		}))
+	}
+}
+
+/// created is in seconds since the Unix epoch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TeamDetailsReply {
+	pub team_id: u64,
+	pub name: String,
+	pub created: u64,
+	pub properties: Vec<TeamProperty>,
+	pub members: Vec<TeamMember>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for TeamDetailsReply {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u64_le(self.team_id);
+		put_nul_terminated_ascii(&self.name, buf);
+		buf.put_u64_le(self.created);
+		put_list(&self.properties, buf);
+		put_list(&self.members, buf);
+	}
+}
+
+impl Decode for TeamDetailsReply {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, team_id) = le_u64(input)?;
+		let (input, name) = parse_nul_terminated_ascii(input)?;
+		let (input, created) = le_u64(input)?;
+		let (input, properties) = decode_list(input)?;
+		let (input, members) = decode_list(input)?;
+
+		Ok((input, TeamDetailsReply {
+			team_id,
+			name: name.to_owned(),
+			created,
+			properties,
+			members,
+		}))
+	}
+}
+
+/// Body of /xteam/xteamprops.srf, answered with an empty body.  Properties
+/// not listed are left as they are.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SetTeamPropertiesRequest {
+	pub title_id: u32,
+	pub user: Xuid,
+	pub team_id: u64,
+	pub properties: Vec<TeamProperty>,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for SetTeamPropertiesRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		self.user.put(buf);
+		buf.put_u64_le(self.team_id);
+		put_list(&self.properties, buf);
+	}
+}
+
+impl Decode for SetTeamPropertiesRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, user) = Xuid::decode(input)?;
+		let (input, team_id) = le_u64(input)?;
+		let (input, properties) = decode_list(input)?;
+
+		Ok((input, SetTeamPropertiesRequest {
+			title_id,
+			user,
+			team_id,
+			properties,
+		}))
+	}
+}
+
+/// Body of requests user makes about another user of a team:
+/// /xteam/xteaminvite.srf and /xteam/xteamremove.srf, both answered with an
+/// empty body.  Members remove themselves to leave a team.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TeamMemberRequest {
+	pub title_id: u32,
+	pub user: Xuid,
+	pub team_id: u64,
+	pub member: Xuid,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for TeamMemberRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		self.user.put(buf);
+		buf.put_u64_le(self.team_id);
+		self.member.put(buf);
+	}
+}
+
+impl Decode for TeamMemberRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, user) = Xuid::decode(input)?;
+		let (input, team_id) = le_u64(input)?;
+		let (input, member) = Xuid::decode(input)?;
+
+		Ok((input, TeamMemberRequest {
+			title_id,
+			user,
+			team_id,
+			member,
+		}))
+	}
+}
+
+/// Body of /xteam/xteamrole.srf, answered with an empty body.  Giving
+/// another member TEAM_ROLE_OWNER hands the team over to them, and leaves
+/// the old owner an officer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SetTeamRoleRequest {
+	pub title_id: u32,
+	pub user: Xuid,
+	pub team_id: u64,
+	pub member: Xuid,
+	pub role: u32,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for SetTeamRoleRequest {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u32_le(self.title_id);
+		self.user.put(buf);
+		buf.put_u64_le(self.team_id);
+		self.member.put(buf);
+		buf.put_u32_le(self.role);
+	}
+}
+
+impl Decode for SetTeamRoleRequest {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, title_id) = le_u32(input)?;
+		let (input, user) = Xuid::decode(input)?;
+		let (input, team_id) = le_u64(input)?;
+		let (input, member) = Xuid::decode(input)?;
+		let (input, role) = le_u32(input)?;
+
+		Ok((input, SetTeamRoleRequest {
+			title_id,
+			user,
+			team_id,
+			member,
+			role,
+		}))
+	}
+}
+
+/// Data of the TEAM_INVITE pulse event telling a console one of its users
+/// has been invited to a team.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TeamInviteNotification {
+	pub team_id: u64,
+	pub inviter: Xuid,
+}
+
+impl<AnyBufMut: BufMut> BufPut<AnyBufMut> for TeamInviteNotification {
+	fn put(&self, buf: &mut AnyBufMut) {
+		buf.put_u64_le(self.team_id);
+		self.inviter.put(buf);
+	}
+}
+
+impl Decode for TeamInviteNotification {
+	fn decode<'a>(input: &'a [u8]) -> nom::IResult<&'a [u8], Self> {
+		let (input, team_id) = le_u64(input)?;
+		let (input, inviter) = Xuid::decode(input)?;
+
+		Ok((input, TeamInviteNotification {
+			team_id,
+			inviter,
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use hex_literal::hex;
+
+	use xbox_sys::codec::test_codec;
+
+	use super::*;
+
+	#[test]
+	fn create_codec() {
+		test_codec(&hex!["
+			0b00414c
+			3292446c90740900
+			5265647300
+			0200
+			01000000 0200 6869
+			00000100 0000
+		"], CreateTeamRequest {
+			title_id: 0x4C41000B,
+			owner: Xuid(0x000974906c449232),
+			name: "Reds".to_owned(),
+			properties: vec![
+				TeamProperty { id: TEAM_PROPERTY_DESCRIPTION, data: b"hi".to_vec() },
+				TeamProperty { id: 0x0001_0000, data: vec![] },
+			],
+		});
+
+		test_codec(&hex!["0700000000000000"], CreateTeamReply {
+			team_id: 7,
+		});
+	}
+
+	#[test]
+	fn enumerate_codec() {
+		test_codec(&hex!["
+			0b00414c
+			3292446c90740900
+		"], EnumerateTeamsRequest {
+			title_id: 0x4C41000B,
+			user: Xuid(0x000974906c449232),
+		});
+
+		test_codec(&hex!["
+			0100
+			0700000000000000 5265647300 01000000 0300
+			0100
+			0800000000000000 426c75657300 0100000000000900 00f1536500000000
+		"], EnumerateTeamsReply {
+			teams: vec![TeamSummary {
+				team_id: 7,
+				name: "Reds".to_owned(),
+				role: TEAM_ROLE_OFFICER,
+				member_count: 3,
+			}],
+			invitations: vec![TeamInvitation {
+				team_id: 8,
+				name: "Blues".to_owned(),
+				inviter: Xuid(0x0009_0000_0000_0001),
+				sent: 1_700_000_000,
+			}],
+		});
+	}
+
+	#[test]
+	fn details_codec() {
+		test_codec(&hex!["
+			0b00414c
+			3292446c90740900
+			0700000000000000
+		"], TeamRequest {
+			title_id: 0x4C41000B,
+			user: Xuid(0x000974906c449232),
+			team_id: 7,
+		});
+
+		test_codec(&hex!["
+			0700000000000000
+			5265647300
+			00f1536500000000
+			0100
+			02000000 0200 6767
+			0200
+			3292446c90740900 02000000 00f1536500000000
+			0100000000000900 00000000 007e7b6500000000
+		"], TeamDetailsReply {
+			team_id: 7,
+			name: "Reds".to_owned(),
+			created: 1_700_000_000,
+			properties: vec![TeamProperty { id: TEAM_PROPERTY_MOTTO, data: b"gg".to_vec() }],
+			members: vec![
+				TeamMember { xuid: Xuid(0x000974906c449232), role: TEAM_ROLE_OWNER, joined: 1_700_000_000 },
+				TeamMember { xuid: Xuid(0x0009_0000_0000_0001), role: TEAM_ROLE_MEMBER, joined: 1_702_592_000 },
+			],
+		});
+	}
+
+	#[test]
+	fn update_codec() {
+		test_codec(&hex!["
+			0b00414c
+			3292446c90740900
+			0700000000000000
+			0100
+			03000000 0000
+		"], SetTeamPropertiesRequest {
+			title_id: 0x4C41000B,
+			user: Xuid(0x000974906c449232),
+			team_id: 7,
+			properties: vec![TeamProperty { id: TEAM_PROPERTY_URL, data: vec![] }],
+		});
+
+		test_codec(&hex!["
+			0b00414c
+			3292446c90740900
+			0700000000000000
+			0100000000000900
+		"], TeamMemberRequest {
+			title_id: 0x4C41000B,
+			user: Xuid(0x000974906c449232),
+			team_id: 7,
+			member: Xuid(0x0009_0000_0000_0001),
+		});
+
+		test_codec(&hex!["
+			0b00414c
+			3292446c90740900
+			0700000000000000
+			0100000000000900
+			01000000
+		"], SetTeamRoleRequest {
+			title_id: 0x4C41000B,
+			user: Xuid(0x000974906c449232),
+			team_id: 7,
+			member: Xuid(0x0009_0000_0000_0001),
+			role: TEAM_ROLE_OFFICER,
+		});
+
+		test_codec(&hex!["
+			0700000000000000
+			3292446c90740900
+		"], TeamInviteNotification {
+			team_id: 7,
+			inviter: Xuid(0x000974906c449232),
+		});
+	}
+
+	#[test]
+	fn truncated_property_is_rejected() {
+		assert!(CreateTeamRequest::decode(&hex!["
+			0b00414c 3292446c90740900 5200 0100 01000000 0300 6869
+		"]).is_err());
+	}
+}
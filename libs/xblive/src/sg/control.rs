@@ -141,6 +141,91 @@ impl<'a> SgToXbPulse<'a> {
     }
 }
 
+/// Kind of a PulseEvent.
+///
+/// PROVISIONAL: there's no public reference for the event kinds or the
+/// framing of the events payload of a pulse.  These values and the layout of
+/// PulseEvent are this server's own, so consoles only make sense of them
+/// where the client side follows the same convention.  Replace them once the
+/// real encoding is known.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PulseEventKind(pub u8);
+
+impl PulseEventKind {
+    pub const PRESENCE:      PulseEventKind = PulseEventKind(0x01);
+    pub const BUDDY_REQUEST: PulseEventKind = PulseEventKind(0x02);
+    pub const GAME_INVITE:   PulseEventKind = PulseEventKind(0x03);
+    pub const MESSAGE:       PulseEventKind = PulseEventKind(0x04);
+    pub const TEAM_INVITE:   PulseEventKind = PulseEventKind(0x05);
+}
+
+/// A single notification carried in the events payload of a pulse.  Events
+/// are packed back to back as a kind, the index of the user slot the event
+/// is for, the length of the data, and then the data itself.  Provisional,
+/// like PulseEventKind.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PulseEvent {
+    pub kind: PulseEventKind,
+    pub user_index: u8,
+    pub data: Vec<u8>,
+}
+
+impl PulseEvent {
+    pub const HEADER_LEN: usize = 4;
+
+    fn parse(i: &[u8]) -> nom::IResult<&[u8], PulseEvent> {
+        let (i, kind) = le_u8(i)?;
+        let (i, user_index) = le_u8(i)?;
+        let (i, len) = le_u16(i)?;
+
+        let len = len as usize;
+
+        if i.len() < len {
+            return Err(nom::Err::Incomplete(nom::Needed::Unknown))
+        }
+
+        Ok((&i[len..], PulseEvent {
+            kind: PulseEventKind(kind),
+            user_index,
+            data: i[..len].to_vec(),
+        }))
+    }
+
+    /// Parse a complete events payload, failing if any trailing bytes
+    /// don't make up a whole event.
+    pub fn parse_all(i: &[u8]) -> Option<Vec<PulseEvent>> {
+        let mut events = vec![];
+        let mut rem = i;
+
+        while !rem.is_empty() {
+            let (next, event) = Self::parse(rem).ok()?;
+            events.push(event);
+            rem = next;
+        }
+
+        Some(events)
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        Self::HEADER_LEN + self.data.len()
+    }
+
+    pub fn build(&self) -> Option<Vec<u8>> {
+        if self.data.len() > (u16::MAX as usize) {
+            return None;
+        }
+
+        let mut buf = vec![];
+
+        buf.put_u8(self.kind.0);
+        buf.put_u8(self.user_index);
+        buf.put_u16_le(self.data.len() as u16);
+        buf.put_slice(&self.data);
+
+        Some(buf)
+    }
+}
+
 #[derive(Debug)]
 #[repr(C)]
 pub struct XbToSgQosInit {
@@ -816,4 +901,38 @@ mod tests {
             events: &[],
         })
     }
+
+    #[test]
+    fn pulse_event_round_trip() {
+        let events = vec![
+            PulseEvent {
+                kind: PulseEventKind::BUDDY_REQUEST,
+                user_index: 2,
+                data: hex!["32 92 44 6c 90 74 09 00"].to_vec(),
+            },
+            PulseEvent {
+                kind: PulseEventKind::PRESENCE,
+                user_index: 0,
+                data: vec![],
+            },
+        ];
+
+        let mut bytes = vec![];
+        for event in events.iter() {
+            bytes.append(&mut event.build().unwrap());
+        }
+
+        assert_eq!(bytes.as_slice(), &hex!["
+            02 02 08 00 32 92 44 6c 90 74 09 00
+            01 00 00 00
+        "]);
+
+        assert_eq!(PulseEvent::parse_all(&bytes), Some(events));
+    }
+
+    #[test]
+    fn pulse_event_truncated() {
+        assert_eq!(PulseEvent::parse_all(&hex!["03 01 04 00 aa bb"]), None);
+        assert_eq!(PulseEvent::parse_all(&hex!["03 01"]), None);
+    }
 }
\ No newline at end of file
@@ -19,7 +19,7 @@ pub const SIGNATURE_LEN: usize = 8;
 pub const STORED_ACCOUNT_LEN: usize = 0x6c;
 
 /// Core xbox live user id type
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Xuid(pub u64);
 
 impl Xuid {
@@ -8,7 +8,18 @@ use crate::codec::{BufPut, Decode};
 pub struct HResult(pub u32);
 
 impl HResult {
-	pub const SUCCESS: HResult = HResult(0x0000_0000);
+	pub const SUCCESS:   HResult = HResult(0x0000_0000);
+	pub const E_NOTIMPL: HResult = HResult(0x8000_4001);
+	pub const E_FAIL:    HResult = HResult(0x8000_4005);
+
+	pub const E_ACCESSDENIED: HResult = HResult(0x8007_0005);
+	pub const E_INVALIDARG:   HResult = HResult(0x8007_0057);
+
+	// HRESULT_FROM_WIN32 of ERROR_FILE_NOT_FOUND, ERROR_DISK_FULL and
+	// ERROR_ALREADY_EXISTS.
+	pub const E_FILE_NOT_FOUND: HResult = HResult(0x8007_0002);
+	pub const E_DISK_FULL:      HResult = HResult(0x8007_0070);
+	pub const E_ALREADY_EXISTS: HResult = HResult(0x8007_00B7);
 
 	pub const XONLINETASK_S_SUCCESS:       HResult = HResult(0x0015_00F0);
 	pub const XONLINETASK_S_RESULTS_AVAIL: HResult = HResult(0x0015_00F1);
@@ -0,0 +1,321 @@
+//! Title and dashboard updates.
+//!
+//! The registry is a directory with one TOML file per title, named with the
+//! title id in hex (the dashboard is fffe0000), listing the updates on offer:
+//!
+//! ```toml
+//! [[update]]
+//! version = 0x00010003
+//! package = "4c41000b/update-1.3.xbx"
+//!
+//! [[update]]
+//! version = 0x00010002
+//! package = "4c41000b/update-1.2.xbx"
+//! from = [0x00010000]
+//! ```
+//!
+//! A console is offered the newest update with a version above its own that
+//! it can take: one without `from`, or one whose `from` lists the console's
+//! version.  Packages are paths relative to the registry directory, and are
+//! sized and hashed once, when the registry is loaded.
+
+use serde::Deserialize;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use xblive::crypto::primitives::{Sha1Digest, sha1};
+use xblive::service::autoupdate::MAX_CHUNK_LEN;
+
+/// File extension of title update files.  The file stem is the title id in
+/// hex.
+pub const UPDATE_FILE_EXTENSION: &str = "toml";
+
+#[derive(Debug, Deserialize)]
+struct UpdateEntry {
+    version: u32,
+    package: String,
+    from: Option<Vec<u32>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateFile {
+    #[serde(rename = "update", default)]
+    updates: Vec<UpdateEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Update {
+    pub title_id: u32,
+    pub version: u32,
+    /// The versions that can take this update, or None if any older
+    /// version can.
+    pub from: Option<Vec<u32>>,
+    pub package: PathBuf,
+    pub package_size: u64,
+    pub hash: Sha1Digest,
+}
+
+impl Update {
+    pub fn applies_to(&self, title_version: u32) -> bool {
+        if title_version >= self.version {
+            return false;
+        }
+
+        match &self.from {
+            Some(from) => from.contains(&title_version),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum UpdateLoadError {
+    Io(PathBuf, io::Error),
+    BadFileName(PathBuf),
+    Parse(PathBuf, toml::de::Error),
+    /// A package path that leaves the registry directory.
+    BadPackagePath(PathBuf, String),
+    DuplicateVersion(PathBuf, u32),
+}
+
+#[derive(Debug)]
+pub enum UpdateError {
+    NoSuchUpdate,
+    BadRange,
+    Io(io::Error),
+}
+
+impl From<io::Error> for UpdateError {
+    fn from(err: io::Error) -> Self {
+        UpdateError::Io(err)
+    }
+}
+
+/// Every update on offer, keyed by title id and the version it updates to.
+#[derive(Debug, Default)]
+pub struct Updates {
+    updates: BTreeMap<(u32, u32), Update>,
+    /// Declare every title current, whatever the registry holds.
+    none_required: bool,
+}
+
+fn io_err(path: &Path) -> impl FnOnce(io::Error) -> UpdateLoadError + '_ {
+    move |err| UpdateLoadError::Io(path.to_owned(), err)
+}
+
+/// Packages have to stay inside the registry directory.
+fn is_relative_package_path(package: &str) -> bool {
+    let path = Path::new(package);
+
+    !package.is_empty() && path.components().all(|component| matches!(component, Component::Normal(_)))
+}
+
+impl Updates {
+    pub fn new() -> Self {
+        Updates {
+            updates: BTreeMap::new(),
+            none_required: false,
+        }
+    }
+
+    /// A registry that tells every console no update is required.
+    pub fn none_required() -> Self {
+        Updates {
+            updates: BTreeMap::new(),
+            none_required: true,
+        }
+    }
+
+    /// Load every title update file in dir.
+    pub fn load_dir(dir: &Path) -> Result<Self, UpdateLoadError> {
+        let mut updates = Updates::new();
+
+        for entry in fs::read_dir(dir).map_err(io_err(dir))? {
+            let path = entry.map_err(io_err(dir))?.path();
+
+            if path.extension().and_then(|ext| ext.to_str()) != Some(UPDATE_FILE_EXTENSION) {
+                continue;
+            }
+
+            let title_id = path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| u32::from_str_radix(stem, 16).ok())
+                .ok_or_else(|| UpdateLoadError::BadFileName(path.clone()))?;
+
+            let source = fs::read_to_string(&path).map_err(io_err(&path))?;
+            let file: UpdateFile = toml::from_str(&source)
+                .map_err(|err| UpdateLoadError::Parse(path.clone(), err))?;
+
+            for entry in file.updates {
+                if !is_relative_package_path(&entry.package) {
+                    return Err(UpdateLoadError::BadPackagePath(path, entry.package));
+                }
+
+                if updates.updates.contains_key(&(title_id, entry.version)) {
+                    return Err(UpdateLoadError::DuplicateVersion(path, entry.version));
+                }
+
+                let package = dir.join(&entry.package);
+                let data = fs::read(&package).map_err(io_err(&package))?;
+
+                updates.add(Update {
+                    title_id,
+                    version: entry.version,
+                    from: entry.from,
+                    package,
+                    package_size: data.len() as u64,
+                    hash: sha1(&[&data]),
+                });
+            }
+        }
+
+        Ok(updates)
+    }
+
+    pub fn add(&mut self, update: Update) {
+        self.updates.insert((update.title_id, update.version), update);
+    }
+
+    /// The update a console running title_version should install, or None
+    /// if it's current.
+    pub fn check(&self, title_id: u32, title_version: u32) -> Option<&Update> {
+        if self.none_required {
+            return None;
+        }
+
+        self.updates.range((title_id, 0)..=(title_id, u32::MAX))
+            .rev()
+            .map(|(_, update)| update)
+            .find(|update| update.applies_to(title_version))
+    }
+
+    /// Up to len bytes of an update package from offset, fewer at the end of
+    /// the package.
+    pub async fn read(&self, title_id: u32, version: u32, offset: u64, len: u32) -> Result<Vec<u8>, UpdateError> {
+        if self.none_required {
+            return Err(UpdateError::NoSuchUpdate);
+        }
+
+        let update = self.updates.get(&(title_id, version)).ok_or(UpdateError::NoSuchUpdate)?;
+
+        if offset > update.package_size || len > MAX_CHUNK_LEN {
+            return Err(UpdateError::BadRange);
+        }
+
+        let len = (update.package_size - offset).min(len as u64) as usize;
+
+        let mut package = tokio::fs::File::open(&update.package).await?;
+        package.seek(SeekFrom::Start(offset)).await?;
+
+        let mut data = vec![0; len];
+        package.read_exact(&mut data).await?;
+
+        Ok(data)
+    }
+
+    pub fn len(&self) -> usize {
+        self.updates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.updates.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TITLE_ID: u32 = 0x4C41000B;
+
+    fn registry() -> (tempfile::TempDir, Updates) {
+        let root = tempfile::tempdir().unwrap();
+
+        fs::create_dir(root.path().join("4c41000b")).unwrap();
+        fs::write(root.path().join("4c41000b").join("1.3.xbx"), b"version 1.3").unwrap();
+        fs::write(root.path().join("4c41000b").join("1.2.xbx"), b"version 1.2").unwrap();
+        fs::write(root.path().join("4c41000b.toml"), "
+            [[update]]
+            version = 0x00010003
+            package = \"4c41000b/1.3.xbx\"
+            from = [0x00010002]
+
+            [[update]]
+            version = 0x00010002
+            package = \"4c41000b/1.2.xbx\"
+        ").unwrap();
+
+        let updates = Updates::load_dir(root.path()).unwrap();
+
+        (root, updates)
+    }
+
+    #[test]
+    fn check_for_updates() {
+        let (_root, updates) = registry();
+        assert_eq!(updates.len(), 2);
+
+        let update = updates.check(TITLE_ID, 0x0001_0002).unwrap();
+        assert_eq!(update.version, 0x0001_0003);
+        assert_eq!(update.package_size, 11);
+        assert_eq!(update.hash, sha1(&[b"version 1.3"]));
+
+        // Older versions have to go through 1.2 first.
+        assert_eq!(updates.check(TITLE_ID, 0x0001_0000).unwrap().version, 0x0001_0002);
+
+        assert!(updates.check(TITLE_ID, 0x0001_0003).is_none());
+        assert!(updates.check(TITLE_ID, 0x0002_0000).is_none());
+        assert!(updates.check(TITLE_ID + 1, 0).is_none());
+    }
+
+    #[test]
+    fn none_required() {
+        let updates = Updates::none_required();
+        assert!(updates.check(TITLE_ID, 0).is_none());
+    }
+
+    #[tokio::test]
+    async fn read_packages() {
+        let (_root, updates) = registry();
+
+        assert_eq!(updates.read(TITLE_ID, 0x0001_0003, 8, 8).await.unwrap(), b"1.3");
+        assert!(matches!(updates.read(TITLE_ID, 0x0001_0003, 12, 8).await, Err(UpdateError::BadRange)));
+        assert!(matches!(updates.read(TITLE_ID, 0x0001_0004, 0, 8).await, Err(UpdateError::NoSuchUpdate)));
+    }
+
+    #[test]
+    fn bad_registries_are_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("4c41000b.toml"), "[[update]]\nversion = 2\npackage = \"../escape.xbx\"\n").unwrap();
+        assert!(matches!(
+            Updates::load_dir(root.path()),
+            Err(UpdateLoadError::BadPackagePath(_, package)) if package == "../escape.xbx"
+        ));
+
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("a.xbx"), b"a").unwrap();
+        fs::write(root.path().join("4c41000b.toml"), "
+            [[update]]
+            version = 2
+            package = \"a.xbx\"
+
+            [[update]]
+            version = 2
+            package = \"a.xbx\"
+        ").unwrap();
+        assert!(matches!(Updates::load_dir(root.path()), Err(UpdateLoadError::DuplicateVersion(_, 2))));
+
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("4c41000b.toml"), "[[update]]\nversion = 2\npackage = \"missing.xbx\"\n").unwrap();
+        assert!(matches!(Updates::load_dir(root.path()), Err(UpdateLoadError::Io(_, _))));
+
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("halo.toml"), "").unwrap();
+        assert!(matches!(Updates::load_dir(root.path()), Err(UpdateLoadError::BadFileName(_))));
+    }
+}
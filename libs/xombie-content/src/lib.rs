@@ -0,0 +1,387 @@
+//! Downloadable content offers.
+//!
+//! The catalogue is a directory of packages, one directory per title named
+//! with the title id in hex, holding one directory per offer named with the
+//! offer id in hex:
+//!
+//! ```text
+//! content/
+//!     4c41000b/
+//!         0000000100000001/
+//!             offer.toml
+//!             maps.xbx
+//!             maps.xbe
+//! ```
+//!
+//! offer.toml describes the offer:
+//!
+//! ```toml
+//! name = "Map Pack"
+//! flags = 0x00000001
+//! files = ["maps.xbe", "maps.xbx"]
+//! ```
+//!
+//! `flags` are matched against the flags a title enumerates with, 0 unless
+//! set.  `files` lists the package's files in the order consoles download
+//! them; without it every other file in the offer's directory is part of the
+//! package, in name order.  Files are sized and hashed once, when the
+//! catalogue is loaded.
+
+use serde::Deserialize;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use xblive::crypto::primitives::{Sha1Digest, sha1};
+use xblive::service::content::{MAX_CHUNK_LEN, MAX_ENUM_RESULTS};
+
+/// The name of the file describing an offer, inside the offer's directory.
+pub const OFFER_FILE_NAME: &str = "offer.toml";
+
+#[derive(Debug, Deserialize)]
+struct OfferFile {
+    name: String,
+    #[serde(default)]
+    flags: u32,
+    files: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackageFile {
+    /// The name consoles see, relative to the offer's directory.
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub hash: Sha1Digest,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Offer {
+    pub title_id: u32,
+    pub offer_id: u64,
+    pub name: String,
+    pub flags: u32,
+    pub files: Vec<PackageFile>,
+}
+
+impl Offer {
+    pub fn package_size(&self) -> u64 {
+        self.files.iter().map(|file| file.size).sum()
+    }
+}
+
+#[derive(Debug)]
+pub enum CatalogueLoadError {
+    Io(PathBuf, io::Error),
+    BadDirName(PathBuf),
+    Parse(PathBuf, toml::de::Error),
+    /// A package file listed in offer.toml that isn't a plain name of a file
+    /// in the offer's directory.
+    BadFileName(PathBuf, String),
+    EmptyPackage(PathBuf),
+}
+
+#[derive(Debug)]
+pub enum ContentError {
+    NoSuchOffer,
+    NoSuchFile,
+    BadRange,
+    Io(io::Error),
+}
+
+impl From<io::Error> for ContentError {
+    fn from(err: io::Error) -> Self {
+        ContentError::Io(err)
+    }
+}
+
+/// Every offer known to the content service, keyed by title id and offer
+/// id.
+#[derive(Debug, Default)]
+pub struct Catalogue {
+    offers: BTreeMap<(u32, u64), Offer>,
+}
+
+fn io_err(path: &Path) -> impl FnOnce(io::Error) -> CatalogueLoadError + '_ {
+    move |err| CatalogueLoadError::Io(path.to_owned(), err)
+}
+
+fn hex_dir_name(path: &Path) -> Option<&str> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .filter(|name| !name.is_empty() && name.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// A name listed in offer.toml has to name a file directly inside the
+/// offer's directory.
+fn is_plain_file_name(name: &str) -> bool {
+    !name.is_empty() &&
+        name != OFFER_FILE_NAME &&
+        !name.chars().all(|c| c == '.') &&
+        name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+}
+
+impl Catalogue {
+    pub fn new() -> Self {
+        Catalogue {
+            offers: BTreeMap::new(),
+        }
+    }
+
+    /// Load every offer of every title in dir.  Entries that aren't
+    /// directories are skipped, so the catalogue can carry a README.
+    pub fn load_dir(dir: &Path) -> Result<Self, CatalogueLoadError> {
+        let mut catalogue = Catalogue::new();
+
+        for entry in fs::read_dir(dir).map_err(io_err(dir))? {
+            let title_dir = entry.map_err(io_err(dir))?.path();
+
+            if !title_dir.is_dir() {
+                continue;
+            }
+
+            let title_id = hex_dir_name(&title_dir)
+                .and_then(|name| u32::from_str_radix(name, 16).ok())
+                .ok_or_else(|| CatalogueLoadError::BadDirName(title_dir.clone()))?;
+
+            for entry in fs::read_dir(&title_dir).map_err(io_err(&title_dir))? {
+                let offer_dir = entry.map_err(io_err(&title_dir))?.path();
+
+                if !offer_dir.is_dir() {
+                    continue;
+                }
+
+                let offer_id = hex_dir_name(&offer_dir)
+                    .and_then(|name| u64::from_str_radix(name, 16).ok())
+                    .ok_or_else(|| CatalogueLoadError::BadDirName(offer_dir.clone()))?;
+
+                let offer = load_offer(title_id, offer_id, &offer_dir)?;
+                catalogue.add_offer(offer);
+            }
+        }
+
+        Ok(catalogue)
+    }
+
+    pub fn add_offer(&mut self, offer: Offer) {
+        self.offers.insert((offer.title_id, offer.offer_id), offer);
+    }
+
+    pub fn get(&self, title_id: u32, offer_id: u64) -> Option<&Offer> {
+        self.offers.get(&(title_id, offer_id))
+    }
+
+    /// The title's offers with every one of required_flags set, in offer id
+    /// order, skipping the first start.  Also returns how many offers
+    /// matched in all.
+    pub fn enumerate(&self, title_id: u32, required_flags: u32, start: u32, max_results: u16) -> (u32, Vec<&Offer>) {
+        let matching: Vec<&Offer> = self.offers.range((title_id, 0)..=(title_id, u64::MAX))
+            .map(|(_, offer)| offer)
+            .filter(|offer| offer.flags & required_flags == required_flags)
+            .collect();
+
+        let total = matching.len() as u32;
+
+        let page = matching.into_iter()
+            .skip(start as usize)
+            .take(max_results.min(MAX_ENUM_RESULTS) as usize)
+            .collect();
+
+        (total, page)
+    }
+
+    /// Up to len bytes of a package file from offset, fewer at the end of
+    /// the file.
+    pub async fn read(&self, title_id: u32, offer_id: u64, file_index: u16, offset: u64, len: u32) -> Result<Vec<u8>, ContentError> {
+        let offer = self.get(title_id, offer_id).ok_or(ContentError::NoSuchOffer)?;
+        let file = offer.files.get(file_index as usize).ok_or(ContentError::NoSuchFile)?;
+
+        if offset > file.size || len > MAX_CHUNK_LEN {
+            return Err(ContentError::BadRange);
+        }
+
+        let len = (file.size - offset).min(len as u64) as usize;
+
+        let mut package = tokio::fs::File::open(&file.path).await?;
+        package.seek(SeekFrom::Start(offset)).await?;
+
+        let mut data = vec![0; len];
+        package.read_exact(&mut data).await?;
+
+        Ok(data)
+    }
+
+    pub fn len(&self) -> usize {
+        self.offers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offers.is_empty()
+    }
+}
+
+fn load_offer(title_id: u32, offer_id: u64, dir: &Path) -> Result<Offer, CatalogueLoadError> {
+    let offer_path = dir.join(OFFER_FILE_NAME);
+
+    let source = fs::read_to_string(&offer_path).map_err(io_err(&offer_path))?;
+    let offer_file: OfferFile = toml::from_str(&source)
+        .map_err(|err| CatalogueLoadError::Parse(offer_path.clone(), err))?;
+
+    let names = match offer_file.files {
+        Some(names) => {
+            if let Some(bad_name) = names.iter().find(|name| !is_plain_file_name(name)) {
+                return Err(CatalogueLoadError::BadFileName(offer_path, bad_name.clone()));
+            }
+
+            names
+        }
+        None => {
+            let mut names = vec![];
+
+            for entry in fs::read_dir(dir).map_err(io_err(dir))? {
+                let path = entry.map_err(io_err(dir))?.path();
+
+                match path.file_name().and_then(|name| name.to_str()) {
+                    Some(name) if path.is_file() && name != OFFER_FILE_NAME => names.push(name.to_owned()),
+                    _ => {}
+                }
+            }
+
+            names.sort();
+            names
+        }
+    };
+
+    if names.is_empty() {
+        return Err(CatalogueLoadError::EmptyPackage(dir.to_owned()));
+    }
+
+    let mut files = vec![];
+
+    for name in names {
+        let path = dir.join(&name);
+        let data = fs::read(&path).map_err(io_err(&path))?;
+
+        files.push(PackageFile {
+            name,
+            path,
+            size: data.len() as u64,
+            hash: sha1(&[&data]),
+        });
+    }
+
+    Ok(Offer {
+        title_id,
+        offer_id,
+        name: offer_file.name,
+        flags: offer_file.flags,
+        files,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TITLE_ID: u32 = 0x4C41000B;
+
+    fn write_offer(root: &Path, offer_dir: &str, offer: &str, files: &[(&str, &[u8])]) {
+        let dir = root.join("4c41000b").join(offer_dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(OFFER_FILE_NAME), offer).unwrap();
+
+        for (name, data) in files {
+            fs::write(dir.join(name), data).unwrap();
+        }
+    }
+
+    fn catalogue() -> (tempfile::TempDir, Catalogue) {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("README"), "not an offer").unwrap();
+
+        write_offer(root.path(), "0000000100000001", "name = \"Map Pack\"\nflags = 3\n",
+            &[("b.xbx", b"abc"), ("a.xbe", b"0123456789")]);
+        write_offer(root.path(), "0000000100000002", "name = \"Skins\"\nfiles = [\"skins.xbx\"]\n",
+            &[("skins.xbx", b"skins"), ("notes.txt", b"left out")]);
+
+        let catalogue = Catalogue::load_dir(root.path()).unwrap();
+
+        (root, catalogue)
+    }
+
+    #[test]
+    fn load_catalogue() {
+        let (_root, catalogue) = catalogue();
+        assert_eq!(catalogue.len(), 2);
+
+        let map_pack = catalogue.get(TITLE_ID, 0x0000_0001_0000_0001).unwrap();
+        assert_eq!(map_pack.name, "Map Pack");
+        assert_eq!(map_pack.flags, 3);
+        assert_eq!(map_pack.package_size(), 13);
+
+        let names: Vec<_> = map_pack.files.iter().map(|file| file.name.as_str()).collect();
+        assert_eq!(names, vec!["a.xbe", "b.xbx"]);
+        assert_eq!(map_pack.files[1].hash, sha1(&[b"abc"]));
+
+        let skins = catalogue.get(TITLE_ID, 0x0000_0001_0000_0002).unwrap();
+        assert_eq!(skins.files.len(), 1);
+        assert_eq!(skins.files[0].name, "skins.xbx");
+    }
+
+    #[test]
+    fn enumerate_by_flags() {
+        let (_root, catalogue) = catalogue();
+
+        let (total, offers) = catalogue.enumerate(TITLE_ID, 0, 0, 10);
+        assert_eq!(total, 2);
+        assert_eq!(offers.len(), 2);
+
+        let (total, offers) = catalogue.enumerate(TITLE_ID, 2, 0, 10);
+        assert_eq!(total, 1);
+        assert_eq!(offers[0].name, "Map Pack");
+
+        let (total, offers) = catalogue.enumerate(TITLE_ID, 0, 1, 10);
+        assert_eq!(total, 2);
+        assert_eq!(offers[0].name, "Skins");
+
+        assert_eq!(catalogue.enumerate(TITLE_ID + 1, 0, 0, 10).0, 0);
+    }
+
+    #[tokio::test]
+    async fn read_chunks() {
+        let (_root, catalogue) = catalogue();
+        let offer_id = 0x0000_0001_0000_0001;
+
+        assert_eq!(catalogue.read(TITLE_ID, offer_id, 0, 2, 4).await.unwrap(), b"2345");
+        assert_eq!(catalogue.read(TITLE_ID, offer_id, 0, 8, 4).await.unwrap(), b"89");
+        assert_eq!(catalogue.read(TITLE_ID, offer_id, 0, 10, 4).await.unwrap(), b"");
+
+        assert!(matches!(catalogue.read(TITLE_ID, offer_id, 0, 11, 4).await, Err(ContentError::BadRange)));
+        assert!(matches!(catalogue.read(TITLE_ID, offer_id, 0, 0, MAX_CHUNK_LEN + 1).await, Err(ContentError::BadRange)));
+        assert!(matches!(catalogue.read(TITLE_ID, offer_id, 2, 0, 4).await, Err(ContentError::NoSuchFile)));
+        assert!(matches!(catalogue.read(TITLE_ID, 3, 0, 0, 4).await, Err(ContentError::NoSuchOffer)));
+    }
+
+    #[test]
+    fn bad_offers_are_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        write_offer(root.path(), "1", "name = \"Escape\"\nfiles = [\"../secret\"]\n", &[]);
+        assert!(matches!(Catalogue::load_dir(root.path()), Err(CatalogueLoadError::BadFileName(_, name)) if name == "../secret"));
+
+        let root = tempfile::tempdir().unwrap();
+        write_offer(root.path(), "1", "name = \"Empty\"\n", &[]);
+        assert!(matches!(Catalogue::load_dir(root.path()), Err(CatalogueLoadError::EmptyPackage(_))));
+
+        let root = tempfile::tempdir().unwrap();
+        write_offer(root.path(), "maps", "name = \"Maps\"\n", &[("maps.xbx", b"maps")]);
+        assert!(matches!(Catalogue::load_dir(root.path()), Err(CatalogueLoadError::BadDirName(_))));
+
+        let root = tempfile::tempdir().unwrap();
+        write_offer(root.path(), "1", "flags = 1\n", &[("maps.xbx", b"maps")]);
+        assert!(matches!(Catalogue::load_dir(root.path()), Err(CatalogueLoadError::Parse(_, _))));
+    }
+}
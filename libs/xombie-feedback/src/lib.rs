@@ -0,0 +1,222 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use xblive::service::feedback::{MAX_TEXT_LEN, NUM_FEEDBACK_TYPES};
+
+use xbox_sys::account::Xuid;
+
+pub mod store;
+
+use store::{FeedbackStore, StoreError};
+
+/// Most reports a single query returns.
+pub const MAX_QUERY_RESULTS: u32 = 100;
+
+/// A complaint or compliment one player made about another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub id: u64,
+    pub title_id: u32,
+    pub reporter: Xuid,
+    pub target: Xuid,
+    pub feedback_type: u32,
+    pub text: Option<String>,
+    pub submitted: SystemTime,
+}
+
+/// Which reports a moderator wants to see.  Every filter that's set has to
+/// match.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReportQuery {
+    pub reporter: Option<Xuid>,
+    pub target: Option<Xuid>,
+    pub title_id: Option<u32>,
+    pub feedback_type: Option<u32>,
+    /// Only reports older than the one with this id, for paging.
+    pub before: Option<u64>,
+    pub limit: u32,
+}
+
+impl Default for ReportQuery {
+    fn default() -> Self {
+        ReportQuery {
+            reporter: None,
+            target: None,
+            title_id: None,
+            feedback_type: None,
+            before: None,
+            limit: MAX_QUERY_RESULTS,
+        }
+    }
+}
+
+impl ReportQuery {
+    pub fn matches(&self, report: &Report) -> bool {
+        fn matches<T: PartialEq>(filter: Option<T>, value: T) -> bool {
+            filter.is_none() || filter == Some(value)
+        }
+
+        matches(self.reporter, report.reporter) &&
+            matches(self.target, report.target) &&
+            matches(self.title_id, report.title_id) &&
+            matches(self.feedback_type, report.feedback_type) &&
+            self.before.iter().all(|before| report.id < *before)
+    }
+}
+
+#[derive(Debug)]
+pub enum FeedbackError {
+    /// Players can't report themselves.
+    BadTarget,
+    UnknownType(u32),
+    TextTooLong,
+    Store(StoreError),
+}
+
+impl From<StoreError> for FeedbackError {
+    fn from(err: StoreError) -> Self {
+        FeedbackError::Store(err)
+    }
+}
+
+pub struct Feedback {
+    store: Arc<dyn FeedbackStore>,
+}
+
+impl Debug for Feedback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Feedback").finish()
+    }
+}
+
+impl Feedback {
+    pub fn new(store: Arc<dyn FeedbackStore>) -> Self {
+        Feedback {
+            store,
+        }
+    }
+
+    /// Record reporter's feedback about target.  An empty text is kept as no
+    /// text at all.  Returns the id of the report.
+    pub async fn submit(&self, title_id: u32, reporter: Xuid, target: Xuid, feedback_type: u32, text: &str) -> Result<u64, FeedbackError> {
+        if reporter == target {
+            return Err(FeedbackError::BadTarget);
+        }
+
+        if feedback_type >= NUM_FEEDBACK_TYPES {
+            return Err(FeedbackError::UnknownType(feedback_type));
+        }
+
+        if text.len() > MAX_TEXT_LEN {
+            return Err(FeedbackError::TextTooLong);
+        }
+
+        let report = Report {
+            id: 0,
+            title_id,
+            reporter,
+            target,
+            feedback_type,
+            text: if text.is_empty() { None } else { Some(text.to_owned()) },
+            submitted: SystemTime::now(),
+        };
+
+        Ok(self.store.add(report).await?)
+    }
+
+    /// Reports matching query, newest first, at most MAX_QUERY_RESULTS of
+    /// them.
+    pub async fn query(&self, query: &ReportQuery) -> Result<Vec<Report>, FeedbackError> {
+        let query = ReportQuery {
+            limit: query.limit.min(MAX_QUERY_RESULTS),
+            ..query.clone()
+        };
+
+        Ok(self.store.query(&query).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use xblive::service::feedback::*;
+
+    use super::*;
+    use super::store::memory::MemoryStore;
+
+    const TITLE_ID: u32 = 0x4C41000B;
+
+    const ALICE: Xuid = Xuid(0x0009_0000_0000_0001);
+    const BOB: Xuid = Xuid(0x0009_0000_0000_0002);
+    const CAROL: Xuid = Xuid(0x0009_0000_0000_0003);
+
+    fn feedback() -> Feedback {
+        Feedback::new(Arc::new(MemoryStore::new()))
+    }
+
+    #[tokio::test]
+    async fn reports_are_queried_newest_first() {
+        let feedback = feedback();
+
+        feedback.submit(TITLE_ID, ALICE, BOB, FEEDBACK_NEG_SCREAMING, "").await.unwrap();
+        feedback.submit(TITLE_ID, CAROL, BOB, FEEDBACK_NEG_HARASSMENT, "kept following me").await.unwrap();
+        feedback.submit(TITLE_ID + 1, ALICE, CAROL, FEEDBACK_POS_ATTITUDE, "").await.unwrap();
+        feedback.submit(TITLE_ID, ALICE, BOB, FEEDBACK_NEG_HARASSMENT, "").await.unwrap();
+
+        let about_bob = feedback.query(&ReportQuery {
+            target: Some(BOB),
+            ..Default::default()
+        }).await.unwrap();
+
+        assert_eq!(about_bob.iter().map(|report| report.id).collect::<Vec<_>>(), vec![4, 2, 1]);
+        assert_eq!(about_bob[0].text, None);
+        assert_eq!(about_bob[1].text.as_deref(), Some("kept following me"));
+
+        let harassment = feedback.query(&ReportQuery {
+            target: Some(BOB),
+            feedback_type: Some(FEEDBACK_NEG_HARASSMENT),
+            before: Some(4),
+            ..Default::default()
+        }).await.unwrap();
+
+        assert_eq!(harassment.len(), 1);
+        assert_eq!(harassment[0].reporter, CAROL);
+
+        let by_alice = feedback.query(&ReportQuery {
+            reporter: Some(ALICE),
+            title_id: Some(TITLE_ID + 1),
+            ..Default::default()
+        }).await.unwrap();
+
+        assert_eq!(by_alice.len(), 1);
+        assert_eq!(by_alice[0].target, CAROL);
+
+        let first = feedback.query(&ReportQuery {
+            limit: 1,
+            ..Default::default()
+        }).await.unwrap();
+
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].id, 4);
+    }
+
+    #[tokio::test]
+    async fn bad_feedback_is_rejected() {
+        let feedback = feedback();
+
+        assert!(matches!(
+            feedback.submit(TITLE_ID, ALICE, ALICE, FEEDBACK_POS_SESSION, "").await,
+            Err(FeedbackError::BadTarget)
+        ));
+        assert!(matches!(
+            feedback.submit(TITLE_ID, ALICE, BOB, NUM_FEEDBACK_TYPES, "").await,
+            Err(FeedbackError::UnknownType(NUM_FEEDBACK_TYPES))
+        ));
+        assert!(matches!(
+            feedback.submit(TITLE_ID, ALICE, BOB, FEEDBACK_NEG_NICKNAME, &"a".repeat(MAX_TEXT_LEN + 1)).await,
+            Err(FeedbackError::TextTooLong)
+        ));
+
+        assert!(feedback.query(&ReportQuery::default()).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,40 @@
+//! Where feedback reports live.
+//!
+//! Feedback decides what a player may report; a FeedbackStore only keeps
+//! reports and finds them again for moderators.
+
+use async_trait::async_trait;
+
+use crate::{Report, ReportQuery};
+
+pub mod memory;
+pub mod postgres;
+
+#[derive(Debug)]
+pub enum StoreError {
+    Pg(tokio_postgres::Error),
+    Pool(xombie::db::PoolError),
+    CannotParseXuid(std::num::ParseIntError),
+}
+
+impl From<tokio_postgres::Error> for StoreError {
+    fn from(pg_err: tokio_postgres::Error) -> Self {
+        StoreError::Pg(pg_err)
+    }
+}
+
+impl From<xombie::db::PoolError> for StoreError {
+    fn from(pool_err: xombie::db::PoolError) -> Self {
+        StoreError::Pool(pool_err)
+    }
+}
+
+#[async_trait]
+pub trait FeedbackStore: Send + Sync {
+    /// Keep report, ignoring its id.  Returns the id it was given, larger
+    /// than that of every report added before it.
+    async fn add(&self, report: Report) -> Result<u64, StoreError>;
+
+    /// Up to query.limit reports matching query, newest first.
+    async fn query(&self, query: &ReportQuery) -> Result<Vec<Report>, StoreError>;
+}
@@ -0,0 +1,49 @@
+use async_trait::async_trait;
+
+use tokio::sync::Mutex;
+
+use crate::{Report, ReportQuery};
+
+use super::{FeedbackStore, StoreError};
+
+/// Reports kept in process, lost when the SG restarts.
+pub struct MemoryStore {
+    reports: Mutex<Vec<Report>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore {
+            reports: Mutex::new(vec![]),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::new()
+    }
+}
+
+#[async_trait]
+impl FeedbackStore for MemoryStore {
+    async fn add(&self, mut report: Report) -> Result<u64, StoreError> {
+        let mut reports = self.reports.lock().await;
+
+        report.id = reports.len() as u64 + 1;
+        reports.push(report);
+
+        Ok(reports.len() as u64)
+    }
+
+    async fn query(&self, query: &ReportQuery) -> Result<Vec<Report>, StoreError> {
+        let reports = self.reports.lock().await;
+
+        Ok(reports.iter()
+            .rev()
+            .filter(|report| query.matches(report))
+            .take(query.limit as usize)
+            .cloned()
+            .collect())
+    }
+}
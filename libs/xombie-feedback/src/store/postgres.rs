@@ -0,0 +1,84 @@
+use async_trait::async_trait;
+
+use xombie::db::{self, FeedbackError, FeedbackRecord, Pool};
+
+use crate::{Report, ReportQuery};
+
+use super::{FeedbackStore, StoreError};
+
+/// Reports kept in the database, where moderators can get at them.
+pub struct PostgresStore {
+    pool: Pool,
+}
+
+impl PostgresStore {
+    /// Use connections from pool for feedback storage.  Its tables come from
+    /// schema/0005_feedback.sql.
+    pub fn new(pool: Pool) -> Self {
+        PostgresStore {
+            pool,
+        }
+    }
+}
+
+impl From<FeedbackError> for StoreError {
+    fn from(err: FeedbackError) -> Self {
+        match err {
+            FeedbackError::Pg(pg_err) => StoreError::Pg(pg_err),
+            FeedbackError::CannotParseXuid(parse_err) => StoreError::CannotParseXuid(parse_err),
+        }
+    }
+}
+
+impl From<FeedbackRecord> for Report {
+    fn from(record: FeedbackRecord) -> Self {
+        Report {
+            id: record.id,
+            title_id: record.title_id,
+            reporter: record.reporter,
+            target: record.target,
+            feedback_type: record.feedback_type,
+            text: record.text,
+            submitted: record.submitted,
+        }
+    }
+}
+
+impl From<Report> for FeedbackRecord {
+    fn from(report: Report) -> Self {
+        FeedbackRecord {
+            id: report.id,
+            title_id: report.title_id,
+            reporter: report.reporter,
+            target: report.target,
+            feedback_type: report.feedback_type,
+            text: report.text,
+            submitted: report.submitted,
+        }
+    }
+}
+
+#[async_trait]
+impl FeedbackStore for PostgresStore {
+    async fn add(&self, report: Report) -> Result<u64, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::put_feedback_record(&client, &report.into()).await?)
+    }
+
+    async fn query(&self, query: &ReportQuery) -> Result<Vec<Report>, StoreError> {
+        let client = self.pool.get().await?;
+
+        let records = db::get_feedback_records(
+            &client,
+            query.reporter,
+            query.target,
+            query.title_id,
+            query.feedback_type,
+            query.before,
+            query.limit,
+        ).await?;
+
+        Ok(records.into_iter().map(Report::from).collect())
+    }
+}
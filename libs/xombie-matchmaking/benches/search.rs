@@ -0,0 +1,148 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::runtime::Runtime;
+
+use xblive::addr::Addr;
+use xblive::net::{Eui48, InAddr};
+use xblive::service::matchmaking::{SearchAttribute, SearchAttributeKind};
+
+use xbox_sys::account::Xuid;
+
+use xombie_matchmaking::{Matchmaking, Slots, Title, Users};
+use xombie_matchmaking::procedure::Procedures;
+use xombie_matchmaking::store::SessionStore;
+use xombie_matchmaking::store::memory::MemoryStore;
+use xombie_matchmaking::store::redis::RedisStore;
+
+/// The Redis to benchmark RedisStore against, if any.  Its database is
+/// flushed before each population, so point it at one of its own.
+const REDIS_URL_VAR: &str = "XOMBIE_BENCH_REDIS_URL";
+
+const SESSION_TTL: Duration = Duration::from_secs(600);
+
+const TITLE: Title = Title { id: 0x4C41000B, ver: 0x00010000 };
+
+const NOISE_TITLE: Title = Title { id: 0x4D530004, ver: 0x00000102 };
+
+const MODES: i64 = 8;
+
+const MAPS: [&str; 12] = [
+    "Ascension", "Beaver Creek", "Burial Mounds", "Chill Out", "Colossus", "Coagulation",
+    "Foundation", "Headlong", "Ivory Tower", "Lockout", "Midship", "Zanzibar",
+];
+
+fn users(machine: u64) -> Users {
+    Users {
+        machine: Xuid(machine),
+        user: vec![Xuid(0x0009_0000_0000_0000 | machine)],
+    }
+}
+
+fn host_address(n: u32) -> Addr {
+    let [a, b, c, d] = n.to_be_bytes();
+
+    Addr {
+        addr: InAddr([10, b, c, d]),
+        addr_online: InAddr([192, 168, c, d]),
+        port_online: 1000,
+        enet: Eui48([0, 0x50, 0xF2, a, c, d]),
+        online: [0; 20],
+    }
+}
+
+fn attributes(mode: i64, map: &str) -> Vec<SearchAttribute> {
+    vec![
+        SearchAttribute { tag: 1, kind: SearchAttributeKind::Integer(mode) },
+        SearchAttribute { tag: 2, kind: SearchAttributeKind::String(String::from(map)) },
+    ]
+}
+
+type NewStore = Box<dyn Fn() -> Arc<dyn SessionStore>>;
+
+/// The stores to benchmark: always a MemoryStore, and a RedisStore when
+/// REDIS_URL_VAR names a Redis.
+fn stores(rt: &Runtime) -> Vec<(&'static str, NewStore)> {
+    let mut stores: Vec<(&'static str, NewStore)> = vec![
+        ("memory", Box::new(|| Arc::new(MemoryStore::new(SESSION_TTL)))),
+    ];
+
+    if let Ok(url) = std::env::var(REDIS_URL_VAR) {
+        let handle = rt.handle().clone();
+
+        stores.push(("redis", Box::new(move || handle.block_on(async {
+            let client = redis::Client::open(url.as_str()).unwrap();
+            let mut conn = client.get_multiplexed_tokio_connection().await.unwrap();
+            redis::cmd("FLUSHDB").query_async::<_, ()>(&mut conn).await.unwrap();
+
+            Arc::new(RedisStore::connect(&url, SESSION_TTL).await.unwrap())
+        }))));
+    }
+
+    stores
+}
+
+/// A matchmaking service keeping sessions in store, holding sessions for the
+/// benchmarked title, plus as many again for another title that searches
+/// should never look at.
+fn populated(rt: &Runtime, store: Arc<dyn SessionStore>, sessions: u32) -> Matchmaking {
+    let mut procedures = Procedures::new();
+    procedures.add_title(TITLE.id, "procedure 1\n    where params-by-tag\n    max-results 50").unwrap();
+
+    let matchmaking = Matchmaking::new(procedures, store, None);
+
+    rt.block_on(async {
+        for n in 0..sessions {
+            for (title, machine) in [(TITLE, n as u64), (NOISE_TITLE, (sessions + n) as u64)] {
+                let mode = n as i64 % MODES;
+                let map = MAPS[n as usize % MAPS.len()];
+
+                matchmaking.create_session(
+                    users(machine),
+                    machine,
+                    title,
+                    host_address(machine as u32),
+                    8, 0, 1, 0,
+                    attributes(mode, map),
+                ).await.unwrap();
+            }
+        }
+    });
+
+    matchmaking
+}
+
+fn search(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("search");
+
+    for (name, store) in stores(&rt) {
+        for sessions in [1_000, 10_000, 50_000] {
+            let matchmaking = populated(&rt, store(), sessions);
+
+            group.bench_with_input(BenchmarkId::new(format!("{}/mode_and_map", name), sessions), &matchmaking, |b, matchmaking| {
+                b.iter(|| rt.block_on(async {
+                    matchmaking.search_for_sessions(users(u64::MAX), None, TITLE, 1, 1, Slots::Public, &attributes(3, "Zanzibar"))
+                        .await
+                        .unwrap()
+                }))
+            });
+
+            group.bench_with_input(BenchmarkId::new(format!("{}/no_match", name), sessions), &matchmaking, |b, matchmaking| {
+                b.iter(|| rt.block_on(async {
+                    matchmaking.search_for_sessions(users(u64::MAX), None, TITLE, 1, 1, Slots::Public, &attributes(MODES, "Zanzibar"))
+                        .await
+                        .unwrap()
+                }))
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, search);
+criterion_main!(benches);
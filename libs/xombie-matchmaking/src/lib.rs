@@ -1,20 +1,31 @@
-use log::error;
+use log::{error, info};
 
-use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, SystemTime};
 
-use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 
 use xblive::addr::Addr;
 use xblive::crypto::primitives::KeyId;
-use xblive::service::matchmaking::SearchAttribute;
-use xblive::ver::LibraryVersion;
+use xblive::service::matchmaking::{SearchAttribute, SearchAttributeKind};
 
 use xbox_sys::account::Xuid;
 use xbox_sys::crypto::SymmetricKey;
 
+pub mod procedure;
+pub mod rank;
+pub mod store;
+
+use procedure::{Procedures, SearchProcedure};
+use rank::SearcherInfo;
+use store::{SessionStore, StoreError};
+
+/// How many candidates a search ranks for every result it returns.  The
+/// candidates are the oldest matches the store finds, so a search costs about
+/// the same however many sessions a title has.
+const CANDIDATES_PER_RESULT: usize = 4;
+
 pub struct CreatedSession {
     pub session_id: KeyId,
     pub key_exchange_key: SymmetricKey,
@@ -23,11 +34,27 @@ pub struct CreatedSession {
 #[derive(Debug)]
 pub enum SessionCreateError {
     UnableToGenerateSessionId,
-    OverlappingCreationTime,
+    Store(StoreError),
+}
+
+impl From<StoreError> for SessionCreateError {
+    fn from(err: StoreError) -> Self {
+        SessionCreateError::Store(err)
+    }
 }
 
 #[derive(Debug)]
 pub enum SessionDeleteError {
+    SessionIdNotFound,
+    UserMismatch,
+    TitleMismatch,
+    Store(StoreError),
+}
+
+impl From<StoreError> for SessionDeleteError {
+    fn from(err: StoreError) -> Self {
+        SessionDeleteError::Store(err)
+    }
 }
 
 #[derive(Debug)]
@@ -36,14 +63,31 @@ pub enum SessionUpdateError {
     UserMismatch,
     TitleMismatch,
     HostAddressMismatch,
+    Store(StoreError),
+}
+
+impl From<StoreError> for SessionUpdateError {
+    fn from(err: StoreError) -> Self {
+        SessionUpdateError::Store(err)
+    }
 }
 
 #[derive(Debug)]
 pub enum SessionSearchError {
+    UnknownProcedure,
+    Store(StoreError),
+}
+
+impl From<StoreError> for SessionSearchError {
+    fn from(err: StoreError) -> Self {
+        SessionSearchError::Store(err)
+    }
 }
 
 pub struct Matchmaking {
-    internal_state: Arc<Mutex<InternalState>>,
+    store: Arc<dyn SessionStore>,
+    procedures: Procedures,
+    region: Option<String>,
 }
 
 impl Debug for Matchmaking {
@@ -52,22 +96,50 @@ impl Debug for Matchmaking {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Users {
     pub machine: Xuid,
     pub user: Vec<Xuid>,
 }
 
 impl Matchmaking {
-    pub fn new() -> Self {
+    /// Sessions created through this instance are tagged with region, the
+    /// region of the SG serving the host, and searches prefer sessions from
+    /// their own region.
+    pub fn new(procedures: Procedures, store: Arc<dyn SessionStore>, region: Option<String>) -> Self {
         Matchmaking {
-            internal_state: Arc::new(Mutex::new(InternalState::new())),
+            store,
+            procedures,
+            region,
         }
     }
 
+    /// Periodically drop expired sessions for as long as the returned task
+    /// runs.
+    pub fn spawn_reaper(&self, period: Duration) -> JoinHandle<()> {
+        let store = self.store.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+
+            loop {
+                interval.tick().await;
+
+                match store.reap_expired(SystemTime::now()).await {
+                    Ok(0) => {}
+                    Ok(reaped) => info!("Reaped {} expired matchmaking sessions", reaped),
+                    Err(err) => error!("Unable to reap matchmaking sessions: {:?}", err),
+                }
+            }
+        })
+    }
+
+    /// Create a session hosted by users through connection, the SG
+    /// connection the host's console is talking to us on.
     pub async fn create_session(
         &self,
         users: Users,
+        connection: u64,
         title: Title,
         host_address: Addr,
         public_open: u32,
@@ -76,25 +148,43 @@ impl Matchmaking {
         private_filled: u32,
         attributes: Vec<SearchAttribute>,
     ) -> Result<CreatedSession, SessionCreateError> {
-        self.internal_state
-            .lock()
-            .await
-            .create_session(
-                users,
-                title,
-                host_address,
-                public_open,
-                private_open,
-                public_filled,
-                private_filled,
-                attributes,
-            )
-            .await
+        let session_id = KeyId(rand::random());
+        let key_exchange_key = SymmetricKey(rand::random());
+        let creation_time = SystemTime::now();
+
+        let session = Session {
+            session_id,
+            key_exchange_key,
+            title,
+            host_users: users,
+            host_connection: connection,
+            host_address,
+            region: self.region.clone(),
+            public_open,
+            private_open,
+            public_filled,
+            private_filled,
+            attributes,
+            creation_time,
+            last_refresh: creation_time,
+        };
+
+        if !self.store.insert(session).await? {
+            return Err(SessionCreateError::UnableToGenerateSessionId)
+        }
+
+        Ok(CreatedSession {
+            session_id,
+            key_exchange_key,
+        })
     }
 
+    /// Update a session, which from then on belongs to connection.  A host
+    /// that reconnected takes its sessions along with its first update.
     pub async fn update_session(
         &self,
         users: Users,
+        connection: u64,
         title: Title,
         session_id: KeyId,
         host_address: Addr,
@@ -104,55 +194,152 @@ impl Matchmaking {
         private_filled: u32,
         attributes: Vec<SearchAttribute>,
     ) -> Result<CreatedSession, SessionUpdateError> {
-        self.internal_state
-            .lock()
-            .await
-            .update_session(
-                users,
-                title,
-                session_id,
-                host_address,
-                public_open,
-                private_open,
-                public_filled,
-                private_filled,
-                attributes,
-            )
-            .await
+        let mut existing_session = self.store.get(session_id)
+            .await?
+            .ok_or(SessionUpdateError::SessionIdNotFound)?;
+
+        if existing_session.host_users != users {
+            return Err(SessionUpdateError::UserMismatch);
+        }
+        if existing_session.title != title {
+            return Err(SessionUpdateError::TitleMismatch);
+        }
+        if existing_session.host_address != host_address {
+            return Err(SessionUpdateError::HostAddressMismatch);
+        }
+
+        existing_session.host_connection = connection;
+        existing_session.public_open = public_open;
+        existing_session.private_open = private_open;
+        existing_session.public_filled = public_filled;
+        existing_session.private_filled = private_filled;
+        existing_session.attributes = attributes;
+        existing_session.last_refresh = SystemTime::now();
+
+        let created_session = CreatedSession {
+            session_id: existing_session.session_id,
+            key_exchange_key: existing_session.key_exchange_key,
+        };
+
+        // The session may have expired or been closed since it was read.
+        if !self.store.replace(existing_session).await? {
+            return Err(SessionUpdateError::SessionIdNotFound);
+        }
+
+        Ok(created_session)
     }
 
-    pub async fn close_session(&self) -> Result<(), SessionDeleteError> {
-        todo!()
+    pub async fn close_session(
+        &self,
+        users: Users,
+        title: Title,
+        session_id: KeyId,
+    ) -> Result<(), SessionDeleteError> {
+        let existing_session = self.store.get(session_id)
+            .await?
+            .ok_or(SessionDeleteError::SessionIdNotFound)?;
+
+        if existing_session.host_users != users {
+            return Err(SessionDeleteError::UserMismatch);
+        }
+        if existing_session.title != title {
+            return Err(SessionDeleteError::TitleMismatch);
+        }
+
+        self.store.remove(session_id).await?;
+
+        Ok(())
+    }
+
+    /// Drop every session machine hosts through connection, for when that
+    /// connection goes away.  Sessions the machine has since taken over on a
+    /// newer connection are left alone.  Returns how many sessions were
+    /// dropped.
+    pub async fn close_sessions_for_machine(&self, machine: Xuid, connection: u64) -> Result<usize, SessionDeleteError> {
+        let mut closed = 0;
+
+        for session_id in self.store.sessions_for_machine(machine).await? {
+            if self.store.remove_hosted_through(session_id, connection).await?.is_some() {
+                closed += 1;
+            }
+        }
+
+        Ok(closed)
     }
 
+    /// Find up to the procedure's max_results sessions with num_users of the
+    /// given kind of slot open, best first for a searcher at
+    /// searcher_address.  Sessions the searching machine hosts itself are
+    /// never offered back to it.
     pub async fn search_for_sessions(
         &self,
         users: Users,
+        searcher_address: Option<Addr>,
         title: Title,
+        procedure_index: u32,
         num_users: u16,
-        flags: u16,
-        max_results: usize,
+        slots: Slots,
         attributes: &[SearchAttribute],
-    ) -> Result<Vec<SearchResult>, SessionSearchError> {
-        self.internal_state
-            .lock()
-            .await
-            .search_for_sessions(
-                users,
-                title,
-                num_users,
-                flags,
-                max_results,
-                attributes
-            )
-            .await
+    ) -> Result<SessionSearchResults, SessionSearchError> {
+        let procedure = self.procedures.get(title.id, procedure_index)
+            .ok_or(SessionSearchError::UnknownProcedure)?;
+
+        let required = procedure.required_values(attributes);
+        let filter = |session: &Session| {
+            session.host_users.machine != users.machine
+                && session.matches(title, procedure, num_users, slots, attributes)
+        };
+
+        // Only the oldest matches are ranked, but both they and the ranking
+        // are deterministic, so the same search gets the same results in the
+        // same order as long as the sessions don't change.
+        let candidates = procedure.max_results.saturating_mul(CANDIDATES_PER_RESULT);
+        let sessions = self.store.search(title, &required, &filter, candidates).await?;
+
+        let sessions = rank::best(sessions, &SearcherInfo {
+            address: searcher_address,
+            region: self.region.as_deref(),
+        }, procedure.max_results);
+
+        let results = sessions.into_iter()
+            .map(|session| SearchResult {
+                session_id: session.session_id,
+                host_address: session.host_address,
+                key_exchange_key: session.key_exchange_key,
+                public_open: session.public_open,
+                private_open: session.private_open,
+                public_filled: session.public_filled,
+                private_filled: session.private_filled,
+                attributes: session.attributes,
+            })
+            .collect();
+
+        Ok(SessionSearchResults {
+            results,
+            logging_threshold: procedure.logging_threshold,
+        })
     }
 }
 
+/// Which of a session's slots players are looking to fill.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Slots {
+    /// Open to anyone who finds the session.
+    Public,
+    /// Held back for players the host invited.
+    Private,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Title {
     pub id: u32,
-    pub ver: LibraryVersion,
+    pub ver: u32,
+}
+
+#[derive(Debug)]
+pub struct SessionSearchResults {
+    pub results: Vec<SearchResult>,
+    pub logging_threshold: u32,
 }
 
 #[derive(Debug)]
@@ -167,152 +354,424 @@ pub struct SearchResult {
     pub attributes: Vec<SearchAttribute>,
 }
 
-#[derive(Debug)]
-#[allow(dead_code)]
-struct Session {
-    session_id: KeyId,
-    key_exchange_key: SymmetricKey,
-    title: Title,
-    host_users: Users,
-    host_address: Addr,
-    public_open: u32,
-    private_open: u32,
-    public_filled: u32,
-    private_filled: u32,
-    attributes: Vec<SearchAttribute>,
-    creation_time: Instant,
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Session {
+    pub session_id: KeyId,
+    pub key_exchange_key: SymmetricKey,
+    pub title: Title,
+    pub host_users: Users,
+    /// The SG connection the host last created or updated the session
+    /// through.
+    pub host_connection: u64,
+    pub host_address: Addr,
+    /// Region of the SG the host registered through, if it has one.
+    pub region: Option<String>,
+    pub public_open: u32,
+    pub private_open: u32,
+    pub public_filled: u32,
+    pub private_filled: u32,
+    pub attributes: Vec<SearchAttribute>,
+    pub creation_time: SystemTime,
+    pub last_refresh: SystemTime,
 }
 
-struct InternalState {
-    sessions: BTreeMap<KeyId, Session>,
-    open_sessions: BTreeMap<Instant, KeyId>,
+impl Session {
+    /// Whether num_users more players can join into the given kind of slot.
+    fn has_room_for(&self, num_users: u16, slots: Slots) -> bool {
+        let open = match slots {
+            Slots::Public => self.public_open,
+            Slots::Private => self.private_open,
+        };
+
+        open >= num_users as u32
+    }
+
+    pub fn is_expired(&self, now: SystemTime, session_ttl: Duration) -> bool {
+        now.duration_since(self.last_refresh)
+            .map(|idle| idle >= session_ttl)
+            .unwrap_or(false)
+    }
+
+    fn matches(&self, title: Title, procedure: &SearchProcedure, num_users: u16, slots: Slots, params: &[SearchAttribute]) -> bool {
+        self.title == title
+            && self.has_room_for(num_users, slots)
+            && procedure.matches(&self.attributes, params)
+    }
 }
 
-impl InternalState {
-    fn new() -> Self {
-        InternalState {
-            sessions: BTreeMap::new(),
-            open_sessions: BTreeMap::new(),
+/// Every search attribute has to be satisfied by the session attribute with
+/// the same tag.  Integers and strings have to be equal, while a null search
+/// attribute only requires the session to carry the tag at all.  Attributes
+/// the codec couldn't make sense of don't constrain the search.
+fn attributes_match(session_attributes: &[SearchAttribute], search_attributes: &[SearchAttribute]) -> bool {
+    search_attributes.iter().all(|search_attribute| {
+        if let SearchAttributeKind::Unknown(_) = search_attribute.kind {
+            return true;
+        }
+
+        let session_attribute = match session_attributes.iter().find(|attribute| attribute.tag == search_attribute.tag) {
+            Some(session_attribute) => session_attribute,
+            None => return false,
+        };
+
+        match (&search_attribute.kind, &session_attribute.kind) {
+            (SearchAttributeKind::Null, _) => true,
+            (SearchAttributeKind::Integer(wanted), SearchAttributeKind::Integer(have)) => wanted == have,
+            (SearchAttributeKind::String(wanted), SearchAttributeKind::String(have)) => wanted == have,
+            _ => false,
         }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use xblive::net::{Eui48, InAddr};
+
+    use crate::store::memory::MemoryStore;
+    use crate::store::redis::RedisStore;
+    use crate::store::redis::fake::FakeRedis;
+
+    use super::*;
+
+    const TITLE: Title = Title { id: 0x4C41000B, ver: 0x00010000 };
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    const CAPPED_PROCEDURES: &str = "
+procedure 1
+    where params-by-tag
+procedure 2
+    where params-by-tag
+    max-results 2
+    logging-threshold 50
+";
+
+    fn attr(tag: u16, kind: SearchAttributeKind) -> SearchAttribute {
+        SearchAttribute { tag, kind }
     }
 
-    #[allow(unused_variables)]
-    async fn create_session(
-        &mut self,
-        users: Users,
-        title: Title,
-        host_address: Addr,
-        public_open: u32,
-        private_open: u32,
-        public_filled: u32,
-        private_filled: u32,
-        attributes: Vec<SearchAttribute>,
-    ) -> Result<CreatedSession, SessionCreateError> {
-        let session_id = KeyId(rand::random());
-        let key_exchange_key = SymmetricKey(rand::random());
-        let creation_time = Instant::now();
+    fn users(machine: u64) -> Users {
+        Users {
+            machine: Xuid(machine),
+            user: vec![],
+        }
+    }
 
-        let session = Session {
-            session_id,
-            key_exchange_key,
-            title,
-            host_users: users,
-            host_address,
+    fn host_address(last_octet: u8) -> Addr {
+        Addr {
+            addr: InAddr([10, 0, 0, last_octet]),
+            addr_online: InAddr([192, 168, 0, last_octet]),
+            port_online: 1000,
+            enet: Eui48([0, 0x50, 0xF2, 0, 0, last_octet]),
+            online: [0; 20],
+        }
+    }
+
+    fn matchmaking_with(store: Arc<dyn SessionStore>) -> Matchmaking {
+        let mut procedures = Procedures::new();
+        procedures.add_title(TITLE.id, CAPPED_PROCEDURES).unwrap();
+
+        Matchmaking::new(procedures, store, None)
+    }
+
+    /// The same matchmaking behaviour is expected whichever store backs it.
+    fn matchmakings() -> Vec<Matchmaking> {
+        vec![
+            matchmaking_with(Arc::new(MemoryStore::new(TTL))),
+            matchmaking_with(Arc::new(RedisStore::new(FakeRedis::new(), TTL))),
+        ]
+    }
+
+    async fn create(matchmaking: &Matchmaking, machine: u64, public_open: u32, private_open: u32, attributes: Vec<SearchAttribute>) -> KeyId {
+        let created = matchmaking.create_session(
+            users(machine),
+            machine,
+            TITLE,
+            host_address(machine as u8),
             public_open,
             private_open,
-            public_filled,
-            private_filled,
+            0,
+            0,
             attributes,
-            creation_time,
-        };
+        ).await.unwrap();
 
-        if self.sessions.contains_key(&session_id) {
-            return Err(SessionCreateError::UnableToGenerateSessionId)
+        // Sessions are ordered by creation time, so keep them distinct.
+        std::thread::sleep(std::time::Duration::from_millis(2));
+
+        created.session_id
+    }
+
+    async fn search_with(matchmaking: &Matchmaking, procedure_index: u32, title: Title, num_users: u16, attributes: &[SearchAttribute]) -> Vec<KeyId> {
+        search_for(matchmaking, procedure_index, title, num_users, Slots::Public, attributes).await
+    }
+
+    async fn search_slots(matchmaking: &Matchmaking, num_users: u16, slots: Slots) -> Vec<KeyId> {
+        search_for(matchmaking, 1, TITLE, num_users, slots, &[]).await
+    }
+
+    async fn search_for(matchmaking: &Matchmaking, procedure_index: u32, title: Title, num_users: u16, slots: Slots, attributes: &[SearchAttribute]) -> Vec<KeyId> {
+        matchmaking.search_for_sessions(users(0xFF), None, title, procedure_index, num_users, slots, attributes)
+            .await
+            .unwrap()
+            .results
+            .iter()
+            .map(|result| result.session_id)
+            .collect()
+    }
+
+    async fn search(matchmaking: &Matchmaking, title: Title, num_users: u16, attributes: &[SearchAttribute]) -> Vec<KeyId> {
+        search_with(matchmaking, 1, title, num_users, attributes).await
+    }
+
+    #[test]
+    fn integer_attributes_have_to_be_equal() {
+        let session = [attr(1, SearchAttributeKind::Integer(3))];
+
+        assert!(attributes_match(&session, &[attr(1, SearchAttributeKind::Integer(3))]));
+        assert!(!attributes_match(&session, &[attr(1, SearchAttributeKind::Integer(4))]));
+        assert!(!attributes_match(&session, &[attr(2, SearchAttributeKind::Integer(3))]));
+        assert!(!attributes_match(&session, &[attr(1, SearchAttributeKind::String(String::from("3")))]));
+    }
+
+    #[test]
+    fn string_attributes_have_to_be_equal() {
+        let session = [attr(1, SearchAttributeKind::String(String::from("Blood Gulch")))];
+
+        assert!(attributes_match(&session, &[attr(1, SearchAttributeKind::String(String::from("Blood Gulch")))]));
+        assert!(!attributes_match(&session, &[attr(1, SearchAttributeKind::String(String::from("Sidewinder")))]));
+        assert!(!attributes_match(&session, &[attr(1, SearchAttributeKind::Integer(0))]));
+    }
+
+    #[test]
+    fn null_attributes_only_require_the_tag() {
+        let session = [
+            attr(1, SearchAttributeKind::Integer(3)),
+            attr(2, SearchAttributeKind::Null),
+        ];
+
+        assert!(attributes_match(&session, &[attr(1, SearchAttributeKind::Null)]));
+        assert!(attributes_match(&session, &[attr(2, SearchAttributeKind::Null)]));
+        assert!(!attributes_match(&session, &[attr(3, SearchAttributeKind::Null)]));
+        assert!(!attributes_match(&session, &[attr(2, SearchAttributeKind::Integer(0))]));
+    }
+
+    #[test]
+    fn unknown_attributes_are_ignored() {
+        let session = [attr(1, SearchAttributeKind::Integer(3))];
+
+        assert!(attributes_match(&session, &[attr(0, SearchAttributeKind::Unknown(vec![0xFF; 3]))]));
+        assert!(attributes_match(&session, &[]));
+        assert!(attributes_match(&[], &[]));
+    }
+
+    #[tokio::test]
+    async fn search_filters_on_title_slots_and_attributes() {
+        for matchmaking in matchmakings() {
+            let full = create(&matchmaking, 1, 0, 4, vec![attr(1, SearchAttributeKind::Integer(3))]).await;
+            let one_slot = create(&matchmaking, 2, 1, 0, vec![attr(1, SearchAttributeKind::Integer(3))]).await;
+            let roomy = create(&matchmaking, 3, 4, 0, vec![attr(1, SearchAttributeKind::Integer(3))]).await;
+            let other_mode = create(&matchmaking, 4, 4, 0, vec![attr(1, SearchAttributeKind::Integer(5))]).await;
+
+            let mode_3 = [attr(1, SearchAttributeKind::Integer(3))];
+
+            assert_eq!(search(&matchmaking, TITLE, 0, &mode_3).await, vec![full, one_slot, roomy]);
+            assert_eq!(search(&matchmaking, TITLE, 1, &mode_3).await, vec![one_slot, roomy]);
+            assert_eq!(search(&matchmaking, TITLE, 2, &mode_3).await, vec![roomy]);
+            assert_eq!(search(&matchmaking, TITLE, 2, &[]).await, vec![roomy, other_mode]);
+
+            let other_version = Title { ver: TITLE.ver + 1, ..TITLE };
+            assert_eq!(search(&matchmaking, other_version, 0, &[]).await, vec![]);
         }
+    }
 
-        if self.open_sessions.contains_key(&creation_time) {
-            error!("TODO: Too many back to back session creation requests: overlapping session create time");
-            return Err(SessionCreateError::OverlappingCreationTime)
+    #[tokio::test]
+    async fn search_counts_the_kind_of_slot_asked_for() {
+        for matchmaking in matchmakings() {
+            let public_only = create(&matchmaking, 1, 2, 0, vec![]).await;
+            let private_only = create(&matchmaking, 2, 0, 2, vec![]).await;
+            let both = create(&matchmaking, 3, 1, 3, vec![]).await;
+
+            assert_eq!(search_slots(&matchmaking, 1, Slots::Public).await, vec![public_only, both]);
+            assert_eq!(search_slots(&matchmaking, 2, Slots::Public).await, vec![public_only]);
+            assert_eq!(search_slots(&matchmaking, 1, Slots::Private).await, vec![private_only, both]);
+            assert_eq!(search_slots(&matchmaking, 3, Slots::Private).await, vec![both]);
         }
+    }
 
-        self.open_sessions.insert(creation_time, session_id);
-        self.sessions.insert(session_id, session);
+    #[tokio::test]
+    async fn search_results_are_oldest_first_and_capped() {
+        for matchmaking in matchmakings() {
+            let mut created = vec![];
+            for machine in 1..=5 {
+                created.push(create(&matchmaking, machine, 1, 0, vec![]).await);
+            }
 
-        Ok(CreatedSession {
-            session_id,
-            key_exchange_key,
-        })
+            assert_eq!(search(&matchmaking, TITLE, 1, &[]).await, created);
+            assert_eq!(search_with(&matchmaking, 2, TITLE, 1, &[]).await, created[..2]);
+        }
     }
 
-    pub async fn update_session(
-        &mut self,
-        users: Users,
-        title: Title,
-        session_id: KeyId,
-        host_address: Addr,
-        public_open: u32,
-        private_open: u32,
-        public_filled: u32,
-        private_filled: u32,
-        attributes: Vec<SearchAttribute>,
-    ) -> Result<CreatedSession, SessionUpdateError> {
-        let existing_session = self.sessions.get_mut(&session_id)
-            .ok_or(SessionUpdateError::SessionIdNotFound)?;
+    #[tokio::test]
+    async fn search_results_are_ranked_and_carry_the_logging_threshold() {
+        for matchmaking in matchmakings() {
+            let empty = create(&matchmaking, 1, 4, 0, vec![]).await;
+            let half_full = matchmaking.create_session(users(2), 2, TITLE, host_address(2), 4, 0, 4, 0, vec![])
+                .await
+                .unwrap()
+                .session_id;
 
-        if existing_session.host_users != users {
-            return Err(SessionUpdateError::UserMismatch);
+            let search = matchmaking.search_for_sessions(users(0xFF), None, TITLE, 2, 1, Slots::Public, &[]).await.unwrap();
+            let ids: Vec<_> = search.results.iter().map(|result| result.session_id).collect();
+
+            assert_eq!(ids, vec![half_full, empty]);
+            assert_eq!(search.logging_threshold, 50);
         }
-        if existing_session.title != title {
-            return Err(SessionUpdateError::TitleMismatch);
+    }
+
+    #[tokio::test]
+    async fn search_ranks_only_the_oldest_candidates() {
+        for matchmaking in matchmakings() {
+            // Procedure 2 returns two results, out of eight candidates.
+            for machine in 1..=7 {
+                create(&matchmaking, machine, 4, 0, vec![]).await;
+            }
+
+            let half_full = matchmaking.create_session(users(8), 8, TITLE, host_address(8), 4, 0, 4, 0, vec![])
+                .await
+                .unwrap()
+                .session_id;
+
+            std::thread::sleep(std::time::Duration::from_millis(2));
+
+            let too_new = matchmaking.create_session(users(9), 9, TITLE, host_address(9), 4, 0, 4, 0, vec![])
+                .await
+                .unwrap()
+                .session_id;
+
+            let results = search_with(&matchmaking, 2, TITLE, 1, &[]).await;
+
+            assert_eq!(results[0], half_full);
+            assert!(!results.contains(&too_new));
         }
-        if existing_session.host_address != host_address {
-            return Err(SessionUpdateError::HostAddressMismatch);
+    }
+
+    #[tokio::test]
+    async fn search_skips_the_searchers_own_sessions() {
+        for matchmaking in matchmakings() {
+            create(&matchmaking, 0xFF, 4, 0, vec![]).await;
+            let other = create(&matchmaking, 1, 4, 0, vec![]).await;
+
+            assert_eq!(search(&matchmaking, TITLE, 1, &[]).await, vec![other]);
         }
+    }
 
-        existing_session.public_open = public_open;
-        existing_session.private_open = private_open;
-        existing_session.public_filled = public_filled;
-        existing_session.private_filled = private_filled;
-        existing_session.attributes = attributes;
+    #[tokio::test]
+    async fn search_requires_a_known_procedure() {
+        for matchmaking in matchmakings() {
+            let search = matchmaking.search_for_sessions(users(0xFF), None, TITLE, 3, 0, Slots::Public, &[]).await;
+            assert!(matches!(search, Err(SessionSearchError::UnknownProcedure)));
+        }
+    }
 
-        Ok(CreatedSession {
-            session_id: existing_session.session_id,
-            key_exchange_key: existing_session.key_exchange_key,
-        })
+    #[tokio::test]
+    async fn update_session_requires_the_host() {
+        for matchmaking in matchmakings() {
+            let session_id = create(&matchmaking, 1, 4, 0, vec![]).await;
+
+            let update = |users, title, host_address| {
+                matchmaking.update_session(users, 1, title, session_id, host_address, 2, 0, 2, 0, vec![attr(1, SearchAttributeKind::Integer(7))])
+            };
+
+            assert!(matches!(update(users(2), TITLE, host_address(1)).await, Err(SessionUpdateError::UserMismatch)));
+            assert!(matches!(update(users(1), Title { id: 0, ..TITLE }, host_address(1)).await, Err(SessionUpdateError::TitleMismatch)));
+            assert!(matches!(update(users(1), TITLE, host_address(2)).await, Err(SessionUpdateError::HostAddressMismatch)));
+            assert_eq!(search(&matchmaking, TITLE, 3, &[]).await, vec![session_id]);
+
+            assert!(update(users(1), TITLE, host_address(1)).await.is_ok());
+            assert_eq!(search(&matchmaking, TITLE, 3, &[]).await, vec![]);
+            assert_eq!(search(&matchmaking, TITLE, 2, &[attr(1, SearchAttributeKind::Integer(7))]).await, vec![session_id]);
+        }
     }
 
-    #[allow(unused_variables)]
-    async fn search_for_sessions(
-        &mut self,
-        users: Users,
-        title: Title,
-        num_users: u16,
-        flags: u16,
-        max_results: usize,
-        attributes: &[SearchAttribute],
-    ) -> Result<Vec<SearchResult>, SessionSearchError> {
-        let mut results = vec![];
-
-        for (_, session) in self.sessions.iter() {
-            if title == session.title {
-                error!("TODO: compare attributes and user count");
-                results.push(SearchResult {
-                    session_id: session.session_id,
-                    host_address: session.host_address,
-                    key_exchange_key: session.key_exchange_key,
-                    public_open: session.public_open,
-                    private_open: session.private_open,
-                    public_filled: session.public_filled,
-                    private_filled: session.private_filled,
-                    attributes: session.attributes.clone()
-                })
-            }
+    #[tokio::test]
+    async fn close_session_requires_the_host() {
+        for matchmaking in matchmakings() {
+            let session_id = create(&matchmaking, 1, 4, 0, vec![]).await;
 
-            if results.len() >= max_results {
-                break;
-            }
+            assert!(matches!(matchmaking.close_session(users(2), TITLE, session_id).await, Err(SessionDeleteError::UserMismatch)));
+            assert!(matches!(matchmaking.close_session(users(1), Title { id: 0, ..TITLE }, session_id).await, Err(SessionDeleteError::TitleMismatch)));
+            assert_eq!(search(&matchmaking, TITLE, 0, &[]).await, vec![session_id]);
+
+            assert!(matchmaking.close_session(users(1), TITLE, session_id).await.is_ok());
+            assert!(matches!(matchmaking.close_session(users(1), TITLE, session_id).await, Err(SessionDeleteError::SessionIdNotFound)));
+
+            assert_eq!(search(&matchmaking, TITLE, 0, &[]).await, vec![]);
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_sessions_cannot_be_found_or_updated() {
+        let matchmaking = matchmaking_with(Arc::new(MemoryStore::new(Duration::ZERO)));
+
+        let session_id = create(&matchmaking, 1, 4, 0, vec![]).await;
+
+        assert_eq!(search(&matchmaking, TITLE, 0, &[]).await, vec![]);
+
+        let update = matchmaking.update_session(users(1), 1, TITLE, session_id, host_address(1), 4, 0, 0, 0, vec![]).await;
+        assert!(matches!(update, Err(SessionUpdateError::SessionIdNotFound)));
+    }
+
+    #[tokio::test]
+    async fn sessions_are_closed_with_their_host_machine() {
+        for matchmaking in matchmakings() {
+            let first = create(&matchmaking, 1, 4, 0, vec![]).await;
+            let other = create(&matchmaking, 2, 4, 0, vec![]).await;
+            let second = create(&matchmaking, 1, 4, 0, vec![]).await;
+
+            assert_eq!(search(&matchmaking, TITLE, 0, &[]).await, vec![first, other, second]);
+
+            assert_eq!(matchmaking.close_sessions_for_machine(Xuid(1), 1).await.unwrap(), 2);
+            assert_eq!(matchmaking.close_sessions_for_machine(Xuid(1), 1).await.unwrap(), 0);
+
+            assert_eq!(search(&matchmaking, TITLE, 0, &[]).await, vec![other]);
         }
+    }
 
-        Ok(results)
+    #[tokio::test]
+    async fn an_old_connection_closing_leaves_sessions_taken_over_by_a_new_one() {
+        for matchmaking in matchmakings() {
+            // Connection A hosts two sessions, then the console reconnects
+            // as B and keeps one of them going.
+            let (a, b) = (10, 11);
+
+            let kept = matchmaking.create_session(users(1), a, TITLE, host_address(1), 4, 0, 0, 0, vec![])
+                .await
+                .unwrap()
+                .session_id;
+            let abandoned = matchmaking.create_session(users(1), a, TITLE, host_address(1), 4, 0, 0, 0, vec![])
+                .await
+                .unwrap()
+                .session_id;
+            let created_by_b = matchmaking.create_session(users(1), b, TITLE, host_address(1), 4, 0, 0, 0, vec![])
+                .await
+                .unwrap()
+                .session_id;
+
+            matchmaking.update_session(users(1), b, TITLE, kept, host_address(1), 3, 0, 1, 0, vec![])
+                .await
+                .unwrap();
+
+            assert_eq!(matchmaking.close_sessions_for_machine(Xuid(1), a).await.unwrap(), 1);
+
+            let mut left = search(&matchmaking, TITLE, 0, &[]).await;
+            left.sort();
+            let mut expected = vec![kept, created_by_b];
+            expected.sort();
+            assert_eq!(left, expected);
+            assert!(!left.contains(&abandoned));
+
+            assert_eq!(matchmaking.close_sessions_for_machine(Xuid(1), b).await.unwrap(), 2);
+            assert_eq!(search(&matchmaking, TITLE, 0, &[]).await, vec![]);
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,560 @@
+//! Server side search procedures.
+//!
+//! Titles don't send queries, they send the index of a search procedure along
+//! with its parameters as search attributes.  The procedures themselves are
+//! data, one file per title, so that supporting a new title doesn't need a
+//! new build.  A procedure file looks like:
+//!
+//! ```text
+//! # Comments run to the end of the line
+//! procedure 1
+//!     where 0x0001 = param 0
+//!     where 0x0002 >= param 1
+//!     where 0x0003 != "private"
+//!
+//! procedure 2
+//!     where params-by-tag
+//!     max-results 25
+//!     logging-threshold 100
+//! ```
+//!
+//! Every `where` clause has to hold for a session to match.  A clause
+//! compares the session attribute with the given tag against either a
+//! literal or one of the search parameters, picked by position.  Clauses
+//! against a null parameter are skipped, so a title can leave a field as
+//! "any".  `params-by-tag` instead matches each parameter against the
+//! session attribute with the parameter's own tag.
+//!
+//! `max-results` caps how many sessions a search returns, 10 unless set.
+//! `logging-threshold` is passed on to consoles with the results, 0 unless
+//! set.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use xblive::service::matchmaking::{SearchAttribute, SearchAttributeKind};
+
+/// File extension of procedure files.  The file stem is the title id in hex.
+pub const PROCEDURE_FILE_EXTENSION: &str = "proc";
+
+pub const DEFAULT_MAX_RESULTS: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn parse(token: &str) -> Option<Op> {
+        match token {
+            "=" => Some(Op::Eq),
+            "!=" => Some(Op::Ne),
+            "<" => Some(Op::Lt),
+            "<=" => Some(Op::Le),
+            ">" => Some(Op::Gt),
+            ">=" => Some(Op::Ge),
+            _ => None,
+        }
+    }
+
+    fn holds<T: Ord + ?Sized>(&self, session_value: &T, value: &T) -> bool {
+        match self {
+            Op::Eq => session_value == value,
+            Op::Ne => session_value != value,
+            Op::Lt => session_value < value,
+            Op::Le => session_value <= value,
+            Op::Gt => session_value > value,
+            Op::Ge => session_value >= value,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Param(usize),
+    Integer(i64),
+    String(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Clause {
+    Compare {
+        attribute: u16,
+        op: Op,
+        operand: Operand,
+    },
+    ParamsByTag,
+}
+
+impl Clause {
+    fn holds(&self, session_attributes: &[SearchAttribute], params: &[SearchAttribute]) -> bool {
+        match self {
+            Clause::Compare { attribute, op, operand } => {
+                let value = match operand {
+                    Operand::Param(index) => match params.get(*index) {
+                        Some(param) => param.kind.clone(),
+                        None => return false,
+                    },
+                    Operand::Integer(i) => SearchAttributeKind::Integer(*i),
+                    Operand::String(s) => SearchAttributeKind::String(s.clone()),
+                };
+
+                let session_value = session_attributes.iter()
+                    .find(|session_attribute| session_attribute.tag == *attribute)
+                    .map(|session_attribute| &session_attribute.kind);
+
+                match (session_value, &value) {
+                    (_, SearchAttributeKind::Null) => true,
+                    (Some(SearchAttributeKind::Integer(have)), SearchAttributeKind::Integer(wanted)) => op.holds(have, wanted),
+                    (Some(SearchAttributeKind::String(have)), SearchAttributeKind::String(wanted)) => op.holds(have.as_str(), wanted.as_str()),
+                    _ => false,
+                }
+            }
+            Clause::ParamsByTag => crate::attributes_match(session_attributes, params),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchProcedure {
+    pub clauses: Vec<Clause>,
+    pub max_results: usize,
+    pub logging_threshold: u32,
+}
+
+impl Default for SearchProcedure {
+    fn default() -> Self {
+        SearchProcedure {
+            clauses: vec![],
+            max_results: DEFAULT_MAX_RESULTS,
+            logging_threshold: 0,
+        }
+    }
+}
+
+impl SearchProcedure {
+    pub fn matches(&self, session_attributes: &[SearchAttribute], params: &[SearchAttribute]) -> bool {
+        self.clauses.iter()
+            .all(|clause| clause.holds(session_attributes, params))
+    }
+
+    /// Attribute values any matching session is known to carry, so stores can
+    /// narrow down the candidates before every clause is checked.
+    pub fn required_values(&self, params: &[SearchAttribute]) -> Vec<(u16, SearchAttributeKind)> {
+        let mut required = vec![];
+
+        for clause in self.clauses.iter() {
+            match clause {
+                Clause::Compare { attribute, op: Op::Eq, operand } => {
+                    let value = match operand {
+                        Operand::Param(index) => match params.get(*index) {
+                            Some(param) => param.kind.clone(),
+                            None => continue,
+                        },
+                        Operand::Integer(i) => SearchAttributeKind::Integer(*i),
+                        Operand::String(s) => SearchAttributeKind::String(s.clone()),
+                    };
+
+                    if is_indexable(&value) {
+                        required.push((*attribute, value));
+                    }
+                }
+                Clause::Compare { .. } => {}
+                Clause::ParamsByTag => {
+                    for param in params.iter().filter(|param| is_indexable(&param.kind)) {
+                        required.push((param.tag, param.kind.clone()));
+                    }
+                }
+            }
+        }
+
+        required
+    }
+}
+
+fn is_indexable(value: &SearchAttributeKind) -> bool {
+    matches!(value, SearchAttributeKind::Integer(_) | SearchAttributeKind::String(_))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProcedureParseErrorKind {
+    ExpectedProcedure,
+    BadProcedureIndex,
+    DuplicateProcedure(u32),
+    BadAttributeTag,
+    BadOperator,
+    BadOperand,
+    BadSetting,
+    UnterminatedString,
+    TrailingTokens,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ProcedureParseError {
+    pub line: usize,
+    pub kind: ProcedureParseErrorKind,
+}
+
+#[derive(Debug)]
+pub enum ProcedureLoadError {
+    Io(io::Error),
+    BadFileName(String),
+    Parse(String, ProcedureParseError),
+}
+
+impl From<io::Error> for ProcedureLoadError {
+    fn from(err: io::Error) -> Self {
+        ProcedureLoadError::Io(err)
+    }
+}
+
+/// Every search procedure known to the matchmaking service, keyed by title id
+/// and procedure index.
+#[derive(Debug, Default)]
+pub struct Procedures {
+    procedures: BTreeMap<(u32, u32), SearchProcedure>,
+}
+
+impl Procedures {
+    pub fn new() -> Self {
+        Procedures {
+            procedures: BTreeMap::new(),
+        }
+    }
+
+    /// Load every procedure file in dir.
+    pub fn load_dir(dir: &Path) -> Result<Self, ProcedureLoadError> {
+        let mut procedures = Procedures::new();
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+
+            if path.extension().and_then(|ext| ext.to_str()) != Some(PROCEDURE_FILE_EXTENSION) {
+                continue;
+            }
+
+            let file_name = path.display().to_string();
+
+            let title_id = path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| u32::from_str_radix(stem, 16).ok())
+                .ok_or_else(|| ProcedureLoadError::BadFileName(file_name.clone()))?;
+
+            let source = fs::read_to_string(&path)?;
+
+            procedures.add_title(title_id, &source)
+                .map_err(|err| ProcedureLoadError::Parse(file_name, err))?;
+        }
+
+        Ok(procedures)
+    }
+
+    /// Parse the procedures of a single title and add them.
+    pub fn add_title(&mut self, title_id: u32, source: &str) -> Result<(), ProcedureParseError> {
+        for (index, procedure) in parse_procedures(source)? {
+            self.procedures.insert((title_id, index), procedure);
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, title_id: u32, index: u32) -> Option<&SearchProcedure> {
+        self.procedures.get(&(title_id, index))
+    }
+
+    pub fn len(&self) -> usize {
+        self.procedures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.procedures.is_empty()
+    }
+}
+
+pub fn parse_procedures(source: &str) -> Result<BTreeMap<u32, SearchProcedure>, ProcedureParseError> {
+    use ProcedureParseErrorKind::*;
+
+    let mut procedures = BTreeMap::new();
+    let mut current: Option<(u32, SearchProcedure)> = None;
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let err = |kind| ProcedureParseError { line: line_number, kind };
+
+        let tokens = tokenize(line).map_err(err)?;
+
+        match tokens.first().map(|token| token.as_str()) {
+            None => continue,
+            Some("procedure") => {
+                if tokens.len() != 2 {
+                    return Err(err(TrailingTokens));
+                }
+
+                let index = parse_u32(&tokens[1]).ok_or(err(BadProcedureIndex))?;
+                if procedures.contains_key(&index) || matches!(current, Some((current_index, _)) if current_index == index) {
+                    return Err(err(DuplicateProcedure(index)));
+                }
+
+                if let Some((index, procedure)) = current.take() {
+                    procedures.insert(index, procedure);
+                }
+
+                current = Some((index, SearchProcedure::default()));
+            }
+            Some("where") => {
+                let procedure = match current.as_mut() {
+                    Some((_, procedure)) => procedure,
+                    None => return Err(err(ExpectedProcedure)),
+                };
+
+                let clause = parse_clause(&tokens[1..]).map_err(err)?;
+                procedure.clauses.push(clause);
+            }
+            Some(setting @ ("max-results" | "logging-threshold")) => {
+                let procedure = match current.as_mut() {
+                    Some((_, procedure)) => procedure,
+                    None => return Err(err(ExpectedProcedure)),
+                };
+
+                let value = match &tokens[1..] {
+                    [value] => parse_u32(value).ok_or(err(BadSetting))?,
+                    [] => return Err(err(BadSetting)),
+                    _ => return Err(err(TrailingTokens)),
+                };
+
+                match setting {
+                    "max-results" => procedure.max_results = value as usize,
+                    _ => procedure.logging_threshold = value,
+                }
+            }
+            Some(_) => return Err(err(ExpectedProcedure)),
+        }
+    }
+
+    if let Some((index, procedure)) = current.take() {
+        procedures.insert(index, procedure);
+    }
+
+    Ok(procedures)
+}
+
+fn parse_clause(tokens: &[String]) -> Result<Clause, ProcedureParseErrorKind> {
+    use ProcedureParseErrorKind::*;
+
+    match tokens {
+        [keyword] if keyword == "params-by-tag" => Ok(Clause::ParamsByTag),
+        [attribute, op, operand @ ..] => {
+            let attribute = parse_u32(attribute)
+                .and_then(|attribute| u16::try_from(attribute).ok())
+                .ok_or(BadAttributeTag)?;
+            let op = Op::parse(op).ok_or(BadOperator)?;
+
+            let operand = match operand {
+                [keyword, index] if keyword == "param" => {
+                    Operand::Param(parse_u32(index).ok_or(BadOperand)? as usize)
+                }
+                [literal] if literal.starts_with('"') => {
+                    Operand::String(literal[1..].to_string())
+                }
+                [literal] => {
+                    Operand::Integer(parse_i64(literal).ok_or(BadOperand)?)
+                }
+                [] => return Err(BadOperand),
+                _ => return Err(TrailingTokens),
+            };
+
+            Ok(Clause::Compare { attribute, op, operand })
+        }
+        _ => Err(BadOperand),
+    }
+}
+
+/// Split a line into whitespace separated tokens, dropping comments.  String
+/// literals are kept as a single token with their leading quote so they can
+/// be told apart from bare words.
+fn tokenize(line: &str) -> Result<Vec<String>, ProcedureParseErrorKind> {
+    let mut tokens = vec![];
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => break,
+            c if c.is_whitespace() => continue,
+            '"' => {
+                let mut token = String::from('"');
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => token.push(c),
+                        None => return Err(ProcedureParseErrorKind::UnterminatedString),
+                    }
+                }
+                tokens.push(token);
+            }
+            c => {
+                let mut token = String::from(c);
+                while let Some(c) = chars.peek() {
+                    if c.is_whitespace() || *c == '#' {
+                        break;
+                    }
+                    token.push(*c);
+                    chars.next();
+                }
+                tokens.push(token);
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse_u32(token: &str) -> Option<u32> {
+    match token.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => token.parse().ok(),
+    }
+}
+
+fn parse_i64(token: &str) -> Option<i64> {
+    let (negative, token) = match token.strip_prefix('-') {
+        Some(token) => (true, token),
+        None => (false, token),
+    };
+
+    let value = match token.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => token.parse().ok()?,
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attr(tag: u16, kind: SearchAttributeKind) -> SearchAttribute {
+        SearchAttribute { tag, kind }
+    }
+
+    const SOURCE: &str = r#"
+# Ranked and social playlists
+procedure 1
+    where 0x0001 = param 0      # game mode
+    where 0x0002 >= param 1
+    where 0x0003 != "private"
+
+procedure 0x2
+    where params-by-tag
+    max-results 25
+    logging-threshold 100
+"#;
+
+    #[test]
+    fn parses_procedures() {
+        let procedures = parse_procedures(SOURCE).unwrap();
+
+        assert_eq!(procedures.len(), 2);
+        assert_eq!(procedures[&1].clauses, vec![
+            Clause::Compare { attribute: 1, op: Op::Eq, operand: Operand::Param(0) },
+            Clause::Compare { attribute: 2, op: Op::Ge, operand: Operand::Param(1) },
+            Clause::Compare { attribute: 3, op: Op::Ne, operand: Operand::String(String::from("private")) },
+        ]);
+        assert_eq!(procedures[&2].clauses, vec![Clause::ParamsByTag]);
+
+        assert_eq!((procedures[&1].max_results, procedures[&1].logging_threshold), (DEFAULT_MAX_RESULTS, 0));
+        assert_eq!((procedures[&2].max_results, procedures[&2].logging_threshold), (25, 100));
+    }
+
+    #[test]
+    fn procedure_clauses_compare_params() {
+        let procedures = parse_procedures(SOURCE).unwrap();
+        let procedure = &procedures[&1];
+
+        let session = [
+            attr(1, SearchAttributeKind::Integer(2)),
+            attr(2, SearchAttributeKind::Integer(10)),
+            attr(3, SearchAttributeKind::String(String::from("public"))),
+        ];
+
+        let params = |mode, level| [attr(0, mode), attr(0, level)];
+
+        assert!(procedure.matches(&session, &params(SearchAttributeKind::Integer(2), SearchAttributeKind::Integer(10))));
+        assert!(procedure.matches(&session, &params(SearchAttributeKind::Integer(2), SearchAttributeKind::Integer(5))));
+        assert!(!procedure.matches(&session, &params(SearchAttributeKind::Integer(2), SearchAttributeKind::Integer(11))));
+        assert!(!procedure.matches(&session, &params(SearchAttributeKind::Integer(3), SearchAttributeKind::Integer(5))));
+
+        // A null parameter means "any".
+        assert!(procedure.matches(&session, &params(SearchAttributeKind::Null, SearchAttributeKind::Null)));
+
+        // Missing parameters never match.
+        assert!(!procedure.matches(&session, &[attr(0, SearchAttributeKind::Integer(2))]));
+
+        let private_session = [
+            attr(1, SearchAttributeKind::Integer(2)),
+            attr(2, SearchAttributeKind::Integer(10)),
+            attr(3, SearchAttributeKind::String(String::from("private"))),
+        ];
+        assert!(!procedure.matches(&private_session, &params(SearchAttributeKind::Null, SearchAttributeKind::Null)));
+    }
+
+    #[test]
+    fn required_values_come_from_equality_clauses() {
+        let procedures = parse_procedures(SOURCE).unwrap();
+
+        let params = [
+            attr(7, SearchAttributeKind::Integer(2)),
+            attr(8, SearchAttributeKind::Integer(10)),
+        ];
+        assert_eq!(procedures[&1].required_values(&params), vec![(1, SearchAttributeKind::Integer(2))]);
+        assert_eq!(procedures[&2].required_values(&params), params.iter().map(|param| (param.tag, param.kind.clone())).collect::<Vec<_>>());
+
+        let any_params = [
+            attr(7, SearchAttributeKind::Null),
+            attr(8, SearchAttributeKind::Integer(10)),
+        ];
+        assert_eq!(procedures[&1].required_values(&any_params), vec![]);
+        assert_eq!(procedures[&2].required_values(&any_params), vec![(8, SearchAttributeKind::Integer(10))]);
+    }
+
+    #[test]
+    fn reports_parse_errors_with_line_numbers() {
+        use ProcedureParseErrorKind::*;
+
+        let cases = [
+            ("where 1 = 2", 1, ExpectedProcedure),
+            ("procedure 1\nprocedure 1", 2, DuplicateProcedure(1)),
+            ("procedure x", 1, BadProcedureIndex),
+            ("procedure 1\n    where 0x10000 = 1", 2, BadAttributeTag),
+            ("procedure 1\n    where 1 ~ 1", 2, BadOperator),
+            ("procedure 1\n    where 1 = param", 2, BadOperand),
+            ("procedure 1\n    where 1 = \"open", 2, UnterminatedString),
+            ("procedure 1 2", 1, TrailingTokens),
+            ("max-results 5", 1, ExpectedProcedure),
+            ("procedure 1\n    max-results", 2, BadSetting),
+            ("procedure 1\n    logging-threshold -1", 2, BadSetting),
+            ("procedure 1\n    max-results 5 6", 2, TrailingTokens),
+        ];
+
+        for (source, line, kind) in cases {
+            assert_eq!(parse_procedures(source), Err(ProcedureParseError { line, kind }), "{}", source);
+        }
+    }
+
+    #[test]
+    fn procedures_are_keyed_by_title() {
+        let mut procedures = Procedures::new();
+        procedures.add_title(0x4C41000B, SOURCE).unwrap();
+
+        assert!(procedures.get(0x4C41000B, 1).is_some());
+        assert!(procedures.get(0x4C41000B, 3).is_none());
+        assert!(procedures.get(0x4D530004, 1).is_none());
+    }
+}
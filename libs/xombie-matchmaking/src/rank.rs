@@ -0,0 +1,244 @@
+//! Ordering search results so consoles probe the most promising hosts first.
+//!
+//! Consoles QoS probe the hosts they're handed in order, and usually join the
+//! first that answers well enough, so the order matters more than the exact
+//! set of results.  Sessions are ranked on what the server knows without
+//! probing anything itself:
+//!
+//! * whether the searcher and host can reach each other through their NATs,
+//! * whether the host registered through an SG in the searcher's region,
+//! * how full the session is, as fuller sessions start sooner,
+//! * and finally age, oldest first, so equally good sessions keep a stable
+//!   order.
+
+use std::cmp::Reverse;
+use std::time::SystemTime;
+
+use xblive::addr::Addr;
+
+use crate::Session;
+
+/// How reachable a console is from the rest of the internet, as far as can
+/// be told from the address it reports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NatType {
+    /// Not behind a NAT at all.
+    Open,
+    /// Behind a NAT that kept the XNet port, so it's most likely forwarded or
+    /// mapped consistently.
+    Moderate,
+    /// Behind a NAT that rewrote the XNet port.
+    Strict,
+}
+
+impl NatType {
+    pub fn of(addr: &Addr) -> NatType {
+        if addr.addr == addr.addr_online {
+            NatType::Open
+        } else if addr.port_online == xblive::sg::UDP_PORT {
+            NatType::Moderate
+        } else {
+            NatType::Strict
+        }
+    }
+
+    /// How hard it'll be for two consoles to connect, 0 being no trouble at
+    /// all.  An open console can reach anyone, two strict ones usually can't
+    /// reach each other at all.
+    fn connection_difficulty(self, other: NatType) -> u8 {
+        match (self, other) {
+            (NatType::Open, _) | (_, NatType::Open) => 0,
+            (NatType::Moderate, NatType::Moderate) => 1,
+            (NatType::Moderate, NatType::Strict) | (NatType::Strict, NatType::Moderate) => 2,
+            (NatType::Strict, NatType::Strict) => 3,
+        }
+    }
+}
+
+/// Who's searching, as far as ranking is concerned.
+#[derive(Clone, Debug, Default)]
+pub struct SearcherInfo<'a> {
+    pub address: Option<Addr>,
+    pub region: Option<&'a str>,
+}
+
+type RankKey = (u8, bool, Reverse<u64>, SystemTime);
+
+fn rank_key(session: &Session, searcher: &SearcherInfo<'_>) -> RankKey {
+    let host_nat = NatType::of(&session.host_address);
+
+    // Without the searcher's address, assume it's behind a typical NAT.
+    let searcher_nat = searcher.address
+        .as_ref()
+        .map(NatType::of)
+        .unwrap_or(NatType::Moderate);
+
+    let other_region = session.region.as_deref() != searcher.region;
+
+    (
+        host_nat.connection_difficulty(searcher_nat),
+        other_region,
+        Reverse(fill_per_mille(session)),
+        session.creation_time,
+    )
+}
+
+/// How much of the public side of a session is taken, in thousandths.
+fn fill_per_mille(session: &Session) -> u64 {
+    let filled = session.public_filled as u64;
+    let slots = filled + session.public_open as u64;
+
+    if slots == 0 {
+        return 0
+    }
+
+    filled * 1000 / slots
+}
+
+/// Sort sessions best first for searcher.
+pub fn rank(sessions: &mut [Session], searcher: &SearcherInfo<'_>) {
+    sessions.sort_by_cached_key(|session| rank_key(session, searcher));
+}
+
+/// The best count of sessions for searcher, best first.  Only those are
+/// sorted, so picking a few out of many matches stays cheap.  Sessions that
+/// rank equal keep the order they were given in.
+pub fn best(sessions: Vec<Session>, searcher: &SearcherInfo<'_>, count: usize) -> Vec<Session> {
+    if count == 0 {
+        return vec![]
+    }
+
+    let mut keyed: Vec<_> = sessions.into_iter()
+        .enumerate()
+        .map(|(index, session)| ((rank_key(&session, searcher), index), session))
+        .collect();
+
+    if count < keyed.len() {
+        keyed.select_nth_unstable_by(count - 1, |(a, _), (b, _)| a.cmp(b));
+        keyed.truncate(count);
+    }
+
+    keyed.sort_unstable_by_key(|(key, _)| *key);
+
+    keyed.into_iter()
+        .map(|(_, session)| session)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use xblive::crypto::primitives::KeyId;
+    use xblive::net::{Eui48, InAddr};
+
+    use xbox_sys::account::Xuid;
+    use xbox_sys::crypto::SymmetricKey;
+
+    use crate::{Title, Users};
+
+    use super::*;
+
+    fn address(addr: [u8; 4], addr_online: [u8; 4], port_online: u16) -> Addr {
+        Addr {
+            addr: InAddr(addr),
+            addr_online: InAddr(addr_online),
+            port_online,
+            enet: Eui48([0, 0x50, 0xF2, 0, 0, 1]),
+            online: [0; 20],
+        }
+    }
+
+    fn open() -> Addr {
+        address([203, 0, 113, 7], [203, 0, 113, 7], 3074)
+    }
+
+    fn moderate() -> Addr {
+        address([192, 168, 0, 2], [198, 51, 100, 2], 3074)
+    }
+
+    fn strict() -> Addr {
+        address([192, 168, 0, 3], [198, 51, 100, 3], 41822)
+    }
+
+    fn session(id: u8, host_address: Addr, region: Option<&str>, public_filled: u32, public_open: u32, age_secs: u64) -> Session {
+        let creation_time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000 - age_secs);
+
+        Session {
+            session_id: KeyId([id; 8]),
+            key_exchange_key: SymmetricKey([id; 16]),
+            title: Title { id: 0x4C41000B, ver: 0x00010000 },
+            host_users: Users {
+                machine: Xuid(id as u64),
+                user: vec![],
+            },
+            host_connection: id as u64,
+            host_address,
+            region: region.map(String::from),
+            public_open,
+            private_open: 0,
+            public_filled,
+            private_filled: 0,
+            attributes: vec![],
+            creation_time,
+            last_refresh: creation_time,
+        }
+    }
+
+    fn ranked(mut sessions: Vec<Session>, searcher: SearcherInfo<'_>) -> Vec<u8> {
+        rank(&mut sessions, &searcher);
+
+        sessions.iter()
+            .map(|session| session.session_id.0[0])
+            .collect()
+    }
+
+    #[test]
+    fn nat_type_comes_from_the_address() {
+        assert_eq!(NatType::of(&open()), NatType::Open);
+        assert_eq!(NatType::of(&moderate()), NatType::Moderate);
+        assert_eq!(NatType::of(&strict()), NatType::Strict);
+    }
+
+    #[test]
+    fn reachable_hosts_come_first() {
+        let sessions = vec![
+            session(1, strict(), None, 0, 8, 30),
+            session(2, moderate(), None, 0, 8, 20),
+            session(3, open(), None, 0, 8, 10),
+        ];
+
+        let searcher = |address| SearcherInfo { address: Some(address), region: None };
+
+        assert_eq!(ranked(sessions.clone(), searcher(strict())), vec![3, 2, 1]);
+        assert_eq!(ranked(sessions.clone(), searcher(moderate())), vec![3, 2, 1]);
+
+        // Anyone can reach an open searcher, so it's down to age.
+        assert_eq!(ranked(sessions, searcher(open())), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn same_region_hosts_come_before_fuller_sessions() {
+        let sessions = vec![
+            session(1, moderate(), Some("us-west"), 7, 1, 30),
+            session(2, moderate(), Some("eu"), 1, 7, 20),
+            session(3, moderate(), Some("eu"), 4, 4, 10),
+        ];
+
+        let searcher = SearcherInfo { address: Some(moderate()), region: Some("eu") };
+
+        assert_eq!(ranked(sessions, searcher), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn equally_good_sessions_stay_oldest_first() {
+        let sessions = vec![
+            session(1, moderate(), None, 0, 0, 10),
+            session(2, moderate(), None, 2, 2, 20),
+            session(3, moderate(), None, 0, 0, 30),
+            session(4, moderate(), None, 2, 2, 40),
+        ];
+
+        assert_eq!(ranked(sessions, SearcherInfo::default()), vec![4, 2, 3, 1]);
+    }
+}
@@ -0,0 +1,353 @@
+//! Where matchmaking sessions live.
+//!
+//! Matchmaking decides who may create, update, close and find sessions; a
+//! SessionStore only keeps them.  The in memory store is lost on restart and
+//! private to one SG, while the Redis store can be shared by every SG node.
+//!
+//! Stores are responsible for expiry: a session that hasn't been refreshed
+//! within the store's session ttl must no longer be returned, whether or not
+//! it's been reaped yet.
+
+use async_trait::async_trait;
+
+use std::time::SystemTime;
+
+use xblive::crypto::primitives::KeyId;
+use xblive::service::matchmaking::SearchAttributeKind;
+
+use xbox_sys::account::Xuid;
+
+use crate::{Session, Title};
+
+pub mod memory;
+pub mod redis;
+
+#[derive(Debug)]
+pub enum StoreError {
+    Redis(::redis::RedisError),
+    CorruptSession(String),
+}
+
+impl From<::redis::RedisError> for StoreError {
+    fn from(err: ::redis::RedisError) -> Self {
+        StoreError::Redis(err)
+    }
+}
+
+/// Decides whether a search should return a session, beyond it having the
+/// required attribute values.
+pub type SessionFilter<'a> = dyn Fn(&Session) -> bool + Send + Sync + 'a;
+
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    /// Add a new session.  Returns false, leaving the store untouched, if a
+    /// session with the same id already exists.
+    async fn insert(&self, session: Session) -> Result<bool, StoreError>;
+
+    async fn get(&self, session_id: KeyId) -> Result<Option<Session>, StoreError>;
+
+    /// Overwrite an existing session, restarting its ttl.  Returns false,
+    /// leaving the store untouched, if the session no longer exists.
+    async fn replace(&self, session: Session) -> Result<bool, StoreError>;
+
+    async fn remove(&self, session_id: KeyId) -> Result<Option<Session>, StoreError>;
+
+    /// Remove a session, but only if it's still hosted through connection.
+    async fn remove_hosted_through(&self, session_id: KeyId, connection: u64) -> Result<Option<Session>, StoreError>;
+
+    /// Every live session for title, oldest first.
+    async fn sessions_for_title(&self, title: Title) -> Result<Vec<Session>, StoreError>;
+
+    /// The oldest max_results live sessions for title that carry every one of
+    /// the required attribute values and pass filter.  Stores with indexes
+    /// on attributes can skip most of a busy title's sessions.
+    async fn search(
+        &self,
+        title: Title,
+        required: &[(u16, SearchAttributeKind)],
+        filter: &SessionFilter<'_>,
+        max_results: usize,
+    ) -> Result<Vec<Session>, StoreError> {
+        Ok(self.sessions_for_title(title)
+            .await?
+            .into_iter()
+            .filter(|session| has_required_values(session, required) && filter(session))
+            .take(max_results)
+            .collect())
+    }
+
+    async fn sessions_for_machine(&self, machine: Xuid) -> Result<Vec<KeyId>, StoreError>;
+
+    /// Drop whatever expired sessions are still being held on to.  Returns
+    /// how many sessions were dropped.
+    async fn reap_expired(&self, now: SystemTime) -> Result<usize, StoreError>;
+}
+
+pub fn has_required_values(session: &Session, required: &[(u16, SearchAttributeKind)]) -> bool {
+    required.iter().all(|(tag, value)| {
+        session.attributes.iter()
+            .any(|attribute| attribute.tag == *tag && attribute.kind == *value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use xblive::addr::Addr;
+    use xblive::net::{Eui48, InAddr};
+    use xblive::service::matchmaking::{SearchAttribute, SearchAttributeKind};
+
+    use xbox_sys::crypto::SymmetricKey;
+
+    use crate::Users;
+
+    use super::memory::MemoryStore;
+    use super::redis::RedisStore;
+    use super::redis::fake::FakeRedis;
+    use super::*;
+
+    const TITLE: Title = Title { id: 0x4D530004, ver: 0x00000102 };
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn session(id: u8, machine: u64, title: Title, creation_time: SystemTime) -> Session {
+        Session {
+            session_id: KeyId([id; 8]),
+            key_exchange_key: SymmetricKey([id; 16]),
+            title,
+            host_users: Users {
+                machine: Xuid(machine),
+                user: vec![Xuid(0x0009_0000_0000_0001), Xuid(0x0009_0000_0000_0002)],
+            },
+            host_connection: 0x1234_5678_9abc_def0,
+            host_address: Addr {
+                addr: InAddr([10, 0, 0, id]),
+                addr_online: InAddr([192, 168, 0, id]),
+                port_online: 1000,
+                enet: Eui48([0, 0x50, 0xF2, 0, 0, id]),
+                online: [id; 20],
+            },
+            region: Some(String::from("eu-west")),
+            public_open: 4,
+            private_open: 2,
+            public_filled: 1,
+            private_filled: 0,
+            attributes: vec![
+                SearchAttribute { tag: 1, kind: SearchAttributeKind::Integer(-3) },
+                SearchAttribute { tag: 2, kind: SearchAttributeKind::String(String::from("Zanzibar")) },
+                SearchAttribute { tag: 3, kind: SearchAttributeKind::Null },
+            ],
+            creation_time,
+            last_refresh: creation_time,
+        }
+    }
+
+    /// The current time truncated to whole milliseconds, as that's all the
+    /// Redis store keeps.
+    fn now_millis() -> SystemTime {
+        let since_epoch = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+
+        SystemTime::UNIX_EPOCH + Duration::from_millis(since_epoch.as_millis() as u64)
+    }
+
+    fn ids(sessions: Vec<Session>) -> Vec<KeyId> {
+        sessions.iter().map(|session| session.session_id).collect()
+    }
+
+    async fn exercise(store: Arc<dyn SessionStore>) {
+        let base = now_millis();
+        let at = |millis| base + Duration::from_millis(millis);
+
+        let first = session(1, 1, TITLE, at(300));
+        let second = session(2, 2, TITLE, at(100));
+        let other_title = session(3, 1, Title { ver: 0x103, ..TITLE }, at(200));
+
+        assert!(store.insert(first.clone()).await.unwrap());
+        assert!(store.insert(second.clone()).await.unwrap());
+        assert!(store.insert(other_title.clone()).await.unwrap());
+        assert!(!store.insert(session(1, 5, TITLE, at(400))).await.unwrap());
+
+        assert_eq!(store.get(first.session_id).await.unwrap(), Some(first.clone()));
+        assert_eq!(store.get(KeyId([9; 8])).await.unwrap(), None);
+
+        assert_eq!(ids(store.sessions_for_title(TITLE).await.unwrap()), vec![second.session_id, first.session_id]);
+
+        let mut machine_sessions = store.sessions_for_machine(Xuid(1)).await.unwrap();
+        machine_sessions.sort();
+        assert_eq!(machine_sessions, vec![first.session_id, other_title.session_id]);
+
+        let mut updated = first.clone();
+        updated.public_open = 0;
+        updated.attributes.clear();
+        updated.last_refresh = at(500);
+        assert!(store.replace(updated.clone()).await.unwrap());
+        assert_eq!(store.get(first.session_id).await.unwrap(), Some(updated.clone()));
+        assert_eq!(ids(store.sessions_for_title(TITLE).await.unwrap()), vec![second.session_id, first.session_id]);
+
+        assert_eq!(store.remove(first.session_id).await.unwrap(), Some(updated.clone()));
+        assert_eq!(store.remove(first.session_id).await.unwrap(), None);
+        assert!(!store.replace(updated).await.unwrap());
+        assert_eq!(store.get(first.session_id).await.unwrap(), None);
+        assert_eq!(ids(store.sessions_for_title(TITLE).await.unwrap()), vec![second.session_id]);
+        assert_eq!(store.sessions_for_machine(Xuid(1)).await.unwrap(), vec![other_title.session_id]);
+    }
+
+    async fn exercise_search(store: Arc<dyn SessionStore>) {
+        let base = now_millis();
+        let at = |millis| base + Duration::from_millis(millis);
+
+        let zanzibar = (2, SearchAttributeKind::String(String::from("Zanzibar")));
+        let ascension = (2, SearchAttributeKind::String(String::from("Ascension")));
+        let everything = |_: &Session| true;
+
+        let first = session(1, 1, TITLE, at(300));
+        let second = session(2, 2, TITLE, at(100));
+        let mut third = session(3, 3, TITLE, at(200));
+        third.attributes[1].kind = ascension.1.clone();
+
+        for session in [&first, &second, &third] {
+            store.insert(session.clone()).await.unwrap();
+        }
+
+        let search = |required: Vec<(u16, SearchAttributeKind)>, max_results| {
+            let store = store.clone();
+            async move {
+                ids(store.search(TITLE, &required, &everything, max_results).await.unwrap())
+            }
+        };
+
+        assert_eq!(search(vec![], 10).await, vec![second.session_id, third.session_id, first.session_id]);
+        assert_eq!(search(vec![zanzibar.clone()], 10).await, vec![second.session_id, first.session_id]);
+        assert_eq!(search(vec![zanzibar.clone()], 1).await, vec![second.session_id]);
+        assert_eq!(search(vec![zanzibar.clone(), (1, SearchAttributeKind::Integer(-3))], 10).await, vec![second.session_id, first.session_id]);
+        assert_eq!(search(vec![zanzibar.clone(), (1, SearchAttributeKind::Integer(4))], 10).await, vec![]);
+
+        let not_second = |session: &Session| session.session_id != second.session_id;
+        assert_eq!(ids(store.search(TITLE, std::slice::from_ref(&zanzibar), &not_second, 10).await.unwrap()), vec![first.session_id]);
+
+        // Changing a session's attributes moves it between indexes.
+        let mut moved = first.clone();
+        moved.attributes[1].kind = ascension.1.clone();
+        store.replace(moved).await.unwrap();
+
+        assert_eq!(search(vec![zanzibar.clone()], 10).await, vec![second.session_id]);
+        assert_eq!(search(vec![ascension.clone()], 10).await, vec![third.session_id, first.session_id]);
+
+        store.remove(third.session_id).await.unwrap();
+        assert_eq!(search(vec![ascension], 10).await, vec![first.session_id]);
+    }
+
+    #[tokio::test]
+    async fn memory_store_search() {
+        exercise_search(Arc::new(MemoryStore::new(TTL))).await;
+    }
+
+    #[tokio::test]
+    async fn redis_store_search() {
+        exercise_search(Arc::new(RedisStore::new(FakeRedis::new(), TTL))).await;
+    }
+
+    #[tokio::test]
+    async fn memory_store() {
+        exercise(Arc::new(MemoryStore::new(TTL))).await;
+    }
+
+    #[tokio::test]
+    async fn redis_store() {
+        exercise(Arc::new(RedisStore::new(FakeRedis::new(), TTL))).await;
+    }
+
+    #[tokio::test]
+    async fn memory_store_expires_sessions() {
+        let store = MemoryStore::new(TTL);
+
+        let now = SystemTime::now();
+        let stale = session(1, 1, TITLE, now - TTL);
+        let fresh = session(2, 1, TITLE, now);
+
+        store.insert(stale.clone()).await.unwrap();
+        store.insert(fresh.clone()).await.unwrap();
+
+        assert_eq!(store.get(stale.session_id).await.unwrap(), None);
+        assert_eq!(ids(store.sessions_for_title(TITLE).await.unwrap()), vec![fresh.session_id]);
+
+        assert_eq!(store.reap_expired(now).await.unwrap(), 1);
+        assert_eq!(store.reap_expired(now).await.unwrap(), 0);
+        assert_eq!(store.sessions_for_machine(Xuid(1)).await.unwrap(), vec![fresh.session_id]);
+
+        assert_eq!(store.reap_expired(now + TTL).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn redis_store_expires_sessions() {
+        let redis = FakeRedis::new();
+        let store = RedisStore::new(redis.clone(), TTL);
+
+        let stale = session(1, 1, TITLE, now_millis());
+        store.insert(stale.clone()).await.unwrap();
+
+        redis.advance(TTL / 2);
+
+        let fresh = session(2, 1, TITLE, now_millis() + Duration::from_millis(1));
+        store.insert(fresh.clone()).await.unwrap();
+
+        assert_eq!(store.reap_expired(SystemTime::now()).await.unwrap(), 0);
+
+        redis.advance(TTL / 2);
+
+        assert_eq!(store.get(stale.session_id).await.unwrap(), None);
+        assert_eq!(ids(store.sessions_for_title(TITLE).await.unwrap()), vec![fresh.session_id]);
+        assert_eq!(store.sessions_for_machine(Xuid(1)).await.unwrap(), vec![fresh.session_id]);
+
+        // The indexes were already cleaned up as the expired session was
+        // skipped over above.
+        assert_eq!(store.reap_expired(SystemTime::now()).await.unwrap(), 0);
+
+        redis.advance(TTL);
+
+        assert_eq!(store.reap_expired(SystemTime::now()).await.unwrap(), 1);
+        assert!(redis.is_empty());
+    }
+
+    #[tokio::test]
+    async fn redis_store_does_not_bring_expired_sessions_back() {
+        let redis = FakeRedis::new();
+        let store = RedisStore::new(redis.clone(), TTL);
+
+        let expired = session(1, 1, TITLE, now_millis());
+        store.insert(expired.clone()).await.unwrap();
+
+        redis.advance(TTL);
+
+        assert!(!store.replace(expired.clone()).await.unwrap());
+        assert_eq!(store.get(expired.session_id).await.unwrap(), None);
+        assert_eq!(store.remove_hosted_through(expired.session_id, expired.host_connection).await.unwrap(), None);
+
+        // A session can come back under the same id, and is indexed again.
+        assert!(store.insert(expired.clone()).await.unwrap());
+        assert_eq!(ids(store.sessions_for_title(TITLE).await.unwrap()), vec![expired.session_id]);
+    }
+
+    #[tokio::test]
+    async fn sessions_are_only_removed_through_their_connection() {
+        let stores: Vec<Arc<dyn SessionStore>> = vec![
+            Arc::new(MemoryStore::new(TTL)),
+            Arc::new(RedisStore::new(FakeRedis::new(), TTL)),
+        ];
+
+        for store in stores {
+            let hosted = session(1, 1, TITLE, now_millis());
+            store.insert(hosted.clone()).await.unwrap();
+
+            assert_eq!(store.remove_hosted_through(hosted.session_id, hosted.host_connection + 1).await.unwrap(), None);
+            assert_eq!(store.get(hosted.session_id).await.unwrap(), Some(hosted.clone()));
+
+            assert_eq!(store.remove_hosted_through(hosted.session_id, hosted.host_connection).await.unwrap(), Some(hosted.clone()));
+            assert_eq!(store.get(hosted.session_id).await.unwrap(), None);
+        }
+    }
+}
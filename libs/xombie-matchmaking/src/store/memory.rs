@@ -0,0 +1,356 @@
+use async_trait::async_trait;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::RwLock;
+
+use xblive::crypto::primitives::KeyId;
+use xblive::service::matchmaking::{SearchAttribute, SearchAttributeKind};
+
+use xbox_sys::account::Xuid;
+
+use crate::{Session, Title};
+
+use super::{SessionFilter, SessionStore, StoreError, has_required_values};
+
+/// Sessions kept in process, lost when the SG restarts.
+///
+/// Sessions are sharded by title, each shard behind its own lock, so
+/// searches only ever contend with writes to the same title.  Within a shard
+/// sessions are indexed by creation time and by every integer and string
+/// attribute value.
+pub struct MemoryStore {
+    shards: RwLock<BTreeMap<Title, Arc<RwLock<TitleShard>>>>,
+    locations: RwLock<BTreeMap<KeyId, Location>>,
+    session_ttl: Duration,
+}
+
+/// Where to find a session from just its id.
+#[derive(Clone, Copy)]
+struct Location {
+    title: Title,
+    machine: Xuid,
+}
+
+/// Sessions order by creation time, with the id breaking ties.
+type SessionOrder = (SystemTime, KeyId);
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum IndexedValue {
+    Integer(i64),
+    String(String),
+}
+
+impl IndexedValue {
+    fn from_kind(kind: &SearchAttributeKind) -> Option<IndexedValue> {
+        match kind {
+            SearchAttributeKind::Integer(i) => Some(IndexedValue::Integer(*i)),
+            SearchAttributeKind::String(s) => Some(IndexedValue::String(s.clone())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct TitleShard {
+    sessions: BTreeMap<KeyId, Session>,
+    by_creation_time: BTreeSet<SessionOrder>,
+    by_attribute: BTreeMap<(u16, IndexedValue), BTreeSet<SessionOrder>>,
+}
+
+impl TitleShard {
+    fn indexed_attributes(attributes: &[SearchAttribute]) -> impl Iterator<Item = (u16, IndexedValue)> + '_ {
+        attributes.iter()
+            .filter_map(|attribute| Some((attribute.tag, IndexedValue::from_kind(&attribute.kind)?)))
+    }
+
+    fn insert(&mut self, session: Session) {
+        let order = (session.creation_time, session.session_id);
+
+        self.by_creation_time.insert(order);
+        for key in Self::indexed_attributes(&session.attributes) {
+            self.by_attribute.entry(key)
+                .or_default()
+                .insert(order);
+        }
+
+        self.sessions.insert(session.session_id, session);
+    }
+
+    fn remove(&mut self, session_id: KeyId) -> Option<Session> {
+        let session = self.sessions.remove(&session_id)?;
+        let order = (session.creation_time, session_id);
+
+        self.by_creation_time.remove(&order);
+        for key in Self::indexed_attributes(&session.attributes) {
+            if let Some(index) = self.by_attribute.get_mut(&key) {
+                index.remove(&order);
+                if index.is_empty() {
+                    self.by_attribute.remove(&key);
+                }
+            }
+        }
+
+        Some(session)
+    }
+
+    /// The sessions that might have every required value, oldest first.  The
+    /// smallest index of the required values is walked, or every session if
+    /// nothing's required.
+    fn candidates<'a>(&'a self, required: &[(u16, SearchAttributeKind)]) -> Box<dyn Iterator<Item = &'a Session> + 'a> {
+        let mut smallest: Option<&BTreeSet<SessionOrder>> = None;
+
+        for (tag, value) in required {
+            let index = match IndexedValue::from_kind(value) {
+                Some(value) => self.by_attribute.get(&(*tag, value)),
+                None => continue,
+            };
+
+            let index = match index {
+                Some(index) => index,
+                None => return Box::new(std::iter::empty()),
+            };
+
+            smallest = match smallest {
+                Some(smallest) if smallest.len() <= index.len() => Some(smallest),
+                _ => Some(index),
+            };
+        }
+
+        let order = smallest.unwrap_or(&self.by_creation_time);
+
+        Box::new(order.iter().filter_map(|(_, session_id)| self.sessions.get(session_id)))
+    }
+}
+
+impl MemoryStore {
+    pub fn new(session_ttl: Duration) -> Self {
+        MemoryStore {
+            shards: RwLock::new(BTreeMap::new()),
+            locations: RwLock::new(BTreeMap::new()),
+            session_ttl,
+        }
+    }
+
+    fn is_live(&self, session: &Session, now: SystemTime) -> bool {
+        !session.is_expired(now, self.session_ttl)
+    }
+
+    async fn shard(&self, title: Title) -> Option<Arc<RwLock<TitleShard>>> {
+        self.shards.read().await.get(&title).cloned()
+    }
+
+    async fn shard_or_default(&self, title: Title) -> Arc<RwLock<TitleShard>> {
+        if let Some(shard) = self.shard(title).await {
+            return shard;
+        }
+
+        self.shards.write().await
+            .entry(title)
+            .or_default()
+            .clone()
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemoryStore {
+    async fn insert(&self, session: Session) -> Result<bool, StoreError> {
+        let mut locations = self.locations.write().await;
+
+        if locations.contains_key(&session.session_id) {
+            return Ok(false)
+        }
+
+        locations.insert(session.session_id, Location {
+            title: session.title,
+            machine: session.host_users.machine,
+        });
+
+        self.shard_or_default(session.title).await
+            .write().await
+            .insert(session);
+
+        Ok(true)
+    }
+
+    async fn get(&self, session_id: KeyId) -> Result<Option<Session>, StoreError> {
+        let now = SystemTime::now();
+
+        let location = match self.locations.read().await.get(&session_id) {
+            Some(location) => *location,
+            None => return Ok(None),
+        };
+
+        let shard = match self.shard(location.title).await {
+            Some(shard) => shard,
+            None => return Ok(None),
+        };
+
+        let shard = shard.read().await;
+
+        Ok(shard.sessions.get(&session_id)
+            .filter(|session| self.is_live(session, now))
+            .cloned())
+    }
+
+    /// Only the session's shard is locked for writing.  A session removed
+    /// after its location was looked up is gone from the shard too, so it
+    /// can't be brought back.
+    async fn replace(&self, session: Session) -> Result<bool, StoreError> {
+        let location = match self.locations.read().await.get(&session.session_id) {
+            Some(location) => *location,
+            None => return Ok(false),
+        };
+
+        let shard = match self.shard(location.title).await {
+            Some(shard) => shard,
+            None => return Ok(false),
+        };
+
+        let mut shard = shard.write().await;
+
+        if shard.remove(session.session_id).is_none() {
+            return Ok(false)
+        }
+        shard.insert(session);
+
+        Ok(true)
+    }
+
+    async fn remove(&self, session_id: KeyId) -> Result<Option<Session>, StoreError> {
+        let mut locations = self.locations.write().await;
+
+        let location = match locations.remove(&session_id) {
+            Some(location) => location,
+            None => return Ok(None),
+        };
+
+        let shard = match self.shard(location.title).await {
+            Some(shard) => shard,
+            None => return Ok(None),
+        };
+
+        let session = shard.write().await.remove(session_id);
+
+        Ok(session)
+    }
+
+    async fn remove_hosted_through(&self, session_id: KeyId, connection: u64) -> Result<Option<Session>, StoreError> {
+        let mut locations = self.locations.write().await;
+
+        let location = match locations.get(&session_id) {
+            Some(location) => *location,
+            None => return Ok(None),
+        };
+
+        let shard = match self.shard(location.title).await {
+            Some(shard) => shard,
+            None => return Ok(None),
+        };
+
+        let mut shard = shard.write().await;
+
+        match shard.sessions.get(&session_id) {
+            Some(session) if session.host_connection == connection => {}
+            _ => return Ok(None),
+        }
+
+        locations.remove(&session_id);
+
+        Ok(shard.remove(session_id))
+    }
+
+    async fn sessions_for_title(&self, title: Title) -> Result<Vec<Session>, StoreError> {
+        let now = SystemTime::now();
+
+        let shard = match self.shard(title).await {
+            Some(shard) => shard,
+            None => return Ok(vec![]),
+        };
+
+        let shard = shard.read().await;
+
+        Ok(shard.candidates(&[])
+            .filter(|session| self.is_live(session, now))
+            .cloned()
+            .collect())
+    }
+
+    async fn search(
+        &self,
+        title: Title,
+        required: &[(u16, SearchAttributeKind)],
+        filter: &SessionFilter<'_>,
+        max_results: usize,
+    ) -> Result<Vec<Session>, StoreError> {
+        let now = SystemTime::now();
+
+        let shard = match self.shard(title).await {
+            Some(shard) => shard,
+            None => return Ok(vec![]),
+        };
+
+        let shard = shard.read().await;
+
+        Ok(shard.candidates(required)
+            .filter(|session| self.is_live(session, now))
+            .filter(|session| has_required_values(session, required) && filter(session))
+            .take(max_results)
+            .cloned()
+            .collect())
+    }
+
+    async fn sessions_for_machine(&self, machine: Xuid) -> Result<Vec<KeyId>, StoreError> {
+        let hosted: Vec<_> = self.locations.read().await.iter()
+            .filter(|(_, location)| location.machine == machine)
+            .map(|(session_id, _)| *session_id)
+            .collect();
+
+        let mut live = vec![];
+        for session_id in hosted {
+            if self.get(session_id).await?.is_some() {
+                live.push(session_id);
+            }
+        }
+
+        Ok(live)
+    }
+
+    async fn reap_expired(&self, now: SystemTime) -> Result<usize, StoreError> {
+        let mut locations = self.locations.write().await;
+        let mut shards = self.shards.write().await;
+
+        let mut reaped = 0;
+
+        for shard in shards.values() {
+            let mut shard = shard.write().await;
+
+            let expired: Vec<_> = shard.sessions.values()
+                .filter(|session| !self.is_live(session, now))
+                .map(|session| session.session_id)
+                .collect();
+
+            for session_id in expired.iter() {
+                shard.remove(*session_id);
+                locations.remove(session_id);
+            }
+
+            reaped += expired.len();
+        }
+
+        let mut empty_titles = vec![];
+        for (title, shard) in shards.iter() {
+            if shard.read().await.sessions.is_empty() {
+                empty_titles.push(*title);
+            }
+        }
+
+        for title in empty_titles {
+            shards.remove(&title);
+        }
+
+        Ok(reaped)
+    }
+}
@@ -0,0 +1,573 @@
+//! Sessions kept in Redis, so they survive SG restarts and are shared between
+//! SG nodes.
+//!
+//! Every session is a hash at `xmatch:session:<session id>` that Redis
+//! expires once the session ttl passes without an update.  Sessions are
+//! indexed by title in sorted sets at `xmatch:title:<title id>:<version>`,
+//! by host machine in sorted sets at `xmatch:machine:<xuid>`, and by every
+//! integer and string attribute value in sorted sets at
+//! `xmatch:attribute:<title id>:<version>:<tag>:<i or s>:<value>`, with
+//! strings hex encoded.  All of them are scored by creation time, so
+//! searches can ZINTER the indexes of the values they require and still walk
+//! the sessions oldest first.  Each hash lists the indexes it's in, so they
+//! can be kept in step with it, and every index is registered in the set at
+//! `xmatch:indexes`.
+//!
+//! Anything that touches a hash and its indexes together runs as a single
+//! Lua script, so concurrent SGs never see or leave one without the other.
+//! The scripts reach keys they aren't handed, so the store needs a single
+//! Redis rather than a cluster.  Index entries still outlive the hashes Redis
+//! expires; they're dropped whenever they're found dangling, and by
+//! reap_expired.
+
+use async_trait::async_trait;
+
+use redis::aio::MultiplexedConnection;
+use redis::RedisResult;
+
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+use xblive::addr::Addr;
+use xblive::crypto::primitives::KeyId;
+use xblive::service::matchmaking::{SearchAttribute, SearchAttributeKind};
+
+use xbox_sys::account::Xuid;
+use xbox_sys::codec::{BufPut, Decode};
+use xbox_sys::crypto::SymmetricKey;
+
+use crate::{Session, Title, Users};
+
+use super::{SessionFilter, SessionStore, StoreError, has_required_values};
+
+#[cfg(test)]
+pub mod fake;
+
+const SESSION_KEY_PREFIX: &str = "xmatch:session:";
+const INDEXES_KEY: &str = "xmatch:indexes";
+
+/// Creates (ARGV[1] "create") or replaces (ARGV[1] "replace") the hash at
+/// KEYS[1], returning 0 and changing nothing if it already exists or no
+/// longer exists respectively.  The hash expires ARGV[2] milliseconds later
+/// and ARGV[3] is added to each index in KEYS[3..] with the score at the
+/// same position in ARGV[4..], after being dropped from the indexes the hash
+/// was in before.  The indexes are registered in the set at KEYS[2].  The
+/// rest of ARGV are the hash's field and value pairs.
+const WRITE_INDEXED_SCRIPT: &str = r"
+local exists = redis.call('EXISTS', KEYS[1]) == 1
+if exists ~= (ARGV[1] == 'replace') then
+    return 0
+end
+if exists then
+    for index in string.gmatch(redis.call('HGET', KEYS[1], 'indexes') or '', '[^,]+') do
+        redis.call('ZREM', index, ARGV[3])
+    end
+    redis.call('DEL', KEYS[1])
+end
+local indexes = {}
+for i = 3, #KEYS do
+    redis.call('ZADD', KEYS[i], ARGV[i + 1], ARGV[3])
+    redis.call('SADD', KEYS[2], KEYS[i])
+    table.insert(indexes, KEYS[i])
+end
+redis.call('HSET', KEYS[1], 'indexes', table.concat(indexes, ','), unpack(ARGV, #KEYS + 2))
+redis.call('PEXPIRE', KEYS[1], ARGV[2])
+return 1
+";
+
+/// Deletes the hash at KEYS[1] and drops ARGV[1] from the indexes it's in,
+/// returning what the hash held.  If ARGV[2] is given, nothing happens
+/// unless the hash's ARGV[2] field holds ARGV[3].
+const REMOVE_INDEXED_SCRIPT: &str = r"
+local hash = redis.call('HGETALL', KEYS[1])
+if #hash == 0 then
+    return hash
+end
+if ARGV[2] and redis.call('HGET', KEYS[1], ARGV[2]) ~= ARGV[3] then
+    return {}
+end
+for index in string.gmatch(redis.call('HGET', KEYS[1], 'indexes') or '', '[^,]+') do
+    redis.call('ZREM', index, ARGV[1])
+end
+redis.call('DEL', KEYS[1])
+return hash
+";
+
+/// Drops the members of the index at KEYS[1] whose hashes, at ARGV[1]
+/// followed by the member, are gone, unregistering the index from the set at
+/// KEYS[2] once it's empty.  Only the members in ARGV[2..] are checked, or
+/// every member if there are none.  Returns how many were dropped.
+const PRUNE_INDEX_SCRIPT: &str = r"
+local members = {unpack(ARGV, 2)}
+if #members == 0 then
+    members = redis.call('ZRANGE', KEYS[1], 0, -1)
+end
+local pruned = 0
+for _, member in ipairs(members) do
+    if redis.call('EXISTS', ARGV[1] .. member) == 0 and redis.call('ZREM', KEYS[1], member) == 1 then
+        pruned = pruned + 1
+    end
+end
+if redis.call('EXISTS', KEYS[1]) == 0 then
+    redis.call('SREM', KEYS[2], KEYS[1])
+end
+return pruned
+";
+
+/// Whether a write may create a hash or only replace an existing one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteMode {
+    Create,
+    Replace,
+}
+
+/// The handful of Redis operations the store needs, so it can be run against
+/// an in process fake.  Every one of them is atomic.
+#[async_trait]
+pub trait RedisConnection: Send + Sync {
+    /// Write every field of the hash at key, expiring it after ttl, and move
+    /// member from the indexes the hash was in to the given indexes, scored
+    /// as given.  Each index is registered at INDEXES_KEY.  Returns false,
+    /// changing nothing, if the hash already exists when creating or no
+    /// longer exists when replacing.
+    async fn write_indexed(
+        &self,
+        mode: WriteMode,
+        key: &str,
+        fields: &[(&'static str, String)],
+        ttl: Duration,
+        member: &str,
+        indexes: &[(String, i64)],
+    ) -> RedisResult<bool>;
+
+    /// Delete the hash at key, dropping member from the indexes it's in, and
+    /// return what it held.  With only_if, the hash is left alone, and
+    /// nothing returned, unless the named field holds the given value.
+    async fn remove_indexed(&self, key: &str, member: &str, only_if: Option<(&str, &str)>) -> RedisResult<BTreeMap<String, String>>;
+
+    /// HGETALL, which is empty for a missing key.
+    async fn hgetall(&self, key: &str) -> RedisResult<BTreeMap<String, String>>;
+
+    /// Every member of a sorted set, lowest score first.
+    async fn zrange(&self, key: &str) -> RedisResult<Vec<String>>;
+
+    /// The members in every one of the sorted sets, lowest score first,
+    /// as ZINTER with AGGREGATE MIN.  Needs Redis 6.2 or later.
+    async fn zinter(&self, keys: &[String]) -> RedisResult<Vec<String>>;
+
+    async fn smembers(&self, key: &str) -> RedisResult<Vec<String>>;
+
+    /// Drop the members of index whose hashes, at hash_prefix followed by the
+    /// member, are gone, unregistering the index once it's empty.  Only the
+    /// given members are checked, or all of them if None.  Returns how many
+    /// members were dropped.
+    async fn prune_index(&self, index: &str, hash_prefix: &str, members: Option<&[String]>) -> RedisResult<usize>;
+}
+
+#[async_trait]
+impl RedisConnection for MultiplexedConnection {
+    async fn write_indexed(
+        &self,
+        mode: WriteMode,
+        key: &str,
+        fields: &[(&'static str, String)],
+        ttl: Duration,
+        member: &str,
+        indexes: &[(String, i64)],
+    ) -> RedisResult<bool> {
+        let mut cmd = redis::cmd("EVAL");
+        cmd.arg(WRITE_INDEXED_SCRIPT).arg(2 + indexes.len()).arg(key).arg(INDEXES_KEY);
+        for (index, _) in indexes {
+            cmd.arg(index);
+        }
+
+        cmd.arg(match mode {
+            WriteMode::Create => "create",
+            WriteMode::Replace => "replace",
+        });
+        cmd.arg(ttl.as_millis() as u64).arg(member);
+        for (_, score) in indexes {
+            cmd.arg(*score);
+        }
+        for (field, value) in fields {
+            cmd.arg(*field).arg(value);
+        }
+
+        cmd.query_async(&mut self.clone()).await
+    }
+
+    async fn remove_indexed(&self, key: &str, member: &str, only_if: Option<(&str, &str)>) -> RedisResult<BTreeMap<String, String>> {
+        let mut cmd = redis::cmd("EVAL");
+        cmd.arg(REMOVE_INDEXED_SCRIPT).arg(1).arg(key).arg(member);
+        if let Some((field, value)) = only_if {
+            cmd.arg(field).arg(value);
+        }
+
+        cmd.query_async(&mut self.clone()).await
+    }
+
+    async fn hgetall(&self, key: &str) -> RedisResult<BTreeMap<String, String>> {
+        redis::cmd("HGETALL").arg(key).query_async(&mut self.clone()).await
+    }
+
+    async fn zrange(&self, key: &str) -> RedisResult<Vec<String>> {
+        redis::cmd("ZRANGE").arg(key).arg(0).arg(-1).query_async(&mut self.clone()).await
+    }
+
+    async fn zinter(&self, keys: &[String]) -> RedisResult<Vec<String>> {
+        redis::cmd("ZINTER").arg(keys.len()).arg(keys).arg("AGGREGATE").arg("MIN").query_async(&mut self.clone()).await
+    }
+
+    async fn smembers(&self, key: &str) -> RedisResult<Vec<String>> {
+        redis::cmd("SMEMBERS").arg(key).query_async(&mut self.clone()).await
+    }
+
+    async fn prune_index(&self, index: &str, hash_prefix: &str, members: Option<&[String]>) -> RedisResult<usize> {
+        let mut cmd = redis::cmd("EVAL");
+        cmd.arg(PRUNE_INDEX_SCRIPT).arg(2).arg(index).arg(INDEXES_KEY).arg(hash_prefix);
+        for member in members.unwrap_or_default() {
+            cmd.arg(member);
+        }
+
+        cmd.query_async(&mut self.clone()).await
+    }
+}
+
+pub struct RedisStore<C: RedisConnection> {
+    conn: C,
+    session_ttl: Duration,
+}
+
+impl RedisStore<MultiplexedConnection> {
+    pub async fn connect(url: &str, session_ttl: Duration) -> Result<Self, StoreError> {
+        let client = redis::Client::open(url)?;
+        let conn = client.get_multiplexed_tokio_connection().await?;
+
+        Ok(RedisStore::new(conn, session_ttl))
+    }
+}
+
+impl<C: RedisConnection> RedisStore<C> {
+    pub fn new(conn: C, session_ttl: Duration) -> Self {
+        RedisStore {
+            conn,
+            session_ttl,
+        }
+    }
+
+    async fn write(&self, mode: WriteMode, session: &Session) -> Result<bool, StoreError> {
+        Ok(self.conn.write_indexed(
+            mode,
+            &session_key(&session.session_id),
+            &session_fields(session),
+            self.session_ttl,
+            &to_hex(&session.session_id.0),
+            &session_indexes(session),
+        ).await?)
+    }
+
+    async fn read(&self, session_id: KeyId) -> Result<Option<Session>, StoreError> {
+        let key = session_key(&session_id);
+        let fields = self.conn.hgetall(&key).await?;
+
+        if fields.is_empty() {
+            return Ok(None)
+        }
+
+        session_from_fields(session_id, &fields)
+            .map(Some)
+            .ok_or(StoreError::CorruptSession(key))
+    }
+
+    async fn remove_where(&self, session_id: KeyId, only_if: Option<(&str, &str)>) -> Result<Option<Session>, StoreError> {
+        let key = session_key(&session_id);
+        let fields = self.conn.remove_indexed(&key, &to_hex(&session_id.0), only_if).await?;
+
+        if fields.is_empty() {
+            return Ok(None)
+        }
+
+        session_from_fields(session_id, &fields)
+            .map(Some)
+            .ok_or(StoreError::CorruptSession(key))
+    }
+
+    /// The live sessions in an index, lowest score first, dropping any
+    /// dangling entries found along the way.
+    async fn indexed_sessions(&self, index: &str) -> Result<Vec<Session>, StoreError> {
+        let mut sessions = vec![];
+        let mut dangling = vec![];
+
+        for id in self.conn.zrange(index).await? {
+            match self.read(parse_session_id(&id)?).await? {
+                Some(session) => sessions.push(session),
+                None => dangling.push(id),
+            }
+        }
+
+        if !dangling.is_empty() {
+            self.conn.prune_index(index, SESSION_KEY_PREFIX, Some(&dangling)).await?;
+        }
+
+        Ok(sessions)
+    }
+}
+
+#[async_trait]
+impl<C: RedisConnection> SessionStore for RedisStore<C> {
+    async fn insert(&self, session: Session) -> Result<bool, StoreError> {
+        self.write(WriteMode::Create, &session).await
+    }
+
+    async fn get(&self, session_id: KeyId) -> Result<Option<Session>, StoreError> {
+        self.read(session_id).await
+    }
+
+    async fn replace(&self, session: Session) -> Result<bool, StoreError> {
+        self.write(WriteMode::Replace, &session).await
+    }
+
+    async fn remove(&self, session_id: KeyId) -> Result<Option<Session>, StoreError> {
+        self.remove_where(session_id, None).await
+    }
+
+    async fn remove_hosted_through(&self, session_id: KeyId, connection: u64) -> Result<Option<Session>, StoreError> {
+        self.remove_where(session_id, Some(("connection", &connection_field(connection)))).await
+    }
+
+    async fn sessions_for_title(&self, title: Title) -> Result<Vec<Session>, StoreError> {
+        self.indexed_sessions(&title_index_key(title)).await
+    }
+
+    /// Only the sessions in every index of the required values are read, or
+    /// all of the title's sessions if none of the values are indexed.
+    async fn search(
+        &self,
+        title: Title,
+        required: &[(u16, SearchAttributeKind)],
+        filter: &SessionFilter<'_>,
+        max_results: usize,
+    ) -> Result<Vec<Session>, StoreError> {
+        let mut indexes: Vec<_> = required.iter()
+            .filter_map(|(tag, value)| attribute_index_key(title, *tag, value))
+            .collect();
+        indexes.sort();
+        indexes.dedup();
+
+        let ids = match indexes.as_slice() {
+            [] => self.conn.zrange(&title_index_key(title)).await?,
+            [index] => self.conn.zrange(index).await?,
+            _ => self.conn.zinter(&indexes).await?,
+        };
+
+        // Dangling entries are just skipped; reap_expired drops them.
+        let mut sessions = vec![];
+        for id in ids {
+            if sessions.len() >= max_results {
+                break;
+            }
+
+            let session = match self.read(parse_session_id(&id)?).await? {
+                Some(session) => session,
+                None => continue,
+            };
+
+            if has_required_values(&session, required) && filter(&session) {
+                sessions.push(session);
+            }
+        }
+
+        Ok(sessions)
+    }
+
+    async fn sessions_for_machine(&self, machine: Xuid) -> Result<Vec<KeyId>, StoreError> {
+        Ok(self.indexed_sessions(&machine_index_key(machine))
+            .await?
+            .iter()
+            .map(|session| session.session_id)
+            .collect())
+    }
+
+    /// Redis expires the sessions themselves, so all that's left is dropping
+    /// the index entries pointing at them.  Returns how many sessions were
+    /// still in their title's index.
+    async fn reap_expired(&self, _now: SystemTime) -> Result<usize, StoreError> {
+        let mut reaped = 0;
+
+        for index in self.conn.smembers(INDEXES_KEY).await? {
+            let pruned = self.conn.prune_index(&index, SESSION_KEY_PREFIX, None).await?;
+
+            if index.starts_with(TITLE_INDEX_PREFIX) {
+                reaped += pruned;
+            }
+        }
+
+        Ok(reaped)
+    }
+}
+
+fn parse_session_id(id: &str) -> Result<KeyId, StoreError> {
+    from_hex(id)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(KeyId)
+        .ok_or_else(|| StoreError::CorruptSession(String::from(id)))
+}
+
+const TITLE_INDEX_PREFIX: &str = "xmatch:title:";
+
+fn session_key(session_id: &KeyId) -> String {
+    format!("{}{}", SESSION_KEY_PREFIX, to_hex(&session_id.0))
+}
+
+fn title_index_key(title: Title) -> String {
+    format!("{}{:08x}:{:08x}", TITLE_INDEX_PREFIX, title.id, title.ver)
+}
+
+fn machine_index_key(machine: Xuid) -> String {
+    format!("xmatch:machine:{:016x}", machine.0)
+}
+
+/// The index of sessions for title with value for tag, or None for values
+/// that aren't indexed.
+fn attribute_index_key(title: Title, tag: u16, value: &SearchAttributeKind) -> Option<String> {
+    let value = match value {
+        SearchAttributeKind::Integer(i) => format!("i:{}", i),
+        SearchAttributeKind::String(s) => format!("s:{}", to_hex(s.as_bytes())),
+        _ => return None,
+    };
+
+    Some(format!("xmatch:attribute:{:08x}:{:08x}:{:04x}:{}", title.id, title.ver, tag, value))
+}
+
+fn connection_field(connection: u64) -> String {
+    format!("{:016x}", connection)
+}
+
+/// Every index a session belongs in, with its score there.
+fn session_indexes(session: &Session) -> Vec<(String, i64)> {
+    let created = unix_millis(session.creation_time) as i64;
+
+    let mut indexes = vec![
+        title_index_key(session.title),
+        machine_index_key(session.host_users.machine),
+    ];
+
+    for attribute in session.attributes.iter() {
+        if let Some(index) = attribute_index_key(session.title, attribute.tag, &attribute.kind) {
+            if !indexes.contains(&index) {
+                indexes.push(index);
+            }
+        }
+    }
+
+    indexes.into_iter()
+        .map(|index| (index, created))
+        .collect()
+}
+
+fn session_fields(session: &Session) -> Vec<(&'static str, String)> {
+    let mut host_address = vec![];
+    session.host_address.put(&mut host_address);
+
+    let users: Vec<_> = session.host_users.user.iter()
+        .map(|user| format!("{:016x}", user.0))
+        .collect();
+
+    let attributes: Vec<_> = session.attributes.iter()
+        .map(|attribute| {
+            let mut encoded = vec![];
+            attribute.put(&mut encoded);
+            to_hex(&encoded)
+        })
+        .collect();
+
+    vec![
+        ("key_exchange_key", to_hex(&session.key_exchange_key.0)),
+        ("title_id", session.title.id.to_string()),
+        ("title_ver", session.title.ver.to_string()),
+        ("machine", format!("{:016x}", session.host_users.machine.0)),
+        ("users", users.join(",")),
+        ("connection", connection_field(session.host_connection)),
+        ("host_address", to_hex(&host_address)),
+        ("region", session.region.clone().unwrap_or_default()),
+        ("public_open", session.public_open.to_string()),
+        ("private_open", session.private_open.to_string()),
+        ("public_filled", session.public_filled.to_string()),
+        ("private_filled", session.private_filled.to_string()),
+        ("attributes", attributes.join(",")),
+        ("creation_time", unix_millis(session.creation_time).to_string()),
+        ("last_refresh", unix_millis(session.last_refresh).to_string()),
+    ]
+}
+
+fn session_from_fields(session_id: KeyId, fields: &BTreeMap<String, String>) -> Option<Session> {
+    let field = |name: &str| fields.get(name).map(|value| value.as_str());
+    let number = |name: &str| field(name)?.parse::<u32>().ok();
+    let xuid = |value: &str| u64::from_str_radix(value, 16).ok().map(Xuid);
+    let time = |name: &str| {
+        let millis = field(name)?.parse().ok()?;
+        Some(SystemTime::UNIX_EPOCH + Duration::from_millis(millis))
+    };
+
+    let key_exchange_key = from_hex(field("key_exchange_key")?)?;
+    let host_address = from_hex(field("host_address")?)?;
+    let (_, host_address) = Addr::decode(&host_address).ok()?;
+
+    Some(Session {
+        session_id,
+        key_exchange_key: SymmetricKey(key_exchange_key.try_into().ok()?),
+        title: Title {
+            id: number("title_id")?,
+            ver: number("title_ver")?,
+        },
+        host_users: Users {
+            machine: xuid(field("machine")?)?,
+            user: split_list(field("users")?)
+                .map(xuid)
+                .collect::<Option<_>>()?,
+        },
+        host_connection: u64::from_str_radix(field("connection")?, 16).ok()?,
+        host_address,
+        region: field("region")
+            .filter(|region| !region.is_empty())
+            .map(String::from),
+        public_open: number("public_open")?,
+        private_open: number("private_open")?,
+        public_filled: number("public_filled")?,
+        private_filled: number("private_filled")?,
+        attributes: split_list(field("attributes")?)
+            .map(|attribute| {
+                let (_, attribute) = SearchAttribute::decode(&from_hex(attribute)?).ok()?;
+                Some(attribute)
+            })
+            .collect::<Option<_>>()?,
+        creation_time: time("creation_time")?,
+        last_refresh: time("last_refresh")?,
+    })
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').filter(|item| !item.is_empty())
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).ok().filter(|pair| pair.len() == 2)?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
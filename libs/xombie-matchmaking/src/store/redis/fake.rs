@@ -0,0 +1,231 @@
+use async_trait::async_trait;
+
+use redis::RedisResult;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::{INDEXES_KEY, RedisConnection, WriteMode};
+
+/// The hash field the store's scripts list a hash's indexes in, comma
+/// separated.
+const INDEXES_FIELD: &str = "indexes";
+
+/// Just enough of Redis, in process, to test the Redis store.  Time only
+/// moves when advance is called.
+#[derive(Clone, Default)]
+pub struct FakeRedis {
+    state: Arc<Mutex<FakeState>>,
+}
+
+#[derive(Default)]
+struct FakeState {
+    now: Duration,
+    hashes: BTreeMap<String, (BTreeMap<String, String>, Duration)>,
+    sorted_sets: BTreeMap<String, BTreeMap<String, i64>>,
+    sets: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl FakeState {
+    fn expire(&mut self) {
+        let now = self.now;
+        self.hashes.retain(|_, (_, deadline)| *deadline > now);
+    }
+}
+
+impl FakeRedis {
+    pub fn new() -> Self {
+        FakeRedis::default()
+    }
+
+    pub fn advance(&self, elapsed: Duration) {
+        self.state.lock().unwrap().now += elapsed;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.expire();
+
+        state.hashes.is_empty() && state.sorted_sets.is_empty() && state.sets.is_empty()
+    }
+
+    fn with_state<T>(&self, f: impl FnOnce(&mut FakeState) -> T) -> RedisResult<T> {
+        let mut state = self.state.lock().unwrap();
+        state.expire();
+
+        Ok(f(&mut state))
+    }
+}
+
+impl FakeState {
+    fn indexes_of(&self, key: &str) -> Vec<String> {
+        self.hashes.get(key)
+            .and_then(|(hash, _)| hash.get(INDEXES_FIELD))
+            .map(|indexes| indexes.split(',').filter(|index| !index.is_empty()).map(String::from).collect())
+            .unwrap_or_default()
+    }
+
+    fn zrem(&mut self, key: &str, member: &str) -> bool {
+        let sorted_set = match self.sorted_sets.get_mut(key) {
+            Some(sorted_set) => sorted_set,
+            None => return false,
+        };
+
+        let removed = sorted_set.remove(member).is_some();
+        if sorted_set.is_empty() {
+            self.sorted_sets.remove(key);
+        }
+
+        removed
+    }
+}
+
+#[async_trait]
+impl RedisConnection for FakeRedis {
+    async fn write_indexed(
+        &self,
+        mode: WriteMode,
+        key: &str,
+        fields: &[(&'static str, String)],
+        ttl: Duration,
+        member: &str,
+        indexes: &[(String, i64)],
+    ) -> RedisResult<bool> {
+        self.with_state(|state| {
+            let exists = state.hashes.contains_key(key);
+            if exists != (mode == WriteMode::Replace) {
+                return false;
+            }
+
+            for index in state.indexes_of(key) {
+                state.zrem(&index, member);
+            }
+
+            let mut hash: BTreeMap<_, _> = fields.iter()
+                .map(|(field, value)| (String::from(*field), value.clone()))
+                .collect();
+            hash.insert(String::from(INDEXES_FIELD), indexes.iter().map(|(index, _)| index.as_str()).collect::<Vec<_>>().join(","));
+
+            for (index, score) in indexes {
+                state.sorted_sets.entry(index.clone())
+                    .or_default()
+                    .insert(String::from(member), *score);
+                state.sets.entry(String::from(INDEXES_KEY))
+                    .or_default()
+                    .insert(index.clone());
+            }
+
+            let deadline = state.now + ttl;
+            state.hashes.insert(String::from(key), (hash, deadline));
+
+            true
+        })
+    }
+
+    async fn remove_indexed(&self, key: &str, member: &str, only_if: Option<(&str, &str)>) -> RedisResult<BTreeMap<String, String>> {
+        self.with_state(|state| {
+            let hash = match state.hashes.get(key) {
+                Some((hash, _)) => hash.clone(),
+                None => return BTreeMap::new(),
+            };
+
+            if let Some((field, value)) = only_if {
+                if hash.get(field).map(|held| held.as_str()) != Some(value) {
+                    return BTreeMap::new();
+                }
+            }
+
+            for index in state.indexes_of(key) {
+                state.zrem(&index, member);
+            }
+            state.hashes.remove(key);
+
+            hash
+        })
+    }
+
+    async fn hgetall(&self, key: &str) -> RedisResult<BTreeMap<String, String>> {
+        self.with_state(|state| {
+            state.hashes.get(key)
+                .map(|(hash, _)| hash.clone())
+                .unwrap_or_default()
+        })
+    }
+
+    async fn zrange(&self, key: &str) -> RedisResult<Vec<String>> {
+        self.with_state(|state| {
+            let mut members: Vec<_> = state.sorted_sets.get(key)
+                .map(|sorted_set| sorted_set.iter().map(|(member, score)| (*score, member.clone())).collect())
+                .unwrap_or_default();
+            members.sort();
+
+            members.into_iter()
+                .map(|(_, member)| member)
+                .collect()
+        })
+    }
+
+    async fn zinter(&self, keys: &[String]) -> RedisResult<Vec<String>> {
+        self.with_state(|state| {
+            let sorted_sets: Vec<_> = keys.iter()
+                .map(|key| state.sorted_sets.get(key))
+                .collect::<Option<_>>()
+                .unwrap_or_default();
+
+            let (first, rest) = match sorted_sets.split_first() {
+                Some(split) => split,
+                None => return vec![],
+            };
+
+            let mut members: Vec<_> = first.iter()
+                .filter_map(|(member, score)| {
+                    let min = rest.iter()
+                        .map(|sorted_set| sorted_set.get(member).copied())
+                        .try_fold(*score, |min, score| Some(min.min(score?)))?;
+                    Some((min, member.clone()))
+                })
+                .collect();
+            members.sort();
+
+            members.into_iter()
+                .map(|(_, member)| member)
+                .collect()
+        })
+    }
+
+    async fn smembers(&self, key: &str) -> RedisResult<Vec<String>> {
+        self.with_state(|state| {
+            state.sets.get(key)
+                .map(|set| set.iter().cloned().collect())
+                .unwrap_or_default()
+        })
+    }
+
+    async fn prune_index(&self, index: &str, hash_prefix: &str, members: Option<&[String]>) -> RedisResult<usize> {
+        let members = match members {
+            Some(members) => members.to_vec(),
+            None => self.zrange(index).await?,
+        };
+
+        self.with_state(|state| {
+            let mut pruned = 0;
+            for member in members {
+                if !state.hashes.contains_key(&format!("{}{}", hash_prefix, member)) && state.zrem(index, &member) {
+                    pruned += 1;
+                }
+            }
+
+            if !state.sorted_sets.contains_key(index) {
+                if let Some(set) = state.sets.get_mut(INDEXES_KEY) {
+                    set.remove(index);
+                    if set.is_empty() {
+                        state.sets.remove(INDEXES_KEY);
+                    }
+                }
+            }
+
+            pruned
+        })
+    }
+}
@@ -0,0 +1,434 @@
+use log::{error, info};
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::task::JoinHandle;
+
+use xblive::service::messaging::*;
+
+use xbox_sys::account::Xuid;
+
+use xombie_presence::{BuddyStatus, Presence};
+
+pub mod store;
+
+use store::{MessageStore, StoreError};
+
+/// How long a message is kept when the sender doesn't ask for less.
+pub const MESSAGE_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// One recipient's copy of a message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub id: u64,
+    pub recipient: Xuid,
+    pub sender: Xuid,
+    /// The title the message was sent from.
+    pub title_id: u32,
+    pub flags: u32,
+    pub text: String,
+    pub attachment_len: u32,
+    pub sent: SystemTime,
+    pub expires: SystemTime,
+}
+
+impl Message {
+    pub fn is_read(&self) -> bool {
+        self.flags & MESSAGE_FLAG_READ != 0
+    }
+}
+
+/// A message as a sender writes it, before it's copied to each recipient.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NewMessage {
+    pub title_id: u32,
+    pub sender: Xuid,
+    /// Only the bits in MESSAGE_FLAGS_TITLE_MASK are kept.
+    pub flags: u32,
+    /// How long to keep the message, MESSAGE_TTL at most.
+    pub ttl: Option<Duration>,
+    pub text: String,
+    /// Empty if there's no attachment.
+    pub attachment: Vec<u8>,
+}
+
+/// Why a message wasn't delivered to one of its recipients.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Refusal {
+    OwnAccount,
+    /// One of the two users has blocked the other.
+    Blocked,
+    /// Only buddies can message each other.
+    NotBuddies,
+    InboxFull,
+}
+
+/// How sending to one recipient went, with the id of their copy if it was
+/// delivered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Delivery {
+    pub recipient: Xuid,
+    pub result: Result<u64, Refusal>,
+}
+
+#[derive(Debug)]
+pub enum MessagingError {
+    TooManyRecipients,
+    TextTooLong,
+    AttachmentTooLarge,
+    NoSuchMessage,
+    Store(StoreError),
+    Presence(xombie_presence::store::StoreError),
+}
+
+impl From<StoreError> for MessagingError {
+    fn from(err: StoreError) -> Self {
+        MessagingError::Store(err)
+    }
+}
+
+impl From<xombie_presence::store::StoreError> for MessagingError {
+    fn from(err: xombie_presence::store::StoreError) -> Self {
+        MessagingError::Presence(err)
+    }
+}
+
+pub struct Messaging {
+    store: Arc<dyn MessageStore>,
+}
+
+impl Debug for Messaging {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Messaging").finish()
+    }
+}
+
+impl Messaging {
+    pub fn new(store: Arc<dyn MessageStore>) -> Self {
+        Messaging {
+            store,
+        }
+    }
+
+    /// Periodically drop expired messages for as long as the returned task
+    /// runs.
+    pub fn spawn_reaper(&self, period: Duration) -> JoinHandle<()> {
+        let store = self.store.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+
+            loop {
+                interval.tick().await;
+
+                match store.reap_expired(SystemTime::now()).await {
+                    Ok(0) => {}
+                    Ok(reaped) => info!("Reaped {} expired messages", reaped),
+                    Err(err) => error!("Unable to reap messages: {:?}", err),
+                }
+            }
+        })
+    }
+
+    /// Why sender can't message recipient, if they can't, leaving aside
+    /// whether there's room in recipient's inbox.
+    async fn check_recipient(&self, presence: &Presence, sender: Xuid, recipient: Xuid) -> Result<Option<Refusal>, MessagingError> {
+        if sender == recipient {
+            return Ok(Some(Refusal::OwnAccount));
+        }
+
+        if presence.blocked_between(sender, recipient).await? {
+            return Ok(Some(Refusal::Blocked));
+        }
+
+        let buddies = presence.buddies(sender).await?;
+        if !buddies.iter().any(|buddy| buddy.xuid == recipient && buddy.status == BuddyStatus::Ok) {
+            return Ok(Some(Refusal::NotBuddies));
+        }
+
+        Ok(None)
+    }
+
+    /// Copy message into the inbox of each recipient the sender is allowed
+    /// to message.  Returns how it went for each recipient, in order.
+    pub async fn send(&self, presence: &Presence, message: &NewMessage, recipients: &[Xuid]) -> Result<Vec<Delivery>, MessagingError> {
+        if recipients.len() > MAX_RECIPIENTS as usize {
+            return Err(MessagingError::TooManyRecipients);
+        }
+
+        if message.text.len() > MAX_TEXT_LEN {
+            return Err(MessagingError::TextTooLong);
+        }
+
+        if message.attachment.len() > MAX_ATTACHMENT_LEN {
+            return Err(MessagingError::AttachmentTooLarge);
+        }
+
+        let now = SystemTime::now();
+        let ttl = message.ttl.map_or(MESSAGE_TTL, |ttl| ttl.min(MESSAGE_TTL));
+
+        let mut flags = message.flags & MESSAGE_FLAGS_TITLE_MASK;
+        if !message.attachment.is_empty() {
+            flags |= MESSAGE_FLAG_HAS_ATTACHMENT;
+        }
+
+        let mut refusals = vec![];
+        let mut copies = vec![];
+
+        for recipient in recipients {
+            let refusal = self.check_recipient(presence, message.sender, *recipient).await?;
+
+            if refusal.is_none() {
+                copies.push(Message {
+                    id: 0,
+                    recipient: *recipient,
+                    sender: message.sender,
+                    title_id: message.title_id,
+                    flags,
+                    text: message.text.clone(),
+                    attachment_len: message.attachment.len() as u32,
+                    sent: now,
+                    expires: now + ttl,
+                });
+            }
+
+            refusals.push(refusal);
+        }
+
+        let attachment = if message.attachment.is_empty() {
+            None
+        } else {
+            Some(message.attachment.clone())
+        };
+
+        // Whether there's room in each inbox is only settled as the copies go
+        // in, all sharing the one attachment.
+        let mut ids = self.store.add(copies, attachment, MAX_INBOX_MESSAGES as u32).await?.into_iter();
+
+        Ok(recipients.iter().zip(refusals)
+            .map(|(recipient, refusal)| Delivery {
+                recipient: *recipient,
+                result: match refusal {
+                    Some(refusal) => Err(refusal),
+                    None => ids.next().flatten().ok_or(Refusal::InboxFull),
+                },
+            })
+            .collect())
+    }
+
+    /// recipient's inbox, newest message first.
+    pub async fn inbox(&self, recipient: Xuid) -> Result<Vec<Message>, MessagingError> {
+        Ok(self.store.list(recipient, SystemTime::now()).await?)
+    }
+
+    pub async fn get(&self, recipient: Xuid, id: u64) -> Result<Message, MessagingError> {
+        self.store.get(recipient, id, SystemTime::now()).await?
+            .ok_or(MessagingError::NoSuchMessage)
+    }
+
+    /// NoSuchMessage if the message has no attachment.
+    pub async fn attachment(&self, recipient: Xuid, id: u64) -> Result<Vec<u8>, MessagingError> {
+        self.store.attachment(recipient, id, SystemTime::now()).await?
+            .ok_or(MessagingError::NoSuchMessage)
+    }
+
+    pub async fn mark_read(&self, recipient: Xuid, id: u64) -> Result<(), MessagingError> {
+        if !self.store.set_flags(recipient, id, MESSAGE_FLAG_READ, SystemTime::now()).await? {
+            return Err(MessagingError::NoSuchMessage);
+        }
+
+        Ok(())
+    }
+
+    pub async fn delete(&self, recipient: Xuid, id: u64) -> Result<(), MessagingError> {
+        if !self.store.delete(recipient, id).await? {
+            return Err(MessagingError::NoSuchMessage);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use xombie_presence::store::memory::{MemoryListStore, MemoryStore as MemoryPresenceStore};
+
+    use super::*;
+    use super::store::memory::MemoryStore;
+
+    const TITLE_ID: u32 = 0x4C41000B;
+
+    const ALICE: Xuid = Xuid(0x0009_0000_0000_0001);
+    const BOB: Xuid = Xuid(0x0009_0000_0000_0002);
+    const CAROL: Xuid = Xuid(0x0009_0000_0000_0003);
+    const DAVE: Xuid = Xuid(0x0009_0000_0000_0004);
+
+    /// Alice is buddies with Bob and Carol, but Carol has since blocked her.
+    /// Dave is nobody's buddy.
+    async fn presence() -> Presence {
+        let lists = MemoryListStore::new();
+        lists.add_account(ALICE, "Alice").await;
+        lists.add_account(BOB, "Bob").await;
+        lists.add_account(CAROL, "Carol").await;
+        lists.add_account(DAVE, "Dave").await;
+
+        let presence = Presence::new(Arc::new(MemoryPresenceStore::new()), Arc::new(lists));
+
+        presence.add_buddy(ALICE, BOB).await.unwrap();
+        presence.accept_buddy(BOB, ALICE).await.unwrap();
+        presence.add_buddy(ALICE, CAROL).await.unwrap();
+        presence.accept_buddy(CAROL, ALICE).await.unwrap();
+        presence.block_user(CAROL, ALICE).await.unwrap();
+
+        presence
+    }
+
+    fn message(text: &str, attachment: &[u8]) -> NewMessage {
+        NewMessage {
+            title_id: TITLE_ID,
+            sender: ALICE,
+            flags: 0x0001_0000 | MESSAGE_FLAG_READ,
+            ttl: None,
+            text: text.to_owned(),
+            attachment: attachment.to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn only_buddies_get_messages() {
+        let presence = presence().await;
+        let messaging = Messaging::new(Arc::new(MemoryStore::new()));
+
+        let deliveries = messaging.send(&presence, &message("gg", b"voice"), &[BOB, CAROL, DAVE, ALICE]).await.unwrap();
+
+        assert_eq!(deliveries, vec![
+            Delivery { recipient: BOB, result: Ok(1) },
+            Delivery { recipient: CAROL, result: Err(Refusal::Blocked) },
+            Delivery { recipient: DAVE, result: Err(Refusal::NotBuddies) },
+            Delivery { recipient: ALICE, result: Err(Refusal::OwnAccount) },
+        ]);
+
+        let inbox = messaging.inbox(BOB).await.unwrap();
+        assert_eq!(inbox.len(), 1);
+        assert_eq!(inbox[0].sender, ALICE);
+        assert_eq!(inbox[0].text, "gg");
+        assert_eq!(inbox[0].flags, 0x0001_0000 | MESSAGE_FLAG_HAS_ATTACHMENT);
+        assert_eq!(inbox[0].attachment_len, 5);
+        assert_eq!(messaging.attachment(BOB, 1).await.unwrap(), b"voice");
+
+        assert!(messaging.inbox(CAROL).await.unwrap().is_empty());
+        assert!(messaging.inbox(DAVE).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn messages_are_read_and_deleted() {
+        let presence = presence().await;
+        let messaging = Messaging::new(Arc::new(MemoryStore::new()));
+
+        messaging.send(&presence, &message("first", &[]), &[BOB]).await.unwrap();
+        messaging.send(&presence, &message("second", &[]), &[BOB]).await.unwrap();
+
+        let inbox = messaging.inbox(BOB).await.unwrap();
+        assert_eq!(inbox.iter().map(|message| message.text.as_str()).collect::<Vec<_>>(), vec!["second", "first"]);
+        assert!(matches!(messaging.attachment(BOB, 1).await, Err(MessagingError::NoSuchMessage)));
+
+        messaging.mark_read(BOB, 1).await.unwrap();
+        assert!(messaging.get(BOB, 1).await.unwrap().is_read());
+        assert!(!messaging.get(BOB, 2).await.unwrap().is_read());
+
+        // Messages are only there for their recipient.
+        assert!(matches!(messaging.get(ALICE, 1).await, Err(MessagingError::NoSuchMessage)));
+        assert!(matches!(messaging.delete(ALICE, 1).await, Err(MessagingError::NoSuchMessage)));
+
+        messaging.delete(BOB, 1).await.unwrap();
+        assert!(matches!(messaging.mark_read(BOB, 1).await, Err(MessagingError::NoSuchMessage)));
+        assert_eq!(messaging.inbox(BOB).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn expired_messages_are_hidden_and_reaped() {
+        let presence = presence().await;
+        let store = Arc::new(MemoryStore::new());
+        let messaging = Messaging::new(store.clone());
+
+        let short_lived = NewMessage {
+            ttl: Some(Duration::from_secs(60)),
+            ..message("soon gone", &[])
+        };
+
+        messaging.send(&presence, &short_lived, &[BOB]).await.unwrap();
+        messaging.send(&presence, &message("kept", &[]), &[BOB]).await.unwrap();
+
+        let later = SystemTime::now() + Duration::from_secs(120);
+        assert_eq!(store.list(BOB, later).await.unwrap().len(), 1);
+        assert_eq!(store.reap_expired(later).await.unwrap(), 1);
+        assert_eq!(messaging.inbox(BOB).await.unwrap()[0].text, "kept");
+    }
+
+    #[tokio::test]
+    async fn full_inboxes_refuse_messages() {
+        let presence = presence().await;
+        let messaging = Messaging::new(Arc::new(MemoryStore::new()));
+
+        for _ in 0..MAX_INBOX_MESSAGES {
+            messaging.send(&presence, &message("spam", &[]), &[BOB]).await.unwrap();
+        }
+
+        let deliveries = messaging.send(&presence, &message("spam", &[]), &[BOB]).await.unwrap();
+        assert_eq!(deliveries[0].result, Err(Refusal::InboxFull));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn racing_sends_cannot_overfill_inboxes() {
+        // Two SGs sharing one store, with the inbox one short of full.
+        let presence = Arc::new(presence().await);
+        let store = Arc::new(MemoryStore::new());
+        let messagings: Vec<_> = (0..2).map(|_| Arc::new(Messaging::new(store.clone()))).collect();
+
+        for _ in 1..MAX_INBOX_MESSAGES {
+            messagings[0].send(&presence, &message("spam", &[]), &[BOB]).await.unwrap();
+        }
+
+        let sends: Vec<_> = (0..8)
+            .map(|n| {
+                let messaging = messagings[n % 2].clone();
+                let presence = presence.clone();
+                tokio::spawn(async move {
+                    messaging.send(&presence, &message("spam", &[]), &[BOB]).await.unwrap()
+                })
+            })
+            .collect();
+
+        let mut delivered = 0;
+        for send in sends {
+            if send.await.unwrap()[0].result.is_ok() {
+                delivered += 1;
+            }
+        }
+
+        assert_eq!(delivered, 1);
+        assert_eq!(messagings[0].inbox(BOB).await.unwrap().len(), MAX_INBOX_MESSAGES as usize);
+    }
+
+    #[tokio::test]
+    async fn oversized_messages_are_rejected() {
+        let presence = presence().await;
+        let messaging = Messaging::new(Arc::new(MemoryStore::new()));
+
+        let recipients = vec![BOB; MAX_RECIPIENTS as usize + 1];
+        assert!(matches!(
+            messaging.send(&presence, &message("", &[]), &recipients).await,
+            Err(MessagingError::TooManyRecipients)
+        ));
+        assert!(matches!(
+            messaging.send(&presence, &message(&"a".repeat(MAX_TEXT_LEN + 1), &[]), &[BOB]).await,
+            Err(MessagingError::TextTooLong)
+        ));
+        assert!(matches!(
+            messaging.send(&presence, &message("", &vec![0; MAX_ATTACHMENT_LEN + 1]), &[BOB]).await,
+            Err(MessagingError::AttachmentTooLarge)
+        ));
+    }
+}
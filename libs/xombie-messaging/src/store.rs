@@ -0,0 +1,66 @@
+//! Where inboxes live.
+//!
+//! Messaging decides who may message whom; a MessageStore only keeps each
+//! recipient's copy of a message, and the attachment the copies share.
+//! Expired messages are never handed back, whether or not they've been
+//! reaped yet.
+
+use async_trait::async_trait;
+
+use std::time::SystemTime;
+
+use xbox_sys::account::Xuid;
+
+use crate::Message;
+
+pub mod memory;
+pub mod postgres;
+
+#[derive(Debug)]
+pub enum StoreError {
+    Pg(tokio_postgres::Error),
+    Pool(xombie::db::PoolError),
+    CannotParseXuid(std::num::ParseIntError),
+}
+
+impl From<tokio_postgres::Error> for StoreError {
+    fn from(pg_err: tokio_postgres::Error) -> Self {
+        StoreError::Pg(pg_err)
+    }
+}
+
+impl From<xombie::db::PoolError> for StoreError {
+    fn from(pool_err: xombie::db::PoolError) -> Self {
+        StoreError::Pool(pool_err)
+    }
+}
+
+#[async_trait]
+pub trait MessageStore: Send + Sync {
+    /// Put each of copies in its recipient's inbox, ignoring its id, as long
+    /// as the inbox holds fewer than max_inbox messages that haven't expired
+    /// by the time the copy was sent.  The check and the add are one step, so
+    /// sends racing each other, even through different SGs, can't overfill
+    /// an inbox.  The copies added share the one attachment.  Returns the id
+    /// each copy was given, in order, or None where the inbox was full.
+    async fn add(&self, copies: Vec<Message>, attachment: Option<Vec<u8>>, max_inbox: u32) -> Result<Vec<Option<u64>>, StoreError>;
+
+    /// recipient's inbox, newest message first.
+    async fn list(&self, recipient: Xuid, now: SystemTime) -> Result<Vec<Message>, StoreError>;
+
+    async fn get(&self, recipient: Xuid, id: u64, now: SystemTime) -> Result<Option<Message>, StoreError>;
+
+    /// None if there's no such message or it has no attachment.
+    async fn attachment(&self, recipient: Xuid, id: u64, now: SystemTime) -> Result<Option<Vec<u8>>, StoreError>;
+
+    /// Set flags on a message, leaving the others as they were.  Returns
+    /// false if there was no such message.
+    async fn set_flags(&self, recipient: Xuid, id: u64, flags: u32, now: SystemTime) -> Result<bool, StoreError>;
+
+    /// Returns false if there was no such message.
+    async fn delete(&self, recipient: Xuid, id: u64) -> Result<bool, StoreError>;
+
+    /// Drop every message that has expired by now, and any attachment no
+    /// message refers to any more.  Returns how many messages were dropped.
+    async fn reap_expired(&self, now: SystemTime) -> Result<u64, StoreError>;
+}
@@ -0,0 +1,177 @@
+use async_trait::async_trait;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use tokio::sync::Mutex;
+
+use xbox_sys::account::Xuid;
+
+use crate::Message;
+
+use super::{MessageStore, StoreError};
+
+/// Inboxes kept in process, lost when the SG restarts.
+pub struct MemoryStore {
+    state: Mutex<MemoryState>,
+}
+
+/// Each message with the attachment its copies share, which goes with the
+/// last of them.
+type StoredMessage = (Message, Option<Arc<Vec<u8>>>);
+
+#[derive(Default)]
+struct MemoryState {
+    last_id: u64,
+    messages: BTreeMap<u64, StoredMessage>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore {
+            state: Mutex::new(MemoryState::default()),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::new()
+    }
+}
+
+impl MemoryState {
+    fn find(&mut self, recipient: Xuid, id: u64, now: SystemTime) -> Option<&mut StoredMessage> {
+        self.messages.get_mut(&id)
+            .filter(|(message, _)| message.recipient == recipient && message.expires > now)
+    }
+}
+
+#[async_trait]
+impl MessageStore for MemoryStore {
+    async fn add(&self, copies: Vec<Message>, attachment: Option<Vec<u8>>, max_inbox: u32) -> Result<Vec<Option<u64>>, StoreError> {
+        let mut state = self.state.lock().await;
+        let attachment = attachment.map(Arc::new);
+        let mut ids = vec![];
+
+        for mut copy in copies {
+            let in_inbox = state.messages.values()
+                .filter(|(message, _)| message.recipient == copy.recipient && message.expires > copy.sent)
+                .count();
+
+            if in_inbox >= max_inbox as usize {
+                ids.push(None);
+                continue;
+            }
+
+            state.last_id += 1;
+            copy.id = state.last_id;
+            state.messages.insert(copy.id, (copy, attachment.clone()));
+
+            ids.push(Some(state.last_id));
+        }
+
+        Ok(ids)
+    }
+
+    async fn list(&self, recipient: Xuid, now: SystemTime) -> Result<Vec<Message>, StoreError> {
+        let state = self.state.lock().await;
+
+        Ok(state.messages.values()
+            .rev()
+            .map(|(message, _)| message)
+            .filter(|message| message.recipient == recipient && message.expires > now)
+            .cloned()
+            .collect())
+    }
+
+    async fn get(&self, recipient: Xuid, id: u64, now: SystemTime) -> Result<Option<Message>, StoreError> {
+        Ok(self.state.lock().await.find(recipient, id, now)
+            .map(|(message, _)| message.clone()))
+    }
+
+    async fn attachment(&self, recipient: Xuid, id: u64, now: SystemTime) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self.state.lock().await.find(recipient, id, now)
+            .and_then(|(_, attachment)| attachment.as_deref().cloned()))
+    }
+
+    async fn set_flags(&self, recipient: Xuid, id: u64, flags: u32, now: SystemTime) -> Result<bool, StoreError> {
+        let mut state = self.state.lock().await;
+
+        match state.find(recipient, id, now) {
+            Some((message, _)) => {
+                message.flags |= flags;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete(&self, recipient: Xuid, id: u64) -> Result<bool, StoreError> {
+        let mut state = self.state.lock().await;
+
+        match state.messages.get(&id) {
+            Some((message, _)) if message.recipient == recipient => {
+                state.messages.remove(&id);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn reap_expired(&self, now: SystemTime) -> Result<u64, StoreError> {
+        let mut state = self.state.lock().await;
+        let before = state.messages.len();
+
+        state.messages.retain(|_, (message, _)| message.expires > now);
+
+        Ok((before - state.messages.len()) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn copy(recipient: Xuid, sent: SystemTime) -> Message {
+        Message {
+            id: 0,
+            recipient,
+            sender: Xuid(0x0009_0000_0000_0001),
+            title_id: 0x4C41000B,
+            flags: 0,
+            text: "gg".to_owned(),
+            attachment_len: 5,
+            sent,
+            expires: sent + Duration::from_secs(60),
+        }
+    }
+
+    #[tokio::test]
+    async fn copies_share_their_attachment() {
+        let store = MemoryStore::new();
+        let now = SystemTime::now();
+        let bob = Xuid(0x0009_0000_0000_0002);
+        let carol = Xuid(0x0009_0000_0000_0003);
+
+        let ids = store.add(vec![copy(bob, now), copy(carol, now)], Some(b"voice".to_vec()), 1).await.unwrap();
+        assert_eq!(ids, vec![Some(1), Some(2)]);
+
+        {
+            let state = store.state.lock().await;
+            let attachments: Vec<_> = state.messages.values().map(|(_, attachment)| attachment.clone().unwrap()).collect();
+            assert!(Arc::ptr_eq(&attachments[0], &attachments[1]));
+        }
+
+        // Full inboxes are skipped, and the attachment outlives the copy
+        // that's deleted.
+        let ids = store.add(vec![copy(bob, now)], None, 1).await.unwrap();
+        assert_eq!(ids, vec![None]);
+
+        store.delete(bob, 1).await.unwrap();
+        assert_eq!(store.attachment(carol, 2, now).await.unwrap().unwrap(), b"voice");
+    }
+}
@@ -0,0 +1,117 @@
+use async_trait::async_trait;
+
+use std::time::SystemTime;
+
+use xbox_sys::account::Xuid;
+
+use xombie::db::{self, MessageError, MessageRecord, Pool};
+
+use crate::Message;
+
+use super::{MessageStore, StoreError};
+
+/// Inboxes kept in the database so messages outlive the SG.
+pub struct PostgresStore {
+    pool: Pool,
+}
+
+impl PostgresStore {
+    /// Use connections from pool for message storage.  Its tables come from
+    /// schema/0006_messages.sql and schema/0010_message_attachments.sql.
+    pub fn new(pool: Pool) -> Self {
+        PostgresStore {
+            pool,
+        }
+    }
+}
+
+impl From<MessageError> for StoreError {
+    fn from(err: MessageError) -> Self {
+        match err {
+            MessageError::Pg(pg_err) => StoreError::Pg(pg_err),
+            MessageError::CannotParseXuid(parse_err) => StoreError::CannotParseXuid(parse_err),
+        }
+    }
+}
+
+impl From<MessageRecord> for Message {
+    fn from(record: MessageRecord) -> Self {
+        Message {
+            id: record.id,
+            recipient: record.recipient,
+            sender: record.sender,
+            title_id: record.title_id,
+            flags: record.flags,
+            text: record.text,
+            attachment_len: record.attachment_len,
+            sent: record.sent,
+            expires: record.expires,
+        }
+    }
+}
+
+impl From<Message> for MessageRecord {
+    fn from(message: Message) -> Self {
+        MessageRecord {
+            id: message.id,
+            recipient: message.recipient,
+            sender: message.sender,
+            title_id: message.title_id,
+            flags: message.flags,
+            text: message.text,
+            attachment_len: message.attachment_len,
+            sent: message.sent,
+            expires: message.expires,
+        }
+    }
+}
+
+#[async_trait]
+impl MessageStore for PostgresStore {
+    async fn add(&self, copies: Vec<Message>, attachment: Option<Vec<u8>>, max_inbox: u32) -> Result<Vec<Option<u64>>, StoreError> {
+        let mut client = self.pool.get().await?;
+        let records: Vec<MessageRecord> = copies.into_iter().map(MessageRecord::from).collect();
+
+        Ok(db::put_message_records(&mut client, &records, attachment.as_deref(), max_inbox).await?)
+    }
+
+    async fn list(&self, recipient: Xuid, now: SystemTime) -> Result<Vec<Message>, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::get_message_records(&client, recipient, now).await?
+            .into_iter()
+            .map(Message::from)
+            .collect())
+    }
+
+    async fn get(&self, recipient: Xuid, id: u64, now: SystemTime) -> Result<Option<Message>, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::get_message_record(&client, recipient, id, now).await?
+            .map(Message::from))
+    }
+
+    async fn attachment(&self, recipient: Xuid, id: u64, now: SystemTime) -> Result<Option<Vec<u8>>, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::get_message_attachment(&client, recipient, id, now).await?)
+    }
+
+    async fn set_flags(&self, recipient: Xuid, id: u64, flags: u32, now: SystemTime) -> Result<bool, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::set_message_flags(&client, recipient, id, flags, now).await?)
+    }
+
+    async fn delete(&self, recipient: Xuid, id: u64) -> Result<bool, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::delete_message_record(&client, recipient, id).await?)
+    }
+
+    async fn reap_expired(&self, now: SystemTime) -> Result<u64, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::delete_expired_message_records(&client, now).await?)
+    }
+}
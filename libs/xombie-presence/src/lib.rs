@@ -0,0 +1,819 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use xblive::addr::Addr;
+use xblive::crypto::primitives::KeyId;
+use xblive::service::presence::{BUDDY_STATUS_OK, BUDDY_STATUS_PENDING, BUDDY_STATUS_REQUEST, MAX_BLOCKS, MAX_BUDDIES};
+
+use xbox_sys::account::Xuid;
+
+pub mod store;
+
+use store::{ListStore, PresenceStore, StoreError};
+
+/// How long an invitation is held for an invitee who isn't online before
+/// it's dropped, as the session it was for has likely ended by then.
+pub const INVITATION_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// What's known about a user from the presence messages their console sends.
+/// Kept around once they go offline so their last title and session can still
+/// be reported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UserPresence {
+    pub user: Xuid,
+    /// The console the user was last signed in on.
+    pub machine: Xuid,
+    /// The SG connection from machine the user was last seen through, so a
+    /// console's old connection closing after it reconnected doesn't take
+    /// the user offline.
+    pub connection: u64,
+    /// The slot the user is signed in to on machine, if they got one.
+    pub user_index: Option<u8>,
+    pub online: bool,
+    /// The state flags from the user's last Alive.
+    pub state: u32,
+    pub title_id: u32,
+    /// Zero until an Alive2 reports the version.
+    pub title_version: u32,
+    pub xnaddr: Option<Addr>,
+    pub xnkid: Option<KeyId>,
+    pub match_session_id: u64,
+    pub nickname: Vec<u8>,
+    /// The title's rich presence, opaque to everything but the title.
+    /// Dropped when the user switches titles.
+    pub title_stuff: Vec<u8>,
+    pub last_seen: SystemTime,
+}
+
+/// A buddy, along with their presence if they're online.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BuddyPresence {
+    pub xuid: Xuid,
+    pub presence: Option<UserPresence>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ListVersions {
+    pub buddy_list: u32,
+    pub block_list: u32,
+}
+
+/// Where a buddy list entry stands, from the point of view of the list's
+/// owner.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BuddyStatus {
+    /// Both sides are buddies.
+    Ok,
+    /// The owner asked and is waiting on the buddy.
+    Pending,
+    /// The buddy asked and is waiting on the owner.
+    Request,
+}
+
+impl BuddyStatus {
+    pub fn code(self) -> u8 {
+        match self {
+            BuddyStatus::Ok => BUDDY_STATUS_OK,
+            BuddyStatus::Pending => BUDDY_STATUS_PENDING,
+            BuddyStatus::Request => BUDDY_STATUS_REQUEST,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            BUDDY_STATUS_OK => Some(BuddyStatus::Ok),
+            BUDDY_STATUS_PENDING => Some(BuddyStatus::Pending),
+            BUDDY_STATUS_REQUEST => Some(BuddyStatus::Request),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Buddy {
+    pub xuid: Xuid,
+    pub gamertag: String,
+    pub status: BuddyStatus,
+}
+
+/// An invitation into host's session, waiting to be passed on to the
+/// invitee.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Invitation {
+    pub host: Xuid,
+    pub title_id: u32,
+    pub xnkid: KeyId,
+    pub sent: SystemTime,
+}
+
+/// Why a change to a buddy or block list, or an invitation, was refused.
+#[derive(Debug)]
+pub enum ListError {
+    OwnAccount,
+    NoSuchUser,
+    ListFull,
+    /// Accepting or rejecting a buddy who never asked.
+    NoRequest,
+    /// One of the two users has blocked the other.
+    Blocked,
+    /// Only buddies can be invited.
+    NotBuddies,
+    Store(StoreError),
+}
+
+impl From<StoreError> for ListError {
+    fn from(err: StoreError) -> Self {
+        ListError::Store(err)
+    }
+}
+
+/// The part of a user's presence reported by a single message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PresenceUpdate {
+    /// From an Alive: what the user's doing.
+    Activity {
+        title_id: u32,
+        state: u32,
+        match_session_id: u64,
+        nickname: Vec<u8>,
+        title_stuff: Vec<u8>,
+    },
+    /// From an Alive2: what the console's running and how to reach it.
+    Connection {
+        title_id: u32,
+        title_version: u32,
+        xnaddr: Addr,
+        xnkid: KeyId,
+    },
+}
+
+pub struct Presence {
+    store: Arc<dyn PresenceStore>,
+    lists: Arc<dyn ListStore>,
+}
+
+impl Debug for Presence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Presence{{}}")
+    }
+}
+
+impl Presence {
+    pub fn new(store: Arc<dyn PresenceStore>, lists: Arc<dyn ListStore>) -> Self {
+        Presence {
+            store,
+            lists,
+        }
+    }
+
+    /// Record that user is online on machine through connection, along with
+    /// whatever their console just reported.  Returns the versions of the
+    /// user's lists, for the console to compare against its own.
+    pub async fn user_alive(&self, machine: Xuid, connection: u64, user_index: Option<u8>, user: Xuid, update: PresenceUpdate) -> Result<ListVersions, StoreError> {
+        let now = SystemTime::now();
+
+        let mut presence = match self.store.get(user).await? {
+            Some(presence) if presence.machine == machine => presence,
+            // A user signing in somewhere else doesn't carry anything over
+            // from the console they were last on.
+            _ => UserPresence {
+                user,
+                machine,
+                connection,
+                user_index,
+                online: true,
+                state: 0,
+                title_id: 0,
+                title_version: 0,
+                xnaddr: None,
+                xnkid: None,
+                match_session_id: 0,
+                nickname: vec![],
+                title_stuff: vec![],
+                last_seen: now,
+            },
+        };
+
+        presence.connection = connection;
+        presence.user_index = user_index;
+        presence.online = true;
+        presence.last_seen = now;
+
+        match update {
+            PresenceUpdate::Activity { title_id, state, match_session_id, nickname, title_stuff } => {
+                if presence.title_id != title_id {
+                    presence.title_id = title_id;
+                    presence.title_version = 0;
+                }
+                presence.state = state;
+                presence.match_session_id = match_session_id;
+                presence.nickname = nickname;
+                presence.title_stuff = title_stuff;
+            }
+            PresenceUpdate::Connection { title_id, title_version, xnaddr, xnkid } => {
+                if presence.title_id != title_id {
+                    presence.title_stuff = vec![];
+                }
+                presence.title_id = title_id;
+                presence.title_version = title_version;
+                presence.xnaddr = Some(xnaddr);
+                presence.xnkid = Some(xnkid);
+            }
+        }
+
+        self.store.put(presence).await?;
+
+        self.lists.list_versions(user).await
+    }
+
+    /// Record that user signed out of machine.  Returns false if they weren't
+    /// online there.
+    pub async fn user_gone(&self, machine: Xuid, user: Xuid) -> Result<bool, StoreError> {
+        self.store.set_offline(user, machine, None, SystemTime::now()).await
+    }
+
+    /// Mark every user last seen through connection from machine offline, for
+    /// when that connection goes away.  Users the machine has since been seen
+    /// with through a newer connection stay online.  Returns how many users
+    /// went offline.
+    pub async fn machine_gone(&self, machine: Xuid, connection: u64) -> Result<usize, StoreError> {
+        let now = SystemTime::now();
+        let mut gone = 0;
+
+        for user in self.store.online_users_on_machine(machine).await? {
+            if self.store.set_offline(user, machine, Some(connection), now).await? {
+                gone += 1;
+            }
+        }
+
+        Ok(gone)
+    }
+
+    pub async fn get(&self, user: Xuid) -> Result<Option<UserPresence>, StoreError> {
+        self.store.get(user).await
+    }
+
+    pub async fn list_versions(&self, user: Xuid) -> Result<ListVersions, StoreError> {
+        self.lists.list_versions(user).await
+    }
+
+    pub async fn buddies(&self, user: Xuid) -> Result<Vec<Buddy>, StoreError> {
+        self.lists.buddies(user).await
+    }
+
+    /// user asks buddy to be buddies.  If buddy already asked user, they
+    /// become buddies straight away.  Returns where the friendship stands for
+    /// user afterwards.
+    pub async fn add_buddy(&self, user: Xuid, buddy: Xuid) -> Result<BuddyStatus, ListError> {
+        if user == buddy {
+            return Err(ListError::OwnAccount);
+        }
+
+        if self.lists.gamertag(buddy).await?.is_none() {
+            return Err(ListError::NoSuchUser);
+        }
+
+        if self.blocked_between(user, buddy).await? {
+            return Err(ListError::Blocked);
+        }
+
+        let buddies = self.lists.buddies(user).await?;
+
+        match buddy_status(&buddies, buddy) {
+            Some(BuddyStatus::Request) => {
+                self.set_friendship(user, buddy, BuddyStatus::Ok, BuddyStatus::Ok).await?;
+                return Ok(BuddyStatus::Ok);
+            }
+            Some(status) => return Ok(status),
+            None => {}
+        }
+
+        if buddies.len() >= MAX_BUDDIES || self.lists.buddies(buddy).await?.len() >= MAX_BUDDIES {
+            return Err(ListError::ListFull);
+        }
+
+        self.set_friendship(user, buddy, BuddyStatus::Pending, BuddyStatus::Request).await?;
+
+        Ok(BuddyStatus::Pending)
+    }
+
+    /// user agrees to buddy's request.
+    pub async fn accept_buddy(&self, user: Xuid, buddy: Xuid) -> Result<(), ListError> {
+        self.check_request(user, buddy).await?;
+
+        self.set_friendship(user, buddy, BuddyStatus::Ok, BuddyStatus::Ok).await?;
+
+        Ok(())
+    }
+
+    /// user turns down buddy's request, taking it off both lists.
+    pub async fn reject_buddy(&self, user: Xuid, buddy: Xuid) -> Result<(), ListError> {
+        self.check_request(user, buddy).await?;
+
+        self.delete_buddy(user, buddy).await?;
+
+        Ok(())
+    }
+
+    /// End a friendship, or withdraw a request, from either side.  Returns
+    /// false if the two weren't on each other's lists.
+    pub async fn delete_buddy(&self, user: Xuid, buddy: Xuid) -> Result<bool, StoreError> {
+        let removed = self.lists.remove_buddy(user, buddy).await?;
+        let removed_other = self.lists.remove_buddy(buddy, user).await?;
+
+        Ok(removed || removed_other)
+    }
+
+    pub async fn blocks(&self, user: Xuid) -> Result<Vec<Xuid>, StoreError> {
+        self.lists.blocks(user).await
+    }
+
+    /// user blocks blocked, ending any friendship or request between them.
+    /// Returns false if blocked was already blocked.
+    pub async fn block_user(&self, user: Xuid, blocked: Xuid) -> Result<bool, ListError> {
+        if user == blocked {
+            return Err(ListError::OwnAccount);
+        }
+
+        if self.lists.gamertag(blocked).await?.is_none() {
+            return Err(ListError::NoSuchUser);
+        }
+
+        let blocks = self.lists.blocks(user).await?;
+        if blocks.contains(&blocked) {
+            return Ok(false);
+        }
+
+        if blocks.len() >= MAX_BLOCKS {
+            return Err(ListError::ListFull);
+        }
+
+        self.lists.block(user, blocked).await?;
+        self.delete_buddy(user, blocked).await?;
+
+        Ok(true)
+    }
+
+    /// Returns false if blocked wasn't blocked.
+    pub async fn unblock_user(&self, user: Xuid, blocked: Xuid) -> Result<bool, StoreError> {
+        self.lists.unblock(user, blocked).await
+    }
+
+    /// Whether either user has blocked the other.  Blocked users can't ask
+    /// each other to be buddies, invite each other, or see each other.
+    pub async fn blocked_between(&self, user: Xuid, other: Xuid) -> Result<bool, StoreError> {
+        Ok(self.lists.blocks(user).await?.contains(&other)
+            || self.lists.blocks(other).await?.contains(&user))
+    }
+
+    /// The presence of user as viewer is allowed to see it, which is not at
+    /// all if either has blocked the other.
+    pub async fn visible_presence(&self, viewer: Xuid, user: Xuid) -> Result<Option<UserPresence>, StoreError> {
+        if self.blocked_between(viewer, user).await? {
+            return Ok(None);
+        }
+
+        self.store.get(user).await
+    }
+
+    /// What each of viewer's buddies is doing.  Only buddies who have agreed
+    /// to be buddies are included, and buddies viewer can't see because of a
+    /// block are left out entirely.
+    pub async fn peek(&self, viewer: Xuid) -> Result<Vec<BuddyPresence>, StoreError> {
+        let mut buddies = vec![];
+
+        for buddy in self.lists.buddies(viewer).await? {
+            if buddy.status != BuddyStatus::Ok || self.blocked_between(viewer, buddy.xuid).await? {
+                continue;
+            }
+
+            let presence = self.store.get(buddy.xuid).await?
+                .filter(|presence| presence.online);
+
+            buddies.push(BuddyPresence {
+                xuid: buddy.xuid,
+                presence,
+            });
+        }
+
+        Ok(buddies)
+    }
+
+    /// host invites invitee into the session xnkid of title_id.  The
+    /// invitation is held until removed with remove_invitation, once
+    /// invitee's console has been told or invitee has answered it.
+    pub async fn invite(&self, host: Xuid, invitee: Xuid, title_id: u32, xnkid: KeyId) -> Result<(), ListError> {
+        if host == invitee {
+            return Err(ListError::OwnAccount);
+        }
+
+        if self.blocked_between(host, invitee).await? {
+            return Err(ListError::Blocked);
+        }
+
+        if buddy_status(&self.lists.buddies(host).await?, invitee) != Some(BuddyStatus::Ok) {
+            return Err(ListError::NotBuddies);
+        }
+
+        let invitation = Invitation {
+            host,
+            title_id,
+            xnkid,
+            sent: SystemTime::now(),
+        };
+
+        self.store.add_invitation(invitee, invitation).await
+            .map_err(ListError::Store)
+    }
+
+    /// Drop the invitation from host into xnkid held for invitee, because the
+    /// host cancelled it, the invitee answered it or invitee's console
+    /// acknowledged being told about it.  Returns false if it had already
+    /// been dropped.
+    pub async fn remove_invitation(&self, invitee: Xuid, host: Xuid, xnkid: KeyId) -> Result<bool, StoreError> {
+        self.store.remove_invitation(invitee, host, xnkid).await
+    }
+
+    /// Every invitation held for invitee, oldest first.  Any that have
+    /// expired are dropped rather than returned.
+    pub async fn invitations(&self, invitee: Xuid) -> Result<Vec<Invitation>, StoreError> {
+        let now = SystemTime::now();
+
+        let mut invitations = vec![];
+
+        for invitation in self.store.invitations(invitee).await? {
+            let expired = match now.duration_since(invitation.sent) {
+                Ok(age) => age >= INVITATION_TTL,
+                Err(_) => false,
+            };
+
+            if expired {
+                self.store.remove_invitation(invitee, invitation.host, invitation.xnkid).await?;
+            } else {
+                invitations.push(invitation);
+            }
+        }
+
+        Ok(invitations)
+    }
+
+    async fn check_request(&self, user: Xuid, buddy: Xuid) -> Result<(), ListError> {
+        match buddy_status(&self.lists.buddies(user).await?, buddy) {
+            Some(BuddyStatus::Request) => Ok(()),
+            _ => Err(ListError::NoRequest),
+        }
+    }
+
+    async fn set_friendship(&self, user: Xuid, buddy: Xuid, status: BuddyStatus, buddy_status: BuddyStatus) -> Result<(), StoreError> {
+        self.lists.set_buddy(user, buddy, status).await?;
+        self.lists.set_buddy(buddy, user, buddy_status).await
+    }
+}
+
+fn buddy_status(buddies: &[Buddy], buddy: Xuid) -> Option<BuddyStatus> {
+    buddies.iter()
+        .find(|entry| entry.xuid == buddy)
+        .map(|entry| entry.status)
+}
+
+#[cfg(test)]
+mod tests {
+    use xblive::net::{Eui48, InAddr};
+
+    use crate::store::memory::{MemoryListStore, MemoryStore};
+
+    use super::*;
+
+    const MACHINE: Xuid = Xuid(0xFA00_0000_0000_0001);
+    const OTHER_MACHINE: Xuid = Xuid(0xFA00_0000_0000_0002);
+    const CONNECTION: u64 = 1;
+    const OTHER_CONNECTION: u64 = 2;
+
+    const USER: Xuid = Xuid(0x0009_0000_0000_0001);
+    const OTHER_USER: Xuid = Xuid(0x0009_0000_0000_0002);
+
+    const THIRD_USER: Xuid = Xuid(0x0009_0000_0000_0003);
+
+    fn presence() -> Presence {
+        Presence::new(Arc::new(MemoryStore::new()), Arc::new(MemoryListStore::new()))
+    }
+
+    async fn presence_with_accounts(accounts: &[Xuid]) -> Presence {
+        let lists = MemoryListStore::new();
+        for (n, account) in accounts.iter().enumerate() {
+            lists.add_account(*account, &format!("user{}", n)).await;
+        }
+
+        Presence::new(Arc::new(MemoryStore::new()), Arc::new(lists))
+    }
+
+    async fn statuses(presence: &Presence, user: Xuid) -> Vec<(Xuid, BuddyStatus)> {
+        presence.buddies(user).await.unwrap().iter()
+            .map(|buddy| (buddy.xuid, buddy.status))
+            .collect()
+    }
+
+    fn activity(title_id: u32, nickname: &[u8]) -> PresenceUpdate {
+        PresenceUpdate::Activity {
+            title_id,
+            state: 3,
+            match_session_id: 0x1122334455667788,
+            nickname: nickname.to_vec(),
+            title_stuff: vec![],
+        }
+    }
+
+    fn connection(title_id: u32, title_version: u32) -> PresenceUpdate {
+        PresenceUpdate::Connection {
+            title_id,
+            title_version,
+            xnaddr: Addr {
+                addr: InAddr([10, 0, 0, 2]),
+                addr_online: InAddr([192, 168, 0, 2]),
+                port_online: 3074,
+                enet: Eui48([0, 0x50, 0xF2, 0, 0, 2]),
+                online: [0; 20],
+            },
+            xnkid: KeyId([7; 8]),
+        }
+    }
+
+    #[tokio::test]
+    async fn alives_are_merged() {
+        let presence = presence();
+
+        assert_eq!(presence.get(USER).await.unwrap(), None);
+
+        let versions = presence.user_alive(MACHINE, CONNECTION, Some(0), USER, connection(0x4C41000B, 0x10000)).await.unwrap();
+        assert_eq!(versions, ListVersions::default());
+
+        presence.user_alive(MACHINE, CONNECTION, Some(0), USER, activity(0x4C41000B, b"mc")).await.unwrap();
+
+        let user = presence.get(USER).await.unwrap().unwrap();
+        assert!(user.online);
+        assert_eq!(user.machine, MACHINE);
+        assert_eq!((user.title_id, user.title_version), (0x4C41000B, 0x10000));
+        assert_eq!(user.xnkid, Some(KeyId([7; 8])));
+        assert_eq!((user.state, user.match_session_id), (3, 0x1122334455667788));
+        assert_eq!(user.nickname, b"mc");
+
+        // Switching titles forgets the old title's version until the next
+        // Alive2.
+        presence.user_alive(MACHINE, CONNECTION, Some(0), USER, activity(0x4D530004, b"")).await.unwrap();
+
+        let user = presence.get(USER).await.unwrap().unwrap();
+        assert_eq!((user.title_id, user.title_version), (0x4D530004, 0));
+        assert_eq!(user.xnkid, Some(KeyId([7; 8])));
+    }
+
+    #[tokio::test]
+    async fn users_go_offline_with_their_machine() {
+        let presence = presence();
+
+        presence.user_alive(MACHINE, CONNECTION, Some(0), USER, activity(0x4C41000B, b"")).await.unwrap();
+        presence.user_alive(MACHINE, CONNECTION, Some(0), OTHER_USER, activity(0x4C41000B, b"")).await.unwrap();
+
+        assert!(presence.user_gone(MACHINE, OTHER_USER).await.unwrap());
+        assert!(!presence.user_gone(MACHINE, OTHER_USER).await.unwrap());
+
+        assert_eq!(presence.machine_gone(MACHINE, CONNECTION).await.unwrap(), 1);
+        assert_eq!(presence.machine_gone(MACHINE, CONNECTION).await.unwrap(), 0);
+
+        let user = presence.get(USER).await.unwrap().unwrap();
+        assert!(!user.online);
+        assert_eq!(user.title_id, 0x4C41000B);
+    }
+
+    #[tokio::test]
+    async fn an_old_connection_closing_leaves_users_online() {
+        let presence = presence();
+
+        presence.user_alive(MACHINE, CONNECTION, Some(0), USER, activity(0x4C41000B, b"")).await.unwrap();
+        presence.user_alive(MACHINE, CONNECTION + 10, Some(0), USER, activity(0x4C41000B, b"")).await.unwrap();
+
+        assert_eq!(presence.machine_gone(MACHINE, CONNECTION).await.unwrap(), 0);
+        assert!(presence.get(USER).await.unwrap().unwrap().online);
+
+        assert_eq!(presence.machine_gone(MACHINE, CONNECTION + 10).await.unwrap(), 1);
+        assert!(!presence.get(USER).await.unwrap().unwrap().online);
+    }
+
+    #[tokio::test]
+    async fn only_the_current_machine_can_sign_a_user_out() {
+        let presence = presence();
+
+        presence.user_alive(MACHINE, CONNECTION, Some(0), USER, connection(0x4C41000B, 0x10000)).await.unwrap();
+        presence.user_alive(OTHER_MACHINE, OTHER_CONNECTION, Some(0), USER, activity(0x4C41000B, b"")).await.unwrap();
+
+        let user = presence.get(USER).await.unwrap().unwrap();
+        assert_eq!(user.machine, OTHER_MACHINE);
+        assert_eq!(user.xnaddr, None);
+
+        assert!(!presence.user_gone(MACHINE, USER).await.unwrap());
+        assert_eq!(presence.machine_gone(MACHINE, CONNECTION).await.unwrap(), 0);
+        assert!(presence.get(USER).await.unwrap().unwrap().online);
+    }
+
+    #[tokio::test]
+    async fn buddy_requests_are_accepted_from_the_other_side() {
+        let presence = presence_with_accounts(&[USER, OTHER_USER]).await;
+
+        assert_eq!(presence.add_buddy(USER, OTHER_USER).await.unwrap(), BuddyStatus::Pending);
+        assert_eq!(statuses(&presence, USER).await, vec![(OTHER_USER, BuddyStatus::Pending)]);
+        assert_eq!(statuses(&presence, OTHER_USER).await, vec![(USER, BuddyStatus::Request)]);
+
+        // Only the user who was asked can accept.
+        assert!(matches!(presence.accept_buddy(USER, OTHER_USER).await, Err(ListError::NoRequest)));
+
+        presence.accept_buddy(OTHER_USER, USER).await.unwrap();
+        assert_eq!(statuses(&presence, USER).await, vec![(OTHER_USER, BuddyStatus::Ok)]);
+        assert_eq!(statuses(&presence, OTHER_USER).await, vec![(USER, BuddyStatus::Ok)]);
+
+        let versions = presence.list_versions(USER).await.unwrap();
+        assert_eq!(versions.buddy_list, 2);
+
+        assert!(presence.delete_buddy(OTHER_USER, USER).await.unwrap());
+        assert!(!presence.delete_buddy(OTHER_USER, USER).await.unwrap());
+        assert_eq!(statuses(&presence, USER).await, vec![]);
+        assert_eq!(presence.list_versions(USER).await.unwrap().buddy_list, 3);
+    }
+
+    #[tokio::test]
+    async fn crossed_buddy_requests_become_a_friendship() {
+        let presence = presence_with_accounts(&[USER, OTHER_USER]).await;
+
+        presence.add_buddy(USER, OTHER_USER).await.unwrap();
+        assert_eq!(presence.add_buddy(OTHER_USER, USER).await.unwrap(), BuddyStatus::Ok);
+
+        assert_eq!(statuses(&presence, USER).await, vec![(OTHER_USER, BuddyStatus::Ok)]);
+
+        // Asking again changes nothing.
+        assert_eq!(presence.add_buddy(USER, OTHER_USER).await.unwrap(), BuddyStatus::Ok);
+    }
+
+    #[tokio::test]
+    async fn rejected_requests_leave_both_lists() {
+        let presence = presence_with_accounts(&[USER, OTHER_USER, THIRD_USER]).await;
+
+        presence.add_buddy(USER, OTHER_USER).await.unwrap();
+        presence.add_buddy(THIRD_USER, OTHER_USER).await.unwrap();
+
+        presence.reject_buddy(OTHER_USER, USER).await.unwrap();
+
+        assert_eq!(statuses(&presence, USER).await, vec![]);
+        assert_eq!(statuses(&presence, OTHER_USER).await, vec![(THIRD_USER, BuddyStatus::Request)]);
+
+        assert!(matches!(presence.reject_buddy(OTHER_USER, USER).await, Err(ListError::NoRequest)));
+    }
+
+    #[tokio::test]
+    async fn bad_buddy_requests_are_refused() {
+        let presence = presence_with_accounts(&[USER]).await;
+
+        assert!(matches!(presence.add_buddy(USER, USER).await, Err(ListError::OwnAccount)));
+        assert!(matches!(presence.add_buddy(USER, OTHER_USER).await, Err(ListError::NoSuchUser)));
+
+        let lists = MemoryListStore::new();
+        lists.add_account(USER, "user").await;
+        lists.add_account(OTHER_USER, "other").await;
+        for n in 0..MAX_BUDDIES as u64 {
+            lists.set_buddy(USER, Xuid(0x0009_1000_0000_0000 + n), BuddyStatus::Ok).await.unwrap();
+        }
+
+        let presence = Presence::new(Arc::new(MemoryStore::new()), Arc::new(lists));
+        assert!(matches!(presence.add_buddy(USER, OTHER_USER).await, Err(ListError::ListFull)));
+        assert!(matches!(presence.add_buddy(OTHER_USER, USER).await, Err(ListError::ListFull)));
+    }
+
+    #[tokio::test]
+    async fn blocking_ends_friendships_and_refuses_requests() {
+        let presence = presence_with_accounts(&[USER, OTHER_USER]).await;
+
+        presence.add_buddy(USER, OTHER_USER).await.unwrap();
+        presence.accept_buddy(OTHER_USER, USER).await.unwrap();
+        presence.user_alive(MACHINE, CONNECTION, Some(0), USER, activity(0x4C41000B, b"")).await.unwrap();
+
+        assert!(presence.block_user(OTHER_USER, USER).await.unwrap());
+        assert!(!presence.block_user(OTHER_USER, USER).await.unwrap());
+
+        assert_eq!(statuses(&presence, USER).await, vec![]);
+        assert_eq!(statuses(&presence, OTHER_USER).await, vec![]);
+        assert_eq!(presence.blocks(OTHER_USER).await.unwrap(), vec![USER]);
+        assert_eq!(presence.list_versions(OTHER_USER).await.unwrap().block_list, 1);
+
+        // Neither side can ask again, and neither can see the other.
+        assert!(matches!(presence.add_buddy(USER, OTHER_USER).await, Err(ListError::Blocked)));
+        assert!(matches!(presence.add_buddy(OTHER_USER, USER).await, Err(ListError::Blocked)));
+        assert_eq!(presence.visible_presence(OTHER_USER, USER).await.unwrap(), None);
+        assert!(presence.visible_presence(THIRD_USER, USER).await.unwrap().is_some());
+
+        assert!(presence.unblock_user(OTHER_USER, USER).await.unwrap());
+        assert!(!presence.unblock_user(OTHER_USER, USER).await.unwrap());
+        assert_eq!(presence.list_versions(OTHER_USER).await.unwrap().block_list, 2);
+        assert_eq!(presence.add_buddy(USER, OTHER_USER).await.unwrap(), BuddyStatus::Pending);
+    }
+
+    #[tokio::test]
+    async fn bad_blocks_are_refused() {
+        let presence = presence_with_accounts(&[USER]).await;
+
+        assert!(matches!(presence.block_user(USER, USER).await, Err(ListError::OwnAccount)));
+        assert!(matches!(presence.block_user(USER, OTHER_USER).await, Err(ListError::NoSuchUser)));
+    }
+
+    #[tokio::test]
+    async fn invitations_are_held_until_removed() {
+        let presence = presence_with_accounts(&[USER, OTHER_USER, THIRD_USER]).await;
+
+        presence.add_buddy(USER, OTHER_USER).await.unwrap();
+        presence.accept_buddy(OTHER_USER, USER).await.unwrap();
+
+        let xnkid = KeyId([1; 8]);
+        let other_xnkid = KeyId([2; 8]);
+
+        presence.invite(USER, OTHER_USER, 0x4C41000B, xnkid).await.unwrap();
+        presence.invite(USER, OTHER_USER, 0x4C41000B, xnkid).await.unwrap();
+        presence.invite(USER, OTHER_USER, 0x4C41000B, other_xnkid).await.unwrap();
+
+        assert!(presence.remove_invitation(OTHER_USER, USER, other_xnkid).await.unwrap());
+        assert!(!presence.remove_invitation(OTHER_USER, USER, other_xnkid).await.unwrap());
+
+        // Reading them doesn't let them go.
+        for _ in 0..2 {
+            let invitations = presence.invitations(OTHER_USER).await.unwrap();
+            assert_eq!(invitations.len(), 1);
+            assert_eq!((invitations[0].host, invitations[0].title_id, invitations[0].xnkid), (USER, 0x4C41000B, xnkid));
+        }
+
+        assert!(presence.remove_invitation(OTHER_USER, USER, xnkid).await.unwrap());
+        assert_eq!(presence.invitations(OTHER_USER).await.unwrap(), vec![]);
+    }
+
+    #[tokio::test]
+    async fn expired_invitations_are_dropped() {
+        let store = Arc::new(MemoryStore::new());
+        let presence = Presence::new(store.clone(), Arc::new(MemoryListStore::new()));
+
+        store.add_invitation(OTHER_USER, Invitation {
+            host: USER,
+            title_id: 0x4C41000B,
+            xnkid: KeyId([1; 8]),
+            sent: SystemTime::now() - INVITATION_TTL,
+        }).await.unwrap();
+
+        assert_eq!(presence.invitations(OTHER_USER).await.unwrap(), vec![]);
+        assert_eq!(store.invitations(OTHER_USER).await.unwrap(), vec![]);
+    }
+
+    #[tokio::test]
+    async fn only_buddies_can_be_invited() {
+        let presence = presence_with_accounts(&[USER, OTHER_USER, THIRD_USER]).await;
+
+        presence.add_buddy(USER, OTHER_USER).await.unwrap();
+
+        assert!(matches!(presence.invite(USER, USER, 0x4C41000B, KeyId([1; 8])).await, Err(ListError::OwnAccount)));
+        assert!(matches!(presence.invite(USER, OTHER_USER, 0x4C41000B, KeyId([1; 8])).await, Err(ListError::NotBuddies)));
+        assert!(matches!(presence.invite(USER, THIRD_USER, 0x4C41000B, KeyId([1; 8])).await, Err(ListError::NotBuddies)));
+
+        presence.block_user(THIRD_USER, USER).await.unwrap();
+        assert!(matches!(presence.invite(USER, THIRD_USER, 0x4C41000B, KeyId([1; 8])).await, Err(ListError::Blocked)));
+
+        assert_eq!(presence.invitations(OTHER_USER).await.unwrap(), vec![]);
+    }
+
+    #[tokio::test]
+    async fn peek_shows_what_buddies_are_playing() {
+        let presence = presence_with_accounts(&[USER, OTHER_USER, THIRD_USER]).await;
+
+        presence.add_buddy(USER, OTHER_USER).await.unwrap();
+        presence.accept_buddy(OTHER_USER, USER).await.unwrap();
+        presence.add_buddy(USER, THIRD_USER).await.unwrap();
+
+        presence.user_alive(OTHER_MACHINE, OTHER_CONNECTION, Some(1), OTHER_USER, PresenceUpdate::Activity {
+            title_id: 0x4C41000B,
+            state: 3,
+            match_session_id: 0,
+            nickname: b"mc".to_vec(),
+            title_stuff: vec![0xAA, 0xBB],
+        }).await.unwrap();
+        presence.user_alive(MACHINE, CONNECTION, Some(0), THIRD_USER, activity(0x4C41000B, b"")).await.unwrap();
+
+        // Pending buddies aren't peeked.
+        let peeked = presence.peek(USER).await.unwrap();
+        assert_eq!(peeked.len(), 1);
+        assert_eq!(peeked[0].xuid, OTHER_USER);
+
+        let other = peeked[0].presence.as_ref().unwrap();
+        assert_eq!((other.title_id, other.state), (0x4C41000B, 3));
+        assert_eq!((other.nickname.as_slice(), other.title_stuff.as_slice()), (&b"mc"[..], &[0xAA, 0xBB][..]));
+
+        // A new title's Alive2 drops the old title's rich presence.
+        presence.user_alive(OTHER_MACHINE, OTHER_CONNECTION, Some(1), OTHER_USER, connection(0x4D530004, 0x102)).await.unwrap();
+        let peeked = presence.peek(USER).await.unwrap();
+        assert_eq!(peeked[0].presence.as_ref().unwrap().title_stuff, Vec::<u8>::new());
+
+        presence.user_gone(OTHER_MACHINE, OTHER_USER).await.unwrap();
+        assert_eq!(presence.peek(USER).await.unwrap(), vec![BuddyPresence { xuid: OTHER_USER, presence: None }]);
+    }
+}
@@ -0,0 +1,99 @@
+//! Where presence lives.
+//!
+//! Presence decides what an Alive or a disconnect means for a user; a
+//! PresenceStore only keeps the result.  Buddy and block lists outlive
+//! any one connection, so they're kept separately in a ListStore.
+
+use async_trait::async_trait;
+
+use std::time::SystemTime;
+
+use xbox_sys::account::Xuid;
+
+use xblive::crypto::primitives::KeyId;
+
+use crate::{Buddy, BuddyStatus, Invitation, ListVersions, UserPresence};
+
+pub mod memory;
+pub mod postgres;
+
+#[derive(Debug)]
+pub enum StoreError {
+    Pg(tokio_postgres::Error),
+    Pool(xombie::db::PoolError),
+    CannotParseXuid(std::num::ParseIntError),
+    UnknownBuddyStatus(i16),
+    /// A stored address or key id that doesn't decode.
+    CorruptRecord,
+}
+
+impl From<tokio_postgres::Error> for StoreError {
+    fn from(pg_err: tokio_postgres::Error) -> Self {
+        StoreError::Pg(pg_err)
+    }
+}
+
+impl From<xombie::db::PoolError> for StoreError {
+    fn from(pool_err: xombie::db::PoolError) -> Self {
+        StoreError::Pool(pool_err)
+    }
+}
+
+#[async_trait]
+pub trait PresenceStore: Send + Sync {
+    async fn get(&self, user: Xuid) -> Result<Option<UserPresence>, StoreError>;
+
+    /// Add or overwrite the presence of presence.user.
+    async fn put(&self, presence: UserPresence) -> Result<(), StoreError>;
+
+    /// Every user currently online through machine.
+    async fn online_users_on_machine(&self, machine: Xuid) -> Result<Vec<Xuid>, StoreError>;
+
+    /// Mark user offline as of now, as long as they're online on machine and,
+    /// if one is given, were last seen through connection.  Returns false,
+    /// leaving them be, otherwise.
+    async fn set_offline(&self, user: Xuid, machine: Xuid, connection: Option<u64>, now: SystemTime) -> Result<bool, StoreError>;
+
+    /// Hold an invitation until invitee's console has been told about it,
+    /// replacing any earlier one from the same host into the same session.
+    async fn add_invitation(&self, invitee: Xuid, invitation: Invitation) -> Result<(), StoreError>;
+
+    /// Every invitation held for invitee, oldest first.
+    async fn invitations(&self, invitee: Xuid) -> Result<Vec<Invitation>, StoreError>;
+
+    /// Returns false if no invitation from host into xnkid was held for
+    /// invitee.
+    async fn remove_invitation(&self, invitee: Xuid, host: Xuid, xnkid: KeyId) -> Result<bool, StoreError>;
+}
+
+#[async_trait]
+pub trait ListStore: Send + Sync {
+    /// Every entry on user's buddy list, with the status as user sees it.
+    async fn buddies(&self, user: Xuid) -> Result<Vec<Buddy>, StoreError>;
+
+    /// Add buddy to user's list or change its status, bumping the version of
+    /// user's buddy list.
+    async fn set_buddy(&self, user: Xuid, buddy: Xuid, status: BuddyStatus) -> Result<(), StoreError>;
+
+    /// Take buddy off user's list, bumping the version of user's buddy list.
+    /// Returns false if buddy wasn't on it.
+    async fn remove_buddy(&self, user: Xuid, buddy: Xuid) -> Result<bool, StoreError>;
+
+    /// Every user user has blocked.
+    async fn blocks(&self, user: Xuid) -> Result<Vec<Xuid>, StoreError>;
+
+    /// Add blocked to user's block list, bumping its version.  Returns false
+    /// if blocked was already on it.
+    async fn block(&self, user: Xuid, blocked: Xuid) -> Result<bool, StoreError>;
+
+    /// Take blocked off user's block list, bumping its version.  Returns
+    /// false if blocked wasn't on it.
+    async fn unblock(&self, user: Xuid, blocked: Xuid) -> Result<bool, StoreError>;
+
+    /// The versions of user's buddy and block lists, zero for lists that have
+    /// never changed.
+    async fn list_versions(&self, user: Xuid) -> Result<ListVersions, StoreError>;
+
+    /// The gamertag of an account, or None if there's no such account.
+    async fn gamertag(&self, user: Xuid) -> Result<Option<String>, StoreError>;
+}
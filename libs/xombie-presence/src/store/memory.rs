@@ -0,0 +1,245 @@
+use async_trait::async_trait;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::SystemTime;
+
+use tokio::sync::Mutex;
+
+use xblive::crypto::primitives::KeyId;
+
+use xbox_sys::account::Xuid;
+
+use crate::{Buddy, BuddyStatus, Invitation, ListVersions, UserPresence};
+
+use super::{ListStore, PresenceStore, StoreError};
+
+/// Presence kept in process, lost when the SG restarts.  Consoles send an
+/// Alive every few minutes, so online users reappear soon after a restart.
+/// Invitations are lost for good, but the sessions they were for usually end
+/// with the restart too.
+pub struct MemoryStore {
+    users: Mutex<BTreeMap<Xuid, UserPresence>>,
+    invitations: Mutex<BTreeMap<Xuid, Vec<Invitation>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore {
+            users: Mutex::new(BTreeMap::new()),
+            invitations: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::new()
+    }
+}
+
+#[async_trait]
+impl PresenceStore for MemoryStore {
+    async fn get(&self, user: Xuid) -> Result<Option<UserPresence>, StoreError> {
+        Ok(self.users.lock().await.get(&user).cloned())
+    }
+
+    async fn put(&self, presence: UserPresence) -> Result<(), StoreError> {
+        self.users.lock().await.insert(presence.user, presence);
+
+        Ok(())
+    }
+
+    async fn online_users_on_machine(&self, machine: Xuid) -> Result<Vec<Xuid>, StoreError> {
+        Ok(self.users.lock().await.values()
+            .filter(|presence| presence.online && presence.machine == machine)
+            .map(|presence| presence.user)
+            .collect())
+    }
+
+    async fn set_offline(&self, user: Xuid, machine: Xuid, connection: Option<u64>, now: SystemTime) -> Result<bool, StoreError> {
+        let mut users = self.users.lock().await;
+
+        let presence = match users.get_mut(&user) {
+            Some(presence) if presence.online && presence.machine == machine => presence,
+            _ => return Ok(false),
+        };
+
+        if connection.map(|connection| connection != presence.connection).unwrap_or(false) {
+            return Ok(false);
+        }
+
+        presence.online = false;
+        presence.last_seen = now;
+
+        Ok(true)
+    }
+
+    async fn add_invitation(&self, invitee: Xuid, invitation: Invitation) -> Result<(), StoreError> {
+        let mut invitations = self.invitations.lock().await;
+        let held = invitations.entry(invitee).or_default();
+
+        held.retain(|held| held.host != invitation.host || held.xnkid != invitation.xnkid);
+        held.push(invitation);
+
+        Ok(())
+    }
+
+    async fn invitations(&self, invitee: Xuid) -> Result<Vec<Invitation>, StoreError> {
+        Ok(self.invitations.lock().await
+            .get(&invitee)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn remove_invitation(&self, invitee: Xuid, host: Xuid, xnkid: KeyId) -> Result<bool, StoreError> {
+        let mut invitations = self.invitations.lock().await;
+
+        let held = match invitations.get_mut(&invitee) {
+            Some(held) => held,
+            None => return Ok(false),
+        };
+
+        let before = held.len();
+        held.retain(|held| held.host != host || held.xnkid != xnkid);
+        let removed = held.len() != before;
+
+        if held.is_empty() {
+            invitations.remove(&invitee);
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Buddy and block lists kept in process, for tests and for running without a
+/// database.  Only accounts added with add_account exist.
+pub struct MemoryListStore {
+    state: Mutex<ListState>,
+}
+
+#[derive(Default)]
+struct ListState {
+    accounts: BTreeMap<Xuid, String>,
+    buddies: BTreeMap<Xuid, BTreeMap<Xuid, BuddyStatus>>,
+    blocks: BTreeMap<Xuid, BTreeSet<Xuid>>,
+    versions: BTreeMap<Xuid, ListVersions>,
+}
+
+impl MemoryListStore {
+    pub fn new() -> Self {
+        MemoryListStore {
+            state: Mutex::new(ListState::default()),
+        }
+    }
+
+    pub async fn add_account(&self, user: Xuid, gamertag: &str) {
+        self.state.lock().await.accounts.insert(user, gamertag.to_owned());
+    }
+}
+
+impl Default for MemoryListStore {
+    fn default() -> Self {
+        MemoryListStore::new()
+    }
+}
+
+impl ListState {
+    fn bump_buddy_list(&mut self, user: Xuid) {
+        let versions = self.versions.entry(user).or_default();
+        versions.buddy_list = versions.buddy_list.wrapping_add(1);
+    }
+
+    fn bump_block_list(&mut self, user: Xuid) {
+        let versions = self.versions.entry(user).or_default();
+        versions.block_list = versions.block_list.wrapping_add(1);
+    }
+}
+
+#[async_trait]
+impl ListStore for MemoryListStore {
+    async fn buddies(&self, user: Xuid) -> Result<Vec<Buddy>, StoreError> {
+        let state = self.state.lock().await;
+
+        let buddies = match state.buddies.get(&user) {
+            Some(buddies) => buddies,
+            None => return Ok(vec![]),
+        };
+
+        Ok(buddies.iter()
+            .map(|(xuid, status)| Buddy {
+                xuid: *xuid,
+                gamertag: state.accounts.get(xuid).cloned().unwrap_or_default(),
+                status: *status,
+            })
+            .collect())
+    }
+
+    async fn set_buddy(&self, user: Xuid, buddy: Xuid, status: BuddyStatus) -> Result<(), StoreError> {
+        let mut state = self.state.lock().await;
+
+        state.buddies.entry(user).or_default().insert(buddy, status);
+        state.bump_buddy_list(user);
+
+        Ok(())
+    }
+
+    async fn remove_buddy(&self, user: Xuid, buddy: Xuid) -> Result<bool, StoreError> {
+        let mut state = self.state.lock().await;
+
+        let removed = match state.buddies.get_mut(&user) {
+            Some(buddies) => buddies.remove(&buddy).is_some(),
+            None => false,
+        };
+
+        if removed {
+            state.bump_buddy_list(user);
+        }
+
+        Ok(removed)
+    }
+
+    async fn blocks(&self, user: Xuid) -> Result<Vec<Xuid>, StoreError> {
+        Ok(self.state.lock().await.blocks
+            .get(&user)
+            .map(|blocks| blocks.iter().copied().collect())
+            .unwrap_or_default())
+    }
+
+    async fn block(&self, user: Xuid, blocked: Xuid) -> Result<bool, StoreError> {
+        let mut state = self.state.lock().await;
+
+        let added = state.blocks.entry(user).or_default().insert(blocked);
+
+        if added {
+            state.bump_block_list(user);
+        }
+
+        Ok(added)
+    }
+
+    async fn unblock(&self, user: Xuid, blocked: Xuid) -> Result<bool, StoreError> {
+        let mut state = self.state.lock().await;
+
+        let removed = match state.blocks.get_mut(&user) {
+            Some(blocks) => blocks.remove(&blocked),
+            None => false,
+        };
+
+        if removed {
+            state.bump_block_list(user);
+        }
+
+        Ok(removed)
+    }
+
+    async fn list_versions(&self, user: Xuid) -> Result<ListVersions, StoreError> {
+        Ok(self.state.lock().await.versions
+            .get(&user)
+            .copied()
+            .unwrap_or_default())
+    }
+
+    async fn gamertag(&self, user: Xuid) -> Result<Option<String>, StoreError> {
+        Ok(self.state.lock().await.accounts.get(&user).cloned())
+    }
+}
@@ -0,0 +1,252 @@
+use async_trait::async_trait;
+
+use std::time::SystemTime;
+
+use xblive::addr::Addr;
+use xblive::crypto::primitives::KeyId;
+
+use xbox_sys::account::Xuid;
+use xbox_sys::codec::{BufPut, Decode};
+
+use xombie::db::{self, FriendshipError, InvitationRecord, Pool, PresenceError, PresenceRecord};
+
+use crate::{Buddy, BuddyStatus, Invitation, ListVersions, UserPresence};
+
+use super::{ListStore, PresenceStore, StoreError};
+
+/// Presence kept in the database, so it and held invitations outlive any one
+/// SG.  Users online through an SG that dies without closing its connections
+/// stay online until their console connects again.
+pub struct PostgresPresenceStore {
+    pool: Pool,
+}
+
+impl PostgresPresenceStore {
+    /// Use connections from pool for presence.  Its tables come from
+    /// schema/0008_presence.sql.
+    pub fn new(pool: Pool) -> Self {
+        PostgresPresenceStore {
+            pool,
+        }
+    }
+}
+
+impl From<PresenceError> for StoreError {
+    fn from(err: PresenceError) -> Self {
+        match err {
+            PresenceError::Pg(pg_err) => StoreError::Pg(pg_err),
+            PresenceError::CannotParseXuid(parse_err) => StoreError::CannotParseXuid(parse_err),
+        }
+    }
+}
+
+fn presence_record(presence: &UserPresence) -> PresenceRecord {
+    let xnaddr = presence.xnaddr.map(|xnaddr| {
+        let mut encoded = vec![];
+        xnaddr.put(&mut encoded);
+        encoded
+    });
+
+    PresenceRecord {
+        user: presence.user,
+        machine: presence.machine,
+        connection: presence.connection,
+        user_index: presence.user_index,
+        online: presence.online,
+        state: presence.state,
+        title_id: presence.title_id,
+        title_version: presence.title_version,
+        xnaddr,
+        xnkid: presence.xnkid.map(|xnkid| xnkid.0.to_vec()),
+        match_session_id: presence.match_session_id,
+        nickname: presence.nickname.clone(),
+        title_stuff: presence.title_stuff.clone(),
+        last_seen: presence.last_seen,
+    }
+}
+
+fn key_id(encoded: &[u8]) -> Result<KeyId, StoreError> {
+    Ok(KeyId(encoded.try_into().map_err(|_| StoreError::CorruptRecord)?))
+}
+
+fn user_presence(record: PresenceRecord) -> Result<UserPresence, StoreError> {
+    let xnaddr = match record.xnaddr {
+        Some(encoded) => Some(Addr::decode(&encoded).map_err(|_| StoreError::CorruptRecord)?.1),
+        None => None,
+    };
+
+    Ok(UserPresence {
+        user: record.user,
+        machine: record.machine,
+        connection: record.connection,
+        user_index: record.user_index,
+        online: record.online,
+        state: record.state,
+        title_id: record.title_id,
+        title_version: record.title_version,
+        xnaddr,
+        xnkid: record.xnkid.as_deref().map(key_id).transpose()?,
+        match_session_id: record.match_session_id,
+        nickname: record.nickname,
+        title_stuff: record.title_stuff,
+        last_seen: record.last_seen,
+    })
+}
+
+#[async_trait]
+impl PresenceStore for PostgresPresenceStore {
+    async fn get(&self, user: Xuid) -> Result<Option<UserPresence>, StoreError> {
+        let client = self.pool.get().await?;
+
+        db::get_presence_record(&client, user).await?
+            .map(user_presence)
+            .transpose()
+    }
+
+    async fn put(&self, presence: UserPresence) -> Result<(), StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::put_presence_record(&client, &presence_record(&presence)).await?)
+    }
+
+    async fn online_users_on_machine(&self, machine: Xuid) -> Result<Vec<Xuid>, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::get_online_users_on_machine(&client, machine).await?)
+    }
+
+    async fn set_offline(&self, user: Xuid, machine: Xuid, connection: Option<u64>, now: SystemTime) -> Result<bool, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::set_presence_offline(&client, user, machine, connection, now).await?)
+    }
+
+    async fn add_invitation(&self, invitee: Xuid, invitation: Invitation) -> Result<(), StoreError> {
+        let client = self.pool.get().await?;
+
+        let record = InvitationRecord {
+            host: invitation.host,
+            xnkid: invitation.xnkid.0.to_vec(),
+            title_id: invitation.title_id,
+            sent: invitation.sent,
+        };
+
+        Ok(db::put_invitation_record(&client, invitee, &record).await?)
+    }
+
+    async fn invitations(&self, invitee: Xuid) -> Result<Vec<Invitation>, StoreError> {
+        let client = self.pool.get().await?;
+
+        db::get_invitation_records(&client, invitee).await?
+            .into_iter()
+            .map(|record| Ok(Invitation {
+                host: record.host,
+                title_id: record.title_id,
+                xnkid: key_id(&record.xnkid)?,
+                sent: record.sent,
+            }))
+            .collect()
+    }
+
+    async fn remove_invitation(&self, invitee: Xuid, host: Xuid, xnkid: KeyId) -> Result<bool, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::delete_invitation_record(&client, invitee, host, &xnkid.0).await?)
+    }
+}
+
+/// Buddy and block lists kept in the database alongside the accounts they
+/// refer to.
+pub struct PostgresListStore {
+    pool: Pool,
+}
+
+impl PostgresListStore {
+    /// Use connections from pool for list storage.  Its tables come from
+    /// schema/0002_presence_lists.sql.
+    pub fn new(pool: Pool) -> Self {
+        PostgresListStore {
+            pool,
+        }
+    }
+}
+
+impl From<FriendshipError> for StoreError {
+    fn from(err: FriendshipError) -> Self {
+        match err {
+            FriendshipError::Pg(pg_err) => StoreError::Pg(pg_err),
+            FriendshipError::CannotParseXuid(parse_err) => StoreError::CannotParseXuid(parse_err),
+        }
+    }
+}
+
+#[async_trait]
+impl ListStore for PostgresListStore {
+    async fn buddies(&self, user: Xuid) -> Result<Vec<Buddy>, StoreError> {
+        let client = self.pool.get().await?;
+
+        let mut buddies = vec![];
+
+        for friendship in db::get_friendships(&client, user).await? {
+            let status = BuddyStatus::from_code(friendship.status as u8)
+                .ok_or(StoreError::UnknownBuddyStatus(friendship.status))?;
+
+            buddies.push(Buddy {
+                xuid: friendship.buddy,
+                // Accounts can be deleted out from under a friendship; the
+                // console is still told about the entry so it can be removed.
+                gamertag: friendship.buddy_gamertag.unwrap_or_default(),
+                status,
+            });
+        }
+
+        Ok(buddies)
+    }
+
+    async fn set_buddy(&self, user: Xuid, buddy: Xuid, status: BuddyStatus) -> Result<(), StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::set_friendship(&client, user, buddy, status.code() as i16).await?)
+    }
+
+    async fn remove_buddy(&self, user: Xuid, buddy: Xuid) -> Result<bool, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::delete_friendship(&client, user, buddy).await?)
+    }
+
+    async fn blocks(&self, user: Xuid) -> Result<Vec<Xuid>, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::get_blocks(&client, user).await?)
+    }
+
+    async fn block(&self, user: Xuid, blocked: Xuid) -> Result<bool, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::add_block(&client, user, blocked).await?)
+    }
+
+    async fn unblock(&self, user: Xuid, blocked: Xuid) -> Result<bool, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::delete_block(&client, user, blocked).await?)
+    }
+
+    async fn list_versions(&self, user: Xuid) -> Result<ListVersions, StoreError> {
+        let client = self.pool.get().await?;
+
+        let (buddy_list, block_list) = db::get_list_versions(&client, user).await?;
+
+        Ok(ListVersions {
+            buddy_list,
+            block_list,
+        })
+    }
+
+    async fn gamertag(&self, user: Xuid) -> Result<Option<String>, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::get_gamertag_for_xuid(&client, user).await?)
+    }
+}
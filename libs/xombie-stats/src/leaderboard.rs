@@ -0,0 +1,373 @@
+//! Title defined leaderboards.
+//!
+//! Which leaderboards a title has, and what may be written to them, is data,
+//! one TOML file per title, so that supporting a new title doesn't need a new
+//! build.  A leaderboard file looks like:
+//!
+//! ```toml
+//! [[leaderboard]]
+//! id = 1
+//! name = "Deathmatch"
+//! rating = 0x0001
+//!
+//! [[leaderboard.column]]
+//! attribute = 0x0001
+//! type = "longlong"
+//! aggregate = "sum"
+//!
+//! [[leaderboard.column]]
+//! attribute = 0x0002
+//! type = "string"
+//! ```
+//!
+//! Every attribute a title writes has to be one of the leaderboard's columns
+//! and of the column's type.  `aggregate` says how a write is combined with
+//! what's already stored: `replace` (the default), `sum`, `min` or `max`.
+//! Rows are ranked by the value of the `rating` column, highest first, which
+//! has to be a `long` or `longlong` column.
+
+use serde::Deserialize;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use xblive::service::stats::StatValue;
+
+/// File extension of leaderboard files.  The file stem is the title id in hex.
+pub const LEADERBOARD_FILE_EXTENSION: &str = "toml";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnKind {
+    Long,
+    LongLong,
+    Double,
+    String,
+}
+
+impl ColumnKind {
+    pub fn accepts(self, value: &StatValue) -> bool {
+        matches!(
+            (self, value),
+            (ColumnKind::Long, StatValue::Long(_)) |
+            (ColumnKind::LongLong, StatValue::LongLong(_)) |
+            (ColumnKind::Double, StatValue::Double(_)) |
+            (ColumnKind::String, StatValue::String(_))
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregate {
+    #[default]
+    Replace,
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    /// Combine a newly written value with the stored one.  Both have already
+    /// been checked against the column's kind.
+    pub fn combine(self, stored: Option<&StatValue>, written: StatValue) -> StatValue {
+        use StatValue::*;
+
+        let stored = match stored {
+            Some(stored) => stored,
+            None => return written,
+        };
+
+        match (self, stored, written) {
+            (Aggregate::Replace, _, written) => written,
+            (Aggregate::Sum, Long(a), Long(b)) => Long(a.saturating_add(b)),
+            (Aggregate::Sum, LongLong(a), LongLong(b)) => LongLong(a.saturating_add(b)),
+            (Aggregate::Sum, Double(a), Double(b)) => Double(a + b),
+            (Aggregate::Min, Long(a), Long(b)) => Long((*a).min(b)),
+            (Aggregate::Min, LongLong(a), LongLong(b)) => LongLong((*a).min(b)),
+            (Aggregate::Min, Double(a), Double(b)) => Double(a.min(b)),
+            (Aggregate::Min, String(a), String(b)) => String(a.clone().min(b)),
+            (Aggregate::Max, Long(a), Long(b)) => Long((*a).max(b)),
+            (Aggregate::Max, LongLong(a), LongLong(b)) => LongLong((*a).max(b)),
+            (Aggregate::Max, Double(a), Double(b)) => Double(a.max(b)),
+            (Aggregate::Max, String(a), String(b)) => String(a.clone().max(b)),
+            // A stored value of another type predates a change to the
+            // leaderboard file.
+            (_, _, written) => written,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct Column {
+    pub attribute: u16,
+    #[serde(rename = "type")]
+    pub kind: ColumnKind,
+    #[serde(default)]
+    pub aggregate: Aggregate,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct Leaderboard {
+    pub id: u32,
+    #[serde(default)]
+    pub name: String,
+    /// The attribute rows are ranked by.
+    pub rating: u16,
+    #[serde(rename = "column", default)]
+    pub columns: Vec<Column>,
+}
+
+impl Leaderboard {
+    pub fn column(&self, attribute: u16) -> Option<&Column> {
+        self.columns.iter().find(|column| column.attribute == attribute)
+    }
+
+    /// The rating of a row with the given columns, zero if the rating
+    /// column hasn't been written yet.
+    pub fn rating_of(&self, stats: &BTreeMap<u16, StatValue>) -> i64 {
+        match stats.get(&self.rating) {
+            Some(StatValue::Long(rating)) => *rating as i64,
+            Some(StatValue::LongLong(rating)) => *rating,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LeaderboardFile {
+    #[serde(rename = "leaderboard", default)]
+    leaderboards: Vec<Leaderboard>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LeaderboardDefinitionError {
+    Parse(String),
+    DuplicateLeaderboard(u32),
+    DuplicateColumn(u32, u16),
+    BadRatingColumn(u32),
+    BadAggregate(u32, u16),
+}
+
+#[derive(Debug)]
+pub enum LeaderboardLoadError {
+    Io(io::Error),
+    BadFileName(String),
+    Definition(String, LeaderboardDefinitionError),
+}
+
+impl From<io::Error> for LeaderboardLoadError {
+    fn from(err: io::Error) -> Self {
+        LeaderboardLoadError::Io(err)
+    }
+}
+
+/// Every leaderboard known to the stats service, keyed by title id and
+/// leaderboard id.
+#[derive(Debug, Default)]
+pub struct Leaderboards {
+    leaderboards: BTreeMap<(u32, u32), Leaderboard>,
+}
+
+impl Leaderboards {
+    pub fn new() -> Self {
+        Leaderboards {
+            leaderboards: BTreeMap::new(),
+        }
+    }
+
+    /// Load every leaderboard file in dir.
+    pub fn load_dir(dir: &Path) -> Result<Self, LeaderboardLoadError> {
+        let mut leaderboards = Leaderboards::new();
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+
+            if path.extension().and_then(|ext| ext.to_str()) != Some(LEADERBOARD_FILE_EXTENSION) {
+                continue;
+            }
+
+            let file_name = path.display().to_string();
+
+            let title_id = path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| u32::from_str_radix(stem, 16).ok())
+                .ok_or_else(|| LeaderboardLoadError::BadFileName(file_name.clone()))?;
+
+            let source = fs::read_to_string(&path)?;
+
+            leaderboards.add_title(title_id, &source)
+                .map_err(|err| LeaderboardLoadError::Definition(file_name, err))?;
+        }
+
+        Ok(leaderboards)
+    }
+
+    /// Parse the leaderboards of a single title and add them.
+    pub fn add_title(&mut self, title_id: u32, source: &str) -> Result<(), LeaderboardDefinitionError> {
+        use LeaderboardDefinitionError::*;
+
+        let file: LeaderboardFile = toml::from_str(source)
+            .map_err(|err| Parse(err.to_string()))?;
+
+        let mut parsed = BTreeMap::new();
+
+        for leaderboard in file.leaderboards {
+            let id = leaderboard.id;
+
+            for (i, column) in leaderboard.columns.iter().enumerate() {
+                if leaderboard.columns[..i].iter().any(|earlier| earlier.attribute == column.attribute) {
+                    return Err(DuplicateColumn(id, column.attribute));
+                }
+
+                let numeric = column.kind != ColumnKind::String;
+                if column.aggregate == Aggregate::Sum && !numeric {
+                    return Err(BadAggregate(id, column.attribute));
+                }
+            }
+
+            match leaderboard.column(leaderboard.rating) {
+                Some(Column { kind: ColumnKind::Long | ColumnKind::LongLong, .. }) => {}
+                _ => return Err(BadRatingColumn(id)),
+            }
+
+            if parsed.insert(id, leaderboard).is_some() {
+                return Err(DuplicateLeaderboard(id));
+            }
+        }
+
+        for (id, leaderboard) in parsed {
+            self.leaderboards.insert((title_id, id), leaderboard);
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, title_id: u32, leaderboard_id: u32) -> Option<&Leaderboard> {
+        self.leaderboards.get(&(title_id, leaderboard_id))
+    }
+
+    /// Every leaderboard of a title.
+    pub fn of_title(&self, title_id: u32) -> impl Iterator<Item = &Leaderboard> {
+        self.leaderboards.range((title_id, 0)..=(title_id, u32::MAX))
+            .map(|(_, leaderboard)| leaderboard)
+    }
+
+    pub fn has_title(&self, title_id: u32) -> bool {
+        self.of_title(title_id).next().is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaderboards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaderboards.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TITLE_ID: u32 = 0x4D53_0004;
+
+    const DEATHMATCH: &str = r#"
+        [[leaderboard]]
+        id = 1
+        name = "Deathmatch"
+        rating = 0x0001
+
+        [[leaderboard.column]]
+        attribute = 0x0001
+        type = "longlong"
+        aggregate = "sum"
+
+        [[leaderboard.column]]
+        attribute = 0x0002
+        type = "string"
+
+        [[leaderboard]]
+        id = 2
+        rating = 3
+
+        [[leaderboard.column]]
+        attribute = 3
+        type = "long"
+        aggregate = "max"
+    "#;
+
+    #[test]
+    fn parse_leaderboards() {
+        let mut leaderboards = Leaderboards::new();
+        leaderboards.add_title(TITLE_ID, DEATHMATCH).unwrap();
+
+        assert_eq!(leaderboards.len(), 2);
+        assert!(leaderboards.has_title(TITLE_ID));
+        assert!(!leaderboards.has_title(TITLE_ID + 1));
+
+        let deathmatch = leaderboards.get(TITLE_ID, 1).unwrap();
+        assert_eq!(deathmatch.name, "Deathmatch");
+        assert_eq!(deathmatch.rating, 1);
+        assert_eq!(deathmatch.columns, vec![
+            Column { attribute: 1, kind: ColumnKind::LongLong, aggregate: Aggregate::Sum },
+            Column { attribute: 2, kind: ColumnKind::String, aggregate: Aggregate::Replace },
+        ]);
+
+        assert_eq!(leaderboards.get(TITLE_ID, 2).unwrap().column(3).unwrap().aggregate, Aggregate::Max);
+        assert_eq!(leaderboards.of_title(TITLE_ID).count(), 2);
+    }
+
+    #[test]
+    fn bad_definitions_are_rejected() {
+        let cases = [
+            ("[[leaderboard]]\nid = 1\nrating = 1\n", LeaderboardDefinitionError::BadRatingColumn(1)),
+            (
+                "[[leaderboard]]\nid = 1\nrating = 1\n[[leaderboard.column]]\nattribute = 1\ntype = \"double\"\n",
+                LeaderboardDefinitionError::BadRatingColumn(1),
+            ),
+            (
+                "[[leaderboard]]\nid = 1\nrating = 1\n[[leaderboard.column]]\nattribute = 1\ntype = \"long\"\n\
+                 [[leaderboard.column]]\nattribute = 1\ntype = \"long\"\n",
+                LeaderboardDefinitionError::DuplicateColumn(1, 1),
+            ),
+            (
+                "[[leaderboard]]\nid = 1\nrating = 1\n[[leaderboard.column]]\nattribute = 1\ntype = \"long\"\n\
+                 [[leaderboard.column]]\nattribute = 2\ntype = \"string\"\naggregate = \"sum\"\n",
+                LeaderboardDefinitionError::BadAggregate(1, 2),
+            ),
+            (
+                "[[leaderboard]]\nid = 1\nrating = 1\n[[leaderboard.column]]\nattribute = 1\ntype = \"long\"\n\
+                 [[leaderboard]]\nid = 1\nrating = 1\n[[leaderboard.column]]\nattribute = 1\ntype = \"long\"\n",
+                LeaderboardDefinitionError::DuplicateLeaderboard(1),
+            ),
+        ];
+
+        for (source, expected) in cases {
+            let mut leaderboards = Leaderboards::new();
+            assert_eq!(leaderboards.add_title(TITLE_ID, source), Err(expected), "{}", source);
+            assert!(leaderboards.is_empty());
+        }
+
+        let mut leaderboards = Leaderboards::new();
+        assert!(matches!(
+            leaderboards.add_title(TITLE_ID, "[[leaderboard]]\nid = \"one\"\n"),
+            Err(LeaderboardDefinitionError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn aggregates_combine_with_stored_values() {
+        use StatValue::*;
+
+        assert_eq!(Aggregate::Sum.combine(None, Long(3)), Long(3));
+        assert_eq!(Aggregate::Sum.combine(Some(&Long(3)), Long(4)), Long(7));
+        assert_eq!(Aggregate::Sum.combine(Some(&Long(i32::MAX)), Long(1)), Long(i32::MAX));
+        assert_eq!(Aggregate::Min.combine(Some(&LongLong(3)), LongLong(4)), LongLong(3));
+        assert_eq!(Aggregate::Max.combine(Some(&Double(3.0)), Double(4.5)), Double(4.5));
+        assert_eq!(Aggregate::Max.combine(Some(&String("b".into())), String("a".into())), String("b".into()));
+        assert_eq!(Aggregate::Replace.combine(Some(&Long(3)), Long(1)), Long(1));
+    }
+}
@@ -0,0 +1,521 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use xblive::service::stats::{ALL_LEADERBOARDS, MAX_PAGE_SIZE, Stat, StatValue, StatsWrite};
+
+use xbox_sys::account::Xuid;
+
+pub mod leaderboard;
+pub mod store;
+
+use leaderboard::{Leaderboard, Leaderboards};
+use store::{StatsStore, StoreError};
+
+/// A user's row on a leaderboard as it's stored, every column they've
+/// written keyed by attribute id.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredRow {
+    pub user: Xuid,
+    pub rating: i64,
+    pub stats: BTreeMap<u16, StatValue>,
+    /// The user's gamertag, if the store knows it.  Ignored when the row is
+    /// written.
+    pub gamertag: Option<String>,
+}
+
+/// A row as it's read back, with only the columns asked for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Row {
+    pub user: Xuid,
+    pub gamertag: Option<String>,
+    /// One based.
+    pub rank: u32,
+    pub rating: i64,
+    pub stats: Vec<Stat>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Page {
+    /// How many rows the whole leaderboard has.
+    pub total_rows: u32,
+    pub rows: Vec<Row>,
+}
+
+#[derive(Debug)]
+pub enum StatsError {
+    /// The title has no leaderboards defined.
+    UnknownTitle,
+    UnknownLeaderboard(u32),
+    /// An attribute that isn't a column of the leaderboard, or a value of
+    /// the wrong type for its column.
+    BadAttribute(u16),
+    Store(StoreError),
+}
+
+impl From<StoreError> for StatsError {
+    fn from(err: StoreError) -> Self {
+        StatsError::Store(err)
+    }
+}
+
+pub struct Stats {
+    leaderboards: Leaderboards,
+    store: Arc<dyn StatsStore>,
+}
+
+impl Debug for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Stats")
+            .field("leaderboards", &self.leaderboards)
+            .finish()
+    }
+}
+
+impl Stats {
+    pub fn new(leaderboards: Leaderboards, store: Arc<dyn StatsStore>) -> Self {
+        Stats {
+            leaderboards,
+            store,
+        }
+    }
+
+    fn leaderboard(&self, title_id: u32, leaderboard_id: u32) -> Result<&Leaderboard, StatsError> {
+        if !self.leaderboards.has_title(title_id) {
+            return Err(StatsError::UnknownTitle);
+        }
+
+        self.leaderboards.get(title_id, leaderboard_id)
+            .ok_or(StatsError::UnknownLeaderboard(leaderboard_id))
+    }
+
+    /// Apply writes to the leaderboards of a title.  Every write is checked
+    /// before any is applied, so a bad one leaves every row untouched.
+    pub async fn write(&self, title_id: u32, writes: &[StatsWrite]) -> Result<(), StatsError> {
+        for write in writes {
+            let leaderboard = self.leaderboard(title_id, write.leaderboard_id)?;
+
+            for stat in &write.stats {
+                let column = leaderboard.column(stat.attribute_id)
+                    .ok_or(StatsError::BadAttribute(stat.attribute_id))?;
+
+                if stat.value != StatValue::Null && !column.kind.accepts(&stat.value) {
+                    return Err(StatsError::BadAttribute(stat.attribute_id));
+                }
+            }
+        }
+
+        for write in writes {
+            let leaderboard = self.leaderboard(title_id, write.leaderboard_id)?;
+
+            let update = |row: Option<StoredRow>| {
+                let mut row = row.unwrap_or(StoredRow {
+                    user: write.user_id,
+                    rating: 0,
+                    stats: BTreeMap::new(),
+                    gamertag: None,
+                });
+
+                for stat in &write.stats {
+                    // A null clears the column rather than being aggregated.
+                    if stat.value == StatValue::Null {
+                        row.stats.remove(&stat.attribute_id);
+                        continue;
+                    }
+
+                    let aggregate = leaderboard.column(stat.attribute_id)
+                        .map(|column| column.aggregate)
+                        .unwrap_or_default();
+
+                    let value = aggregate.combine(row.stats.get(&stat.attribute_id), stat.value.clone());
+                    row.stats.insert(stat.attribute_id, value);
+                }
+
+                row.rating = leaderboard.rating_of(&row.stats);
+                row
+            };
+
+            self.store.update(title_id, write.leaderboard_id, write.user_id, &update).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Rows in rank order starting at the one based rank.
+    pub async fn read_by_rank(
+        &self,
+        title_id: u32,
+        leaderboard_id: u32,
+        rank: u32,
+        page_size: u16,
+        attribute_ids: &[u16],
+    ) -> Result<Page, StatsError> {
+        let leaderboard = self.leaderboard(title_id, leaderboard_id)?;
+        let columns = selected_columns(leaderboard, attribute_ids)?;
+
+        let offset = rank.saturating_sub(1);
+        self.page(title_id, leaderboard_id, offset, page_size, &columns).await
+    }
+
+    /// The page of rows with user as close to the middle as the ends of the
+    /// leaderboard allow.  Empty if user has no row.
+    pub async fn read_around_user(
+        &self,
+        title_id: u32,
+        leaderboard_id: u32,
+        user: Xuid,
+        page_size: u16,
+        attribute_ids: &[u16],
+    ) -> Result<Page, StatsError> {
+        let leaderboard = self.leaderboard(title_id, leaderboard_id)?;
+        let columns = selected_columns(leaderboard, attribute_ids)?;
+
+        let position = match self.store.position(title_id, leaderboard_id, user).await? {
+            Some(position) => position,
+            None => return Ok(Page {
+                total_rows: self.store.count(title_id, leaderboard_id).await?,
+                rows: vec![],
+            }),
+        };
+
+        let page_size = page_size.min(MAX_PAGE_SIZE);
+        let total_rows = self.store.count(title_id, leaderboard_id).await?;
+
+        let last_start = total_rows.saturating_sub(page_size as u32);
+        let offset = position.saturating_sub(page_size as u32 / 2).min(last_start);
+
+        self.page(title_id, leaderboard_id, offset, page_size, &columns).await
+    }
+
+    /// The rows of the given users, in rank order.  Users without a row are
+    /// left out.
+    pub async fn read_users(
+        &self,
+        title_id: u32,
+        leaderboard_id: u32,
+        users: &[Xuid],
+        attribute_ids: &[u16],
+    ) -> Result<Page, StatsError> {
+        let leaderboard = self.leaderboard(title_id, leaderboard_id)?;
+        let columns = selected_columns(leaderboard, attribute_ids)?;
+
+        let mut rows = vec![];
+
+        for user in users {
+            if rows.iter().any(|row: &Row| row.user == *user) {
+                continue;
+            }
+
+            let stored = match self.store.get(title_id, leaderboard_id, *user).await? {
+                Some(stored) => stored,
+                None => continue,
+            };
+
+            let position = match self.store.position(title_id, leaderboard_id, *user).await? {
+                Some(position) => position,
+                None => continue,
+            };
+
+            rows.push(row(stored, position + 1, &columns));
+        }
+
+        rows.sort_by_key(|row| row.rank);
+
+        Ok(Page {
+            total_rows: self.store.count(title_id, leaderboard_id).await?,
+            rows,
+        })
+    }
+
+    /// Take user off a leaderboard, or off every leaderboard of the title if
+    /// leaderboard_id is ALL_LEADERBOARDS.  Resetting a user without a row
+    /// isn't an error.
+    pub async fn reset(&self, title_id: u32, user: Xuid, leaderboard_id: u32) -> Result<u64, StatsError> {
+        if leaderboard_id == ALL_LEADERBOARDS {
+            if !self.leaderboards.has_title(title_id) {
+                return Err(StatsError::UnknownTitle);
+            }
+
+            return Ok(self.store.delete(title_id, None, user).await?);
+        }
+
+        self.leaderboard(title_id, leaderboard_id)?;
+
+        Ok(self.store.delete(title_id, Some(leaderboard_id), user).await?)
+    }
+
+    async fn page(
+        &self,
+        title_id: u32,
+        leaderboard_id: u32,
+        offset: u32,
+        page_size: u16,
+        columns: &[u16],
+    ) -> Result<Page, StatsError> {
+        let page_size = page_size.min(MAX_PAGE_SIZE);
+
+        let rows = self.store.by_rank(title_id, leaderboard_id, offset, page_size as u32).await?
+            .into_iter()
+            .enumerate()
+            .map(|(i, stored)| row(stored, offset + i as u32 + 1, columns))
+            .collect();
+
+        Ok(Page {
+            total_rows: self.store.count(title_id, leaderboard_id).await?,
+            rows,
+        })
+    }
+}
+
+/// The attributes a read returns, every column of the leaderboard if none
+/// were asked for.
+fn selected_columns(leaderboard: &Leaderboard, attribute_ids: &[u16]) -> Result<Vec<u16>, StatsError> {
+    if attribute_ids.is_empty() {
+        return Ok(leaderboard.columns.iter().map(|column| column.attribute).collect());
+    }
+
+    for attribute_id in attribute_ids {
+        if leaderboard.column(*attribute_id).is_none() {
+            return Err(StatsError::BadAttribute(*attribute_id));
+        }
+    }
+
+    Ok(attribute_ids.to_vec())
+}
+
+fn row(mut stored: StoredRow, rank: u32, columns: &[u16]) -> Row {
+    Row {
+        user: stored.user,
+        gamertag: stored.gamertag,
+        rank,
+        rating: stored.rating,
+        stats: columns.iter()
+            .map(|attribute_id| Stat {
+                attribute_id: *attribute_id,
+                value: stored.stats.remove(attribute_id).unwrap_or(StatValue::Null),
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use store::memory::MemoryStore;
+
+    use super::*;
+
+    const TITLE_ID: u32 = 0x4D53_0004;
+
+    const LEADERBOARDS: &str = r#"
+        [[leaderboard]]
+        id = 1
+        rating = 1
+
+        [[leaderboard.column]]
+        attribute = 1
+        type = "longlong"
+        aggregate = "sum"
+
+        [[leaderboard.column]]
+        attribute = 2
+        type = "long"
+        aggregate = "max"
+
+        [[leaderboard.column]]
+        attribute = 3
+        type = "string"
+
+        [[leaderboard]]
+        id = 2
+        rating = 1
+
+        [[leaderboard.column]]
+        attribute = 1
+        type = "long"
+    "#;
+
+    fn stats() -> Stats {
+        let mut leaderboards = Leaderboards::new();
+        leaderboards.add_title(TITLE_ID, LEADERBOARDS).unwrap();
+
+        Stats::new(leaderboards, Arc::new(MemoryStore::new()))
+    }
+
+    fn score(user: u64, leaderboard_id: u32, points: i64) -> StatsWrite {
+        StatsWrite {
+            user_id: Xuid(user),
+            leaderboard_id,
+            stats: vec![Stat { attribute_id: 1, value: StatValue::LongLong(points) }],
+        }
+    }
+
+    fn ranking(page: &Page) -> Vec<(u64, u32, i64)> {
+        page.rows.iter().map(|row| (row.user.0, row.rank, row.rating)).collect()
+    }
+
+    #[tokio::test]
+    async fn writes_aggregate_into_rows() {
+        let stats = stats();
+
+        stats.write(TITLE_ID, &[StatsWrite {
+            user_id: Xuid(1),
+            leaderboard_id: 1,
+            stats: vec![
+                Stat { attribute_id: 1, value: StatValue::LongLong(10) },
+                Stat { attribute_id: 2, value: StatValue::Long(7) },
+                Stat { attribute_id: 3, value: StatValue::String("rocket".into()) },
+            ],
+        }]).await.unwrap();
+
+        stats.write(TITLE_ID, &[StatsWrite {
+            user_id: Xuid(1),
+            leaderboard_id: 1,
+            stats: vec![
+                Stat { attribute_id: 1, value: StatValue::LongLong(5) },
+                Stat { attribute_id: 2, value: StatValue::Long(3) },
+                Stat { attribute_id: 3, value: StatValue::Null },
+            ],
+        }]).await.unwrap();
+
+        let page = stats.read_users(TITLE_ID, 1, &[Xuid(1)], &[]).await.unwrap();
+        assert_eq!(page.total_rows, 1);
+        assert_eq!(page.rows, vec![Row {
+            user: Xuid(1),
+            gamertag: None,
+            rank: 1,
+            rating: 15,
+            stats: vec![
+                Stat { attribute_id: 1, value: StatValue::LongLong(15) },
+                Stat { attribute_id: 2, value: StatValue::Long(7) },
+                Stat { attribute_id: 3, value: StatValue::Null },
+            ],
+        }]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn racing_writes_all_count() {
+        // Two SGs sharing one store.
+        let store = Arc::new(MemoryStore::new());
+        let stats: Vec<_> = (0..2)
+            .map(|_| {
+                let mut leaderboards = Leaderboards::new();
+                leaderboards.add_title(TITLE_ID, LEADERBOARDS).unwrap();
+                Arc::new(Stats::new(leaderboards, store.clone()))
+            })
+            .collect();
+
+        let writes: Vec<_> = (0..64)
+            .map(|n| {
+                let stats = stats[n % 2].clone();
+                tokio::spawn(async move {
+                    stats.write(TITLE_ID, &[score(1, 1, 1)]).await
+                })
+            })
+            .collect();
+
+        for write in writes {
+            write.await.unwrap().unwrap();
+        }
+
+        let page = stats[0].read_by_rank(TITLE_ID, 1, 1, 10, &[1]).await.unwrap();
+        assert_eq!(ranking(&page), vec![(1, 1, 64)]);
+    }
+
+    #[tokio::test]
+    async fn bad_writes_change_nothing() {
+        let stats = stats();
+
+        let wrong_type = StatsWrite {
+            user_id: Xuid(2),
+            leaderboard_id: 1,
+            stats: vec![Stat { attribute_id: 2, value: StatValue::Double(1.0) }],
+        };
+        let unknown_attribute = StatsWrite {
+            user_id: Xuid(2),
+            leaderboard_id: 1,
+            stats: vec![Stat { attribute_id: 9, value: StatValue::Long(1) }],
+        };
+
+        assert!(matches!(
+            stats.write(TITLE_ID, &[score(1, 1, 10), wrong_type]).await,
+            Err(StatsError::BadAttribute(2))
+        ));
+        assert!(matches!(
+            stats.write(TITLE_ID, &[score(1, 1, 10), unknown_attribute]).await,
+            Err(StatsError::BadAttribute(9))
+        ));
+        assert!(matches!(
+            stats.write(TITLE_ID, &[score(1, 3, 10)]).await,
+            Err(StatsError::UnknownLeaderboard(3))
+        ));
+        assert!(matches!(
+            stats.write(TITLE_ID + 1, &[score(1, 1, 10)]).await,
+            Err(StatsError::UnknownTitle)
+        ));
+
+        assert_eq!(stats.read_by_rank(TITLE_ID, 1, 1, 10, &[]).await.unwrap().total_rows, 0);
+    }
+
+    #[tokio::test]
+    async fn reads_by_rank_and_around_user() {
+        let stats = stats();
+
+        let writes: Vec<StatsWrite> = (1..=10).map(|user| score(user, 1, user as i64 * 100)).collect();
+        stats.write(TITLE_ID, &writes).await.unwrap();
+        // Ties are broken by xuid.
+        stats.write(TITLE_ID, &[score(11, 1, 500)]).await.unwrap();
+
+        let page = stats.read_by_rank(TITLE_ID, 1, 1, 3, &[1]).await.unwrap();
+        assert_eq!(page.total_rows, 11);
+        assert_eq!(ranking(&page), vec![(10, 1, 1000), (9, 2, 900), (8, 3, 800)]);
+
+        let page = stats.read_by_rank(TITLE_ID, 1, 6, 3, &[1]).await.unwrap();
+        assert_eq!(ranking(&page), vec![(5, 6, 500), (11, 7, 500), (4, 8, 400)]);
+
+        let page = stats.read_around_user(TITLE_ID, 1, Xuid(5), 5, &[]).await.unwrap();
+        assert_eq!(ranking(&page), vec![(7, 4, 700), (6, 5, 600), (5, 6, 500), (11, 7, 500), (4, 8, 400)]);
+
+        // Pages around users near either end are pulled back inside the
+        // leaderboard.
+        let page = stats.read_around_user(TITLE_ID, 1, Xuid(10), 4, &[]).await.unwrap();
+        assert_eq!(ranking(&page).first(), Some(&(10, 1, 1000)));
+        let page = stats.read_around_user(TITLE_ID, 1, Xuid(1), 4, &[]).await.unwrap();
+        assert_eq!(ranking(&page), vec![(4, 8, 400), (3, 9, 300), (2, 10, 200), (1, 11, 100)]);
+
+        let page = stats.read_around_user(TITLE_ID, 1, Xuid(99), 4, &[]).await.unwrap();
+        assert_eq!(page.total_rows, 11);
+        assert!(page.rows.is_empty());
+
+        let page = stats.read_users(TITLE_ID, 1, &[Xuid(3), Xuid(99), Xuid(9)], &[1]).await.unwrap();
+        assert_eq!(ranking(&page), vec![(9, 2, 900), (3, 9, 300)]);
+
+        assert!(matches!(
+            stats.read_by_rank(TITLE_ID, 1, 1, 3, &[9]).await,
+            Err(StatsError::BadAttribute(9))
+        ));
+    }
+
+    #[tokio::test]
+    async fn resets_remove_rows() {
+        let stats = stats();
+
+        stats.write(TITLE_ID, &[score(1, 1, 10), score(2, 1, 20)]).await.unwrap();
+        stats.write(TITLE_ID, &[StatsWrite {
+            user_id: Xuid(1),
+            leaderboard_id: 2,
+            stats: vec![Stat { attribute_id: 1, value: StatValue::Long(3) }],
+        }]).await.unwrap();
+
+        assert_eq!(stats.reset(TITLE_ID, Xuid(1), 1).await.unwrap(), 1);
+        assert_eq!(ranking(&stats.read_by_rank(TITLE_ID, 1, 1, 10, &[]).await.unwrap()), vec![(2, 1, 20)]);
+        assert_eq!(stats.read_by_rank(TITLE_ID, 2, 1, 10, &[]).await.unwrap().total_rows, 1);
+
+        stats.write(TITLE_ID, &[score(1, 1, 10)]).await.unwrap();
+        assert_eq!(stats.reset(TITLE_ID, Xuid(1), ALL_LEADERBOARDS).await.unwrap(), 2);
+        assert_eq!(stats.read_by_rank(TITLE_ID, 2, 1, 10, &[]).await.unwrap().total_rows, 0);
+        assert_eq!(stats.reset(TITLE_ID, Xuid(1), ALL_LEADERBOARDS).await.unwrap(), 0);
+
+        assert!(matches!(stats.reset(TITLE_ID, Xuid(1), 3).await, Err(StatsError::UnknownLeaderboard(3))));
+        assert!(matches!(stats.reset(TITLE_ID + 1, Xuid(1), ALL_LEADERBOARDS).await, Err(StatsError::UnknownTitle)));
+    }
+}
@@ -0,0 +1,65 @@
+//! Where leaderboard rows live.
+//!
+//! Stats decides what a write means for a row; a StatsStore only keeps rows
+//! and hands them back in rank order.
+
+use async_trait::async_trait;
+
+use xbox_sys::account::Xuid;
+
+use crate::StoredRow;
+
+pub mod memory;
+pub mod postgres;
+
+#[derive(Debug)]
+pub enum StoreError {
+    Pg(tokio_postgres::Error),
+    Pool(xombie::db::PoolError),
+    CannotParseXuid(std::num::ParseIntError),
+    /// A stored row whose columns can't be decoded.
+    CorruptRow(Xuid),
+}
+
+impl From<tokio_postgres::Error> for StoreError {
+    fn from(pg_err: tokio_postgres::Error) -> Self {
+        StoreError::Pg(pg_err)
+    }
+}
+
+impl From<xombie::db::PoolError> for StoreError {
+    fn from(pool_err: xombie::db::PoolError) -> Self {
+        StoreError::Pool(pool_err)
+    }
+}
+
+/// What a write makes of a row, given the row as it stands or None if the
+/// user doesn't have one yet.
+pub type RowUpdate<'a> = dyn Fn(Option<StoredRow>) -> StoredRow + Send + Sync + 'a;
+
+#[async_trait]
+pub trait StatsStore: Send + Sync {
+    async fn get(&self, title_id: u32, leaderboard_id: u32, user: Xuid) -> Result<Option<StoredRow>, StoreError>;
+
+    /// Add or overwrite the row of row.user.
+    async fn put(&self, title_id: u32, leaderboard_id: u32, row: StoredRow) -> Result<(), StoreError>;
+
+    /// Replace user's row with what update makes of it.  Updates of the same
+    /// row are applied one at a time, so none is lost to another.
+    async fn update(&self, title_id: u32, leaderboard_id: u32, user: Xuid, update: &RowUpdate<'_>) -> Result<(), StoreError>;
+
+    /// Take user off a leaderboard, or off every leaderboard of the title if
+    /// leaderboard_id is None.  Returns the number of rows removed.
+    async fn delete(&self, title_id: u32, leaderboard_id: Option<u32>, user: Xuid) -> Result<u64, StoreError>;
+
+    /// How many rows a leaderboard has.
+    async fn count(&self, title_id: u32, leaderboard_id: u32) -> Result<u32, StoreError>;
+
+    /// Up to count rows in rank order, skipping the first offset.  Rows are
+    /// ranked by rating, highest first, with ties broken by xuid.
+    async fn by_rank(&self, title_id: u32, leaderboard_id: u32, offset: u32, count: u32) -> Result<Vec<StoredRow>, StoreError>;
+
+    /// The zero based position of user in rank order, or None if they have
+    /// no row.
+    async fn position(&self, title_id: u32, leaderboard_id: u32, user: Xuid) -> Result<Option<u32>, StoreError>;
+}
@@ -0,0 +1,124 @@
+use async_trait::async_trait;
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use tokio::sync::Mutex;
+
+use xbox_sys::account::Xuid;
+
+use crate::StoredRow;
+
+use super::{RowUpdate, StatsStore, StoreError};
+
+/// Leaderboards kept in process, lost when the SG restarts.
+pub struct MemoryStore {
+    leaderboards: Mutex<BTreeMap<(u32, u32), BTreeMap<Xuid, StoredRow>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore {
+            leaderboards: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::new()
+    }
+}
+
+fn ranked(rows: &BTreeMap<Xuid, StoredRow>) -> Vec<&StoredRow> {
+    let mut ranked: Vec<&StoredRow> = rows.values().collect();
+    ranked.sort_by_key(|row| (Reverse(row.rating), row.user));
+    ranked
+}
+
+#[async_trait]
+impl StatsStore for MemoryStore {
+    async fn get(&self, title_id: u32, leaderboard_id: u32, user: Xuid) -> Result<Option<StoredRow>, StoreError> {
+        let leaderboards = self.leaderboards.lock().await;
+
+        Ok(leaderboards.get(&(title_id, leaderboard_id))
+            .and_then(|rows| rows.get(&user))
+            .cloned())
+    }
+
+    async fn put(&self, title_id: u32, leaderboard_id: u32, row: StoredRow) -> Result<(), StoreError> {
+        self.leaderboards.lock().await
+            .entry((title_id, leaderboard_id))
+            .or_default()
+            .insert(row.user, row);
+
+        Ok(())
+    }
+
+    async fn update(&self, title_id: u32, leaderboard_id: u32, user: Xuid, update: &RowUpdate<'_>) -> Result<(), StoreError> {
+        let mut leaderboards = self.leaderboards.lock().await;
+        let rows = leaderboards.entry((title_id, leaderboard_id)).or_default();
+
+        let row = update(rows.remove(&user));
+        rows.insert(row.user, row);
+
+        Ok(())
+    }
+
+    async fn delete(&self, title_id: u32, leaderboard_id: Option<u32>, user: Xuid) -> Result<u64, StoreError> {
+        let mut leaderboards = self.leaderboards.lock().await;
+        let mut deleted = 0;
+
+        for ((row_title_id, row_leaderboard_id), rows) in leaderboards.iter_mut() {
+            if *row_title_id != title_id {
+                continue;
+            }
+
+            if leaderboard_id.is_some() && leaderboard_id != Some(*row_leaderboard_id) {
+                continue;
+            }
+
+            if rows.remove(&user).is_some() {
+                deleted += 1;
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    async fn count(&self, title_id: u32, leaderboard_id: u32) -> Result<u32, StoreError> {
+        let leaderboards = self.leaderboards.lock().await;
+
+        Ok(leaderboards.get(&(title_id, leaderboard_id))
+            .map(|rows| rows.len() as u32)
+            .unwrap_or(0))
+    }
+
+    async fn by_rank(&self, title_id: u32, leaderboard_id: u32, offset: u32, count: u32) -> Result<Vec<StoredRow>, StoreError> {
+        let leaderboards = self.leaderboards.lock().await;
+
+        let rows = match leaderboards.get(&(title_id, leaderboard_id)) {
+            Some(rows) => rows,
+            None => return Ok(vec![]),
+        };
+
+        Ok(ranked(rows).into_iter()
+            .skip(offset as usize)
+            .take(count as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn position(&self, title_id: u32, leaderboard_id: u32, user: Xuid) -> Result<Option<u32>, StoreError> {
+        let leaderboards = self.leaderboards.lock().await;
+
+        let rows = match leaderboards.get(&(title_id, leaderboard_id)) {
+            Some(rows) => rows,
+            None => return Ok(None),
+        };
+
+        Ok(ranked(rows).iter()
+            .position(|row| row.user == user)
+            .map(|position| position as u32))
+    }
+}
@@ -0,0 +1,157 @@
+use async_trait::async_trait;
+
+use xblive::service::stats::{Stat, decode_stats, put_stats};
+
+use xbox_sys::account::Xuid;
+
+use xombie::db::{self, Pool, StatsError, StatsRecord};
+
+use crate::StoredRow;
+
+use super::{RowUpdate, StatsStore, StoreError};
+
+/// Leaderboards kept in the database so they survive the SG.
+pub struct PostgresStore {
+    pool: Pool,
+}
+
+impl PostgresStore {
+    /// Use connections from pool for leaderboard storage.  Its tables come
+    /// from schema/0003_stats.sql.
+    pub fn new(pool: Pool) -> Self {
+        PostgresStore {
+            pool,
+        }
+    }
+}
+
+impl From<StatsError> for StoreError {
+    fn from(err: StatsError) -> Self {
+        match err {
+            StatsError::Pg(pg_err) => StoreError::Pg(pg_err),
+            StatsError::CannotParseXuid(parse_err) => StoreError::CannotParseXuid(parse_err),
+        }
+    }
+}
+
+fn stored_row(record: StatsRecord) -> Result<StoredRow, StoreError> {
+    let stats = match decode_stats(&record.stats) {
+        Ok(([], stats)) => stats,
+        _ => return Err(StoreError::CorruptRow(record.xuid)),
+    };
+
+    Ok(StoredRow {
+        user: record.xuid,
+        rating: record.rating,
+        stats: stats.into_iter()
+            .map(|stat| (stat.attribute_id, stat.value))
+            .collect(),
+        gamertag: record.gamertag,
+    })
+}
+
+fn stats_record(row: StoredRow) -> StatsRecord {
+    let stats: Vec<Stat> = row.stats.into_iter()
+        .map(|(attribute_id, value)| Stat { attribute_id, value })
+        .collect();
+
+    let mut encoded = vec![];
+    put_stats(&stats, &mut encoded);
+
+    StatsRecord {
+        xuid: row.user,
+        rating: row.rating,
+        stats: encoded,
+        gamertag: row.gamertag,
+    }
+}
+
+#[async_trait]
+impl StatsStore for PostgresStore {
+    async fn get(&self, title_id: u32, leaderboard_id: u32, user: Xuid) -> Result<Option<StoredRow>, StoreError> {
+        let client = self.pool.get().await?;
+
+        db::get_stats_record(&client, title_id, leaderboard_id, user).await?
+            .map(stored_row)
+            .transpose()
+    }
+
+    async fn put(&self, title_id: u32, leaderboard_id: u32, row: StoredRow) -> Result<(), StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::put_stats_record(&client, title_id, leaderboard_id, &stats_record(row)).await?)
+    }
+
+    async fn update(&self, title_id: u32, leaderboard_id: u32, user: Xuid, update: &RowUpdate<'_>) -> Result<(), StoreError> {
+        let mut client = self.pool.get().await?;
+
+        db::update_stats_record(&mut client, title_id, leaderboard_id, user, |record| {
+            let row = record.map(stored_row).transpose()?;
+            Ok::<_, StoreError>(stats_record(update(row)))
+        }).await
+    }
+
+    async fn delete(&self, title_id: u32, leaderboard_id: Option<u32>, user: Xuid) -> Result<u64, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::delete_stats_records(&client, title_id, leaderboard_id, user).await?)
+    }
+
+    async fn count(&self, title_id: u32, leaderboard_id: u32) -> Result<u32, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::count_stats_records(&client, title_id, leaderboard_id).await?)
+    }
+
+    async fn by_rank(&self, title_id: u32, leaderboard_id: u32, offset: u32, count: u32) -> Result<Vec<StoredRow>, StoreError> {
+        let client = self.pool.get().await?;
+
+        db::get_stats_records_by_rank(&client, title_id, leaderboard_id, offset, count).await?
+            .into_iter()
+            .map(stored_row)
+            .collect()
+    }
+
+    async fn position(&self, title_id: u32, leaderboard_id: u32, user: Xuid) -> Result<Option<u32>, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::get_stats_record_position(&client, title_id, leaderboard_id, user).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use xblive::service::stats::StatValue;
+
+    use super::*;
+
+    #[test]
+    fn rows_survive_encoding() {
+        let row = StoredRow {
+            user: Xuid(0x0009_0000_0000_0001),
+            rating: 1200,
+            stats: BTreeMap::from([
+                (1, StatValue::LongLong(1200)),
+                (2, StatValue::String("Master Chief".into())),
+                (3, StatValue::Double(0.5)),
+            ]),
+            gamertag: Some("Master Chief".into()),
+        };
+
+        assert_eq!(stored_row(stats_record(row.clone())).unwrap(), row);
+    }
+
+    #[test]
+    fn corrupt_rows_are_reported() {
+        let record = StatsRecord {
+            xuid: Xuid(1),
+            rating: 0,
+            stats: vec![0x01, 0x00, 0x01],
+            gamertag: None,
+        };
+
+        assert!(matches!(stored_row(record), Err(StoreError::CorruptRow(Xuid(1)))));
+    }
+}
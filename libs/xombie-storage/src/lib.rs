@@ -0,0 +1,492 @@
+use std::fmt::Debug;
+use std::io;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use rand::Rng;
+
+use xblive::crypto::primitives::{Sha1Digest, sha1};
+use xblive::service::storage::{FILE_FLAG_SHARED, MAX_ENUM_RESULTS, MAX_PATH_LEN};
+
+use xbox_sys::account::Xuid;
+
+pub mod quota;
+pub mod store;
+
+use quota::Quotas;
+use store::{BlobStore, FileStore, Put, StoreError};
+
+/// What's known about a stored file, without its contents.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileInfo {
+    pub owner: Xuid,
+    pub path: String,
+    pub flags: u32,
+    pub size: u64,
+    pub hash: Sha1Digest,
+    pub modified: SystemTime,
+    /// What the contents are kept under in the BlobStore.  Every upload gets
+    /// a blob of its own, so contents a record points to are never
+    /// overwritten.
+    pub blob: String,
+}
+
+impl FileInfo {
+    pub fn is_shared(&self) -> bool {
+        self.flags & FILE_FLAG_SHARED != 0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Listing {
+    /// How many files the enumeration could see, not just the ones returned.
+    pub total_files: u32,
+    /// The owner's usage and quota in bytes.
+    pub quota_used: u64,
+    pub quota: u64,
+    pub files: Vec<FileInfo>,
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    BadPath,
+    /// Only a file's owner may change it, or read it if it isn't shared.
+    AccessDenied,
+    NotFound,
+    QuotaExceeded,
+    /// Contents that don't match the hash recorded when they were uploaded.
+    Corrupt,
+    Blob(io::Error),
+    Store(StoreError),
+}
+
+impl From<StoreError> for StorageError {
+    fn from(err: StoreError) -> Self {
+        StorageError::Store(err)
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(err: io::Error) -> Self {
+        StorageError::Blob(err)
+    }
+}
+
+/// Paths are made of non-empty components separated by '/', using letters,
+/// digits, '.', '-' and '_', with no component made only of dots.
+pub fn is_valid_path(path: &str) -> bool {
+    if path.is_empty() || path.len() > MAX_PATH_LEN {
+        return false
+    }
+
+    path.split('/').all(|component| {
+        !component.is_empty() &&
+            !component.chars().all(|c| c == '.') &&
+            component.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+    })
+}
+
+/// A fresh blob name for contents with the given hash.
+fn blob_name(hash: &Sha1Digest) -> String {
+    let hash: String = hash.0.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}-{:016x}", hash, rand::thread_rng().gen::<u64>())
+}
+
+/// Any start of a valid path, including the empty prefix.
+fn is_valid_prefix(prefix: &str) -> bool {
+    prefix.len() <= MAX_PATH_LEN &&
+        prefix.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | '/'))
+}
+
+pub struct Storage {
+    quotas: Quotas,
+    files: Arc<dyn FileStore>,
+    blobs: Arc<dyn BlobStore>,
+}
+
+impl Debug for Storage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Storage")
+            .field("quotas", &self.quotas)
+            .finish()
+    }
+}
+
+impl Storage {
+    pub fn new(quotas: Quotas, files: Arc<dyn FileStore>, blobs: Arc<dyn BlobStore>) -> Self {
+        Storage {
+            quotas,
+            files,
+            blobs,
+        }
+    }
+
+    /// Store data as owner's file at path, replacing whatever was there.
+    pub async fn upload(&self, title_id: u32, owner: Xuid, path: &str, flags: u32, data: &[u8]) -> Result<FileInfo, StorageError> {
+        if !is_valid_path(path) {
+            return Err(StorageError::BadPath)
+        }
+
+        let hash = sha1(&[data]);
+        let file = FileInfo {
+            owner,
+            path: path.to_owned(),
+            flags,
+            size: data.len() as u64,
+            blob: blob_name(&hash),
+            hash,
+            modified: SystemTime::now(),
+        };
+
+        // The contents go in first, under a name nothing else uses, and the
+        // record is switched over to them in the same step as the quota
+        // check.  Until then downloads still see the file being replaced,
+        // whose contents are only removed once nothing points to them.
+        self.blobs.write(title_id, owner, &file.blob, data).await?;
+
+        match self.files.put_within_quota(title_id, file.clone(), self.quotas.get(title_id)).await {
+            Ok(Put::Stored { replaced_blob }) => {
+                // A blob left behind only costs disk space, so the upload
+                // stands even if this fails.
+                if let Some(replaced_blob) = replaced_blob {
+                    let _ = self.blobs.delete(title_id, owner, &replaced_blob).await;
+                }
+
+                Ok(file)
+            }
+            Ok(Put::OverQuota) => {
+                let _ = self.blobs.delete(title_id, owner, &file.blob).await;
+                Err(StorageError::QuotaExceeded)
+            }
+            Err(err) => {
+                let _ = self.blobs.delete(title_id, owner, &file.blob).await;
+                Err(StorageError::Store(err))
+            }
+        }
+    }
+
+    /// The contents of owner's file at path, as long as user owns it or it's
+    /// shared.
+    pub async fn download(&self, title_id: u32, user: Xuid, owner: Xuid, path: &str) -> Result<(FileInfo, Vec<u8>), StorageError> {
+        if !is_valid_path(path) {
+            return Err(StorageError::BadPath)
+        }
+
+        let mut file = self.files.get(title_id, owner, path).await?
+            .ok_or(StorageError::NotFound)?;
+
+        loop {
+            if user != owner && !file.is_shared() {
+                return Err(StorageError::AccessDenied)
+            }
+
+            match self.blobs.read(title_id, owner, &file.blob).await {
+                Ok(data) => {
+                    if sha1(&[&data]) != file.hash {
+                        return Err(StorageError::Corrupt)
+                    }
+
+                    return Ok((file, data))
+                }
+                // The file may have been replaced or deleted since its record
+                // was read, taking the contents with it, so look again.
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    let current = self.files.get(title_id, owner, path).await?
+                        .ok_or(StorageError::NotFound)?;
+
+                    if current.blob == file.blob {
+                        return Err(StorageError::Blob(err))
+                    }
+
+                    file = current;
+                }
+                Err(err) => return Err(StorageError::Blob(err)),
+            }
+        }
+    }
+
+    /// Only owners may delete their own files.
+    pub async fn delete(&self, title_id: u32, user: Xuid, owner: Xuid, path: &str) -> Result<(), StorageError> {
+        if !is_valid_path(path) {
+            return Err(StorageError::BadPath)
+        }
+
+        if user != owner {
+            return Err(StorageError::AccessDenied)
+        }
+
+        let blob = self.files.delete(title_id, owner, path).await?
+            .ok_or(StorageError::NotFound)?;
+
+        // Only the deleted file's own contents go, so an upload racing this
+        // one keeps its own.
+        self.blobs.delete(title_id, owner, &blob).await?;
+
+        Ok(())
+    }
+
+    /// Owner's files under prefix as user sees them: every file if they're
+    /// the owner, only the shared ones otherwise.
+    pub async fn enumerate(
+        &self,
+        title_id: u32,
+        user: Xuid,
+        owner: Xuid,
+        prefix: &str,
+        start: u32,
+        max_results: u16,
+    ) -> Result<Listing, StorageError> {
+        if !is_valid_prefix(prefix) {
+            return Err(StorageError::BadPath)
+        }
+
+        let visible: Vec<FileInfo> = self.files.list(title_id, owner, prefix).await?
+            .into_iter()
+            .filter(|file| user == owner || file.is_shared())
+            .collect();
+
+        let total_files = visible.len() as u32;
+
+        let files = visible.into_iter()
+            .skip(start as usize)
+            .take(max_results.min(MAX_ENUM_RESULTS) as usize)
+            .collect();
+
+        Ok(Listing {
+            total_files,
+            quota_used: self.files.usage(title_id, owner).await?,
+            quota: self.quotas.get(title_id),
+            files,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use store::memory::{MemoryBlobStore, MemoryFileStore};
+
+    use super::*;
+
+    const TITLE_ID: u32 = 0x4C41000B;
+    const OWNER: Xuid = Xuid(0x0009_0000_0000_0001);
+    const OTHER: Xuid = Xuid(0x0009_0000_0000_0002);
+
+    fn storage(quota: u64) -> (Storage, Arc<MemoryBlobStore>) {
+        let blobs = Arc::new(MemoryBlobStore::new());
+        let storage = Storage::new(Quotas::new(quota), Arc::new(MemoryFileStore::new()), blobs.clone());
+
+        (storage, blobs)
+    }
+
+    fn paths(listing: &Listing) -> Vec<&str> {
+        listing.files.iter().map(|file| file.path.as_str()).collect()
+    }
+
+    #[test]
+    fn paths_are_checked() {
+        for path in ["map", "replays/1", "clan/emblem.bmp", "a-b_c/..d"] {
+            assert!(is_valid_path(path), "{}", path);
+        }
+
+        for path in ["", "/map", "map/", "replays//1", "..", "replays/../map", "a b", "c:\\map"] {
+            assert!(!is_valid_path(path), "{}", path);
+        }
+
+        assert!(is_valid_path(&"a".repeat(MAX_PATH_LEN)));
+        assert!(!is_valid_path(&"a".repeat(MAX_PATH_LEN + 1)));
+    }
+
+    #[tokio::test]
+    async fn upload_and_download() {
+        let (storage, _) = storage(100);
+
+        let file = storage.upload(TITLE_ID, OWNER, "replays/1", 0, b"abc").await.unwrap();
+        assert_eq!(file.size, 3);
+        assert_eq!(file.hash, sha1(&[b"abc"]));
+
+        let (downloaded, data) = storage.download(TITLE_ID, OWNER, OWNER, "replays/1").await.unwrap();
+        assert_eq!(downloaded, file);
+        assert_eq!(data, b"abc");
+
+        assert!(matches!(
+            storage.download(TITLE_ID, OTHER, OWNER, "replays/1").await,
+            Err(StorageError::AccessDenied)
+        ));
+        assert!(matches!(
+            storage.download(TITLE_ID + 1, OWNER, OWNER, "replays/1").await,
+            Err(StorageError::NotFound)
+        ));
+
+        storage.upload(TITLE_ID, OWNER, "replays/1", FILE_FLAG_SHARED, b"abcd").await.unwrap();
+        let (_, data) = storage.download(TITLE_ID, OTHER, OWNER, "replays/1").await.unwrap();
+        assert_eq!(data, b"abcd");
+    }
+
+    #[tokio::test]
+    async fn quotas_are_enforced() {
+        let (storage, _) = storage(10);
+
+        storage.upload(TITLE_ID, OWNER, "a", 0, &[0; 6]).await.unwrap();
+        assert!(matches!(
+            storage.upload(TITLE_ID, OWNER, "b", 0, &[0; 5]).await,
+            Err(StorageError::QuotaExceeded)
+        ));
+
+        // Replacing a file only counts the difference, and other users and
+        // titles have their own allowance.
+        storage.upload(TITLE_ID, OWNER, "a", 0, &[0; 10]).await.unwrap();
+        storage.upload(TITLE_ID, OTHER, "b", 0, &[0; 10]).await.unwrap();
+        storage.upload(TITLE_ID + 1, OWNER, "b", 0, &[0; 10]).await.unwrap();
+
+        storage.delete(TITLE_ID, OWNER, OWNER, "a").await.unwrap();
+        storage.upload(TITLE_ID, OWNER, "b", 0, &[0; 5]).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn racing_uploads_cannot_overrun_quotas() {
+        // Two SGs sharing one file store.
+        let files = Arc::new(MemoryFileStore::new());
+        let storages: Vec<_> = (0..2)
+            .map(|_| Arc::new(Storage::new(Quotas::new(10), files.clone(), Arc::new(MemoryBlobStore::new()))))
+            .collect();
+
+        let uploads: Vec<_> = (0..8)
+            .map(|n| {
+                let storage = storages[n % 2].clone();
+                tokio::spawn(async move {
+                    storage.upload(TITLE_ID, OWNER, &format!("replays/{}", n), 0, &[0; 4]).await
+                })
+            })
+            .collect();
+
+        let mut uploaded = 0;
+        for upload in uploads {
+            match upload.await.unwrap() {
+                Ok(_) => uploaded += 1,
+                Err(StorageError::QuotaExceeded) => {}
+                Err(err) => panic!("{:?}", err),
+            }
+        }
+
+        assert_eq!(uploaded, 2);
+        assert_eq!(files.usage(TITLE_ID, OWNER).await.unwrap(), 8);
+    }
+
+    #[tokio::test]
+    async fn replaced_contents_are_removed() {
+        let (storage, blobs) = storage(10);
+
+        let first = storage.upload(TITLE_ID, OWNER, "map", 0, b"map").await.unwrap();
+        let second = storage.upload(TITLE_ID, OWNER, "map", 0, b"map").await.unwrap();
+        assert_ne!(first.blob, second.blob);
+        assert_eq!(blobs.read(TITLE_ID, OWNER, &first.blob).await.unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        // Nor is anything left behind by an upload that doesn't fit.
+        assert!(matches!(
+            storage.upload(TITLE_ID, OWNER, "map", 0, &[0; 11]).await,
+            Err(StorageError::QuotaExceeded)
+        ));
+        assert_eq!(blobs.blob_names().await, vec![second.blob.clone()]);
+        assert_eq!(blobs.read(TITLE_ID, OWNER, &second.blob).await.unwrap(), b"map");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn racing_uploads_to_one_path_stay_whole() {
+        // Two SGs sharing both stores.
+        let files = Arc::new(MemoryFileStore::new());
+        let blobs = Arc::new(MemoryBlobStore::new());
+        let storages: Vec<_> = (0..2)
+            .map(|_| Arc::new(Storage::new(Quotas::new(100), files.clone(), blobs.clone())))
+            .collect();
+
+        let uploads: Vec<_> = (0..32u8)
+            .map(|n| {
+                let storage = storages[n as usize % 2].clone();
+                tokio::spawn(async move {
+                    storage.upload(TITLE_ID, OWNER, "map", 0, &[n; 4]).await.unwrap()
+                })
+            })
+            .collect();
+
+        // Every download in the middle of it all sees one upload or another,
+        // whole.
+        let downloads: Vec<_> = (0..32)
+            .map(|n| {
+                let storage = storages[n % 2].clone();
+                tokio::spawn(async move {
+                    match storage.download(TITLE_ID, OWNER, OWNER, "map").await {
+                        Ok((file, data)) => assert_eq!(sha1(&[&data]), file.hash),
+                        Err(StorageError::NotFound) => {}
+                        Err(err) => panic!("{:?}", err),
+                    }
+                })
+            })
+            .collect();
+
+        for download in downloads {
+            download.await.unwrap();
+        }
+
+        for upload in uploads {
+            upload.await.unwrap();
+        }
+
+        // Only the last upload's contents are left, and they match its
+        // record.
+        let (file, data) = storages[0].download(TITLE_ID, OWNER, OWNER, "map").await.unwrap();
+        assert_eq!(sha1(&[&data]), file.hash);
+        assert_eq!(blobs.blob_names().await, vec![file.blob]);
+        assert_eq!(files.usage(TITLE_ID, OWNER).await.unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn only_owners_delete() {
+        let (storage, blobs) = storage(100);
+
+        let file = storage.upload(TITLE_ID, OWNER, "map", FILE_FLAG_SHARED, b"map").await.unwrap();
+
+        assert!(matches!(storage.delete(TITLE_ID, OTHER, OWNER, "map").await, Err(StorageError::AccessDenied)));
+        storage.delete(TITLE_ID, OWNER, OWNER, "map").await.unwrap();
+        assert!(matches!(storage.delete(TITLE_ID, OWNER, OWNER, "map").await, Err(StorageError::NotFound)));
+
+        assert_eq!(blobs.read(TITLE_ID, OWNER, &file.blob).await.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn enumeration_hides_unshared_files() {
+        let (storage, _) = storage(100);
+
+        storage.upload(TITLE_ID, OWNER, "maps/b", FILE_FLAG_SHARED, b"b").await.unwrap();
+        storage.upload(TITLE_ID, OWNER, "maps/a", 0, b"a").await.unwrap();
+        storage.upload(TITLE_ID, OWNER, "maps/c", FILE_FLAG_SHARED, b"c").await.unwrap();
+        storage.upload(TITLE_ID, OWNER, "replays/1", FILE_FLAG_SHARED, b"1").await.unwrap();
+
+        let listing = storage.enumerate(TITLE_ID, OWNER, OWNER, "maps/", 0, 10).await.unwrap();
+        assert_eq!(paths(&listing), vec!["maps/a", "maps/b", "maps/c"]);
+        assert_eq!(listing.total_files, 3);
+        assert_eq!(listing.quota_used, 4);
+        assert_eq!(listing.quota, 100);
+
+        let listing = storage.enumerate(TITLE_ID, OTHER, OWNER, "maps/", 0, 10).await.unwrap();
+        assert_eq!(paths(&listing), vec!["maps/b", "maps/c"]);
+
+        let listing = storage.enumerate(TITLE_ID, OWNER, OWNER, "", 1, 2).await.unwrap();
+        assert_eq!(listing.total_files, 4);
+        assert_eq!(paths(&listing), vec!["maps/b", "maps/c"]);
+
+        assert!(matches!(
+            storage.enumerate(TITLE_ID, OWNER, OWNER, "maps\\", 0, 10).await,
+            Err(StorageError::BadPath)
+        ));
+    }
+
+    #[tokio::test]
+    async fn corrupt_contents_are_reported() {
+        let (storage, blobs) = storage(100);
+
+        let file = storage.upload(TITLE_ID, OWNER, "map", 0, b"map").await.unwrap();
+        blobs.write(TITLE_ID, OWNER, &file.blob, b"pam").await.unwrap();
+
+        assert!(matches!(storage.download(TITLE_ID, OWNER, OWNER, "map").await, Err(StorageError::Corrupt)));
+    }
+}
@@ -0,0 +1,116 @@
+//! How much each user may store.
+//!
+//! Quotas are per user and title: every user gets the same allowance in a
+//! title, and a title's allowance doesn't count against any other title's.
+//! They're read from a TOML file like:
+//!
+//! ```toml
+//! default = 1048576
+//!
+//! [titles]
+//! 4c41000b = 4194304
+//! ```
+//!
+//! with title ids in hex.  Titles that aren't listed get the default, which
+//! is DEFAULT_QUOTA unless set.
+
+use serde::Deserialize;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Bytes each user may store in a title unless configured otherwise.
+pub const DEFAULT_QUOTA: u64 = 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct QuotaFile {
+    default: Option<u64>,
+    #[serde(default)]
+    titles: BTreeMap<String, u64>,
+}
+
+#[derive(Debug)]
+pub enum QuotaLoadError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    BadTitleId(String),
+}
+
+impl From<io::Error> for QuotaLoadError {
+    fn from(err: io::Error) -> Self {
+        QuotaLoadError::Io(err)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Quotas {
+    default: u64,
+    titles: BTreeMap<u32, u64>,
+}
+
+impl Quotas {
+    pub fn new(default: u64) -> Self {
+        Quotas {
+            default,
+            titles: BTreeMap::new(),
+        }
+    }
+
+    pub fn load_file(path: &Path) -> Result<Self, QuotaLoadError> {
+        Quotas::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, QuotaLoadError> {
+        let file: QuotaFile = toml::from_str(source)
+            .map_err(QuotaLoadError::Parse)?;
+
+        let mut quotas = Quotas::new(file.default.unwrap_or(DEFAULT_QUOTA));
+
+        for (title_id, quota) in file.titles {
+            let title_id = u32::from_str_radix(&title_id, 16)
+                .map_err(|_| QuotaLoadError::BadTitleId(title_id.clone()))?;
+
+            quotas.set_title(title_id, quota);
+        }
+
+        Ok(quotas)
+    }
+
+    pub fn set_title(&mut self, title_id: u32, quota: u64) {
+        self.titles.insert(title_id, quota);
+    }
+
+    /// Bytes each user may store in title_id.
+    pub fn get(&self, title_id: u32) -> u64 {
+        self.titles.get(&title_id).copied().unwrap_or(self.default)
+    }
+}
+
+impl Default for Quotas {
+    fn default() -> Self {
+        Quotas::new(DEFAULT_QUOTA)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_quotas() {
+        let quotas = Quotas::parse("default = 100\n[titles]\n4c41000b = 4096\n").unwrap();
+        assert_eq!(quotas.get(0x4C41000B), 4096);
+        assert_eq!(quotas.get(0x4D530004), 100);
+
+        let quotas = Quotas::parse("[titles]\n4c41000b = 4096\n").unwrap();
+        assert_eq!(quotas.get(0x4D530004), DEFAULT_QUOTA);
+
+        assert!(matches!(
+            Quotas::parse("[titles]\nhalo = 4096\n"),
+            Err(QuotaLoadError::BadTitleId(title_id)) if title_id == "halo"
+        ));
+        assert!(matches!(Quotas::parse("default = \"lots\"\n"), Err(QuotaLoadError::Parse(_))));
+    }
+}
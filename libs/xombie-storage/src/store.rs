@@ -0,0 +1,80 @@
+//! Where stored files live.
+//!
+//! Storage decides who may do what to a file and keeps quotas; a FileStore
+//! only keeps what's known about each file, and a BlobStore only keeps the
+//! contents.  Files are addressed by title, owner and path in a FileStore,
+//! and their contents by title, owner and the blob named in their record.
+
+use async_trait::async_trait;
+
+use std::io;
+
+use xbox_sys::account::Xuid;
+
+use crate::FileInfo;
+
+pub mod fs;
+pub mod memory;
+pub mod postgres;
+
+#[derive(Debug)]
+pub enum StoreError {
+    Pg(tokio_postgres::Error),
+    Pool(xombie::db::PoolError),
+    CannotParseXuid(std::num::ParseIntError),
+    /// A stored hash that isn't a SHA-1 digest.
+    BadHash(Xuid, String),
+}
+
+impl From<tokio_postgres::Error> for StoreError {
+    fn from(pg_err: tokio_postgres::Error) -> Self {
+        StoreError::Pg(pg_err)
+    }
+}
+
+impl From<xombie::db::PoolError> for StoreError {
+    fn from(pool_err: xombie::db::PoolError) -> Self {
+        StoreError::Pool(pool_err)
+    }
+}
+
+/// What FileStore::put_within_quota did.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Put {
+    /// The file went in, replacing one whose contents were in replaced_blob,
+    /// if there was one.
+    Stored { replaced_blob: Option<String> },
+    /// Nothing changed, as the file wouldn't fit.
+    OverQuota,
+}
+
+#[async_trait]
+pub trait FileStore: Send + Sync {
+    async fn get(&self, title_id: u32, owner: Xuid, path: &str) -> Result<Option<FileInfo>, StoreError>;
+
+    /// Add or overwrite the file at file.path as long as the owner's files,
+    /// with it in place of any it replaces, come to no more than quota bytes.
+    /// The check and the write are one step, so uploads racing each other,
+    /// even through different SGs, can't overrun the quota.
+    async fn put_within_quota(&self, title_id: u32, file: FileInfo, quota: u64) -> Result<Put, StoreError>;
+
+    /// Returns the deleted file's blob, or None if there was no such file.
+    async fn delete(&self, title_id: u32, owner: Xuid, path: &str) -> Result<Option<String>, StoreError>;
+
+    /// Every file of owner's whose path starts with prefix, in path order.
+    async fn list(&self, title_id: u32, owner: Xuid, prefix: &str) -> Result<Vec<FileInfo>, StoreError>;
+
+    /// The total size of owner's files.
+    async fn usage(&self, title_id: u32, owner: Xuid) -> Result<u64, StoreError>;
+}
+
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Replace the contents of a blob, creating it if need be.
+    async fn write(&self, title_id: u32, owner: Xuid, blob: &str, data: &[u8]) -> io::Result<()>;
+
+    async fn read(&self, title_id: u32, owner: Xuid, blob: &str) -> io::Result<Vec<u8>>;
+
+    /// Removing a blob that isn't there isn't an error.
+    async fn delete(&self, title_id: u32, owner: Xuid, blob: &str) -> io::Result<()>;
+}
@@ -0,0 +1,118 @@
+use async_trait::async_trait;
+
+use rand::Rng;
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+
+use xblive::crypto::primitives::sha1;
+
+use xbox_sys::account::Xuid;
+
+use super::BlobStore;
+
+/// File contents kept in a directory tree, one directory per title and
+/// owner.  Blobs are named by the hex SHA-1 of their name, so nothing a
+/// console sends is ever interpreted by the filesystem, and even the longest
+/// names fit well inside NAME_MAX.  Files stored before blobs had names of
+/// their own are kept under their path, which their records name instead.
+pub struct FsBlobStore {
+    root: PathBuf,
+}
+
+impl FsBlobStore {
+    pub fn new(root: &Path) -> Self {
+        FsBlobStore {
+            root: root.to_owned(),
+        }
+    }
+
+    fn owner_dir(&self, title_id: u32, owner: Xuid) -> PathBuf {
+        self.root
+            .join(format!("{:08x}", title_id))
+            .join(format!("{:016x}", owner.0))
+    }
+
+    fn blob_path(&self, title_id: u32, owner: Xuid, blob: &str) -> PathBuf {
+        let file_name: String = sha1(&[blob.as_bytes()]).0.iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.owner_dir(title_id, owner).join(file_name)
+    }
+}
+
+#[async_trait]
+impl BlobStore for FsBlobStore {
+    async fn write(&self, title_id: u32, owner: Xuid, blob: &str, data: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(self.owner_dir(title_id, owner)).await?;
+
+        // Written to the side and renamed into place so a download never
+        // sees a partly written file.
+        let blob_path = self.blob_path(title_id, owner, blob);
+        let temp_path = blob_path.with_extension(format!("part-{:08x}", rand::thread_rng().gen::<u32>()));
+
+        if let Err(err) = fs::write(&temp_path, data).await {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(err);
+        }
+
+        fs::rename(&temp_path, &blob_path).await
+    }
+
+    async fn read(&self, title_id: u32, owner: Xuid, blob: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.blob_path(title_id, owner, blob)).await
+    }
+
+    async fn delete(&self, title_id: u32, owner: Xuid, blob: &str) -> io::Result<()> {
+        match fs::remove_file(self.blob_path(title_id, owner, blob)).await {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use xblive::service::storage::MAX_PATH_LEN;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn blobs_round_trip() {
+        let root = tempfile::tempdir().unwrap();
+        let blobs = FsBlobStore::new(root.path());
+        let owner = Xuid(0x0009_0000_0000_0001);
+
+        blobs.write(0x4C41000B, owner, "../replays/1", b"first").await.unwrap();
+        blobs.write(0x4C41000B, owner, "../replays/1", b"second").await.unwrap();
+        assert_eq!(blobs.read(0x4C41000B, owner, "../replays/1").await.unwrap(), b"second");
+
+        // Everything stays inside the owner's directory, with no leftovers
+        // from the writes.
+        let owner_dir = root.path().join("4c41000b").join("0009000000000001");
+        let names: Vec<_> = std::fs::read_dir(&owner_dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(names, vec!["2f0aadb1919e168f4a2a8413687631bf3e215c0e"]);
+
+        blobs.delete(0x4C41000B, owner, "../replays/1").await.unwrap();
+        blobs.delete(0x4C41000B, owner, "../replays/1").await.unwrap();
+        assert_eq!(
+            blobs.read(0x4C41000B, owner, "../replays/1").await.unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn the_longest_paths_fit() {
+        let root = tempfile::tempdir().unwrap();
+        let blobs = FsBlobStore::new(root.path());
+        let owner = Xuid(0x0009_0000_0000_0001);
+        let path = "a".repeat(MAX_PATH_LEN);
+
+        blobs.write(0x4C41000B, owner, &path, b"long").await.unwrap();
+        assert_eq!(blobs.read(0x4C41000B, owner, &path).await.unwrap(), b"long");
+
+        blobs.delete(0x4C41000B, owner, &path).await.unwrap();
+    }
+}
@@ -0,0 +1,131 @@
+use async_trait::async_trait;
+
+use std::collections::BTreeMap;
+use std::io;
+
+use tokio::sync::Mutex;
+
+use xbox_sys::account::Xuid;
+
+use crate::FileInfo;
+
+use super::{BlobStore, FileStore, Put, StoreError};
+
+type FileKey = (u32, Xuid, String);
+
+fn file_key(title_id: u32, owner: Xuid, path: &str) -> FileKey {
+    (title_id, owner, path.to_owned())
+}
+
+/// What's known about stored files, kept in process and lost when the SG
+/// restarts.
+pub struct MemoryFileStore {
+    files: Mutex<BTreeMap<FileKey, FileInfo>>,
+}
+
+impl MemoryFileStore {
+    pub fn new() -> Self {
+        MemoryFileStore {
+            files: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl Default for MemoryFileStore {
+    fn default() -> Self {
+        MemoryFileStore::new()
+    }
+}
+
+#[async_trait]
+impl FileStore for MemoryFileStore {
+    async fn get(&self, title_id: u32, owner: Xuid, path: &str) -> Result<Option<FileInfo>, StoreError> {
+        Ok(self.files.lock().await.get(&file_key(title_id, owner, path)).cloned())
+    }
+
+    async fn put_within_quota(&self, title_id: u32, file: FileInfo, quota: u64) -> Result<Put, StoreError> {
+        let mut files = self.files.lock().await;
+
+        let used: u64 = files.range(file_key(title_id, file.owner, "")..)
+            .take_while(|((file_title_id, file_owner, _), _)| *file_title_id == title_id && *file_owner == file.owner)
+            .filter(|((_, _, path), _)| *path != file.path)
+            .map(|(_, other)| other.size)
+            .sum();
+
+        if used.saturating_add(file.size) > quota {
+            return Ok(Put::OverQuota)
+        }
+
+        let replaced = files.insert(file_key(title_id, file.owner, &file.path), file);
+
+        Ok(Put::Stored {
+            replaced_blob: replaced.map(|replaced| replaced.blob),
+        })
+    }
+
+    async fn delete(&self, title_id: u32, owner: Xuid, path: &str) -> Result<Option<String>, StoreError> {
+        Ok(self.files.lock().await.remove(&file_key(title_id, owner, path)).map(|file| file.blob))
+    }
+
+    async fn list(&self, title_id: u32, owner: Xuid, prefix: &str) -> Result<Vec<FileInfo>, StoreError> {
+        let files = self.files.lock().await;
+
+        Ok(files.range(file_key(title_id, owner, prefix)..)
+            .take_while(|((file_title_id, file_owner, path), _)| {
+                *file_title_id == title_id && *file_owner == owner && path.starts_with(prefix)
+            })
+            .map(|(_, file)| file.clone())
+            .collect())
+    }
+
+    async fn usage(&self, title_id: u32, owner: Xuid) -> Result<u64, StoreError> {
+        Ok(self.list(title_id, owner, "").await?
+            .iter()
+            .map(|file| file.size)
+            .sum())
+    }
+}
+
+/// File contents kept in process, lost when the SG restarts.
+pub struct MemoryBlobStore {
+    blobs: Mutex<BTreeMap<FileKey, Vec<u8>>>,
+}
+
+impl MemoryBlobStore {
+    pub fn new() -> Self {
+        MemoryBlobStore {
+            blobs: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The name of every blob kept, for checking nothing's left behind.
+    #[cfg(test)]
+    pub(crate) async fn blob_names(&self) -> Vec<String> {
+        self.blobs.lock().await.keys().map(|(_, _, blob)| blob.clone()).collect()
+    }
+}
+
+impl Default for MemoryBlobStore {
+    fn default() -> Self {
+        MemoryBlobStore::new()
+    }
+}
+
+#[async_trait]
+impl BlobStore for MemoryBlobStore {
+    async fn write(&self, title_id: u32, owner: Xuid, blob: &str, data: &[u8]) -> io::Result<()> {
+        self.blobs.lock().await.insert(file_key(title_id, owner, blob), data.to_vec());
+        Ok(())
+    }
+
+    async fn read(&self, title_id: u32, owner: Xuid, blob: &str) -> io::Result<Vec<u8>> {
+        self.blobs.lock().await.get(&file_key(title_id, owner, blob))
+            .cloned()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+
+    async fn delete(&self, title_id: u32, owner: Xuid, blob: &str) -> io::Result<()> {
+        self.blobs.lock().await.remove(&file_key(title_id, owner, blob));
+        Ok(())
+    }
+}
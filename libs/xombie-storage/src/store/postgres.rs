@@ -0,0 +1,144 @@
+use async_trait::async_trait;
+
+use xblive::crypto::primitives::Sha1Digest;
+
+use xbox_sys::account::Xuid;
+
+use xombie::db::{self, Pool, StorageError, StoragePut, StorageRecord};
+
+use crate::FileInfo;
+
+use super::{FileStore, Put, StoreError};
+
+/// What's known about stored files, kept in the database so it survives the
+/// SG.
+pub struct PostgresFileStore {
+    pool: Pool,
+}
+
+impl PostgresFileStore {
+    /// Use connections from pool for file metadata.  Its tables come from
+    /// schema/0004_storage.sql and schema/0009_storage_blobs.sql.
+    pub fn new(pool: Pool) -> Self {
+        PostgresFileStore {
+            pool,
+        }
+    }
+}
+
+impl From<StorageError> for StoreError {
+    fn from(err: StorageError) -> Self {
+        match err {
+            StorageError::Pg(pg_err) => StoreError::Pg(pg_err),
+            StorageError::CannotParseXuid(parse_err) => StoreError::CannotParseXuid(parse_err),
+        }
+    }
+}
+
+fn file_info(record: StorageRecord) -> Result<FileInfo, StoreError> {
+    let hash = match record.hash.as_slice().try_into() {
+        Ok(hash) => Sha1Digest(hash),
+        Err(_) => return Err(StoreError::BadHash(record.owner, record.path)),
+    };
+
+    Ok(FileInfo {
+        owner: record.owner,
+        path: record.path,
+        flags: record.flags,
+        size: record.size,
+        hash,
+        modified: record.modified,
+        blob: record.blob,
+    })
+}
+
+fn storage_record(file: FileInfo) -> StorageRecord {
+    StorageRecord {
+        owner: file.owner,
+        path: file.path,
+        flags: file.flags,
+        size: file.size,
+        hash: file.hash.0.to_vec(),
+        modified: file.modified,
+        blob: file.blob,
+    }
+}
+
+#[async_trait]
+impl FileStore for PostgresFileStore {
+    async fn get(&self, title_id: u32, owner: Xuid, path: &str) -> Result<Option<FileInfo>, StoreError> {
+        let client = self.pool.get().await?;
+
+        db::get_storage_record(&client, title_id, owner, path).await?
+            .map(file_info)
+            .transpose()
+    }
+
+    async fn put_within_quota(&self, title_id: u32, file: FileInfo, quota: u64) -> Result<Put, StoreError> {
+        let mut client = self.pool.get().await?;
+
+        Ok(match db::put_storage_record_within_quota(&mut client, title_id, &storage_record(file), quota).await? {
+            StoragePut::Stored { replaced_blob } => Put::Stored { replaced_blob },
+            StoragePut::OverQuota => Put::OverQuota,
+        })
+    }
+
+    async fn delete(&self, title_id: u32, owner: Xuid, path: &str) -> Result<Option<String>, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::delete_storage_record(&client, title_id, owner, path).await?)
+    }
+
+    async fn list(&self, title_id: u32, owner: Xuid, prefix: &str) -> Result<Vec<FileInfo>, StoreError> {
+        let client = self.pool.get().await?;
+
+        db::get_storage_records(&client, title_id, owner, prefix).await?
+            .into_iter()
+            .map(file_info)
+            .collect()
+    }
+
+    async fn usage(&self, title_id: u32, owner: Xuid) -> Result<u64, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::get_storage_usage(&client, title_id, owner).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    #[test]
+    fn files_survive_records() {
+        let file = FileInfo {
+            owner: Xuid(0x0009_0000_0000_0001),
+            path: "replays/1".to_owned(),
+            flags: 1,
+            size: 3,
+            hash: Sha1Digest([0x5a; 20]),
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            blob: "5a5a-0000000000000001".to_owned(),
+        };
+
+        assert_eq!(file_info(storage_record(file.clone())).unwrap(), file);
+    }
+
+    #[test]
+    fn bad_hashes_are_reported() {
+        let mut record = storage_record(FileInfo {
+            owner: Xuid(1),
+            path: "map".to_owned(),
+            flags: 0,
+            size: 0,
+            hash: Sha1Digest([0; 20]),
+            modified: SystemTime::UNIX_EPOCH,
+            blob: "map".to_owned(),
+        });
+        record.hash.truncate(4);
+
+        assert!(matches!(file_info(record), Err(StoreError::BadHash(Xuid(1), path)) if path == "map"));
+    }
+}
@@ -0,0 +1,322 @@
+//! Localized string tables.
+//!
+//! Tables live in a directory with one directory per title, named with the
+//! title id in hex, and a `system` directory for strings every title shares.
+//! Each holds one table per language, named with the language's code and
+//! written in TOML or JSON:
+//!
+//! ```text
+//! strings/
+//!     system/
+//!         en.toml
+//!     4c41000b/
+//!         en.toml
+//!         de.json
+//! ```
+//!
+//! A table maps string ids, in decimal or 0x prefixed hex, to their text:
+//!
+//! ```toml
+//! 1 = "Welcome back"
+//! 0x10 = "Double XP weekend"
+//! ```
+//!
+//! A string missing from the language asked for falls back to English, then
+//! to the system tables in the language asked for, then to the system tables
+//! in English.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use xblive::service::string::*;
+
+/// The directory of strings shared by every title.
+pub const SYSTEM_DIR_NAME: &str = "system";
+
+/// The language lookups fall back to.
+pub const DEFAULT_LANGUAGE: u32 = LANGUAGE_ENGLISH;
+
+const LANGUAGE_CODES: [(&str, u32); 9] = [
+    ("en", LANGUAGE_ENGLISH),
+    ("ja", LANGUAGE_JAPANESE),
+    ("de", LANGUAGE_GERMAN),
+    ("fr", LANGUAGE_FRENCH),
+    ("es", LANGUAGE_SPANISH),
+    ("it", LANGUAGE_ITALIAN),
+    ("ko", LANGUAGE_KOREAN),
+    ("zh", LANGUAGE_TCHINESE),
+    ("pt", LANGUAGE_PORTUGUESE),
+];
+
+/// The language a table file's stem names.
+pub fn language_from_code(code: &str) -> Option<u32> {
+    LANGUAGE_CODES.iter()
+        .find(|(language_code, _)| *language_code == code)
+        .map(|(_, language)| *language)
+}
+
+pub fn is_known_language(language: u32) -> bool {
+    LANGUAGE_CODES.iter().any(|(_, known)| *known == language)
+}
+
+#[derive(Debug)]
+pub enum StringLoadError {
+    Io(PathBuf, io::Error),
+    BadDirName(PathBuf),
+    /// A table not named with a language code and a .toml or .json
+    /// extension.
+    BadFileName(PathBuf),
+    ParseToml(PathBuf, toml::de::Error),
+    ParseJson(PathBuf, serde_json::Error),
+    BadStringId(PathBuf, String),
+    /// Two tables for the same language, one TOML and one JSON.
+    DuplicateLanguage(PathBuf, u32),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StringError {
+    UnknownLanguage,
+    TooManyStrings,
+}
+
+/// A string found by a lookup, in the language it was found in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FoundString<'a> {
+    pub string_id: u32,
+    pub language: u32,
+    pub text: &'a str,
+}
+
+type Table = BTreeMap<u32, String>;
+
+/// Every string table, keyed by title id (None for system strings) and
+/// language.
+#[derive(Debug, Default)]
+pub struct StringTables {
+    tables: BTreeMap<(Option<u32>, u32), Table>,
+}
+
+fn io_err(path: &Path) -> impl FnOnce(io::Error) -> StringLoadError + '_ {
+    move |err| StringLoadError::Io(path.to_owned(), err)
+}
+
+fn parse_string_id(key: &str) -> Option<u32> {
+    match key.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => key.parse().ok(),
+    }
+}
+
+fn load_table(path: &Path) -> Result<(u32, Table), StringLoadError> {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+
+    if extension != Some("toml") && extension != Some("json") {
+        return Err(StringLoadError::BadFileName(path.to_owned()));
+    }
+
+    let language = path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(language_from_code)
+        .ok_or_else(|| StringLoadError::BadFileName(path.to_owned()))?;
+
+    let source = fs::read_to_string(path).map_err(io_err(path))?;
+
+    let strings: BTreeMap<String, String> = if extension == Some("toml") {
+        toml::from_str(&source).map_err(|err| StringLoadError::ParseToml(path.to_owned(), err))?
+    } else {
+        serde_json::from_str(&source).map_err(|err| StringLoadError::ParseJson(path.to_owned(), err))?
+    };
+
+    let mut table = Table::new();
+
+    for (key, text) in strings {
+        let string_id = parse_string_id(&key)
+            .ok_or_else(|| StringLoadError::BadStringId(path.to_owned(), key))?;
+
+        table.insert(string_id, text);
+    }
+
+    Ok((language, table))
+}
+
+impl StringTables {
+    pub fn new() -> Self {
+        StringTables {
+            tables: BTreeMap::new(),
+        }
+    }
+
+    /// Load the system and every title's tables in dir.
+    pub fn load_dir(dir: &Path) -> Result<Self, StringLoadError> {
+        let mut tables = StringTables::new();
+
+        for entry in fs::read_dir(dir).map_err(io_err(dir))? {
+            let path = entry.map_err(io_err(dir))?.path();
+
+            if !path.is_dir() {
+                continue;
+            }
+
+            let name = path.file_name().and_then(|name| name.to_str());
+
+            let title_id = match name {
+                Some(SYSTEM_DIR_NAME) => None,
+                _ => Some(name
+                    .and_then(|name| u32::from_str_radix(name, 16).ok())
+                    .ok_or_else(|| StringLoadError::BadDirName(path.clone()))?),
+            };
+
+            for entry in fs::read_dir(&path).map_err(io_err(&path))? {
+                let path = entry.map_err(io_err(&path))?.path();
+
+                let (language, table) = load_table(&path)?;
+
+                if tables.tables.contains_key(&(title_id, language)) {
+                    return Err(StringLoadError::DuplicateLanguage(path, language));
+                }
+
+                tables.add(title_id, language, table);
+            }
+        }
+
+        Ok(tables)
+    }
+
+    /// Add a table of strings for a title, or the system if title_id is
+    /// None, replacing any table already there.
+    pub fn add(&mut self, title_id: Option<u32>, language: u32, table: BTreeMap<u32, String>) {
+        self.tables.insert((title_id, language), table);
+    }
+
+    fn get(&self, title_id: Option<u32>, language: u32, string_id: u32) -> Option<FoundString<'_>> {
+        let text = self.tables.get(&(title_id, language))?.get(&string_id)?;
+
+        Some(FoundString {
+            string_id,
+            language,
+            text,
+        })
+    }
+
+    /// Look up a string, falling back through the title's default language
+    /// and the system tables.
+    pub fn find(&self, title_id: u32, language: u32, string_id: u32) -> Option<FoundString<'_>> {
+        self.get(Some(title_id), language, string_id)
+            .or_else(|| self.get(Some(title_id), DEFAULT_LANGUAGE, string_id))
+            .or_else(|| self.get(None, language, string_id))
+            .or_else(|| self.get(None, DEFAULT_LANGUAGE, string_id))
+    }
+
+    /// Every string of string_ids that has one, in order.
+    pub fn lookup(&self, title_id: u32, language: u32, string_ids: &[u32]) -> Result<Vec<FoundString<'_>>, StringError> {
+        if !is_known_language(language) {
+            return Err(StringError::UnknownLanguage);
+        }
+
+        if string_ids.len() > MAX_STRING_IDS as usize {
+            return Err(StringError::TooManyStrings);
+        }
+
+        Ok(string_ids.iter()
+            .filter_map(|string_id| self.find(title_id, language, *string_id))
+            .collect())
+    }
+
+    /// The number of tables loaded.
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TITLE_ID: u32 = 0x4C41000B;
+
+    fn tables() -> (tempfile::TempDir, StringTables) {
+        let root = tempfile::tempdir().unwrap();
+
+        fs::create_dir(root.path().join("system")).unwrap();
+        fs::write(root.path().join("system").join("en.toml"), "
+            1 = \"Connection lost\"
+            2 = \"Signed in\"
+        ").unwrap();
+        fs::write(root.path().join("system").join("de.toml"), "2 = \"Angemeldet\"").unwrap();
+
+        fs::create_dir(root.path().join("4c41000b")).unwrap();
+        fs::write(root.path().join("4c41000b").join("en.toml"), "
+            0x10 = \"Double XP weekend\"
+            0x11 = \"Map pack out now\"
+        ").unwrap();
+        fs::write(root.path().join("4c41000b").join("de.json"), r#"{"0x10": "Doppelte XP"}"#).unwrap();
+
+        let tables = StringTables::load_dir(root.path()).unwrap();
+
+        (root, tables)
+    }
+
+    #[test]
+    fn lookups_fall_back() {
+        let (_root, tables) = tables();
+        assert_eq!(tables.len(), 4);
+
+        let found = tables.lookup(TITLE_ID, LANGUAGE_GERMAN, &[0x10, 0x11, 2, 1, 3]).unwrap();
+
+        assert_eq!(found, vec![
+            FoundString { string_id: 0x10, language: LANGUAGE_GERMAN, text: "Doppelte XP" },
+            FoundString { string_id: 0x11, language: LANGUAGE_ENGLISH, text: "Map pack out now" },
+            FoundString { string_id: 2, language: LANGUAGE_GERMAN, text: "Angemeldet" },
+            FoundString { string_id: 1, language: LANGUAGE_ENGLISH, text: "Connection lost" },
+        ]);
+
+        // Other titles only see the system strings.
+        assert!(tables.find(TITLE_ID + 1, LANGUAGE_ENGLISH, 0x10).is_none());
+        assert_eq!(tables.find(TITLE_ID + 1, LANGUAGE_ENGLISH, 1).unwrap().text, "Connection lost");
+    }
+
+    #[test]
+    fn bad_lookups_are_rejected() {
+        let (_root, tables) = tables();
+
+        assert_eq!(tables.lookup(TITLE_ID, 0, &[1]), Err(StringError::UnknownLanguage));
+
+        let string_ids = vec![1; MAX_STRING_IDS as usize + 1];
+        assert_eq!(tables.lookup(TITLE_ID, LANGUAGE_ENGLISH, &string_ids), Err(StringError::TooManyStrings));
+    }
+
+    #[test]
+    fn bad_tables_are_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("system")).unwrap();
+        fs::write(root.path().join("system").join("en.toml"), "1 = \"a\"").unwrap();
+        fs::write(root.path().join("system").join("en.json"), r#"{"1": "a"}"#).unwrap();
+        assert!(matches!(
+            StringTables::load_dir(root.path()),
+            Err(StringLoadError::DuplicateLanguage(_, LANGUAGE_ENGLISH))
+        ));
+
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("system")).unwrap();
+        fs::write(root.path().join("system").join("xx.toml"), "1 = \"a\"").unwrap();
+        assert!(matches!(StringTables::load_dir(root.path()), Err(StringLoadError::BadFileName(_))));
+
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("system")).unwrap();
+        fs::write(root.path().join("system").join("en.toml"), "motd = \"a\"").unwrap();
+        assert!(matches!(
+            StringTables::load_dir(root.path()),
+            Err(StringLoadError::BadStringId(_, key)) if key == "motd"
+        ));
+
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("halo")).unwrap();
+        assert!(matches!(StringTables::load_dir(root.path()), Err(StringLoadError::BadDirName(_))));
+    }
+}
@@ -0,0 +1,644 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use xblive::service::team::*;
+
+use xbox_sys::account::Xuid;
+
+pub mod store;
+
+use store::{AcceptOutcome, CreateOutcome, HandOverOutcome, PropertiesOutcome, StoreError, TeamStore};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Team {
+    pub id: u64,
+    pub title_id: u32,
+    pub name: String,
+    pub created: SystemTime,
+    pub properties: BTreeMap<u32, Vec<u8>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Member {
+    pub xuid: Xuid,
+    pub role: u32,
+    pub joined: SystemTime,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Invitation {
+    pub team_id: u64,
+    pub invitee: Xuid,
+    pub inviter: Xuid,
+    pub sent: SystemTime,
+}
+
+/// A team a user belongs to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Membership {
+    pub team: Team,
+    pub role: u32,
+    pub member_count: u16,
+}
+
+#[derive(Debug)]
+pub enum TeamError {
+    InvalidName,
+    NameTaken,
+    /// No such team, or it belongs to another title.
+    NoSuchTeam,
+    NotAMember,
+    AlreadyMember,
+    PermissionDenied,
+    NoInvitation,
+    TeamFull,
+    TooManyTeams,
+    TooManyProperties,
+    PropertyTooLarge,
+    InvalidRole,
+    /// Owners have to hand the team over, or delete it, rather than leave.
+    OwnerCannotLeave,
+    Store(StoreError),
+}
+
+impl From<StoreError> for TeamError {
+    fn from(err: StoreError) -> Self {
+        TeamError::Store(err)
+    }
+}
+
+/// Names are printable ASCII without spaces at either end.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() &&
+        name.len() <= MAX_NAME_LEN &&
+        name.chars().all(|c| c.is_ascii_graphic() || c == ' ') &&
+        name.trim() == name
+}
+
+fn check_property_sizes(updates: &[(u32, Vec<u8>)]) -> Result<(), TeamError> {
+    if updates.iter().any(|(_, data)| data.len() > MAX_PROPERTY_LEN) {
+        return Err(TeamError::PropertyTooLarge);
+    }
+
+    Ok(())
+}
+
+/// Apply updates to properties.  An empty value removes the property.
+fn update_properties(properties: &mut BTreeMap<u32, Vec<u8>>, updates: &[(u32, Vec<u8>)]) -> Result<(), TeamError> {
+    check_property_sizes(updates)?;
+
+    for (id, data) in updates {
+        if data.is_empty() {
+            properties.remove(id);
+        } else {
+            properties.insert(*id, data.clone());
+        }
+    }
+
+    if properties.len() > MAX_PROPERTIES as usize {
+        return Err(TeamError::TooManyProperties);
+    }
+
+    Ok(())
+}
+
+pub struct Teams {
+    store: Arc<dyn TeamStore>,
+}
+
+impl Debug for Teams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Teams").finish()
+    }
+}
+
+impl Teams {
+    pub fn new(store: Arc<dyn TeamStore>) -> Self {
+        Teams {
+            store,
+        }
+    }
+
+    /// Create a team with owner as its owner and only member.  Returns the
+    /// id of the team.
+    pub async fn create(&self, title_id: u32, owner: Xuid, name: &str, properties: &[(u32, Vec<u8>)]) -> Result<u64, TeamError> {
+        if !is_valid_name(name) {
+            return Err(TeamError::InvalidName);
+        }
+
+        let mut team_properties = BTreeMap::new();
+        update_properties(&mut team_properties, properties)?;
+
+        let now = SystemTime::now();
+
+        let team = Team {
+            id: 0,
+            title_id,
+            name: name.to_owned(),
+            created: now,
+            properties: team_properties,
+        };
+
+        let owner = Member {
+            xuid: owner,
+            role: TEAM_ROLE_OWNER,
+            joined: now,
+        };
+
+        match self.store.create(team, owner, MAX_TEAMS_PER_USER as usize).await? {
+            CreateOutcome::Created(team_id) => Ok(team_id),
+            CreateOutcome::NameTaken => Err(TeamError::NameTaken),
+            CreateOutcome::TooManyTeams => Err(TeamError::TooManyTeams),
+        }
+    }
+
+    /// A team of title_id.
+    pub async fn get(&self, title_id: u32, team_id: u64) -> Result<Team, TeamError> {
+        match self.store.get(team_id).await? {
+            Some(team) if team.title_id == title_id => Ok(team),
+            _ => Err(TeamError::NoSuchTeam),
+        }
+    }
+
+    /// A team of title_id and its members, in the order they joined.
+    pub async fn details(&self, title_id: u32, team_id: u64) -> Result<(Team, Vec<Member>), TeamError> {
+        let team = self.get(title_id, team_id).await?;
+        let members = self.store.members(team_id).await?;
+
+        Ok((team, members))
+    }
+
+    /// Every team of title_id user belongs to, oldest first.
+    pub async fn teams_of(&self, title_id: u32, user: Xuid) -> Result<Vec<Membership>, TeamError> {
+        let mut memberships = vec![];
+
+        for team in self.store.teams_of(title_id, user).await? {
+            let members = self.store.members(team.id).await?;
+
+            let role = match members.iter().find(|member| member.xuid == user) {
+                Some(member) => member.role,
+                // Left since the teams were listed.
+                None => continue,
+            };
+
+            memberships.push(Membership {
+                team,
+                role,
+                member_count: members.len() as u16,
+            });
+        }
+
+        Ok(memberships)
+    }
+
+    /// Every invitation user has to a team of title_id, with the team,
+    /// oldest first.
+    pub async fn invitations_of(&self, title_id: u32, user: Xuid) -> Result<Vec<(Invitation, Team)>, TeamError> {
+        let mut invitations = vec![];
+
+        for invitation in self.store.invitations_of(title_id, user).await? {
+            if let Some(team) = self.store.get(invitation.team_id).await? {
+                invitations.push((invitation, team));
+            }
+        }
+
+        Ok(invitations)
+    }
+
+    /// user's role in a team, NotAMember if they aren't in it.
+    async fn role_of(&self, team_id: u64, user: Xuid) -> Result<u32, TeamError> {
+        self.store.members(team_id).await?
+            .iter()
+            .find(|member| member.xuid == user)
+            .map(|member| member.role)
+            .ok_or(TeamError::NotAMember)
+    }
+
+    /// Change the properties of a team user is an officer of.  Properties
+    /// not in updates are left as they are, and ones with an empty value are
+    /// removed.
+    pub async fn set_properties(&self, title_id: u32, user: Xuid, team_id: u64, updates: &[(u32, Vec<u8>)]) -> Result<(), TeamError> {
+        check_property_sizes(updates)?;
+
+        self.get(title_id, team_id).await?;
+
+        if self.role_of(team_id, user).await? < TEAM_ROLE_OFFICER {
+            return Err(TeamError::PermissionDenied);
+        }
+
+        match self.store.set_properties(team_id, updates, MAX_PROPERTIES as usize).await? {
+            PropertiesOutcome::Set => Ok(()),
+            PropertiesOutcome::NoSuchTeam => Err(TeamError::NoSuchTeam),
+            PropertiesOutcome::TooManyProperties => Err(TeamError::TooManyProperties),
+        }
+    }
+
+    /// Invite invitee to a team user is an officer of.
+    pub async fn invite(&self, title_id: u32, user: Xuid, team_id: u64, invitee: Xuid) -> Result<(), TeamError> {
+        self.get(title_id, team_id).await?;
+
+        let members = self.store.members(team_id).await?;
+
+        let role = members.iter()
+            .find(|member| member.xuid == user)
+            .map(|member| member.role)
+            .ok_or(TeamError::NotAMember)?;
+
+        if role < TEAM_ROLE_OFFICER {
+            return Err(TeamError::PermissionDenied);
+        }
+
+        if members.iter().any(|member| member.xuid == invitee) {
+            return Err(TeamError::AlreadyMember);
+        }
+
+        if members.len() >= MAX_MEMBERS as usize {
+            return Err(TeamError::TeamFull);
+        }
+
+        self.store.add_invitation(Invitation {
+            team_id,
+            invitee,
+            inviter: user,
+            sent: SystemTime::now(),
+        }).await?;
+
+        Ok(())
+    }
+
+    /// Join a team user was invited to.
+    pub async fn accept(&self, title_id: u32, user: Xuid, team_id: u64) -> Result<(), TeamError> {
+        self.get(title_id, team_id).await?;
+
+        let member = Member {
+            xuid: user,
+            role: TEAM_ROLE_MEMBER,
+            joined: SystemTime::now(),
+        };
+
+        let outcome = self.store.accept_invitation(
+            title_id,
+            team_id,
+            member,
+            MAX_MEMBERS as usize,
+            MAX_TEAMS_PER_USER as usize,
+        ).await?;
+
+        match outcome {
+            AcceptOutcome::Joined => Ok(()),
+            AcceptOutcome::NoInvitation => Err(TeamError::NoInvitation),
+            AcceptOutcome::TeamFull => Err(TeamError::TeamFull),
+            AcceptOutcome::TooManyTeams => Err(TeamError::TooManyTeams),
+            AcceptOutcome::AlreadyMember => Err(TeamError::AlreadyMember),
+        }
+    }
+
+    /// Turn down an invitation to a team.
+    pub async fn decline(&self, title_id: u32, user: Xuid, team_id: u64) -> Result<(), TeamError> {
+        self.get(title_id, team_id).await?;
+
+        if self.store.take_invitation(team_id, user).await?.is_none() {
+            return Err(TeamError::NoInvitation);
+        }
+
+        Ok(())
+    }
+
+    /// Remove member from a team.  Members can remove themselves, other than
+    /// the owner, and officers can remove members with a lower role.
+    pub async fn remove(&self, title_id: u32, user: Xuid, team_id: u64, member: Xuid) -> Result<(), TeamError> {
+        self.get(title_id, team_id).await?;
+
+        let role = self.role_of(team_id, user).await?;
+
+        if member == user {
+            if role == TEAM_ROLE_OWNER {
+                return Err(TeamError::OwnerCannotLeave);
+            }
+        } else {
+            let member_role = self.role_of(team_id, member).await?;
+
+            if role < TEAM_ROLE_OFFICER || role <= member_role {
+                return Err(TeamError::PermissionDenied);
+            }
+        }
+
+        if !self.store.remove_member(team_id, member).await? {
+            return Err(TeamError::NotAMember);
+        }
+
+        Ok(())
+    }
+
+    /// Change another member's role in a team user owns.  Making them the
+    /// owner leaves user an officer.
+    pub async fn set_role(&self, title_id: u32, user: Xuid, team_id: u64, member: Xuid, role: u32) -> Result<(), TeamError> {
+        self.get(title_id, team_id).await?;
+
+        if role > TEAM_ROLE_OWNER {
+            return Err(TeamError::InvalidRole);
+        }
+
+        if self.role_of(team_id, user).await? != TEAM_ROLE_OWNER || member == user {
+            return Err(TeamError::PermissionDenied);
+        }
+
+        if role == TEAM_ROLE_OWNER {
+            return match self.store.hand_over(team_id, user, member).await? {
+                HandOverOutcome::HandedOver => Ok(()),
+                HandOverOutcome::NotOwner => Err(TeamError::PermissionDenied),
+                HandOverOutcome::NotAMember => Err(TeamError::NotAMember),
+            };
+        }
+
+        if !self.store.set_role(team_id, member, role).await? {
+            return Err(TeamError::NotAMember);
+        }
+
+        Ok(())
+    }
+
+    /// Disband a team user owns.
+    pub async fn delete(&self, title_id: u32, user: Xuid, team_id: u64) -> Result<(), TeamError> {
+        self.get(title_id, team_id).await?;
+
+        if self.role_of(team_id, user).await? != TEAM_ROLE_OWNER {
+            return Err(TeamError::PermissionDenied);
+        }
+
+        if !self.store.delete(team_id).await? {
+            return Err(TeamError::NoSuchTeam);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::store::memory::MemoryStore;
+
+    const TITLE_ID: u32 = 0x4C41000B;
+
+    const ALICE: Xuid = Xuid(0x0009_0000_0000_0001);
+    const BOB: Xuid = Xuid(0x0009_0000_0000_0002);
+    const CAROL: Xuid = Xuid(0x0009_0000_0000_0003);
+
+    fn teams() -> Teams {
+        Teams::new(Arc::new(MemoryStore::new()))
+    }
+
+    /// Alice owns the team, Bob is an officer and Carol a member.
+    async fn team(teams: &Teams) -> u64 {
+        let team_id = teams.create(TITLE_ID, ALICE, "Reds", &[(TEAM_PROPERTY_MOTTO, b"gg".to_vec())]).await.unwrap();
+
+        for user in [BOB, CAROL] {
+            teams.invite(TITLE_ID, ALICE, team_id, user).await.unwrap();
+            teams.accept(TITLE_ID, user, team_id).await.unwrap();
+        }
+
+        teams.set_role(TITLE_ID, ALICE, team_id, BOB, TEAM_ROLE_OFFICER).await.unwrap();
+
+        team_id
+    }
+
+    fn roles(members: &[Member]) -> Vec<(Xuid, u32)> {
+        members.iter().map(|member| (member.xuid, member.role)).collect()
+    }
+
+    #[tokio::test]
+    async fn teams_are_joined_by_invitation() {
+        let teams = teams();
+        let team_id = team(&teams).await;
+
+        let (team, members) = teams.details(TITLE_ID, team_id).await.unwrap();
+        assert_eq!(team.name, "Reds");
+        assert_eq!(team.properties[&TEAM_PROPERTY_MOTTO], b"gg");
+        assert_eq!(roles(&members), vec![
+            (ALICE, TEAM_ROLE_OWNER),
+            (BOB, TEAM_ROLE_OFFICER),
+            (CAROL, TEAM_ROLE_MEMBER),
+        ]);
+
+        let memberships = teams.teams_of(TITLE_ID, CAROL).await.unwrap();
+        assert_eq!(memberships.len(), 1);
+        assert_eq!(memberships[0].role, TEAM_ROLE_MEMBER);
+        assert_eq!(memberships[0].member_count, 3);
+
+        // Invitations are used up by accepting them.
+        assert!(teams.invitations_of(TITLE_ID, CAROL).await.unwrap().is_empty());
+        assert!(matches!(teams.accept(TITLE_ID, CAROL, team_id).await, Err(TeamError::NoInvitation)));
+
+        // Teams are only visible from their own title.
+        assert!(teams.teams_of(TITLE_ID + 1, CAROL).await.unwrap().is_empty());
+        assert!(matches!(teams.details(TITLE_ID + 1, team_id).await, Err(TeamError::NoSuchTeam)));
+    }
+
+    #[tokio::test]
+    async fn invitations_are_declined() {
+        let teams = teams();
+        let team_id = teams.create(TITLE_ID, ALICE, "Reds", &[]).await.unwrap();
+
+        teams.invite(TITLE_ID, ALICE, team_id, BOB).await.unwrap();
+
+        let invitations = teams.invitations_of(TITLE_ID, BOB).await.unwrap();
+        assert_eq!(invitations.len(), 1);
+        assert_eq!(invitations[0].0.inviter, ALICE);
+        assert_eq!(invitations[0].1.name, "Reds");
+
+        teams.decline(TITLE_ID, BOB, team_id).await.unwrap();
+        assert!(teams.invitations_of(TITLE_ID, BOB).await.unwrap().is_empty());
+        assert!(matches!(teams.accept(TITLE_ID, BOB, team_id).await, Err(TeamError::NoInvitation)));
+    }
+
+    #[tokio::test]
+    async fn roles_limit_what_members_can_do() {
+        let teams = teams();
+        let team_id = team(&teams).await;
+
+        // Members can't invite, officers can't remove their equals.
+        assert!(matches!(teams.invite(TITLE_ID, CAROL, team_id, Xuid(4)).await, Err(TeamError::PermissionDenied)));
+        assert!(matches!(teams.remove(TITLE_ID, BOB, team_id, ALICE).await, Err(TeamError::PermissionDenied)));
+        assert!(matches!(teams.set_role(TITLE_ID, BOB, team_id, CAROL, TEAM_ROLE_OFFICER).await, Err(TeamError::PermissionDenied)));
+        assert!(matches!(teams.delete(TITLE_ID, BOB, team_id).await, Err(TeamError::PermissionDenied)));
+        assert!(matches!(
+            teams.set_properties(TITLE_ID, CAROL, team_id, &[(TEAM_PROPERTY_URL, b"x".to_vec())]).await,
+            Err(TeamError::PermissionDenied)
+        ));
+
+        teams.set_properties(TITLE_ID, BOB, team_id, &[
+            (TEAM_PROPERTY_MOTTO, vec![]),
+            (TEAM_PROPERTY_URL, b"reds.example".to_vec()),
+        ]).await.unwrap();
+        let team = teams.get(TITLE_ID, team_id).await.unwrap();
+        assert_eq!(team.properties.into_iter().collect::<Vec<_>>(), vec![(TEAM_PROPERTY_URL, b"reds.example".to_vec())]);
+
+        teams.remove(TITLE_ID, BOB, team_id, CAROL).await.unwrap();
+        assert!(matches!(teams.remove(TITLE_ID, BOB, team_id, CAROL).await, Err(TeamError::NotAMember)));
+
+        assert!(matches!(teams.remove(TITLE_ID, ALICE, team_id, ALICE).await, Err(TeamError::OwnerCannotLeave)));
+        teams.remove(TITLE_ID, BOB, team_id, BOB).await.unwrap();
+
+        let (_, members) = teams.details(TITLE_ID, team_id).await.unwrap();
+        assert_eq!(roles(&members), vec![(ALICE, TEAM_ROLE_OWNER)]);
+
+        teams.delete(TITLE_ID, ALICE, team_id).await.unwrap();
+        assert!(matches!(teams.get(TITLE_ID, team_id).await, Err(TeamError::NoSuchTeam)));
+    }
+
+    #[tokio::test]
+    async fn ownership_is_handed_over() {
+        let teams = teams();
+        let team_id = team(&teams).await;
+
+        assert!(matches!(teams.set_role(TITLE_ID, ALICE, team_id, CAROL, 3).await, Err(TeamError::InvalidRole)));
+        assert!(matches!(teams.set_role(TITLE_ID, ALICE, team_id, ALICE, TEAM_ROLE_MEMBER).await, Err(TeamError::PermissionDenied)));
+
+        teams.set_role(TITLE_ID, ALICE, team_id, CAROL, TEAM_ROLE_OWNER).await.unwrap();
+
+        let (_, members) = teams.details(TITLE_ID, team_id).await.unwrap();
+        assert_eq!(roles(&members), vec![
+            (ALICE, TEAM_ROLE_OFFICER),
+            (BOB, TEAM_ROLE_OFFICER),
+            (CAROL, TEAM_ROLE_OWNER),
+        ]);
+
+        teams.remove(TITLE_ID, ALICE, team_id, ALICE).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn racing_accepts_cannot_overfill_a_team() {
+        let teams = Arc::new(teams());
+        let team_id = teams.create(TITLE_ID, ALICE, "Reds", &[]).await.unwrap();
+
+        let invitees: Vec<_> = (0..MAX_MEMBERS as u64 + 10).map(|n| Xuid(0x0009_0000_0001_0000 + n)).collect();
+        for invitee in invitees.iter() {
+            teams.invite(TITLE_ID, ALICE, team_id, *invitee).await.unwrap();
+        }
+
+        let accepts: Vec<_> = invitees.into_iter()
+            .map(|invitee| {
+                let teams = teams.clone();
+                tokio::spawn(async move {
+                    teams.accept(TITLE_ID, invitee, team_id).await
+                })
+            })
+            .collect();
+
+        let mut full = 0;
+        for accept in accepts {
+            match accept.await.unwrap() {
+                Ok(()) => {}
+                Err(TeamError::TeamFull) => full += 1,
+                Err(err) => panic!("{:?}", err),
+            }
+        }
+
+        let (_, members) = teams.details(TITLE_ID, team_id).await.unwrap();
+        assert_eq!(members.len(), MAX_MEMBERS as usize);
+        assert_eq!(full, 11);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn racing_creates_cannot_overrun_the_team_limit() {
+        let teams = Arc::new(teams());
+
+        let creates: Vec<_> = (0..MAX_TEAMS_PER_USER as usize + 8)
+            .map(|n| {
+                let teams = teams.clone();
+                tokio::spawn(async move {
+                    teams.create(TITLE_ID, ALICE, &format!("Team {}", n), &[]).await
+                })
+            })
+            .collect();
+
+        let mut too_many = 0;
+        for create in creates {
+            match create.await.unwrap() {
+                Ok(_) => {}
+                Err(TeamError::TooManyTeams) => too_many += 1,
+                Err(err) => panic!("{:?}", err),
+            }
+        }
+
+        assert_eq!(too_many, 8);
+        assert_eq!(teams.teams_of(TITLE_ID, ALICE).await.unwrap().len(), MAX_TEAMS_PER_USER as usize);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn racing_property_updates_all_land() {
+        let teams = Arc::new(teams());
+        let team_id = team(&teams).await;
+
+        let updates: Vec<_> = (0..MAX_PROPERTIES as u32 - 1)
+            .map(|n| {
+                let teams = teams.clone();
+                let officer = if n % 2 == 0 { ALICE } else { BOB };
+                tokio::spawn(async move {
+                    teams.set_properties(TITLE_ID, officer, team_id, &[(0x100 + n, vec![n as u8 + 1])]).await
+                })
+            })
+            .collect();
+
+        for update in updates {
+            update.await.unwrap().unwrap();
+        }
+
+        let team = teams.get(TITLE_ID, team_id).await.unwrap();
+        assert_eq!(team.properties.len(), MAX_PROPERTIES as usize);
+
+        assert!(matches!(
+            teams.set_properties(TITLE_ID, ALICE, team_id, &[(0x200, vec![1])]).await,
+            Err(TeamError::TooManyProperties)
+        ));
+        assert_eq!(teams.get(TITLE_ID, team_id).await.unwrap(), team);
+    }
+
+    #[tokio::test]
+    async fn ownership_is_only_handed_over_once() {
+        let teams = teams();
+        let team_id = team(&teams).await;
+
+        teams.set_role(TITLE_ID, ALICE, team_id, BOB, TEAM_ROLE_OWNER).await.unwrap();
+
+        // Alice's view of the team is out of date by the time her second
+        // hand over reaches the store.
+        assert_eq!(teams.store.hand_over(team_id, ALICE, CAROL).await.unwrap(), HandOverOutcome::NotOwner);
+
+        let (_, members) = teams.details(TITLE_ID, team_id).await.unwrap();
+        assert_eq!(roles(&members), vec![
+            (ALICE, TEAM_ROLE_OFFICER),
+            (BOB, TEAM_ROLE_OWNER),
+            (CAROL, TEAM_ROLE_MEMBER),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn bad_teams_are_rejected() {
+        let teams = teams();
+        teams.create(TITLE_ID, ALICE, "Reds", &[]).await.unwrap();
+
+        assert!(matches!(teams.create(TITLE_ID, BOB, "REDS", &[]).await, Err(TeamError::NameTaken)));
+        teams.create(TITLE_ID + 1, BOB, "Reds", &[]).await.unwrap();
+
+        for name in ["", " Reds", "A name far too long", "Red\u{e9}"] {
+            assert!(matches!(teams.create(TITLE_ID, BOB, name, &[]).await, Err(TeamError::InvalidName)));
+        }
+
+        assert!(matches!(
+            teams.create(TITLE_ID, BOB, "Blues", &[(1, vec![0; MAX_PROPERTY_LEN + 1])]).await,
+            Err(TeamError::PropertyTooLarge)
+        ));
+
+        let properties: Vec<_> = (0..MAX_PROPERTIES as u32 + 1).map(|id| (id, vec![1])).collect();
+        assert!(matches!(teams.create(TITLE_ID, BOB, "Blues", &properties).await, Err(TeamError::TooManyProperties)));
+
+        for i in 1..MAX_TEAMS_PER_USER {
+            teams.create(TITLE_ID, ALICE, &format!("Team {}", i), &[]).await.unwrap();
+        }
+        assert!(matches!(teams.create(TITLE_ID, ALICE, "One more", &[]).await, Err(TeamError::TooManyTeams)));
+    }
+}
@@ -0,0 +1,123 @@
+//! Where teams live.
+//!
+//! Teams decides who may do what to a team; a TeamStore only keeps teams,
+//! their members and the invitations to join them.
+
+use async_trait::async_trait;
+
+use xbox_sys::account::Xuid;
+
+use crate::{Invitation, Member, Team};
+
+pub mod memory;
+pub mod postgres;
+
+#[derive(Debug)]
+pub enum StoreError {
+    Pg(tokio_postgres::Error),
+    Pool(xombie::db::PoolError),
+    CannotParseXuid(std::num::ParseIntError),
+}
+
+impl From<tokio_postgres::Error> for StoreError {
+    fn from(pg_err: tokio_postgres::Error) -> Self {
+        StoreError::Pg(pg_err)
+    }
+}
+
+impl From<xombie::db::PoolError> for StoreError {
+    fn from(pool_err: xombie::db::PoolError) -> Self {
+        StoreError::Pool(pool_err)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CreateOutcome {
+    Created(u64),
+    /// The title already has a team with the same name, ignoring case.
+    NameTaken,
+    TooManyTeams,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PropertiesOutcome {
+    Set,
+    NoSuchTeam,
+    TooManyProperties,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AcceptOutcome {
+    Joined,
+    NoInvitation,
+    TeamFull,
+    TooManyTeams,
+    AlreadyMember,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HandOverOutcome {
+    HandedOver,
+    /// The team has another owner by now.
+    NotOwner,
+    NotAMember,
+}
+
+#[async_trait]
+pub trait TeamStore: Send + Sync {
+    /// Keep team, ignoring its id, with owner as its only member, as long as
+    /// owner is in fewer than max_teams teams of its title.  The check and
+    /// the create are one step, so creates racing each other, even through
+    /// different SGs, can't overrun the limit.
+    async fn create(&self, team: Team, owner: Member, max_teams: usize) -> Result<CreateOutcome, StoreError>;
+
+    async fn get(&self, team_id: u64) -> Result<Option<Team>, StoreError>;
+
+    /// Every team of title_id that user is a member of, oldest first.
+    async fn teams_of(&self, title_id: u32, user: Xuid) -> Result<Vec<Team>, StoreError>;
+
+    /// Apply updates to the properties of a team, in order, with an empty
+    /// value removing the property.  Nothing changes if the team would end up
+    /// with more than max_properties properties.  Updates to the same team
+    /// are applied one at a time, so none undoes another.
+    async fn set_properties(&self, team_id: u64, updates: &[(u32, Vec<u8>)], max_properties: usize) -> Result<PropertiesOutcome, StoreError>;
+
+    /// Drop a team along with its members and invitations.  Returns false if
+    /// there was no such team.
+    async fn delete(&self, team_id: u64) -> Result<bool, StoreError>;
+
+    /// Every member of a team, in the order they joined.
+    async fn members(&self, team_id: u64) -> Result<Vec<Member>, StoreError>;
+
+    /// Use up member's invitation to team_id and add them, in one step, as
+    /// long as the team has fewer than max_members members and they're in
+    /// fewer than max_teams teams of title_id.  The invitation is kept if
+    /// either limit is reached.
+    async fn accept_invitation(
+        &self,
+        title_id: u32,
+        team_id: u64,
+        member: Member,
+        max_members: usize,
+        max_teams: usize,
+    ) -> Result<AcceptOutcome, StoreError>;
+
+    /// Returns false if there was no such member.
+    async fn set_role(&self, team_id: u64, xuid: Xuid, role: u32) -> Result<bool, StoreError>;
+
+    /// Make new_owner the owner and owner an officer, in one step, as long as
+    /// owner still owns the team.
+    async fn hand_over(&self, team_id: u64, owner: Xuid, new_owner: Xuid) -> Result<HandOverOutcome, StoreError>;
+
+    /// Returns false if there was no such member.
+    async fn remove_member(&self, team_id: u64, xuid: Xuid) -> Result<bool, StoreError>;
+
+    /// Keep invitation, replacing any the invitee already had to the team.
+    async fn add_invitation(&self, invitation: Invitation) -> Result<(), StoreError>;
+
+    /// Every invitation invitee has to a team of title_id, oldest first.
+    async fn invitations_of(&self, title_id: u32, invitee: Xuid) -> Result<Vec<Invitation>, StoreError>;
+
+    /// Remove an invitation, returning it if there was one.
+    async fn take_invitation(&self, team_id: u64, invitee: Xuid) -> Result<Option<Invitation>, StoreError>;
+}
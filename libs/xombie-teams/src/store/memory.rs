@@ -0,0 +1,250 @@
+use async_trait::async_trait;
+
+use std::collections::BTreeMap;
+
+use tokio::sync::Mutex;
+
+use xblive::service::team::{TEAM_ROLE_OFFICER, TEAM_ROLE_OWNER};
+
+use xbox_sys::account::Xuid;
+
+use crate::{Invitation, Member, Team};
+
+use super::{AcceptOutcome, CreateOutcome, HandOverOutcome, PropertiesOutcome, StoreError, TeamStore};
+
+/// Teams kept in process, lost when the SG restarts.
+pub struct MemoryStore {
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    last_id: u64,
+    teams: BTreeMap<u64, Team>,
+    members: BTreeMap<u64, Vec<Member>>,
+    invitations: Vec<Invitation>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore {
+            state: Mutex::new(MemoryState::default()),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::new()
+    }
+}
+
+#[async_trait]
+impl TeamStore for MemoryStore {
+    async fn create(&self, mut team: Team, owner: Member, max_teams: usize) -> Result<CreateOutcome, StoreError> {
+        let mut state = self.state.lock().await;
+
+        let teams = state.teams.values()
+            .filter(|other| other.title_id == team.title_id)
+            .filter(|other| state.members[&other.id].iter().any(|member| member.xuid == owner.xuid))
+            .count();
+
+        if teams >= max_teams {
+            return Ok(CreateOutcome::TooManyTeams);
+        }
+
+        let name = team.name.to_lowercase();
+        if state.teams.values().any(|other| other.title_id == team.title_id && other.name.to_lowercase() == name) {
+            return Ok(CreateOutcome::NameTaken);
+        }
+
+        state.last_id += 1;
+        team.id = state.last_id;
+
+        state.members.insert(team.id, vec![owner]);
+        state.teams.insert(team.id, team);
+
+        Ok(CreateOutcome::Created(state.last_id))
+    }
+
+    async fn get(&self, team_id: u64) -> Result<Option<Team>, StoreError> {
+        Ok(self.state.lock().await.teams.get(&team_id).cloned())
+    }
+
+    async fn teams_of(&self, title_id: u32, user: Xuid) -> Result<Vec<Team>, StoreError> {
+        let state = self.state.lock().await;
+
+        Ok(state.teams.values()
+            .filter(|team| team.title_id == title_id)
+            .filter(|team| state.members[&team.id].iter().any(|member| member.xuid == user))
+            .cloned()
+            .collect())
+    }
+
+    async fn set_properties(&self, team_id: u64, updates: &[(u32, Vec<u8>)], max_properties: usize) -> Result<PropertiesOutcome, StoreError> {
+        let mut state = self.state.lock().await;
+
+        let team = match state.teams.get_mut(&team_id) {
+            Some(team) => team,
+            None => return Ok(PropertiesOutcome::NoSuchTeam),
+        };
+
+        let mut properties = team.properties.clone();
+        for (id, data) in updates {
+            if data.is_empty() {
+                properties.remove(id);
+            } else {
+                properties.insert(*id, data.clone());
+            }
+        }
+
+        if properties.len() > max_properties {
+            return Ok(PropertiesOutcome::TooManyProperties);
+        }
+
+        team.properties = properties;
+
+        Ok(PropertiesOutcome::Set)
+    }
+
+    async fn delete(&self, team_id: u64) -> Result<bool, StoreError> {
+        let mut state = self.state.lock().await;
+
+        state.members.remove(&team_id);
+        state.invitations.retain(|invitation| invitation.team_id != team_id);
+
+        Ok(state.teams.remove(&team_id).is_some())
+    }
+
+    async fn members(&self, team_id: u64) -> Result<Vec<Member>, StoreError> {
+        Ok(self.state.lock().await.members.get(&team_id).cloned().unwrap_or_default())
+    }
+
+    async fn accept_invitation(
+        &self,
+        title_id: u32,
+        team_id: u64,
+        member: Member,
+        max_members: usize,
+        max_teams: usize,
+    ) -> Result<AcceptOutcome, StoreError> {
+        let mut state = self.state.lock().await;
+
+        let invitation = state.invitations.iter()
+            .position(|invitation| invitation.team_id == team_id && invitation.invitee == member.xuid);
+
+        let (invitation, members) = match (invitation, state.members.get(&team_id)) {
+            (Some(invitation), Some(members)) => (invitation, members),
+            _ => return Ok(AcceptOutcome::NoInvitation),
+        };
+
+        if members.len() >= max_members {
+            return Ok(AcceptOutcome::TeamFull);
+        }
+
+        let teams = state.teams.values()
+            .filter(|team| team.title_id == title_id)
+            .filter(|team| state.members[&team.id].iter().any(|other| other.xuid == member.xuid))
+            .count();
+
+        if teams >= max_teams {
+            return Ok(AcceptOutcome::TooManyTeams);
+        }
+
+        let already_member = members.iter().any(|other| other.xuid == member.xuid);
+
+        state.invitations.remove(invitation);
+
+        if already_member {
+            return Ok(AcceptOutcome::AlreadyMember);
+        }
+
+        state.members.get_mut(&team_id).unwrap().push(member);
+
+        Ok(AcceptOutcome::Joined)
+    }
+
+    async fn set_role(&self, team_id: u64, xuid: Xuid, role: u32) -> Result<bool, StoreError> {
+        let mut state = self.state.lock().await;
+
+        let member = state.members.get_mut(&team_id)
+            .and_then(|members| members.iter_mut().find(|member| member.xuid == xuid));
+
+        match member {
+            Some(member) => {
+                member.role = role;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn hand_over(&self, team_id: u64, owner: Xuid, new_owner: Xuid) -> Result<HandOverOutcome, StoreError> {
+        let mut state = self.state.lock().await;
+
+        let members = match state.members.get_mut(&team_id) {
+            Some(members) => members,
+            None => return Ok(HandOverOutcome::NotOwner),
+        };
+
+        if !members.iter().any(|member| member.xuid == owner && member.role == TEAM_ROLE_OWNER) {
+            return Ok(HandOverOutcome::NotOwner);
+        }
+
+        if !members.iter().any(|member| member.xuid == new_owner) {
+            return Ok(HandOverOutcome::NotAMember);
+        }
+
+        for member in members.iter_mut() {
+            if member.xuid == new_owner {
+                member.role = TEAM_ROLE_OWNER;
+            } else if member.xuid == owner {
+                member.role = TEAM_ROLE_OFFICER;
+            }
+        }
+
+        Ok(HandOverOutcome::HandedOver)
+    }
+
+    async fn remove_member(&self, team_id: u64, xuid: Xuid) -> Result<bool, StoreError> {
+        let mut state = self.state.lock().await;
+
+        let members = match state.members.get_mut(&team_id) {
+            Some(members) => members,
+            None => return Ok(false),
+        };
+
+        let len = members.len();
+        members.retain(|member| member.xuid != xuid);
+
+        Ok(members.len() != len)
+    }
+
+    async fn add_invitation(&self, invitation: Invitation) -> Result<(), StoreError> {
+        let mut state = self.state.lock().await;
+
+        state.invitations.retain(|other| other.team_id != invitation.team_id || other.invitee != invitation.invitee);
+        state.invitations.push(invitation);
+
+        Ok(())
+    }
+
+    async fn invitations_of(&self, title_id: u32, invitee: Xuid) -> Result<Vec<Invitation>, StoreError> {
+        let state = self.state.lock().await;
+
+        Ok(state.invitations.iter()
+            .filter(|invitation| invitation.invitee == invitee)
+            .filter(|invitation| state.teams.get(&invitation.team_id).map(|team| team.title_id) == Some(title_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn take_invitation(&self, team_id: u64, invitee: Xuid) -> Result<Option<Invitation>, StoreError> {
+        let mut state = self.state.lock().await;
+
+        let index = state.invitations.iter()
+            .position(|invitation| invitation.team_id == team_id && invitation.invitee == invitee);
+
+        Ok(index.map(|index| state.invitations.remove(index)))
+    }
+}
@@ -0,0 +1,225 @@
+use async_trait::async_trait;
+
+use xbox_sys::account::Xuid;
+
+use xblive::service::team::{TEAM_ROLE_OFFICER, TEAM_ROLE_OWNER};
+
+use xombie::db::{
+    self, Pool, TeamAcceptOutcome, TeamCreateOutcome, TeamError, TeamHandOverOutcome, TeamInvitationRecord,
+    TeamMemberRecord, TeamPropertiesOutcome, TeamRecord,
+};
+
+use crate::{Invitation, Member, Team};
+
+use super::{AcceptOutcome, CreateOutcome, HandOverOutcome, PropertiesOutcome, StoreError, TeamStore};
+
+/// Teams kept in the database so they outlive the SG.
+pub struct PostgresStore {
+    pool: Pool,
+}
+
+impl PostgresStore {
+    /// Use connections from pool for team storage.  Its tables come from
+    /// schema/0007_teams.sql.
+    pub fn new(pool: Pool) -> Self {
+        PostgresStore {
+            pool,
+        }
+    }
+}
+
+impl From<TeamError> for StoreError {
+    fn from(err: TeamError) -> Self {
+        match err {
+            TeamError::Pg(pg_err) => StoreError::Pg(pg_err),
+            TeamError::CannotParseXuid(parse_err) => StoreError::CannotParseXuid(parse_err),
+        }
+    }
+}
+
+impl From<TeamRecord> for Team {
+    fn from(record: TeamRecord) -> Self {
+        Team {
+            id: record.id,
+            title_id: record.title_id,
+            name: record.name,
+            created: record.created,
+            properties: record.properties.into_iter().collect(),
+        }
+    }
+}
+
+impl From<Team> for TeamRecord {
+    fn from(team: Team) -> Self {
+        TeamRecord {
+            id: team.id,
+            title_id: team.title_id,
+            name: team.name,
+            created: team.created,
+            properties: team.properties.into_iter().collect(),
+        }
+    }
+}
+
+impl From<TeamMemberRecord> for Member {
+    fn from(record: TeamMemberRecord) -> Self {
+        Member {
+            xuid: record.xuid,
+            role: record.role,
+            joined: record.joined,
+        }
+    }
+}
+
+impl From<Member> for TeamMemberRecord {
+    fn from(member: Member) -> Self {
+        TeamMemberRecord {
+            xuid: member.xuid,
+            role: member.role,
+            joined: member.joined,
+        }
+    }
+}
+
+impl From<TeamInvitationRecord> for Invitation {
+    fn from(record: TeamInvitationRecord) -> Self {
+        Invitation {
+            team_id: record.team_id,
+            invitee: record.invitee,
+            inviter: record.inviter,
+            sent: record.sent,
+        }
+    }
+}
+
+impl From<Invitation> for TeamInvitationRecord {
+    fn from(invitation: Invitation) -> Self {
+        TeamInvitationRecord {
+            team_id: invitation.team_id,
+            invitee: invitation.invitee,
+            inviter: invitation.inviter,
+            sent: invitation.sent,
+        }
+    }
+}
+
+#[async_trait]
+impl TeamStore for PostgresStore {
+    async fn create(&self, team: Team, owner: Member, max_teams: usize) -> Result<CreateOutcome, StoreError> {
+        let mut client = self.pool.get().await?;
+
+        Ok(match db::put_team_record(&mut client, &team.into(), &owner.into(), max_teams).await? {
+            TeamCreateOutcome::Created(id) => CreateOutcome::Created(id),
+            TeamCreateOutcome::NameTaken => CreateOutcome::NameTaken,
+            TeamCreateOutcome::TooManyTeams => CreateOutcome::TooManyTeams,
+        })
+    }
+
+    async fn get(&self, team_id: u64) -> Result<Option<Team>, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::get_team_record(&client, team_id).await?
+            .map(Team::from))
+    }
+
+    async fn teams_of(&self, title_id: u32, user: Xuid) -> Result<Vec<Team>, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::get_team_records_of(&client, title_id, user).await?
+            .into_iter()
+            .map(Team::from)
+            .collect())
+    }
+
+    async fn set_properties(&self, team_id: u64, updates: &[(u32, Vec<u8>)], max_properties: usize) -> Result<PropertiesOutcome, StoreError> {
+        let mut client = self.pool.get().await?;
+
+        Ok(match db::set_team_properties(&mut client, team_id, updates, max_properties).await? {
+            TeamPropertiesOutcome::Set => PropertiesOutcome::Set,
+            TeamPropertiesOutcome::NoSuchTeam => PropertiesOutcome::NoSuchTeam,
+            TeamPropertiesOutcome::TooManyProperties => PropertiesOutcome::TooManyProperties,
+        })
+    }
+
+    async fn delete(&self, team_id: u64) -> Result<bool, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::delete_team_record(&client, team_id).await?)
+    }
+
+    async fn members(&self, team_id: u64) -> Result<Vec<Member>, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::get_team_member_records(&client, team_id).await?
+            .into_iter()
+            .map(Member::from)
+            .collect())
+    }
+
+    async fn accept_invitation(
+        &self,
+        title_id: u32,
+        team_id: u64,
+        member: Member,
+        max_members: usize,
+        max_teams: usize,
+    ) -> Result<AcceptOutcome, StoreError> {
+        let mut client = self.pool.get().await?;
+
+        let outcome = db::accept_team_invitation(&mut client, title_id, team_id, &member.into(), max_members, max_teams).await?;
+
+        Ok(match outcome {
+            TeamAcceptOutcome::Joined => AcceptOutcome::Joined,
+            TeamAcceptOutcome::NoInvitation => AcceptOutcome::NoInvitation,
+            TeamAcceptOutcome::TeamFull => AcceptOutcome::TeamFull,
+            TeamAcceptOutcome::TooManyTeams => AcceptOutcome::TooManyTeams,
+            TeamAcceptOutcome::AlreadyMember => AcceptOutcome::AlreadyMember,
+        })
+    }
+
+    async fn set_role(&self, team_id: u64, xuid: Xuid, role: u32) -> Result<bool, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::set_team_member_role(&client, team_id, xuid, role).await?)
+    }
+
+    async fn hand_over(&self, team_id: u64, owner: Xuid, new_owner: Xuid) -> Result<HandOverOutcome, StoreError> {
+        let mut client = self.pool.get().await?;
+
+        let outcome = db::hand_over_team(&mut client, team_id, owner, new_owner, TEAM_ROLE_OWNER, TEAM_ROLE_OFFICER).await?;
+
+        Ok(match outcome {
+            TeamHandOverOutcome::HandedOver => HandOverOutcome::HandedOver,
+            TeamHandOverOutcome::NotOwner => HandOverOutcome::NotOwner,
+            TeamHandOverOutcome::NotAMember => HandOverOutcome::NotAMember,
+        })
+    }
+
+    async fn remove_member(&self, team_id: u64, xuid: Xuid) -> Result<bool, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::delete_team_member_record(&client, team_id, xuid).await?)
+    }
+
+    async fn add_invitation(&self, invitation: Invitation) -> Result<(), StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::put_team_invitation_record(&client, &invitation.into()).await?)
+    }
+
+    async fn invitations_of(&self, title_id: u32, invitee: Xuid) -> Result<Vec<Invitation>, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::get_team_invitation_records(&client, title_id, invitee).await?
+            .into_iter()
+            .map(Invitation::from)
+            .collect())
+    }
+
+    async fn take_invitation(&self, team_id: u64, invitee: Xuid) -> Result<Option<Invitation>, StoreError> {
+        let client = self.pool.get().await?;
+
+        Ok(db::take_team_invitation_record(&client, team_id, invitee).await?
+            .map(Invitation::from))
+    }
+}
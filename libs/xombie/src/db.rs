@@ -1,4 +1,6 @@
-use std::{net::IpAddr, num::ParseIntError};
+use std::{collections::BTreeMap, net::IpAddr, num::ParseIntError, time::SystemTime};
+
+use deadpool_postgres::{BuildError, Manager};
 
 use tokio_postgres::{Client, NoTls};
 
@@ -6,6 +8,8 @@ use xbox_sys::account::Xuid;
 use xbox_sys::config::{MacAddress, SerialNumber};
 use xbox_sys::crypto::SymmetricKey;
 
+pub use deadpool_postgres::{Pool, PoolError};
+
 pub async fn connect_db_client(pg_addr: &str, pg_port: u16, pg_user: &str, pg_password: &str) -> Result<Client, tokio_postgres::Error> {
     let pg_connection_string =
         format!("host={} port={} user={} password={} dbname=xombie", pg_addr, pg_port, pg_user, pg_password);
@@ -24,6 +28,87 @@ pub async fn connect_db_client(pg_addr: &str, pg_port: u16, pg_user: &str, pg_pa
     Ok(pg_client)
 }
 
+/// Connections to the database for everything in a process to share, opened
+/// as they're first needed, at most max_size at once.  The tables come from
+/// the files in schema/, see apply_schema.
+pub fn db_pool(pg_addr: &str, pg_port: u16, pg_user: &str, pg_password: &str, max_size: usize) -> Result<Pool, BuildError> {
+    let mut pg_config = tokio_postgres::Config::new();
+    pg_config
+        .host(pg_addr)
+        .port(pg_port)
+        .user(pg_user)
+        .password(pg_password)
+        .dbname("xombie");
+
+    Pool::builder(Manager::new(pg_config, NoTls))
+        .max_size(max_size)
+        .build()
+}
+
+/// The files in schema/, in the order they're applied.  New files go on the
+/// end; a file is never changed once it's been released.
+const SCHEMA: &[(&str, &str)] = &[
+    ("0001_client_user_flags", include_str!("../../../schema/0001_client_user_flags.sql")),
+    ("0002_presence_lists", include_str!("../../../schema/0002_presence_lists.sql")),
+    ("0003_stats", include_str!("../../../schema/0003_stats.sql")),
+    ("0004_storage", include_str!("../../../schema/0004_storage.sql")),
+    ("0005_feedback", include_str!("../../../schema/0005_feedback.sql")),
+    ("0006_messages", include_str!("../../../schema/0006_messages.sql")),
+    ("0007_teams", include_str!("../../../schema/0007_teams.sql")),
+    ("0008_presence", include_str!("../../../schema/0008_presence.sql")),
+    ("0009_storage_blobs", include_str!("../../../schema/0009_storage_blobs.sql")),
+    ("0010_message_attachments", include_str!("../../../schema/0010_message_attachments.sql")),
+];
+
+/// Arbitrary key for the advisory lock that stops services starting at the
+/// same time from applying the schema twice.
+const SCHEMA_LOCK: i64 = 0x786f_6d62_6965;
+
+/// Bring the database up to date by applying every file in schema/ that
+/// hasn't been already, recording each in schema_migrations.  Every service
+/// calls this on startup, so upgrading is just a matter of restarting them.
+/// Returns the names of the files applied.
+pub async fn apply_schema(client: &mut Client) -> Result<Vec<&'static str>, tokio_postgres::Error> {
+    let transaction = client.transaction().await?;
+
+    transaction
+        .execute("SELECT pg_advisory_xact_lock($1)", &[&SCHEMA_LOCK])
+        .await?;
+
+    transaction
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                name TEXT PRIMARY KEY,
+                applied TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+            )")
+        .await?;
+
+    let applied = transaction
+        .query("SELECT name FROM schema_migrations", &[])
+        .await?
+        .iter()
+        .map(|row| row.get::<_, String>(0))
+        .collect::<Vec<_>>();
+
+    let mut newly_applied = vec![];
+    for (name, sql) in SCHEMA {
+        if applied.iter().any(|applied| applied == name) {
+            continue;
+        }
+
+        transaction.batch_execute(sql).await?;
+        transaction
+            .execute("INSERT INTO schema_migrations (name) VALUES ($1)", &[name])
+            .await?;
+
+        newly_applied.push(*name);
+    }
+
+    transaction.commit().await?;
+
+    Ok(newly_applied)
+}
+
 #[derive(Debug)]
 pub struct MachineInfo {
     pub xuid: Xuid,
@@ -164,6 +249,17 @@ pub async fn get_xuid_for_gamertag(client: &Client, gamertag: String) -> Option<
     ))
 }
 
+/// The user flags of the account with xuid, or None if there is no such
+/// account.  See schema/0001_client_user_flags.sql.
+pub async fn get_user_flags_for_xuid(client: &Client, xuid: Xuid) -> Result<Option<u32>, tokio_postgres::Error> {
+    let rows = client.query(
+        "SELECT user_flags FROM clients WHERE xuid = $1 LIMIT 1",
+        &[&xuid_string(xuid)]
+    ).await?;
+
+    Ok(rows.first().map(|row| row.get::<_, i32>(0) as u32))
+}
+
 #[derive(Debug)]
 pub struct ClusterInfo {
     pub kdc_nodes: Vec<[u8;4]>,
@@ -205,4 +301,1470 @@ pub async fn get_cluster_addrs(client: &Client) -> Result<ClusterInfo, ReadClust
     }
 
     Ok(cluster_info)
-}
\ No newline at end of file
+}
+#[derive(Debug)]
+pub enum FriendshipError {
+    Pg(tokio_postgres::Error),
+    CannotParseXuid(ParseIntError),
+}
+
+impl From<tokio_postgres::Error> for FriendshipError {
+    fn from(pg_err: tokio_postgres::Error) -> Self {
+        FriendshipError::Pg(pg_err)
+    }
+}
+
+impl From<ParseIntError> for FriendshipError {
+    fn from(parse_error: ParseIntError) -> Self {
+        FriendshipError::CannotParseXuid(parse_error)
+    }
+}
+
+#[derive(Debug)]
+pub struct Friendship {
+    pub buddy: Xuid,
+    pub buddy_gamertag: Option<String>,
+    pub status: i16,
+}
+
+pub async fn get_friendships(client: &Client, xuid: Xuid) -> Result<Vec<Friendship>, FriendshipError> {
+    let rows = client.query(
+        "SELECT friendships.buddy_xuid, clients.gamertag, friendships.status
+            FROM friendships LEFT JOIN clients ON clients.xuid = friendships.buddy_xuid
+            WHERE friendships.xuid = $1
+            ORDER BY friendships.buddy_xuid",
+        &[&xuid_string(xuid)]
+    ).await?;
+
+    let mut friendships = vec![];
+
+    for row in rows {
+        let buddy: String = row.get(0);
+
+        friendships.push(Friendship {
+            buddy: Xuid(u64::from_str_radix(&buddy, 16)?),
+            buddy_gamertag: row.get(1),
+            status: row.get(2),
+        });
+    }
+
+    Ok(friendships)
+}
+
+/// Add or update one side of a friendship, bumping the owner's buddy list
+/// version.
+pub async fn set_friendship(client: &Client, xuid: Xuid, buddy: Xuid, status: i16) -> Result<(), tokio_postgres::Error> {
+    client.execute(
+        "INSERT INTO friendships (xuid, buddy_xuid, status) VALUES ($1, $2, $3)
+            ON CONFLICT (xuid, buddy_xuid) DO UPDATE SET status = EXCLUDED.status",
+        &[&xuid_string(xuid), &xuid_string(buddy), &status]
+    ).await?;
+
+    bump_buddy_list_version(client, xuid).await
+}
+
+/// Remove one side of a friendship.  Returns false if there wasn't one.
+pub async fn delete_friendship(client: &Client, xuid: Xuid, buddy: Xuid) -> Result<bool, tokio_postgres::Error> {
+    let deleted = client.execute(
+        "DELETE FROM friendships WHERE xuid = $1 AND buddy_xuid = $2",
+        &[&xuid_string(xuid), &xuid_string(buddy)]
+    ).await?;
+
+    if deleted == 0 {
+        return Ok(false);
+    }
+
+    bump_buddy_list_version(client, xuid).await?;
+
+    Ok(true)
+}
+
+async fn bump_buddy_list_version(client: &Client, xuid: Xuid) -> Result<(), tokio_postgres::Error> {
+    client.execute(
+        "INSERT INTO presence_list_versions (xuid, buddy_list_version) VALUES ($1, 1)
+            ON CONFLICT (xuid) DO UPDATE
+            SET buddy_list_version = presence_list_versions.buddy_list_version + 1",
+        &[&xuid_string(xuid)]
+    ).await?;
+
+    Ok(())
+}
+
+pub async fn get_blocks(client: &Client, xuid: Xuid) -> Result<Vec<Xuid>, FriendshipError> {
+    let rows = client.query(
+        "SELECT blocked_xuid FROM blocks WHERE xuid = $1 ORDER BY blocked_xuid",
+        &[&xuid_string(xuid)]
+    ).await?;
+
+    let mut blocks = vec![];
+
+    for row in rows {
+        let blocked: String = row.get(0);
+        blocks.push(Xuid(u64::from_str_radix(&blocked, 16)?));
+    }
+
+    Ok(blocks)
+}
+
+/// Add blocked to xuid's block list, bumping its version.  Returns false if
+/// it was already there.
+pub async fn add_block(client: &Client, xuid: Xuid, blocked: Xuid) -> Result<bool, tokio_postgres::Error> {
+    let added = client.execute(
+        "INSERT INTO blocks (xuid, blocked_xuid) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        &[&xuid_string(xuid), &xuid_string(blocked)]
+    ).await?;
+
+    if added == 0 {
+        return Ok(false);
+    }
+
+    bump_block_list_version(client, xuid).await?;
+
+    Ok(true)
+}
+
+/// Take blocked off xuid's block list, bumping its version.  Returns false if
+/// it wasn't there.
+pub async fn delete_block(client: &Client, xuid: Xuid, blocked: Xuid) -> Result<bool, tokio_postgres::Error> {
+    let deleted = client.execute(
+        "DELETE FROM blocks WHERE xuid = $1 AND blocked_xuid = $2",
+        &[&xuid_string(xuid), &xuid_string(blocked)]
+    ).await?;
+
+    if deleted == 0 {
+        return Ok(false);
+    }
+
+    bump_block_list_version(client, xuid).await?;
+
+    Ok(true)
+}
+
+async fn bump_block_list_version(client: &Client, xuid: Xuid) -> Result<(), tokio_postgres::Error> {
+    client.execute(
+        "INSERT INTO presence_list_versions (xuid, block_list_version) VALUES ($1, 1)
+            ON CONFLICT (xuid) DO UPDATE
+            SET block_list_version = presence_list_versions.block_list_version + 1",
+        &[&xuid_string(xuid)]
+    ).await?;
+
+    Ok(())
+}
+
+/// The buddy and block list versions for xuid, zero for lists that have
+/// never changed.
+pub async fn get_list_versions(client: &Client, xuid: Xuid) -> Result<(u32, u32), tokio_postgres::Error> {
+    let rows = client.query(
+        "SELECT buddy_list_version, block_list_version FROM presence_list_versions WHERE xuid = $1",
+        &[&xuid_string(xuid)]
+    ).await?;
+
+    Ok(match rows.first() {
+        Some(row) => (row.get::<_, i32>(0) as u32, row.get::<_, i32>(1) as u32),
+        None => (0, 0),
+    })
+}
+
+pub async fn get_gamertag_for_xuid(client: &Client, xuid: Xuid) -> Result<Option<String>, tokio_postgres::Error> {
+    let rows = client.query(
+        "SELECT gamertag FROM clients WHERE xuid = $1 LIMIT 1",
+        &[&xuid_string(xuid)]
+    ).await?;
+
+    Ok(rows.first().map(|row| row.get(0)))
+}
+
+#[derive(Debug)]
+pub enum PresenceError {
+    Pg(tokio_postgres::Error),
+    CannotParseXuid(ParseIntError),
+}
+
+impl From<tokio_postgres::Error> for PresenceError {
+    fn from(pg_err: tokio_postgres::Error) -> Self {
+        PresenceError::Pg(pg_err)
+    }
+}
+
+impl From<ParseIntError> for PresenceError {
+    fn from(parse_error: ParseIntError) -> Self {
+        PresenceError::CannotParseXuid(parse_error)
+    }
+}
+
+/// A user's presence, with their address and key id in wire encoding.
+#[derive(Debug)]
+pub struct PresenceRecord {
+    pub user: Xuid,
+    pub machine: Xuid,
+    pub connection: u64,
+    pub user_index: Option<u8>,
+    pub online: bool,
+    pub state: u32,
+    pub title_id: u32,
+    pub title_version: u32,
+    pub xnaddr: Option<Vec<u8>>,
+    pub xnkid: Option<Vec<u8>>,
+    pub match_session_id: u64,
+    pub nickname: Vec<u8>,
+    pub title_stuff: Vec<u8>,
+    pub last_seen: SystemTime,
+}
+
+fn presence_record(row: &tokio_postgres::Row) -> Result<PresenceRecord, PresenceError> {
+    let user: String = row.get(0);
+    let machine: String = row.get(1);
+
+    Ok(PresenceRecord {
+        user: Xuid(u64::from_str_radix(&user, 16)?),
+        machine: Xuid(u64::from_str_radix(&machine, 16)?),
+        connection: row.get::<_, i64>(2) as u64,
+        user_index: row.get::<_, Option<i16>>(3).map(|user_index| user_index as u8),
+        online: row.get(4),
+        state: row.get::<_, i32>(5) as u32,
+        title_id: row.get::<_, i32>(6) as u32,
+        title_version: row.get::<_, i32>(7) as u32,
+        xnaddr: row.get(8),
+        xnkid: row.get(9),
+        match_session_id: row.get::<_, i64>(10) as u64,
+        nickname: row.get(11),
+        title_stuff: row.get(12),
+        last_seen: row.get(13),
+    })
+}
+
+pub async fn get_presence_record(client: &Client, xuid: Xuid) -> Result<Option<PresenceRecord>, PresenceError> {
+    let rows = client.query(
+        "SELECT xuid, machine_xuid, connection, user_index, online, state, title_id, title_version,
+                xnaddr, xnkid, match_session_id, nickname, title_stuff, last_seen
+            FROM user_presence WHERE xuid = $1",
+        &[&xuid_string(xuid)]
+    ).await?;
+
+    rows.first().map(presence_record).transpose()
+}
+
+pub async fn put_presence_record(client: &Client, record: &PresenceRecord) -> Result<(), tokio_postgres::Error> {
+    client.execute(
+        "INSERT INTO user_presence (xuid, machine_xuid, connection, user_index, online, state, title_id, title_version,
+                xnaddr, xnkid, match_session_id, nickname, title_stuff, last_seen)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+            ON CONFLICT (xuid) DO UPDATE
+            SET machine_xuid = EXCLUDED.machine_xuid, connection = EXCLUDED.connection,
+                user_index = EXCLUDED.user_index, online = EXCLUDED.online, state = EXCLUDED.state,
+                title_id = EXCLUDED.title_id, title_version = EXCLUDED.title_version,
+                xnaddr = EXCLUDED.xnaddr, xnkid = EXCLUDED.xnkid,
+                match_session_id = EXCLUDED.match_session_id, nickname = EXCLUDED.nickname,
+                title_stuff = EXCLUDED.title_stuff, last_seen = EXCLUDED.last_seen",
+        &[
+            &xuid_string(record.user),
+            &xuid_string(record.machine),
+            &(record.connection as i64),
+            &record.user_index.map(|user_index| user_index as i16),
+            &record.online,
+            &(record.state as i32),
+            &(record.title_id as i32),
+            &(record.title_version as i32),
+            &record.xnaddr,
+            &record.xnkid,
+            &(record.match_session_id as i64),
+            &record.nickname,
+            &record.title_stuff,
+            &record.last_seen,
+        ]
+    ).await?;
+
+    Ok(())
+}
+
+pub async fn get_online_users_on_machine(client: &Client, machine: Xuid) -> Result<Vec<Xuid>, PresenceError> {
+    let rows = client.query(
+        "SELECT xuid FROM user_presence WHERE machine_xuid = $1 AND online ORDER BY xuid",
+        &[&xuid_string(machine)]
+    ).await?;
+
+    let mut users = vec![];
+
+    for row in rows {
+        let user: String = row.get(0);
+        users.push(Xuid(u64::from_str_radix(&user, 16)?));
+    }
+
+    Ok(users)
+}
+
+/// Mark xuid offline as of now, as long as they're online on machine and, if
+/// one is given, were last seen through connection.  Returns false if they
+/// weren't.
+pub async fn set_presence_offline(client: &Client, xuid: Xuid, machine: Xuid, connection: Option<u64>, now: SystemTime) -> Result<bool, tokio_postgres::Error> {
+    let updated = client.execute(
+        "UPDATE user_presence SET online = FALSE, last_seen = $4
+            WHERE xuid = $1 AND machine_xuid = $2 AND online
+            AND ($3::BIGINT IS NULL OR connection = $3)",
+        &[&xuid_string(xuid), &xuid_string(machine), &connection.map(|connection| connection as i64), &now]
+    ).await?;
+
+    Ok(updated != 0)
+}
+
+#[derive(Debug)]
+pub struct InvitationRecord {
+    pub host: Xuid,
+    pub xnkid: Vec<u8>,
+    pub title_id: u32,
+    pub sent: SystemTime,
+}
+
+fn invitation_record(row: &tokio_postgres::Row) -> Result<InvitationRecord, PresenceError> {
+    let host: String = row.get(0);
+
+    Ok(InvitationRecord {
+        host: Xuid(u64::from_str_radix(&host, 16)?),
+        xnkid: row.get(1),
+        title_id: row.get::<_, i32>(2) as u32,
+        sent: row.get(3),
+    })
+}
+
+/// Hold an invitation for invitee, replacing any earlier one from the same
+/// host into the same session.
+pub async fn put_invitation_record(client: &Client, invitee: Xuid, record: &InvitationRecord) -> Result<(), tokio_postgres::Error> {
+    client.execute(
+        "INSERT INTO presence_invitations (invitee_xuid, host_xuid, xnkid, title_id, sent)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (invitee_xuid, host_xuid, xnkid) DO UPDATE
+            SET title_id = EXCLUDED.title_id, sent = EXCLUDED.sent",
+        &[&xuid_string(invitee), &xuid_string(record.host), &record.xnkid, &(record.title_id as i32), &record.sent]
+    ).await?;
+
+    Ok(())
+}
+
+/// Every invitation held for invitee, oldest first.
+pub async fn get_invitation_records(client: &Client, invitee: Xuid) -> Result<Vec<InvitationRecord>, PresenceError> {
+    let rows = client.query(
+        "SELECT host_xuid, xnkid, title_id, sent FROM presence_invitations
+            WHERE invitee_xuid = $1
+            ORDER BY sent",
+        &[&xuid_string(invitee)]
+    ).await?;
+
+    rows.iter().map(invitation_record).collect()
+}
+
+/// Returns false if no invitation from host into xnkid was held for invitee.
+pub async fn delete_invitation_record(client: &Client, invitee: Xuid, host: Xuid, xnkid: &[u8]) -> Result<bool, tokio_postgres::Error> {
+    let deleted = client.execute(
+        "DELETE FROM presence_invitations WHERE invitee_xuid = $1 AND host_xuid = $2 AND xnkid = $3",
+        &[&xuid_string(invitee), &xuid_string(host), &xnkid]
+    ).await?;
+
+    Ok(deleted != 0)
+}
+
+#[derive(Debug)]
+pub enum StatsError {
+    Pg(tokio_postgres::Error),
+    CannotParseXuid(ParseIntError),
+}
+
+impl From<tokio_postgres::Error> for StatsError {
+    fn from(pg_err: tokio_postgres::Error) -> Self {
+        StatsError::Pg(pg_err)
+    }
+}
+
+impl From<ParseIntError> for StatsError {
+    fn from(parse_error: ParseIntError) -> Self {
+        StatsError::CannotParseXuid(parse_error)
+    }
+}
+
+#[derive(Debug)]
+pub struct StatsRecord {
+    pub xuid: Xuid,
+    pub rating: i64,
+    pub stats: Vec<u8>,
+    /// Looked up along with the row.  Ignored when the row is written.
+    pub gamertag: Option<String>,
+}
+
+fn stats_record(row: &tokio_postgres::Row) -> Result<StatsRecord, StatsError> {
+    let xuid: String = row.get(0);
+
+    Ok(StatsRecord {
+        xuid: Xuid(u64::from_str_radix(&xuid, 16)?),
+        rating: row.get(1),
+        stats: row.get(2),
+        gamertag: row.get(3),
+    })
+}
+
+pub async fn get_stats_record(client: &Client, title_id: u32, leaderboard_id: u32, xuid: Xuid) -> Result<Option<StatsRecord>, StatsError> {
+    let rows = client.query(
+        "SELECT xuid, rating, stats, (SELECT gamertag FROM clients WHERE clients.xuid = stats_rows.xuid LIMIT 1)
+            FROM stats_rows
+            WHERE title_id = $1 AND leaderboard_id = $2 AND xuid = $3",
+        &[&(title_id as i32), &(leaderboard_id as i32), &xuid_string(xuid)]
+    ).await?;
+
+    rows.first().map(stats_record).transpose()
+}
+
+const PUT_STATS_RECORD: &str =
+    "INSERT INTO stats_rows (title_id, leaderboard_id, xuid, rating, stats) VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (title_id, leaderboard_id, xuid) DO UPDATE
+        SET rating = EXCLUDED.rating, stats = EXCLUDED.stats";
+
+pub async fn put_stats_record(client: &Client, title_id: u32, leaderboard_id: u32, record: &StatsRecord) -> Result<(), tokio_postgres::Error> {
+    client.execute(
+        PUT_STATS_RECORD,
+        &[&(title_id as i32), &(leaderboard_id as i32), &xuid_string(record.xuid), &record.rating, &record.stats]
+    ).await?;
+
+    Ok(())
+}
+
+/// Replace xuid's row on a leaderboard with what update makes of it, given
+/// the row as it stands or None if there isn't one yet.  Updates of the same
+/// row wait for each other, so none overwrites another.
+pub async fn update_stats_record<E: From<StatsError>>(
+    client: &mut Client,
+    title_id: u32,
+    leaderboard_id: u32,
+    xuid: Xuid,
+    update: impl FnOnce(Option<StatsRecord>) -> Result<StatsRecord, E>,
+) -> Result<(), E> {
+    let transaction = client.transaction().await.map_err(StatsError::from)?;
+
+    // Held until the transaction ends.  A row lock alone wouldn't do, as
+    // there's nothing to lock before the row's first write.
+    transaction.execute(
+        "SELECT pg_advisory_xact_lock($1, hashtext($2))",
+        &[&(title_id as i32), &format!("{}:{}", xuid_string(xuid), leaderboard_id)]
+    ).await.map_err(StatsError::from)?;
+
+    let rows = transaction.query(
+        "SELECT xuid, rating, stats, (SELECT gamertag FROM clients WHERE clients.xuid = stats_rows.xuid LIMIT 1)
+            FROM stats_rows
+            WHERE title_id = $1 AND leaderboard_id = $2 AND xuid = $3",
+        &[&(title_id as i32), &(leaderboard_id as i32), &xuid_string(xuid)]
+    ).await.map_err(StatsError::from)?;
+
+    let record = update(rows.first().map(stats_record).transpose()?)?;
+
+    transaction.execute(
+        PUT_STATS_RECORD,
+        &[&(title_id as i32), &(leaderboard_id as i32), &xuid_string(record.xuid), &record.rating, &record.stats]
+    ).await.map_err(StatsError::from)?;
+
+    transaction.commit().await.map_err(StatsError::from)?;
+
+    Ok(())
+}
+
+/// Remove xuid from one leaderboard, or from every leaderboard of the title
+/// if leaderboard_id is None.  Returns the number of rows removed.
+pub async fn delete_stats_records(client: &Client, title_id: u32, leaderboard_id: Option<u32>, xuid: Xuid) -> Result<u64, tokio_postgres::Error> {
+    match leaderboard_id {
+        Some(leaderboard_id) => client.execute(
+            "DELETE FROM stats_rows WHERE title_id = $1 AND leaderboard_id = $2 AND xuid = $3",
+            &[&(title_id as i32), &(leaderboard_id as i32), &xuid_string(xuid)]
+        ).await,
+        None => client.execute(
+            "DELETE FROM stats_rows WHERE title_id = $1 AND xuid = $2",
+            &[&(title_id as i32), &xuid_string(xuid)]
+        ).await,
+    }
+}
+
+pub async fn count_stats_records(client: &Client, title_id: u32, leaderboard_id: u32) -> Result<u32, tokio_postgres::Error> {
+    let row = client.query_one(
+        "SELECT COUNT(*) FROM stats_rows WHERE title_id = $1 AND leaderboard_id = $2",
+        &[&(title_id as i32), &(leaderboard_id as i32)]
+    ).await?;
+
+    Ok(row.get::<_, i64>(0) as u32)
+}
+
+/// Up to count rows of a leaderboard in rank order, skipping the first
+/// offset.  Ties in rating are broken by xuid so ranks are stable.
+pub async fn get_stats_records_by_rank(client: &Client, title_id: u32, leaderboard_id: u32, offset: u32, count: u32) -> Result<Vec<StatsRecord>, StatsError> {
+    let rows = client.query(
+        "SELECT xuid, rating, stats, (SELECT gamertag FROM clients WHERE clients.xuid = stats_rows.xuid LIMIT 1)
+            FROM stats_rows
+            WHERE title_id = $1 AND leaderboard_id = $2
+            ORDER BY rating DESC, xuid
+            OFFSET $3 LIMIT $4",
+        &[&(title_id as i32), &(leaderboard_id as i32), &(offset as i64), &(count as i64)]
+    ).await?;
+
+    rows.iter().map(stats_record).collect()
+}
+
+/// The zero based position of xuid on a leaderboard, or None if they aren't
+/// on it.
+pub async fn get_stats_record_position(client: &Client, title_id: u32, leaderboard_id: u32, xuid: Xuid) -> Result<Option<u32>, tokio_postgres::Error> {
+    let rows = client.query(
+        "SELECT (SELECT COUNT(*) FROM stats_rows AS above
+                WHERE above.title_id = ranked.title_id AND above.leaderboard_id = ranked.leaderboard_id
+                AND (above.rating > ranked.rating OR (above.rating = ranked.rating AND above.xuid < ranked.xuid)))
+            FROM stats_rows AS ranked
+            WHERE ranked.title_id = $1 AND ranked.leaderboard_id = $2 AND ranked.xuid = $3",
+        &[&(title_id as i32), &(leaderboard_id as i32), &xuid_string(xuid)]
+    ).await?;
+
+    Ok(rows.first().map(|row| row.get::<_, i64>(0) as u32))
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    Pg(tokio_postgres::Error),
+    CannotParseXuid(ParseIntError),
+}
+
+impl From<tokio_postgres::Error> for StorageError {
+    fn from(pg_err: tokio_postgres::Error) -> Self {
+        StorageError::Pg(pg_err)
+    }
+}
+
+impl From<ParseIntError> for StorageError {
+    fn from(parse_error: ParseIntError) -> Self {
+        StorageError::CannotParseXuid(parse_error)
+    }
+}
+
+#[derive(Debug)]
+pub struct StorageRecord {
+    pub owner: Xuid,
+    pub path: String,
+    pub flags: u32,
+    pub size: u64,
+    pub hash: Vec<u8>,
+    pub modified: SystemTime,
+    pub blob: String,
+}
+
+fn storage_record(row: &tokio_postgres::Row) -> Result<StorageRecord, StorageError> {
+    let owner: String = row.get(0);
+
+    Ok(StorageRecord {
+        owner: Xuid(u64::from_str_radix(&owner, 16)?),
+        path: row.get(1),
+        flags: row.get::<_, i32>(2) as u32,
+        size: row.get::<_, i64>(3) as u64,
+        hash: row.get(4),
+        modified: row.get(5),
+        blob: row.get(6),
+    })
+}
+
+pub async fn get_storage_record(client: &Client, title_id: u32, owner: Xuid, path: &str) -> Result<Option<StorageRecord>, StorageError> {
+    let rows = client.query(
+        "SELECT owner_xuid, path, flags, size, hash, modified, blob FROM storage_files
+            WHERE title_id = $1 AND owner_xuid = $2 AND path = $3",
+        &[&(title_id as i32), &xuid_string(owner), &path]
+    ).await?;
+
+    rows.first().map(storage_record).transpose()
+}
+
+/// What put_storage_record_within_quota did.
+#[derive(Debug)]
+pub enum StoragePut {
+    /// The record went in, replacing one whose contents were in
+    /// replaced_blob, if there was one.
+    Stored { replaced_blob: Option<String> },
+    OverQuota,
+}
+
+/// Add or overwrite record as long as the owner's files for the title, with
+/// it in place of any file it replaces, come to no more than quota bytes.
+/// Changes nothing if they wouldn't.
+pub async fn put_storage_record_within_quota(client: &mut Client, title_id: u32, record: &StorageRecord, quota: u64) -> Result<StoragePut, tokio_postgres::Error> {
+    let transaction = client.transaction().await?;
+
+    // Held until the transaction ends, so the owner's other uploads wait here
+    // rather than each seeing room for itself.
+    transaction.execute(
+        "SELECT pg_advisory_xact_lock($1, hashtext($2))",
+        &[&(title_id as i32), &xuid_string(record.owner)]
+    ).await?;
+
+    let row = transaction.query_one(
+        "SELECT COALESCE(SUM(size), 0)::BIGINT FROM storage_files
+            WHERE title_id = $1 AND owner_xuid = $2 AND path <> $3",
+        &[&(title_id as i32), &xuid_string(record.owner), &record.path]
+    ).await?;
+
+    let used = row.get::<_, i64>(0) as u64;
+    if used.saturating_add(record.size) > quota {
+        return Ok(StoragePut::OverQuota)
+    }
+
+    let replaced = transaction.query(
+        "SELECT blob FROM storage_files
+            WHERE title_id = $1 AND owner_xuid = $2 AND path = $3
+            FOR UPDATE",
+        &[&(title_id as i32), &xuid_string(record.owner), &record.path]
+    ).await?;
+
+    transaction.execute(
+        "INSERT INTO storage_files (title_id, owner_xuid, path, flags, size, hash, modified, blob)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (title_id, owner_xuid, path) DO UPDATE
+            SET flags = EXCLUDED.flags, size = EXCLUDED.size, hash = EXCLUDED.hash,
+                modified = EXCLUDED.modified, blob = EXCLUDED.blob",
+        &[
+            &(title_id as i32),
+            &xuid_string(record.owner),
+            &record.path,
+            &(record.flags as i32),
+            &(record.size as i64),
+            &record.hash,
+            &record.modified,
+            &record.blob,
+        ]
+    ).await?;
+
+    transaction.commit().await?;
+
+    Ok(StoragePut::Stored {
+        replaced_blob: replaced.first().map(|row| row.get(0)),
+    })
+}
+
+/// Returns the deleted file's blob, or None if there was no such file.
+pub async fn delete_storage_record(client: &Client, title_id: u32, owner: Xuid, path: &str) -> Result<Option<String>, tokio_postgres::Error> {
+    let rows = client.query(
+        "DELETE FROM storage_files WHERE title_id = $1 AND owner_xuid = $2 AND path = $3
+            RETURNING blob",
+        &[&(title_id as i32), &xuid_string(owner), &path]
+    ).await?;
+
+    Ok(rows.first().map(|row| row.get(0)))
+}
+
+/// Every file owner has stored for a title under paths starting with
+/// prefix, in path order.
+pub async fn get_storage_records(client: &Client, title_id: u32, owner: Xuid, prefix: &str) -> Result<Vec<StorageRecord>, StorageError> {
+    let rows = client.query(
+        "SELECT owner_xuid, path, flags, size, hash, modified, blob FROM storage_files
+            WHERE title_id = $1 AND owner_xuid = $2 AND left(path, length($3)) = $3
+            ORDER BY path",
+        &[&(title_id as i32), &xuid_string(owner), &prefix]
+    ).await?;
+
+    rows.iter().map(storage_record).collect()
+}
+
+/// The total size of every file owner has stored for a title.
+pub async fn get_storage_usage(client: &Client, title_id: u32, owner: Xuid) -> Result<u64, tokio_postgres::Error> {
+    let row = client.query_one(
+        "SELECT COALESCE(SUM(size), 0)::BIGINT FROM storage_files WHERE title_id = $1 AND owner_xuid = $2",
+        &[&(title_id as i32), &xuid_string(owner)]
+    ).await?;
+
+    Ok(row.get::<_, i64>(0) as u64)
+}
+
+#[derive(Debug)]
+pub enum FeedbackError {
+    Pg(tokio_postgres::Error),
+    CannotParseXuid(ParseIntError),
+}
+
+impl From<tokio_postgres::Error> for FeedbackError {
+    fn from(pg_err: tokio_postgres::Error) -> Self {
+        FeedbackError::Pg(pg_err)
+    }
+}
+
+impl From<ParseIntError> for FeedbackError {
+    fn from(parse_error: ParseIntError) -> Self {
+        FeedbackError::CannotParseXuid(parse_error)
+    }
+}
+
+#[derive(Debug)]
+pub struct FeedbackRecord {
+    /// Assigned by the database, ignored when a record is added.
+    pub id: u64,
+    pub title_id: u32,
+    pub reporter: Xuid,
+    pub target: Xuid,
+    pub feedback_type: u32,
+    pub text: Option<String>,
+    pub submitted: SystemTime,
+}
+
+fn feedback_record(row: &tokio_postgres::Row) -> Result<FeedbackRecord, FeedbackError> {
+    let reporter: String = row.get(2);
+    let target: String = row.get(3);
+
+    Ok(FeedbackRecord {
+        id: row.get::<_, i64>(0) as u64,
+        title_id: row.get::<_, i32>(1) as u32,
+        reporter: Xuid(u64::from_str_radix(&reporter, 16)?),
+        target: Xuid(u64::from_str_radix(&target, 16)?),
+        feedback_type: row.get::<_, i32>(4) as u32,
+        text: row.get(5),
+        submitted: row.get(6),
+    })
+}
+
+/// Returns the id the report was given.
+pub async fn put_feedback_record(client: &Client, record: &FeedbackRecord) -> Result<u64, tokio_postgres::Error> {
+    let row = client.query_one(
+        "INSERT INTO feedback_reports (title_id, reporter_xuid, target_xuid, feedback_type, text, submitted)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id",
+        &[
+            &(record.title_id as i32),
+            &xuid_string(record.reporter),
+            &xuid_string(record.target),
+            &(record.feedback_type as i32),
+            &record.text,
+            &record.submitted,
+        ]
+    ).await?;
+
+    Ok(row.get::<_, i64>(0) as u64)
+}
+
+/// Up to limit reports, newest first, matching every filter given.  Pass the
+/// id of the last report of a page as before to get the next page.
+pub async fn get_feedback_records(
+    client: &Client,
+    reporter: Option<Xuid>,
+    target: Option<Xuid>,
+    title_id: Option<u32>,
+    feedback_type: Option<u32>,
+    before: Option<u64>,
+    limit: u32,
+) -> Result<Vec<FeedbackRecord>, FeedbackError> {
+    let rows = client.query(
+        "SELECT id, title_id, reporter_xuid, target_xuid, feedback_type, text, submitted FROM feedback_reports
+            WHERE ($1::TEXT IS NULL OR reporter_xuid = $1)
+            AND ($2::TEXT IS NULL OR target_xuid = $2)
+            AND ($3::INTEGER IS NULL OR title_id = $3)
+            AND ($4::INTEGER IS NULL OR feedback_type = $4)
+            AND ($5::BIGINT IS NULL OR id < $5)
+            ORDER BY id DESC
+            LIMIT $6",
+        &[
+            &reporter.map(xuid_string),
+            &target.map(xuid_string),
+            &title_id.map(|title_id| title_id as i32),
+            &feedback_type.map(|feedback_type| feedback_type as i32),
+            &before.map(|before| before as i64),
+            &(limit as i64),
+        ]
+    ).await?;
+
+    rows.iter().map(feedback_record).collect()
+}
+
+#[derive(Debug)]
+pub enum MessageError {
+    Pg(tokio_postgres::Error),
+    CannotParseXuid(ParseIntError),
+}
+
+impl From<tokio_postgres::Error> for MessageError {
+    fn from(pg_err: tokio_postgres::Error) -> Self {
+        MessageError::Pg(pg_err)
+    }
+}
+
+impl From<ParseIntError> for MessageError {
+    fn from(parse_error: ParseIntError) -> Self {
+        MessageError::CannotParseXuid(parse_error)
+    }
+}
+
+#[derive(Debug)]
+pub struct MessageRecord {
+    /// Assigned by the database, ignored when a record is added.
+    pub id: u64,
+    pub recipient: Xuid,
+    pub sender: Xuid,
+    pub title_id: u32,
+    pub flags: u32,
+    pub text: String,
+    pub attachment_len: u32,
+    pub sent: SystemTime,
+    pub expires: SystemTime,
+}
+
+fn message_record(row: &tokio_postgres::Row) -> Result<MessageRecord, MessageError> {
+    let recipient: String = row.get(1);
+    let sender: String = row.get(2);
+
+    Ok(MessageRecord {
+        id: row.get::<_, i64>(0) as u64,
+        recipient: Xuid(u64::from_str_radix(&recipient, 16)?),
+        sender: Xuid(u64::from_str_radix(&sender, 16)?),
+        title_id: row.get::<_, i32>(3) as u32,
+        flags: row.get::<_, i32>(4) as u32,
+        text: row.get(5),
+        attachment_len: row.get::<_, i32>(6) as u32,
+        sent: row.get(7),
+        expires: row.get(8),
+    })
+}
+
+/// Add each of records to its recipient's inbox as long as it holds fewer
+/// than max_inbox messages that haven't expired by the time the record was
+/// sent.  Every record added refers to the one copy of attachment, if there
+/// is one.  Returns the id each record was given, in order, or None where
+/// its recipient's inbox was full.
+pub async fn put_message_records(
+    client: &mut Client,
+    records: &[MessageRecord],
+    attachment: Option<&[u8]>,
+    max_inbox: u32,
+) -> Result<Vec<Option<u64>>, tokio_postgres::Error> {
+    let transaction = client.transaction().await?;
+
+    // Held until the transaction ends, so sends to the same inbox wait here
+    // rather than each seeing room for itself.  They're taken in order so
+    // that sends to the same recipients can't each hold a lock the other is
+    // waiting on.
+    let mut recipients: Vec<String> = records.iter().map(|record| xuid_string(record.recipient)).collect();
+    recipients.sort();
+    recipients.dedup();
+
+    for recipient in &recipients {
+        transaction.execute("SELECT pg_advisory_xact_lock(hashtext('inbox:' || $1))", &[recipient]).await?;
+    }
+
+    let mut attachment_id: Option<i64> = None;
+    let mut ids = vec![];
+
+    for record in records {
+        let row = transaction.query_one(
+            "SELECT COUNT(*) FROM messages WHERE recipient_xuid = $1 AND expires > $2",
+            &[&xuid_string(record.recipient), &record.sent]
+        ).await?;
+
+        if row.get::<_, i64>(0) as u64 >= max_inbox as u64 {
+            ids.push(None);
+            continue;
+        }
+
+        // Only stored once there's a message to refer to it.
+        if let (None, Some(data)) = (attachment_id, attachment) {
+            let row = transaction.query_one(
+                "INSERT INTO attachments (data) VALUES ($1) RETURNING id",
+                &[&data]
+            ).await?;
+
+            attachment_id = Some(row.get(0));
+        }
+
+        let row = transaction.query_one(
+            "INSERT INTO messages (recipient_xuid, sender_xuid, title_id, flags, text, attachment_len, sent, expires, attachment_id)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                RETURNING id",
+            &[
+                &xuid_string(record.recipient),
+                &xuid_string(record.sender),
+                &(record.title_id as i32),
+                &(record.flags as i32),
+                &record.text,
+                &(record.attachment_len as i32),
+                &record.sent,
+                &record.expires,
+                &attachment_id,
+            ]
+        ).await?;
+
+        ids.push(Some(row.get::<_, i64>(0) as u64));
+    }
+
+    transaction.commit().await?;
+
+    Ok(ids)
+}
+
+/// Every message in recipient's inbox that hasn't expired by now, newest
+/// first.
+pub async fn get_message_records(client: &Client, recipient: Xuid, now: SystemTime) -> Result<Vec<MessageRecord>, MessageError> {
+    let rows = client.query(
+        "SELECT id, recipient_xuid, sender_xuid, title_id, flags, text, attachment_len, sent, expires FROM messages
+            WHERE recipient_xuid = $1 AND expires > $2
+            ORDER BY id DESC",
+        &[&xuid_string(recipient), &now]
+    ).await?;
+
+    rows.iter().map(message_record).collect()
+}
+
+pub async fn get_message_record(client: &Client, recipient: Xuid, id: u64, now: SystemTime) -> Result<Option<MessageRecord>, MessageError> {
+    let rows = client.query(
+        "SELECT id, recipient_xuid, sender_xuid, title_id, flags, text, attachment_len, sent, expires FROM messages
+            WHERE id = $1 AND recipient_xuid = $2 AND expires > $3",
+        &[&(id as i64), &xuid_string(recipient), &now]
+    ).await?;
+
+    rows.first().map(message_record).transpose()
+}
+
+/// None if there's no such message or it has no attachment.
+pub async fn get_message_attachment(client: &Client, recipient: Xuid, id: u64, now: SystemTime) -> Result<Option<Vec<u8>>, tokio_postgres::Error> {
+    let rows = client.query(
+        "SELECT data FROM attachments
+            JOIN messages ON messages.attachment_id = attachments.id
+            WHERE messages.id = $1 AND messages.recipient_xuid = $2 AND messages.expires > $3",
+        &[&(id as i64), &xuid_string(recipient), &now]
+    ).await?;
+
+    Ok(rows.first().map(|row| row.get(0)))
+}
+
+/// Set flags on a message.  Returns false if there was no such message.
+pub async fn set_message_flags(client: &Client, recipient: Xuid, id: u64, flags: u32, now: SystemTime) -> Result<bool, tokio_postgres::Error> {
+    let updated = client.execute(
+        "UPDATE messages SET flags = flags | $1
+            WHERE id = $2 AND recipient_xuid = $3 AND expires > $4",
+        &[&(flags as i32), &(id as i64), &xuid_string(recipient), &now]
+    ).await?;
+
+    Ok(updated != 0)
+}
+
+/// Returns false if there was no such message.
+pub async fn delete_message_record(client: &Client, recipient: Xuid, id: u64) -> Result<bool, tokio_postgres::Error> {
+    let deleted = client.execute(
+        "DELETE FROM messages WHERE id = $1 AND recipient_xuid = $2",
+        &[&(id as i64), &xuid_string(recipient)]
+    ).await?;
+
+    Ok(deleted != 0)
+}
+
+/// Drop every message that has expired by now, from every inbox, and every
+/// attachment no message refers to any more.  Returns the number of messages
+/// dropped.
+pub async fn delete_expired_message_records(client: &Client, now: SystemTime) -> Result<u64, tokio_postgres::Error> {
+    let deleted = client.execute("DELETE FROM messages WHERE expires <= $1", &[&now]).await?;
+
+    // Attachments being sent are only visible once their messages are, so
+    // none is dropped before it's referred to.
+    client.execute(
+        "DELETE FROM attachments
+            WHERE NOT EXISTS (SELECT 1 FROM messages WHERE messages.attachment_id = attachments.id)",
+        &[]
+    ).await?;
+
+    Ok(deleted)
+}
+
+#[derive(Debug)]
+pub enum TeamError {
+    Pg(tokio_postgres::Error),
+    CannotParseXuid(ParseIntError),
+}
+
+impl From<tokio_postgres::Error> for TeamError {
+    fn from(pg_err: tokio_postgres::Error) -> Self {
+        TeamError::Pg(pg_err)
+    }
+}
+
+impl From<ParseIntError> for TeamError {
+    fn from(parse_error: ParseIntError) -> Self {
+        TeamError::CannotParseXuid(parse_error)
+    }
+}
+
+#[derive(Debug)]
+pub struct TeamRecord {
+    /// Assigned by the database, ignored when a record is added.
+    pub id: u64,
+    pub title_id: u32,
+    pub name: String,
+    pub created: SystemTime,
+    pub properties: Vec<(u32, Vec<u8>)>,
+}
+
+#[derive(Debug)]
+pub struct TeamMemberRecord {
+    pub xuid: Xuid,
+    pub role: u32,
+    pub joined: SystemTime,
+}
+
+#[derive(Debug)]
+pub struct TeamInvitationRecord {
+    pub team_id: u64,
+    pub invitee: Xuid,
+    pub inviter: Xuid,
+    pub sent: SystemTime,
+}
+
+fn property_columns(properties: &[(u32, Vec<u8>)]) -> (Vec<i32>, Vec<&[u8]>) {
+    properties.iter()
+        .map(|(id, data)| (*id as i32, data.as_slice()))
+        .unzip()
+}
+
+/// Fill in the properties of teams read from rows of id, title_id, name and
+/// created.
+async fn team_records(client: &Client, rows: &[tokio_postgres::Row]) -> Result<Vec<TeamRecord>, tokio_postgres::Error> {
+    let mut teams: Vec<TeamRecord> = rows.iter()
+        .map(|row| TeamRecord {
+            id: row.get::<_, i64>(0) as u64,
+            title_id: row.get::<_, i32>(1) as u32,
+            name: row.get(2),
+            created: row.get(3),
+            properties: vec![],
+        })
+        .collect();
+
+    if teams.is_empty() {
+        return Ok(teams);
+    }
+
+    let ids: Vec<i64> = teams.iter().map(|team| team.id as i64).collect();
+
+    let rows = client.query(
+        "SELECT team_id, property_id, data FROM team_properties
+            WHERE team_id = ANY($1)
+            ORDER BY property_id",
+        &[&ids]
+    ).await?;
+
+    for row in rows {
+        let team_id = row.get::<_, i64>(0) as u64;
+
+        if let Some(team) = teams.iter_mut().find(|team| team.id == team_id) {
+            team.properties.push((row.get::<_, i32>(1) as u32, row.get(2)));
+        }
+    }
+
+    Ok(teams)
+}
+
+#[derive(Debug)]
+pub enum TeamCreateOutcome {
+    Created(u64),
+    NameTaken,
+    TooManyTeams,
+}
+
+/// Add a team with owner as its only member, as long as owner is in fewer
+/// than max_teams teams of the title and it has no team of that name.
+pub async fn put_team_record(
+    client: &mut Client,
+    record: &TeamRecord,
+    owner: &TeamMemberRecord,
+    max_teams: usize,
+) -> Result<TeamCreateOutcome, tokio_postgres::Error> {
+    let transaction = client.transaction().await?;
+
+    // The same lock accept_team_invitation takes, so neither creates nor
+    // accepts racing each other can put the owner in too many teams.
+    transaction.execute(
+        "SELECT pg_advisory_xact_lock($1, hashtext($2))",
+        &[&(record.title_id as i32), &xuid_string(owner.xuid)]
+    ).await?;
+
+    let teams = transaction.query_one(
+        "SELECT COUNT(*) FROM team_members
+            JOIN teams ON teams.id = team_members.team_id
+            WHERE teams.title_id = $1 AND team_members.xuid = $2",
+        &[&(record.title_id as i32), &xuid_string(owner.xuid)]
+    ).await?;
+
+    if teams.get::<_, i64>(0) as usize >= max_teams {
+        return Ok(TeamCreateOutcome::TooManyTeams)
+    }
+
+    let (property_ids, property_data) = property_columns(&record.properties);
+
+    let row = transaction.query_opt(
+        "WITH inserted AS (
+            INSERT INTO teams (title_id, name, created)
+                VALUES ($1, $2, $3)
+                ON CONFLICT DO NOTHING
+                RETURNING id
+        ), owner AS (
+            INSERT INTO team_members (team_id, xuid, role, joined)
+                SELECT id, $4, $5, $6 FROM inserted
+        ), properties AS (
+            INSERT INTO team_properties (team_id, property_id, data)
+                SELECT inserted.id, property.id, property.data
+                    FROM inserted, UNNEST($7::INTEGER[], $8::BYTEA[]) AS property (id, data)
+        )
+        SELECT id FROM inserted",
+        &[
+            &(record.title_id as i32),
+            &record.name,
+            &record.created,
+            &xuid_string(owner.xuid),
+            &(owner.role as i32),
+            &owner.joined,
+            &property_ids,
+            &property_data,
+        ]
+    ).await?;
+
+    let id = match row {
+        Some(row) => row.get::<_, i64>(0) as u64,
+        None => return Ok(TeamCreateOutcome::NameTaken),
+    };
+
+    transaction.commit().await?;
+
+    Ok(TeamCreateOutcome::Created(id))
+}
+
+pub async fn get_team_record(client: &Client, id: u64) -> Result<Option<TeamRecord>, tokio_postgres::Error> {
+    let rows = client.query(
+        "SELECT id, title_id, name, created FROM teams WHERE id = $1",
+        &[&(id as i64)]
+    ).await?;
+
+    Ok(team_records(client, &rows).await?.pop())
+}
+
+/// Every team of title_id that xuid is a member of, oldest first.
+pub async fn get_team_records_of(client: &Client, title_id: u32, xuid: Xuid) -> Result<Vec<TeamRecord>, tokio_postgres::Error> {
+    let rows = client.query(
+        "SELECT teams.id, teams.title_id, teams.name, teams.created FROM teams
+            JOIN team_members ON team_members.team_id = teams.id
+            WHERE teams.title_id = $1 AND team_members.xuid = $2
+            ORDER BY teams.id",
+        &[&(title_id as i32), &xuid_string(xuid)]
+    ).await?;
+
+    team_records(client, &rows).await
+}
+
+#[derive(Debug)]
+pub enum TeamPropertiesOutcome {
+    Set,
+    NoSuchTeam,
+    TooManyProperties,
+}
+
+/// Apply updates to the properties of a team, in order, with an empty value
+/// removing the property.  Nothing changes if the team would end up with more
+/// than max_properties properties.
+pub async fn set_team_properties(
+    client: &mut Client,
+    id: u64,
+    updates: &[(u32, Vec<u8>)],
+    max_properties: usize,
+) -> Result<TeamPropertiesOutcome, tokio_postgres::Error> {
+    let transaction = client.transaction().await?;
+
+    // Held until the transaction ends, so updates to the team's properties
+    // are applied one after another.
+    let team = transaction.query_opt(
+        "SELECT id FROM teams WHERE id = $1 FOR UPDATE",
+        &[&(id as i64)]
+    ).await?;
+
+    if team.is_none() {
+        return Ok(TeamPropertiesOutcome::NoSuchTeam)
+    }
+
+    // Later updates to a property win over earlier ones.
+    let mut latest = BTreeMap::new();
+    for (property_id, data) in updates {
+        latest.insert(*property_id, data);
+    }
+
+    let (set, removed): (Vec<_>, Vec<_>) = latest.into_iter().partition(|(_, data)| !data.is_empty());
+
+    let removed_ids: Vec<i32> = removed.iter().map(|(property_id, _)| *property_id as i32).collect();
+    let (set_ids, set_data): (Vec<i32>, Vec<&[u8]>) = set.iter()
+        .map(|(property_id, data)| (*property_id as i32, data.as_slice()))
+        .unzip();
+
+    transaction.execute(
+        "DELETE FROM team_properties WHERE team_id = $1 AND property_id = ANY($2)",
+        &[&(id as i64), &removed_ids]
+    ).await?;
+
+    transaction.execute(
+        "INSERT INTO team_properties (team_id, property_id, data)
+            SELECT $1, property.id, property.data
+                FROM UNNEST($2::INTEGER[], $3::BYTEA[]) AS property (id, data)
+            ON CONFLICT (team_id, property_id) DO UPDATE SET data = EXCLUDED.data",
+        &[&(id as i64), &set_ids, &set_data]
+    ).await?;
+
+    let properties = transaction.query_one(
+        "SELECT COUNT(*) FROM team_properties WHERE team_id = $1",
+        &[&(id as i64)]
+    ).await?;
+
+    if properties.get::<_, i64>(0) as usize > max_properties {
+        return Ok(TeamPropertiesOutcome::TooManyProperties)
+    }
+
+    transaction.commit().await?;
+
+    Ok(TeamPropertiesOutcome::Set)
+}
+
+/// Returns false if there was no such team.
+pub async fn delete_team_record(client: &Client, id: u64) -> Result<bool, tokio_postgres::Error> {
+    let deleted = client.execute(
+        "DELETE FROM teams WHERE id = $1",
+        &[&(id as i64)]
+    ).await?;
+
+    Ok(deleted != 0)
+}
+
+/// Every member of a team, in the order they joined.
+pub async fn get_team_member_records(client: &Client, team_id: u64) -> Result<Vec<TeamMemberRecord>, TeamError> {
+    let rows = client.query(
+        "SELECT xuid, role, joined FROM team_members
+            WHERE team_id = $1
+            ORDER BY joined, xuid",
+        &[&(team_id as i64)]
+    ).await?;
+
+    rows.iter()
+        .map(|row| {
+            let xuid: String = row.get(0);
+
+            Ok(TeamMemberRecord {
+                xuid: Xuid(u64::from_str_radix(&xuid, 16)?),
+                role: row.get::<_, i32>(1) as u32,
+                joined: row.get(2),
+            })
+        })
+        .collect()
+}
+
+/// How accepting an invitation to a team went.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TeamAcceptOutcome {
+    Joined,
+    NoInvitation,
+    TeamFull,
+    TooManyTeams,
+    AlreadyMember,
+}
+
+/// Use up record.xuid's invitation to team_id and add them as record, as long
+/// as the team has fewer than max_members members and they're in fewer than
+/// max_teams teams of title_id.  The invitation is kept if either limit is
+/// reached.
+pub async fn accept_team_invitation(
+    client: &mut Client,
+    title_id: u32,
+    team_id: u64,
+    record: &TeamMemberRecord,
+    max_members: usize,
+    max_teams: usize,
+) -> Result<TeamAcceptOutcome, tokio_postgres::Error> {
+    let transaction = client.transaction().await?;
+
+    // Both locks are held until the transaction ends: the user's accepts
+    // within the title wait for each other, and so do all accepts to the team,
+    // so neither limit can be overrun by accepts racing each other.
+    transaction.execute(
+        "SELECT pg_advisory_xact_lock($1, hashtext($2))",
+        &[&(title_id as i32), &xuid_string(record.xuid)]
+    ).await?;
+
+    let team = transaction.query_opt(
+        "SELECT id FROM teams WHERE id = $1 FOR UPDATE",
+        &[&(team_id as i64)]
+    ).await?;
+
+    let taken = transaction.execute(
+        "DELETE FROM team_invitations WHERE team_id = $1 AND invitee_xuid = $2",
+        &[&(team_id as i64), &xuid_string(record.xuid)]
+    ).await?;
+
+    if team.is_none() || taken == 0 {
+        return Ok(TeamAcceptOutcome::NoInvitation)
+    }
+
+    let members = transaction.query_one(
+        "SELECT COUNT(*) FROM team_members WHERE team_id = $1",
+        &[&(team_id as i64)]
+    ).await?;
+
+    if members.get::<_, i64>(0) as usize >= max_members {
+        return Ok(TeamAcceptOutcome::TeamFull)
+    }
+
+    let teams = transaction.query_one(
+        "SELECT COUNT(*) FROM team_members
+            JOIN teams ON teams.id = team_members.team_id
+            WHERE teams.title_id = $1 AND team_members.xuid = $2",
+        &[&(title_id as i32), &xuid_string(record.xuid)]
+    ).await?;
+
+    if teams.get::<_, i64>(0) as usize >= max_teams {
+        return Ok(TeamAcceptOutcome::TooManyTeams)
+    }
+
+    let inserted = transaction.execute(
+        "INSERT INTO team_members (team_id, xuid, role, joined)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT DO NOTHING",
+        &[&(team_id as i64), &xuid_string(record.xuid), &(record.role as i32), &record.joined]
+    ).await?;
+
+    transaction.commit().await?;
+
+    if inserted == 0 {
+        return Ok(TeamAcceptOutcome::AlreadyMember)
+    }
+
+    Ok(TeamAcceptOutcome::Joined)
+}
+
+/// Returns false if there was no such member.
+pub async fn set_team_member_role(client: &Client, team_id: u64, xuid: Xuid, role: u32) -> Result<bool, tokio_postgres::Error> {
+    let updated = client.execute(
+        "UPDATE team_members SET role = $1 WHERE team_id = $2 AND xuid = $3",
+        &[&(role as i32), &(team_id as i64), &xuid_string(xuid)]
+    ).await?;
+
+    Ok(updated != 0)
+}
+
+/// How handing a team over went.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TeamHandOverOutcome {
+    HandedOver,
+    /// The team's owner isn't who it was thought to be any more.
+    NotOwner,
+    NotAMember,
+}
+
+/// Give new_owner owner_role and owner demoted_role, as long as owner still
+/// has owner_role.
+pub async fn hand_over_team(
+    client: &mut Client,
+    team_id: u64,
+    owner: Xuid,
+    new_owner: Xuid,
+    owner_role: u32,
+    demoted_role: u32,
+) -> Result<TeamHandOverOutcome, tokio_postgres::Error> {
+    let transaction = client.transaction().await?;
+
+    // Locking the owner's row makes a second hand over from them wait, and
+    // then find they aren't the owner any more.
+    let row = transaction.query_opt(
+        "SELECT role FROM team_members WHERE team_id = $1 AND xuid = $2 FOR UPDATE",
+        &[&(team_id as i64), &xuid_string(owner)]
+    ).await?;
+
+    if row.map(|row| row.get::<_, i32>(0) as u32) != Some(owner_role) {
+        return Ok(TeamHandOverOutcome::NotOwner)
+    }
+
+    let promoted = transaction.execute(
+        "UPDATE team_members SET role = $1 WHERE team_id = $2 AND xuid = $3",
+        &[&(owner_role as i32), &(team_id as i64), &xuid_string(new_owner)]
+    ).await?;
+
+    if promoted == 0 {
+        return Ok(TeamHandOverOutcome::NotAMember)
+    }
+
+    transaction.execute(
+        "UPDATE team_members SET role = $1 WHERE team_id = $2 AND xuid = $3",
+        &[&(demoted_role as i32), &(team_id as i64), &xuid_string(owner)]
+    ).await?;
+
+    transaction.commit().await?;
+
+    Ok(TeamHandOverOutcome::HandedOver)
+}
+
+/// Returns false if there was no such member.
+pub async fn delete_team_member_record(client: &Client, team_id: u64, xuid: Xuid) -> Result<bool, tokio_postgres::Error> {
+    let deleted = client.execute(
+        "DELETE FROM team_members WHERE team_id = $1 AND xuid = $2",
+        &[&(team_id as i64), &xuid_string(xuid)]
+    ).await?;
+
+    Ok(deleted != 0)
+}
+
+/// Add an invitation, replacing any the invitee already had to the team.
+pub async fn put_team_invitation_record(client: &Client, record: &TeamInvitationRecord) -> Result<(), tokio_postgres::Error> {
+    client.execute(
+        "INSERT INTO team_invitations (team_id, invitee_xuid, inviter_xuid, sent)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (team_id, invitee_xuid) DO UPDATE
+                SET inviter_xuid = EXCLUDED.inviter_xuid, sent = EXCLUDED.sent",
+        &[&(record.team_id as i64), &xuid_string(record.invitee), &xuid_string(record.inviter), &record.sent]
+    ).await?;
+
+    Ok(())
+}
+
+fn team_invitation_record(row: &tokio_postgres::Row) -> Result<TeamInvitationRecord, TeamError> {
+    let invitee: String = row.get(1);
+    let inviter: String = row.get(2);
+
+    Ok(TeamInvitationRecord {
+        team_id: row.get::<_, i64>(0) as u64,
+        invitee: Xuid(u64::from_str_radix(&invitee, 16)?),
+        inviter: Xuid(u64::from_str_radix(&inviter, 16)?),
+        sent: row.get(3),
+    })
+}
+
+/// Every invitation invitee has to a team of title_id, oldest first.
+pub async fn get_team_invitation_records(client: &Client, title_id: u32, invitee: Xuid) -> Result<Vec<TeamInvitationRecord>, TeamError> {
+    let rows = client.query(
+        "SELECT team_invitations.team_id, invitee_xuid, inviter_xuid, sent FROM team_invitations
+            JOIN teams ON teams.id = team_invitations.team_id
+            WHERE teams.title_id = $1 AND invitee_xuid = $2
+            ORDER BY sent",
+        &[&(title_id as i32), &xuid_string(invitee)]
+    ).await?;
+
+    rows.iter().map(team_invitation_record).collect()
+}
+
+/// Remove an invitation, returning it if there was one.
+pub async fn take_team_invitation_record(client: &Client, team_id: u64, invitee: Xuid) -> Result<Option<TeamInvitationRecord>, TeamError> {
+    let rows = client.query(
+        "DELETE FROM team_invitations
+            WHERE team_id = $1 AND invitee_xuid = $2
+            RETURNING team_id, invitee_xuid, inviter_xuid, sent",
+        &[&(team_id as i64), &xuid_string(invitee)]
+    ).await?;
+
+    rows.first().map(team_invitation_record).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_schema_file_is_applied_in_order() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../../schema");
+
+        let mut files = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter_map(|name| name.strip_suffix(".sql").map(str::to_owned))
+            .collect::<Vec<_>>();
+        files.sort();
+
+        let names = SCHEMA.iter().map(|(name, _)| name.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(names, files);
+    }
+}
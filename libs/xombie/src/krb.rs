@@ -6,12 +6,14 @@ use std::convert::TryInto;
 
 use xblive::crypto::primitives::{Rc4HmacDecryptError, rc4_md5_hmac_decrypt, rc4_md5_hmac_encrypt};
 
+use xbox_sys::account::{Xuid, XUID_LEN};
 use xbox_sys::crypto::{SymmetricKey, SYMMETRIC_KEY_LEN};
 
 pub const UDP_PORT: u16 = 88;
 
 pub const AD_TYPE_SERVICE_ADDRESSES: i32 = 10000;
 pub const AD_TYPE_USERS:             i32 = 10001;
+pub const AD_TYPE_TITLE:             i32 = 10002;
 
 pub const USER_AT_DOMAIN: &str = "@xombie.org";
 
@@ -20,6 +22,96 @@ pub const AT_DOMAINS: &[&'static str] = &[
     xblive::user::AT_DOMAIN,
 ];
 
+pub const MAX_USERS: usize = 4;
+
+/// Body of the AD_TYPE_USERS authorization data entry: the machine account
+/// the ticket was issued to and the user accounts signed in on it, by slot.
+/// Empty slots hold Xuid::INVALID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UsersAuthData {
+    pub machine: Xuid,
+    pub users: [Xuid;MAX_USERS],
+    pub user_flags: [u32;MAX_USERS],
+}
+
+impl UsersAuthData {
+    pub const ENCODED_LEN: usize = XUID_LEN + (XUID_LEN * MAX_USERS) + (4 * MAX_USERS);
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::ENCODED_LEN);
+
+        buf.extend_from_slice(&self.machine.0.to_le_bytes());
+        for user in self.users.iter() {
+            buf.extend_from_slice(&user.0.to_le_bytes());
+        }
+        for user_flags in self.user_flags.iter() {
+            buf.extend_from_slice(&user_flags.to_le_bytes());
+        }
+
+        buf
+    }
+
+    pub fn from_raw(raw: &[u8]) -> Option<UsersAuthData> {
+        if raw.len() != Self::ENCODED_LEN {
+            return None;
+        }
+
+        let (machine, rem) = raw.split_at(XUID_LEN);
+        let (users, user_flags) = rem.split_at(XUID_LEN * MAX_USERS);
+
+        let mut ret = UsersAuthData {
+            machine: Xuid(u64::from_le_bytes(machine.try_into().ok()?)),
+            users: [Xuid::INVALID;MAX_USERS],
+            user_flags: [0;MAX_USERS],
+        };
+
+        for (i, user) in users.chunks_exact(XUID_LEN).enumerate() {
+            ret.users[i] = Xuid(u64::from_le_bytes(user.try_into().ok()?));
+        }
+
+        for (i, flags) in user_flags.chunks_exact(4).enumerate() {
+            ret.user_flags[i] = u32::from_le_bytes(flags.try_into().ok()?);
+        }
+
+        Some(ret)
+    }
+}
+
+/// Body of the AD_TYPE_TITLE authorization data entry: the title the console
+/// was running when it requested the ticket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TitleAuthData {
+    pub title_id: u32,
+    pub title_version: u32,
+    pub title_region: u32,
+}
+
+impl TitleAuthData {
+    pub const ENCODED_LEN: usize = 4 + 4 + 4;
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::ENCODED_LEN);
+
+        buf.extend_from_slice(&self.title_id.to_le_bytes());
+        buf.extend_from_slice(&self.title_version.to_le_bytes());
+        buf.extend_from_slice(&self.title_region.to_le_bytes());
+
+        buf
+    }
+
+    pub fn from_raw(raw: &[u8]) -> Option<TitleAuthData> {
+        if raw.len() != Self::ENCODED_LEN {
+            return None;
+        }
+
+        Some(TitleAuthData {
+            title_id: u32::from_le_bytes(raw[0..4].try_into().ok()?),
+            title_version: u32::from_le_bytes(raw[4..8].try_into().ok()?),
+            title_region: u32::from_le_bytes(raw[8..12].try_into().ok()?),
+        })
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum SymmetricKeyCreateError {
     UnknownAlgorithm,
@@ -157,3 +249,39 @@ pub fn find_unique_padata<'a>(padata_type: i32, v: &'a Option<Vec<PaData>>) -> O
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn users_auth_data_round_trip() {
+        let users = UsersAuthData {
+            machine: Xuid(0xfffe_0000_1234_5678),
+            users: [Xuid(0x0009_7490_6c44_9232), Xuid::INVALID, Xuid(3), Xuid::INVALID],
+            user_flags: [0x1, 0, 0x20, 0],
+        };
+
+        let bytes = users.build();
+
+        assert_eq!(bytes.len(), UsersAuthData::ENCODED_LEN);
+        assert_eq!(UsersAuthData::from_raw(&bytes), Some(users));
+    }
+
+    #[test]
+    fn title_auth_data_round_trip() {
+        let title = TitleAuthData {
+            title_id: 0x4c41000b,
+            title_version: 0x0001_0002,
+            title_region: 0x7fff_ffff,
+        };
+
+        assert_eq!(TitleAuthData::from_raw(&title.build()), Some(title));
+        assert_eq!(TitleAuthData::from_raw(&title.build()[1..]), None);
+    }
+
+    #[test]
+    fn users_auth_data_wrong_length() {
+        assert_eq!(UsersAuthData::from_raw(&[]), None);
+        assert_eq!(UsersAuthData::from_raw(&[0;UsersAuthData::ENCODED_LEN + 1]), None);
+    }
+}
@@ -0,0 +1,2 @@
+-- The user flags the KDC puts in SG tickets for each signed-in user.
+ALTER TABLE clients ADD COLUMN IF NOT EXISTS user_flags INTEGER NOT NULL DEFAULT 0;
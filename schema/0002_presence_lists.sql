@@ -0,0 +1,21 @@
+-- Buddy and block lists.  Every friendship is stored from both sides, each
+-- with the status as its owner sees it.  Blocks are only stored on the side
+-- of the user who blocked.
+CREATE TABLE IF NOT EXISTS friendships (
+    xuid TEXT NOT NULL,
+    buddy_xuid TEXT NOT NULL,
+    status SMALLINT NOT NULL,
+    PRIMARY KEY (xuid, buddy_xuid)
+);
+
+CREATE TABLE IF NOT EXISTS blocks (
+    xuid TEXT NOT NULL,
+    blocked_xuid TEXT NOT NULL,
+    PRIMARY KEY (xuid, blocked_xuid)
+);
+
+CREATE TABLE IF NOT EXISTS presence_list_versions (
+    xuid TEXT PRIMARY KEY,
+    buddy_list_version INTEGER NOT NULL DEFAULT 0,
+    block_list_version INTEGER NOT NULL DEFAULT 0
+);
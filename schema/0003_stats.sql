@@ -0,0 +1,13 @@
+-- Leaderboards.  The columns of a row are kept encoded, as only the rating
+-- is ever sorted on.
+CREATE TABLE IF NOT EXISTS stats_rows (
+    title_id INTEGER NOT NULL,
+    leaderboard_id INTEGER NOT NULL,
+    xuid TEXT NOT NULL,
+    rating BIGINT NOT NULL,
+    stats BYTEA NOT NULL,
+    PRIMARY KEY (title_id, leaderboard_id, xuid)
+);
+
+CREATE INDEX IF NOT EXISTS stats_rows_by_rating
+    ON stats_rows (title_id, leaderboard_id, rating DESC, xuid);
@@ -0,0 +1,12 @@
+-- User storage.  Only what's known about each file is kept here; the
+-- contents live on disk.
+CREATE TABLE IF NOT EXISTS storage_files (
+    title_id INTEGER NOT NULL,
+    owner_xuid TEXT NOT NULL,
+    path TEXT NOT NULL,
+    flags INTEGER NOT NULL,
+    size BIGINT NOT NULL,
+    hash BYTEA NOT NULL,
+    modified TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (title_id, owner_xuid, path)
+);
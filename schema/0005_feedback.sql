@@ -0,0 +1,14 @@
+-- Player feedback.  Moderators mostly look up what's been said about one
+-- player, so reports are indexed by target.
+CREATE TABLE IF NOT EXISTS feedback_reports (
+    id BIGSERIAL PRIMARY KEY,
+    title_id INTEGER NOT NULL,
+    reporter_xuid TEXT NOT NULL,
+    target_xuid TEXT NOT NULL,
+    feedback_type INTEGER NOT NULL,
+    text TEXT,
+    submitted TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS feedback_reports_by_target
+    ON feedback_reports (target_xuid, id DESC);
@@ -0,0 +1,24 @@
+-- Messages.  Attachments are kept apart so listing an inbox doesn't drag
+-- them along, and go when their message does.
+CREATE TABLE IF NOT EXISTS messages (
+    id BIGSERIAL PRIMARY KEY,
+    recipient_xuid TEXT NOT NULL,
+    sender_xuid TEXT NOT NULL,
+    title_id INTEGER NOT NULL,
+    flags INTEGER NOT NULL,
+    text TEXT NOT NULL,
+    attachment_len INTEGER NOT NULL,
+    sent TIMESTAMP WITH TIME ZONE NOT NULL,
+    expires TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS messages_by_recipient
+    ON messages (recipient_xuid, id DESC);
+
+CREATE INDEX IF NOT EXISTS messages_by_expiry
+    ON messages (expires);
+
+CREATE TABLE IF NOT EXISTS message_attachments (
+    message_id BIGINT PRIMARY KEY REFERENCES messages (id) ON DELETE CASCADE,
+    data BYTEA NOT NULL
+);
@@ -0,0 +1,40 @@
+-- Teams.  Names are unique within a title whatever their case, and
+-- everything about a team goes when the team does.
+CREATE TABLE IF NOT EXISTS teams (
+    id BIGSERIAL PRIMARY KEY,
+    title_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    created TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS teams_by_name
+    ON teams (title_id, LOWER(name));
+
+CREATE TABLE IF NOT EXISTS team_properties (
+    team_id BIGINT NOT NULL REFERENCES teams (id) ON DELETE CASCADE,
+    property_id INTEGER NOT NULL,
+    data BYTEA NOT NULL,
+    PRIMARY KEY (team_id, property_id)
+);
+
+CREATE TABLE IF NOT EXISTS team_members (
+    team_id BIGINT NOT NULL REFERENCES teams (id) ON DELETE CASCADE,
+    xuid TEXT NOT NULL,
+    role INTEGER NOT NULL,
+    joined TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (team_id, xuid)
+);
+
+CREATE INDEX IF NOT EXISTS team_members_by_xuid
+    ON team_members (xuid);
+
+CREATE TABLE IF NOT EXISTS team_invitations (
+    team_id BIGINT NOT NULL REFERENCES teams (id) ON DELETE CASCADE,
+    invitee_xuid TEXT NOT NULL,
+    inviter_xuid TEXT NOT NULL,
+    sent TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (team_id, invitee_xuid)
+);
+
+CREATE INDEX IF NOT EXISTS team_invitations_by_invitee
+    ON team_invitations (invitee_xuid);
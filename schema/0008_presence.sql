@@ -0,0 +1,31 @@
+-- Presence, kept here rather than in an SG so it survives the SG restarting.
+-- Addresses and key ids are kept in their wire encoding.
+CREATE TABLE IF NOT EXISTS user_presence (
+    xuid TEXT PRIMARY KEY,
+    machine_xuid TEXT NOT NULL,
+    connection BIGINT NOT NULL,
+    user_index SMALLINT,
+    online BOOLEAN NOT NULL,
+    state INTEGER NOT NULL,
+    title_id INTEGER NOT NULL,
+    title_version INTEGER NOT NULL,
+    xnaddr BYTEA,
+    xnkid BYTEA,
+    match_session_id BIGINT NOT NULL,
+    nickname BYTEA NOT NULL,
+    title_stuff BYTEA NOT NULL,
+    last_seen TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS user_presence_online_by_machine
+    ON user_presence (machine_xuid) WHERE online;
+
+-- Invitations into sessions, held until the invitee's console is told.
+CREATE TABLE IF NOT EXISTS presence_invitations (
+    invitee_xuid TEXT NOT NULL,
+    host_xuid TEXT NOT NULL,
+    xnkid BYTEA NOT NULL,
+    title_id INTEGER NOT NULL,
+    sent TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (invitee_xuid, host_xuid, xnkid)
+);
@@ -0,0 +1,6 @@
+-- Each upload's contents go in a blob of their own, named here, so storing
+-- a file never overwrites contents another record still points to.  Files
+-- stored before this kept their contents under their path.
+ALTER TABLE storage_files ADD COLUMN IF NOT EXISTS blob TEXT;
+UPDATE storage_files SET blob = path WHERE blob IS NULL;
+ALTER TABLE storage_files ALTER COLUMN blob SET NOT NULL;
@@ -0,0 +1,25 @@
+-- Attachments are kept once per send rather than once per recipient, with
+-- every recipient's copy of the message referring to the same one.  The
+-- reaper drops an attachment once no message refers to it.
+CREATE TABLE IF NOT EXISTS attachments (
+    id BIGSERIAL PRIMARY KEY,
+    data BYTEA NOT NULL
+);
+
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS attachment_id BIGINT REFERENCES attachments (id);
+
+-- Each attachment stored so far belonged to one message, so it keeps that
+-- message's id.
+INSERT INTO attachments (id, data)
+    SELECT message_id, data FROM message_attachments;
+
+UPDATE messages SET attachment_id = id
+    WHERE id IN (SELECT message_id FROM message_attachments);
+
+SELECT setval(pg_get_serial_sequence('attachments', 'id'), COALESCE(MAX(id), 0) + 1, false)
+    FROM attachments;
+
+DROP TABLE message_attachments;
+
+CREATE INDEX IF NOT EXISTS messages_by_attachment
+    ON messages (attachment_id) WHERE attachment_id IS NOT NULL;
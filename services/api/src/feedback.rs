@@ -0,0 +1,121 @@
+//! Moderator access to the feedback players have filed about each other.
+
+use serde::{Deserialize, Serialize};
+
+use subtle::ConstantTimeEq;
+
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+use xbox_sys::account::Xuid;
+
+use xombie_feedback::{Feedback, Report, ReportQuery, MAX_QUERY_RESULTS};
+
+/// Query string of GET /api/feedback.  Xuids and title ids are in hex.
+#[derive(Debug, Deserialize)]
+struct FeedbackParams {
+    reporter: Option<String>,
+    target: Option<String>,
+    title: Option<String>,
+    #[serde(rename = "type")]
+    feedback_type: Option<u32>,
+    before: Option<u64>,
+    limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct ReportJson {
+    id: u64,
+    title_id: String,
+    reporter: String,
+    target: String,
+    feedback_type: u32,
+    text: Option<String>,
+    /// Seconds since the Unix epoch.
+    submitted: u64,
+}
+
+impl From<Report> for ReportJson {
+    fn from(report: Report) -> Self {
+        ReportJson {
+            id: report.id,
+            title_id: format!("{:08x}", report.title_id),
+            reporter: format!("{:016x}", report.reporter.0),
+            target: format!("{:016x}", report.target.0),
+            feedback_type: report.feedback_type,
+            text: report.text,
+            submitted: report.submitted.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()),
+        }
+    }
+}
+
+fn parse_xuid(xuid: &Option<String>) -> Result<Option<Xuid>, ()> {
+    xuid.as_ref()
+        .map(|xuid| u64::from_str_radix(xuid, 16).map(Xuid).map_err(|_| ()))
+        .transpose()
+}
+
+fn parse_query(params: &FeedbackParams) -> Result<ReportQuery, ()> {
+    Ok(ReportQuery {
+        reporter: parse_xuid(&params.reporter)?,
+        target: parse_xuid(&params.target)?,
+        title_id: params.title.as_ref()
+            .map(|title| u32::from_str_radix(title, 16).map_err(|_| ()))
+            .transpose()?,
+        feedback_type: params.feedback_type,
+        before: params.before,
+        limit: params.limit.unwrap_or(MAX_QUERY_RESULTS),
+    })
+}
+
+/// GET /api/feedback, for requests bearing the operator token.  Without a
+/// token every request is refused.
+pub fn routes(feedback: Arc<Feedback>, operator_token: Option<String>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let operator_token = Arc::new(operator_token);
+
+    warp::path!("api" / "feedback")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::query::<FeedbackParams>())
+        .and_then(move |authorization: Option<String>, params: FeedbackParams| {
+            let feedback = feedback.clone();
+            let operator_token = operator_token.clone();
+
+            async move {
+                query_handler(&feedback, operator_token.as_deref(), authorization.as_deref(), &params).await
+            }
+        })
+}
+
+async fn query_handler(feedback: &Feedback, operator_token: Option<&str>, authorization: Option<&str>, params: &FeedbackParams) -> Result<warp::reply::Response, Infallible> {
+    let authorized = match (operator_token, authorization.and_then(|value| value.strip_prefix("Bearer "))) {
+        // Compared in constant time so the response time says nothing about
+        // how much of a guess was right.
+        (Some(operator_token), Some(token)) => bool::from(operator_token.as_bytes().ct_eq(token.as_bytes())),
+        _ => false,
+    };
+
+    if !authorized {
+        return Ok(StatusCode::UNAUTHORIZED.into_response())
+    }
+
+    let query = match parse_query(params) {
+        Ok(query) => query,
+        Err(()) => return Ok(StatusCode::BAD_REQUEST.into_response()),
+    };
+
+    match feedback.query(&query).await {
+        Ok(reports) => {
+            let reports: Vec<ReportJson> = reports.into_iter().map(ReportJson::from).collect();
+            Ok(warp::reply::json(&reports).into_response())
+        }
+        Err(err) => {
+            eprintln!("Unable to query feedback: {:?} {:?}", err, query);
+            Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
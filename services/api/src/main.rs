@@ -1,12 +1,113 @@
+use clap::Parser;
+
+use std::io;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::Arc;
 
 use tokio::signal::unix::{signal, SignalKind};
 
 use warp::Filter;
 
+use xombie::db::{apply_schema, db_pool};
+use xombie_feedback::Feedback;
+use xombie_feedback::store::postgres::PostgresStore as PostgresFeedbackStore;
+
+mod feedback;
+
+/// Only moderators use the API, so a few connections are plenty.
+const API_PG_POOL_SIZE: usize = 4;
+
+/// Where the operator token comes from without --operator-token-file.  It's
+/// never taken on the command line, where anyone who can list processes
+/// would see it.
+const OPERATOR_TOKEN_VAR: &str = "XOMBIE_OPERATOR_TOKEN";
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(short, long, value_parser, default_value_t = String::from("db"))]
+    pg_addr: String,
+
+    #[clap(short, long, value_parser, default_value_t = 5432)]
+    pg_port: u16,
+
+    #[clap(short, long, value_parser, default_value_t = String::from("postgres"))]
+    pg_user: String,
+
+    #[clap(short, long, value_parser, default_value_t = String::from("postgres"))]
+    pg_password: String,
+
+    /// File holding the bearer token moderators present to review feedback,
+    /// read instead of XOMBIE_OPERATOR_TOKEN.  Without a token the feedback
+    /// routes refuse every request.
+    #[clap(long, value_parser)]
+    operator_token_file: Option<PathBuf>,
+}
+
+/// The operator token from token_file if there is one, or from
+/// OPERATOR_TOKEN_VAR otherwise.  An empty token is no token.
+fn operator_token(token_file: Option<&Path>) -> io::Result<Option<String>> {
+    let token = match token_file {
+        Some(token_file) => std::fs::read_to_string(token_file)?,
+        None => std::env::var(OPERATOR_TOKEN_VAR).unwrap_or_default(),
+    };
+
+    let token = token.trim();
+
+    Ok(if token.is_empty() { None } else { Some(token.to_owned()) })
+}
+
 #[tokio::main]
 async fn main() {
-    let routes = warp::any().map(|| "Hello, World!");
+    let args = Args::parse();
+
+    let operator_token = match operator_token(args.operator_token_file.as_deref()) {
+        Ok(operator_token) => operator_token,
+        Err(err) => {
+            eprintln!("Unable to read the operator token: {:?}", err);
+            exit(1)
+        }
+    };
+
+    let pg = match db_pool(&args.pg_addr, args.pg_port, &args.pg_user, &args.pg_password, API_PG_POOL_SIZE) {
+        Ok(pg) => pg,
+        Err(err) => {
+            eprintln!("Unable to set up the database pool: {:?}", err);
+            exit(1)
+        }
+    };
+
+    let applied = match pg.get().await {
+        Ok(mut client) => apply_schema(&mut client).await,
+        Err(err) => {
+            eprintln!("Unable to connect to the database at {}:{}: {:?}", args.pg_addr, args.pg_port, err);
+            exit(1)
+        }
+    };
+
+    match applied {
+        Ok(applied) => {
+            for name in applied {
+                println!("Applied schema {}", name);
+            }
+        }
+        Err(err) => {
+            eprintln!("Unable to apply the database schema: {:?}", err);
+            exit(1)
+        }
+    }
+
+    let feedback_store = PostgresFeedbackStore::new(pg);
+
+    if operator_token.is_none() {
+        println!("No operator token, feedback can't be reviewed");
+    }
+
+    let feedback = Arc::new(Feedback::new(Arc::new(feedback_store)));
+
+    let routes = feedback::routes(feedback, operator_token)
+        .or(warp::any().map(|| "Hello, World!"));
 
     let mut sigterm_stream = signal(SignalKind::terminate()).unwrap();
 
@@ -19,4 +120,4 @@ async fn main() {
             exit(0)
         }
     }
-}
\ No newline at end of file
+}
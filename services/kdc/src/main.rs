@@ -51,13 +51,19 @@ struct Args {
 async fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
 
-    let client = connect_db_client(
+    let mut client = connect_db_client(
         &args.pg_addr,
         args.pg_port,
         &args.pg_user,
         &args.pg_password
     ).await.unwrap();
 
+    // Tickets carry columns the schema files add, so make sure they're there
+    // before handing any out.
+    for name in apply_schema(&mut client).await? {
+        println!("Applied schema {}", name);
+    }
+
     let client = Arc::new(client);
 
     let addr = format!("{}:{}", args.kdc_addr, args.kdc_port);
@@ -34,6 +34,7 @@ enum TgsProcessError {
     UnableToParseServiceRequestEncData(kerberos_asn1::Error),
     InvalidServiceRequest(&'static str),
     UnableToReadListOfSecureGateways(db::ReadClusterInfoError),
+    UnableToReadUserAccount(tokio_postgres::Error),
     DoTheTimeWarpAgain(KerberosTime),
 }
 
@@ -146,7 +147,53 @@ impl<'a> ValidatedRequest<'a> {
     }
 }
 
-fn construct_pa_service_address(req: &ValidatedRequest<'_>, sg_addr: [u8;4])
+/// Build the users authorization data for the ticket from the users the
+/// console asked for, dropping any that aren't accounts we know about and
+/// carrying over the user flags of those that are.
+async fn construct_users_auth_data(req: &ValidatedRequest<'_>, machine: Xuid, client: &Client)
+    -> Result<UsersAuthData, TgsProcessError>
+{
+    let mut users_auth_data = UsersAuthData {
+        machine,
+        users: [Xuid::INVALID;MAX_USERS],
+        user_flags: [0;MAX_USERS],
+    };
+
+    let service_request = match req.service_request.as_ref() {
+        Some(service_request) => service_request,
+        None => return Ok(users_auth_data),
+    };
+
+    for (i, user) in service_request.xuid.iter().enumerate() {
+        if *user == Xuid::INVALID {
+            continue;
+        }
+
+        if *user == machine {
+            eprintln!("service request for machine account {:x?} as a user", user);
+            continue;
+        }
+
+        let user_flags = db::get_user_flags_for_xuid(client, *user)
+            .await
+            .map_err(|err| {
+                eprintln!("unable to read account of user {:x?}: {}", user, err);
+                TgsProcessError::UnableToReadUserAccount(err)
+            })?;
+
+        match user_flags {
+            Some(user_flags) => {
+                users_auth_data.users[i] = *user;
+                users_auth_data.user_flags[i] = user_flags;
+            }
+            None => eprintln!("service request for unknown user {:x?}", user),
+        }
+    }
+
+    Ok(users_auth_data)
+}
+
+fn construct_pa_service_address(req: &ValidatedRequest<'_>, sg_addr: [u8;4], users: &UsersAuthData)
     -> Result<(Option<PaData>, Option<ServiceAddress>), TgsProcessError>
 {
     let service_request = match req.service_request.as_ref() {
@@ -179,8 +226,8 @@ fn construct_pa_service_address(req: &ValidatedRequest<'_>, sg_addr: [u8;4])
         } 
     }
 
-    let mut hr_user = [0x8000_0001;4];
-    for (i, id) in service_request.xuid.iter().enumerate() {
+    let mut hr_user = [0x8000_0001;MAX_USERS];
+    for (i, id) in users.users.iter().enumerate() {
         if *id != Xuid::INVALID {
             hr_user[i] = 0;
         }
@@ -189,7 +236,7 @@ fn construct_pa_service_address(req: &ValidatedRequest<'_>, sg_addr: [u8;4])
     let service_address = ServiceAddress {
         hr: 0,
         hr_user,
-        user_flags: [0;4],
+        user_flags: users.user_flags,
         bw_limit: 9001,  // Do we still do 'over 9000' jokes?
         _rsvd_28: 0,
         _rsvd_2c: 0,
@@ -227,9 +274,6 @@ async fn internal_tgs_request(tgs_req: TgsReq, stime: KerberosTime, client: &Cli
         .map_err(|err| TgsProcessError::UnableToReadListOfSecureGateways(err))?;
 
     let sg_addr = cluster_info.sg_nodes[0];
-    
-    let (service_address_pa_data, service_address)
-        = construct_pa_service_address(&req, sg_addr)?;
 
     let (gamertag, _domain) = gamertag_from_cname(&req.cname, AT_DOMAINS)
         .unwrap();
@@ -238,6 +282,12 @@ async fn internal_tgs_request(tgs_req: TgsReq, stime: KerberosTime, client: &Cli
         .await
         .unwrap();
 
+    let users_auth_data = construct_users_auth_data(&req, xuid, client)
+        .await?;
+
+    let (service_address_pa_data, service_address)
+        = construct_pa_service_address(&req, sg_addr, &users_auth_data)?;
+
     let (service_session_key, _) = db::get_key_for_xuid(client, xuid, db::KeyType::SgServiceSessionKey)
         .await
         .unwrap();
@@ -256,9 +306,20 @@ async fn internal_tgs_request(tgs_req: TgsReq, stime: KerberosTime, client: &Cli
 
     authorization_data.push(AuthorizationDataEntry {
         ad_type: AD_TYPE_USERS,
-        ad_data: vec![]
+        ad_data: users_auth_data.build(),
     });
 
+    if let Some(service_request) = req.service_request.as_ref() {
+        authorization_data.push(AuthorizationDataEntry {
+            ad_type: AD_TYPE_TITLE,
+            ad_data: TitleAuthData {
+                title_id: service_request.title_id,
+                title_version: service_request.title_version,
+                title_region: service_request.title_region,
+            }.build(),
+        })
+    }
+
     if let Some(service_address) = service_address {
         authorization_data.push(AuthorizationDataEntry {
             ad_type: AD_TYPE_SERVICE_ADDRESSES,
@@ -335,7 +396,16 @@ async fn internal_tgs_request(tgs_req: TgsReq, stime: KerberosTime, client: &Cli
 pub async fn process_tgs_request(tgs_req: TgsReq, stime: KerberosTime, client: &Client)
     -> Result<TgsRep, KrbError>
 {
-    internal_tgs_request(tgs_req, stime, client)
+    let realm = tgs_req.req_body.realm.clone();
+    let sname = tgs_req.req_body.sname.clone();
+
+    internal_tgs_request(tgs_req, stime.clone(), client)
         .await
-        .map_err(|err| err.into())
+        .map_err(|err| match (err, sname) {
+            // Not being able to read an account is our problem rather than
+            // the console's, so tell it to come back later.
+            (TgsProcessError::UnableToReadUserAccount(_), Some(sname)) =>
+                krb_error(error_codes::KDC_ERR_SVC_UNAVAILABLE, None, stime, None, None, realm, sname),
+            (err, _) => err.into(),
+        })
 }
\ No newline at end of file
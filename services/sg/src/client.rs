@@ -12,12 +12,13 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
 use tokio::net::UdpSocket;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, RwLock};
 use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::time::error::Error as TokioTimeError;
 
 use tokio_util::time::delay_queue::{DelayQueue, Expired, self};
 
+use xblive::addr::Addr;
 use xblive::crypto::derivation::TripleDesConnectionKeySet;
 use xblive::crypto::primitives::{BlockCryptError, tdes_cbc_encrypt_in_place, DiffieHellmanModulus, DiffieHellmanResult, sha1_hmac};
 use xblive::net::InAddr;
@@ -30,11 +31,15 @@ use xbox_sys::crypto::{DesIv, SymmetricKey};
 use xombie::krb::{krb_encode_and_encrypt};
 
 use crate::init::ValidatedInitPacket;
+use crate::notify::NotificationQueue;
 use crate::open_clients::SpiReservation;
 use crate::secrets;
 use crate::tracer::PcapngFile;
+use crate::user::CombinedId;
 use crate::Services;
 
+use xombie_matchmaking::Title;
+
 use self::send::SendCtx;
 
 mod ctrl;
@@ -262,6 +267,19 @@ pub struct ClientState {
 
     qos_state: Arc<Mutex<Option<(chrono::DateTime<chrono::Utc>, [u8;8], u16, u8)>>>,
 
+    notifications: Arc<Mutex<NotificationQueue>>,
+
+    /// The users the ticket allows on this connection, in their slots.
+    ticket_users: CombinedId,
+    users: RwLock<CombinedId>,
+
+    title: RwLock<Option<Title>>,
+    xnaddr: RwLock<Option<Addr>>,
+
+    /// Tells this connection apart from any other the machine has had, so
+    /// what it leaves behind when it closes is only what it still owns.
+    connection: u64,
+
     ext_services: Arc<Services>,
 }
 
@@ -270,6 +288,97 @@ impl ClientState {
         self.params.net_name()
     }
 
+    pub fn connection(&self) -> u64 {
+        self.connection
+    }
+
+    /// Snapshot of the machine and users currently signed in on this
+    /// connection.
+    pub async fn users(&self) -> CombinedId {
+        *self.users.read().await
+    }
+
+    /// The title the console is running.  That's what its ticket says, as
+    /// long as the ticket says anything: the KDC only names the title when
+    /// the console asked for a service ticket for one.  Failing that it's
+    /// what the first Alive2 on the connection said.  Either way nothing the
+    /// console sends afterwards can change it.
+    pub async fn title(&self) -> Option<Title> {
+        *self.title.read().await
+    }
+
+    /// Settle on the title an Alive2 reported if the connection doesn't
+    /// have one yet.  Returns the connection's title, or None if it's
+    /// already running some other title.
+    pub async fn claim_title(&self, claimed: Title) -> Option<Title> {
+        let mut title = self.title.write().await;
+
+        match *title {
+            Some(title) if title.id != claimed.id => None,
+            Some(title) => Some(title),
+            None => {
+                *title = Some(claimed);
+                Some(claimed)
+            }
+        }
+    }
+
+    /// The console's own view of its address, as last reported by an Alive2.
+    pub async fn xnaddr(&self) -> Option<Addr> {
+        *self.xnaddr.read().await
+    }
+
+    /// Remember the address the console reported in an Alive2.  Returns
+    /// false, and remembers nothing, if its online address isn't the one the
+    /// console is actually talking to us from.
+    pub async fn update_xnaddr(&self, xnaddr: Addr) -> bool {
+        if xnaddr.addr_online != InAddr::from(self.params.peer.ip()) {
+            eprintln!("ERROR {}: refusing xnaddr {:x?} from another address", self.net_name(), xnaddr);
+            return false;
+        }
+
+        *self.xnaddr.write().await = Some(xnaddr);
+
+        true
+    }
+
+    /// Whether an address a console claims as its own matches what we know
+    /// about this connection.  Its online address always has to be the one
+    /// the console is talking to us from.
+    pub async fn is_own_xnaddr(&self, addr: &Addr) -> bool {
+        if addr.addr_online != InAddr::from(self.params.peer.ip()) {
+            return false;
+        }
+
+        match self.xnaddr().await {
+            Some(xnaddr) => xnaddr == *addr,
+            None => true,
+        }
+    }
+
+    /// Sign a user in to the slot their ticket gave them.  Returns None, and
+    /// leaves them signed out, if the ticket doesn't list them.
+    pub async fn sign_in_user(&self, user: Xuid) -> Option<usize> {
+        let slot = self.users.write().await.sign_in(&self.ticket_users, user);
+
+        match slot {
+            Some(slot) => println!("{} user {:x?} signed in to slot {}", self.net_name(), user, slot),
+            None => eprintln!("ERROR {}: refusing to sign in {:x?}, who isn't on the ticket", self.net_name(), user),
+        }
+
+        slot
+    }
+
+    pub async fn sign_out_user(&self, user: Xuid) -> Option<usize> {
+        let slot = self.users.write().await.sign_out(user);
+
+        if let Some(slot) = slot {
+            println!("{} user {:x?} signed out of slot {}", self.net_name(), user, slot);
+        }
+
+        slot
+    }
+
     async fn pump_overall_expiry(&self) {
         self.delay_resets.lock().await.push((self.timeout_key.clone(), Duration::from_secs(TIMEOUT_SECS as u64)))
     }
@@ -277,6 +386,8 @@ impl ClientState {
 
 pub async fn start_client(init_req: ValidatedInitPacket, sg_to_client_spi: SpiReservation, rx_queue: UnboundedReceiver<Vec<u8>>) {
     let ext_services = init_req.services.clone();
+    let users = init_req.users;
+    let title = init_req.title;
 
     let params = ClientParams::new(init_req, sg_to_client_spi)
         .expect("Unable to build client params");
@@ -304,6 +415,10 @@ pub async fn start_client(init_req: ValidatedInitPacket, sg_to_client_spi: SpiRe
     let send_spi = params.client_to_sg_spi;
     let send_keys = params.keys.sg_to_client.clone();
 
+    let notifications = ext_services.notifications
+        .register(params.machine_user)
+        .await;
+
     let state = Arc::new(ClientState {
         params: params,
         delay_resets: Arc::new(Mutex::new(vec![])),
@@ -319,6 +434,12 @@ pub async fn start_client(init_req: ValidatedInitPacket, sg_to_client_spi: SpiRe
         )),
         tracer: Some(tracer),
         qos_state: Arc::new(Mutex::new(None)),
+        notifications,
+        ticket_users: users,
+        users: RwLock::new(users),
+        title: RwLock::new(title),
+        xnaddr: RwLock::new(None),
+        connection: rand::random(),
         ext_services,
     });
 
@@ -330,6 +451,7 @@ pub async fn start_client(init_req: ValidatedInitPacket, sg_to_client_spi: SpiRe
     ).expect("Unable to create serivce table");
 
     println!("Services {}: {:?}", state.net_name(), state.params.services);
+    println!("Users {}: {:x?}", state.net_name(), state.users().await);
 
     let _ = state.send_ctx.send_raw(&init_resp)
         .await
@@ -368,6 +490,21 @@ pub async fn start_client(init_req: ValidatedInitPacket, sg_to_client_spi: SpiRe
             }
         }
     }
+    state.ext_services.notifications
+        .unregister(state.params.machine_user, &state.notifications)
+        .await;
+
+    match state.ext_services.matchmaking.close_sessions_for_machine(state.params.machine_user, state.connection).await {
+        Ok(0) => {}
+        Ok(closed_sessions) => println!("Closed {} matchmaking sessions hosted by {}", closed_sessions, state.net_name()),
+        Err(err) => eprintln!("Unable to close matchmaking sessions hosted by {}: {:?}", state.net_name(), err),
+    }
+
+    match state.ext_services.presence.machine_gone(state.params.machine_user, state.connection).await {
+        Ok(0) => {}
+        Ok(gone) => println!("Marked {} users on {} offline", gone, state.net_name()),
+        Err(err) => eprintln!("Unable to mark users on {} offline: {:?}", state.net_name(), err),
+    }
 }
 
 #[derive(Debug)]
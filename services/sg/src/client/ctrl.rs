@@ -3,6 +3,8 @@ use log::error;
 use xblive::sg::control::{ControlChunk, ControlPacket, FromRawError, XbToSgPulse, SgToXbPulse, XbToSgQosInit, SgToXbQosResp};
 use xblive::sg::packet::{Opcode, Packet};
 
+use crate::notify::Receipt;
+
 use super::{ClientState, PacketProcessError};
 
 pub async fn on_incoming_control_packet<'a>(
@@ -30,9 +32,32 @@ pub async fn on_incoming_control_packet<'a>(
 }
 
 pub async fn on_incoming_xb_to_sg_pulse<'a>(pulse_in: &XbToSgPulse<'a>, state: &ClientState) -> Result<(), PacketProcessError> {
+    // Without pulse events this is the plain keepalive: echo the console's
+    // seq_ack back.  Otherwise the seq_ack is in the notification queue's own
+    // sequence space, which hasn't been checked against a real console yet.
+    let (seq_ack, events, receipts) = if state.ext_services.notifications.pulse_events() {
+        let mut notifications = state.notifications.lock().await;
+        let receipts = notifications.ack(pulse_in.seq_ack);
+        let (seq_ack, events) = notifications.pulse();
+        (seq_ack, events, receipts)
+    } else {
+        (pulse_in.seq_ack, vec![], vec![])
+    };
+
+    // Whatever the console has now been told about needn't be held any more.
+    for receipt in receipts {
+        match receipt {
+            Receipt::Invitation { invitee, host, xnkid } => {
+                if let Err(err) = state.ext_services.presence.remove_invitation(invitee, host, xnkid).await {
+                    error!("Unable to drop delivered invitation of {:x?} from {:x?}: {:?}", invitee, host, err);
+                }
+            }
+        }
+    }
+
     let pulse_out = ControlChunk::SgToXbPulse(SgToXbPulse {
-        seq_ack: pulse_in.seq_ack,
-        events: &[],
+        seq_ack,
+        events: &events,
     });
 
     send_single_control_chunk(pulse_out, state)
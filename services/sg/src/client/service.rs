@@ -3,12 +3,22 @@ use std::sync::Arc;
 
 use async_trait::async_trait;
 use smoltcp_user_vpn::tcp::AcceptFn;
+use smoltcp_user_vpn::tcp::http::{Request, Response, StatusCode};
 use xblive::{sg::{tcp::TcpHeader, packet::Packet}, net::InAddr};
+use xbox_sys::status::HResult;
 
 use crate::client::{ClientState, PacketProcessError, ServiceMapping, forward, local};
 
+pub mod autoupdate;
+pub mod content;
+pub mod feedback;
 pub mod matchmaking;
+pub mod messaging;
 pub mod presence;
+pub mod stats;
+pub mod storage;
+pub mod string;
+pub mod team;
 mod unimplemented;
 
 #[derive(Debug)]
@@ -33,17 +43,17 @@ static SERVICE_INFO: phf::Map<u32, ServiceInfo> = phf::phf_map! {
         name: "Presence",
     },
     2u32 => ServiceInfo {
-        kind: ServiceKind::Unimplemented,
+        kind: ServiceKind::LocalTcp(string::new_string_connection),
         id: 2,
         name: "String",
     },
     3u32 => ServiceInfo {
-        kind: ServiceKind::Unimplemented,
+        kind: ServiceKind::LocalTcp(autoupdate::new_autoupdate_connection),
         id: 3,
         name: "Auto Update",
     },
     4u32 => ServiceInfo {
-        kind: ServiceKind::Unimplemented,
+        kind: ServiceKind::LocalTcp(content::new_content_connection),
         id: 4,
         name: "Content Available"
     },
@@ -58,12 +68,12 @@ static SERVICE_INFO: phf::Map<u32, ServiceInfo> = phf::phf_map! {
         name: "Matchmaking",
     },
     7u32 => ServiceInfo {
-        kind: ServiceKind::Unimplemented,
+        kind: ServiceKind::LocalTcp(stats::new_stats_connection),
         id: 7,
         name: "Stats",
     },
     8u32 => ServiceInfo {
-        kind: ServiceKind::Unimplemented,
+        kind: ServiceKind::LocalTcp(feedback::new_feedback_connection),
         id: 8,
         name: "Feedback"
     },
@@ -98,7 +108,7 @@ static SERVICE_INFO: phf::Map<u32, ServiceInfo> = phf::phf_map! {
         name: "Resolve",
     },
     15u32 => ServiceInfo {
-        kind: ServiceKind::Unimplemented,
+        kind: ServiceKind::LocalTcp(storage::new_storage_connection),
         id: 15,
         name: "Storage",
     },
@@ -113,12 +123,12 @@ static SERVICE_INFO: phf::Map<u32, ServiceInfo> = phf::phf_map! {
         name: "Game Data",
     },
     18u32 => ServiceInfo {
-        kind: ServiceKind::Unimplemented,
+        kind: ServiceKind::LocalTcp(messaging::new_messaging_connection),
         id: 18,
         name: "Messaging"
     },
     19u32 => ServiceInfo {
-        kind: ServiceKind::Unimplemented,
+        kind: ServiceKind::LocalTcp(team::new_team_connection),
         id: 19,
         name: "Teams",
     },
@@ -133,6 +143,16 @@ static SERVICE_INFO: phf::Map<u32, ServiceInfo> = phf::phf_map! {
 pub enum ServiceInitError {
 }
 
+/// Reply to a request that a service rejected, with the HResult as the body
+/// and in the X-Err header that the console's XOnline tasks check.
+pub fn hresult_failure_response(req: &Request, code: StatusCode, content_type: &str, hr: HResult) -> Response {
+	let mut response = Response::generate_error_response(req, code, content_type, hr.0.to_le_bytes().to_vec());
+
+	response.header.headers.insert("X-Err".to_owned(), format!("{:08x}", hr.0));
+
+	response
+}
+
 #[async_trait]
 pub trait Service {
     async fn on_tcp_packet<'a>(&mut self, header: &TcpHeader, packet: &[u8], state: &ClientState) -> Result<(), PacketProcessError>;
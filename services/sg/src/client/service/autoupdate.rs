@@ -0,0 +1,118 @@
+use log::error;
+
+use smoltcp_user_vpn::tcp::{AcceptFn, http::{Request, Method, gen_http_accept, Response, StatusCode}};
+use xombie_autoupdate::UpdateError;
+
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use xblive::service::autoupdate::*;
+
+use xbox_sys::codec::{BufPut, Decode};
+use xbox_sys::status::HResult;
+
+use crate::client::ClientState;
+use crate::client::service::hresult_failure_response;
+use crate::client::service::unimplemented::not_found_handler;
+
+pub fn new_autoupdate_connection(state: Arc<ClientState>) -> AcceptFn {
+	gen_http_accept(state, Arc::new(move |state, req: Request| async move {
+		match (req.header.method, req.header.path.as_str()) {
+			(Method::Post, "/xupdate/xupdatecheck.srf") => xupdatecheck_handler(state, req).await,
+			(Method::Post, "/xupdate/xupdatedownload.srf") => xupdatedownload_handler(state, req).await,
+			_ => not_found_handler(state, req).await,
+		}
+	}))
+}
+
+async fn xupdatecheck_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let check_request = match UpdateCheckRequest::decode(req.body_bytes()) {
+		Ok((_, check_request)) => check_request,
+		Err(err) => {
+			error!("Unable to decode update check: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_title(&state, check_request.title_id).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	let update = state.ext_services.updates.check(check_request.title_id, check_request.title_version);
+
+	let reply = UpdateCheckReply {
+		update: update.map(|update| UpdateInfo {
+			version: update.version,
+			package_size: update.package_size,
+			hash: update.hash,
+		}),
+	};
+
+	let mut body = vec![];
+	reply.put(&mut body);
+
+	Ok(Response::generate_good_response(&req, CONTENT_TYPE, body))
+}
+
+async fn xupdatedownload_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let download_request = match UpdateDownloadRequest::decode(req.body_bytes()) {
+		Ok((_, download_request)) => download_request,
+		Err(err) => {
+			error!("Unable to decode update download: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_title(&state, download_request.title_id).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	match state.ext_services.updates.read(
+		download_request.title_id,
+		download_request.version,
+		download_request.offset,
+		download_request.len,
+	).await {
+		Ok(data) => Ok(Response::generate_good_response(&req, CONTENT_TYPE, data)),
+		Err(err) => {
+			error!("Unable to read update: {:?} {:?}", err, download_request);
+			Ok(match error_hresult(&err) {
+				Some(hr) => failure_response(&req, hr),
+				None => Response::generate_internal_server_error(&req),
+			})
+		}
+	}
+}
+
+/// Consoles update the title they're running, or the dashboard.
+async fn check_title(state: &ClientState, title_id: u32) -> Result<(), HResult> {
+	if title_id == DASHBOARD_TITLE_ID {
+		return Ok(())
+	}
+
+	match state.title().await {
+		Some(title) if title.id == title_id => Ok(()),
+		_ => {
+			error!("Rejecting update request for title {:08x} from {}", title_id, state.net_name());
+			Err(E_INVALID_TITLE_ID)
+		}
+	}
+}
+
+/// The failure to report to the console, or None if it wasn't the console's
+/// fault.
+fn error_hresult(err: &UpdateError) -> Option<HResult> {
+	match err {
+		UpdateError::NoSuchUpdate => Some(E_UPDATE_NOT_FOUND),
+		UpdateError::BadRange => Some(E_INVALID_RANGE),
+		UpdateError::Io(_) => None,
+	}
+}
+
+fn failure_response(req: &Request, hr: HResult) -> Response {
+	hresult_failure_response(req, StatusCode::Forbidden403, CONTENT_TYPE, hr)
+}
+
+fn bad_request_response(req: &Request) -> Response {
+	Response::generate_error_response(req, StatusCode::BadRequest400, CONTENT_TYPE, vec![])
+}
@@ -0,0 +1,160 @@
+use log::error;
+
+use smoltcp_user_vpn::tcp::{AcceptFn, http::{Request, Method, gen_http_accept, Response, StatusCode}};
+use xombie_content::ContentError;
+
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use xblive::service::content::*;
+
+use xbox_sys::codec::{BufPut, Decode};
+use xbox_sys::status::HResult;
+
+use crate::client::ClientState;
+use crate::client::service::hresult_failure_response;
+use crate::client::service::unimplemented::not_found_handler;
+
+pub fn new_content_connection(state: Arc<ClientState>) -> AcceptFn {
+	gen_http_accept(state, Arc::new(move |state, req: Request| async move {
+		match (req.header.method, req.header.path.as_str()) {
+			(Method::Post, "/xcontent/xcontentenum.srf") => xcontentenum_handler(state, req).await,
+			(Method::Post, "/xcontent/xcontentlocation.srf") => xcontentlocation_handler(state, req).await,
+			(Method::Post, "/xcontent/xcontentdownload.srf") => xcontentdownload_handler(state, req).await,
+			_ => not_found_handler(state, req).await,
+		}
+	}))
+}
+
+async fn xcontentenum_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let enum_request = match ContentEnumerateRequest::decode(req.body_bytes()) {
+		Ok((_, enum_request)) => enum_request,
+		Err(err) => {
+			error!("Unable to decode content enumeration: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_title(&state, enum_request.title_id).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	let (total_offers, offers) = state.ext_services.content.enumerate(
+		enum_request.title_id,
+		enum_request.required_flags,
+		enum_request.start,
+		enum_request.max_results,
+	);
+
+	let reply = ContentEnumerateReply {
+		total_offers,
+		offers: offers.into_iter().map(|offer| ContentOffer {
+			offer_id: offer.offer_id,
+			flags: offer.flags,
+			package_size: offer.package_size(),
+			name: offer.name.clone(),
+		}).collect(),
+	};
+
+	let mut body = vec![];
+	reply.put(&mut body);
+
+	Ok(Response::generate_good_response(&req, CONTENT_TYPE, body))
+}
+
+async fn xcontentlocation_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let location_request = match ContentLocationRequest::decode(req.body_bytes()) {
+		Ok((_, location_request)) => location_request,
+		Err(err) => {
+			error!("Unable to decode content location: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_title(&state, location_request.title_id).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	let offer = match state.ext_services.content.get(location_request.title_id, location_request.offer_id) {
+		Some(offer) => offer,
+		None => {
+			error!("No content offer {:016x} for title {:08x}", location_request.offer_id, location_request.title_id);
+			return Ok(failure_response(&req, E_OFFER_NOT_FOUND))
+		}
+	};
+
+	let reply = ContentLocationReply {
+		offer_id: offer.offer_id,
+		files: offer.files.iter().map(|file| ContentFile {
+			name: file.name.clone(),
+			size: file.size,
+			hash: file.hash,
+		}).collect(),
+	};
+
+	let mut body = vec![];
+	reply.put(&mut body);
+
+	Ok(Response::generate_good_response(&req, CONTENT_TYPE, body))
+}
+
+async fn xcontentdownload_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let download_request = match ContentDownloadRequest::decode(req.body_bytes()) {
+		Ok((_, download_request)) => download_request,
+		Err(err) => {
+			error!("Unable to decode content download: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_title(&state, download_request.title_id).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	match state.ext_services.content.read(
+		download_request.title_id,
+		download_request.offer_id,
+		download_request.file_index,
+		download_request.offset,
+		download_request.len,
+	).await {
+		Ok(data) => Ok(Response::generate_good_response(&req, CONTENT_TYPE, data)),
+		Err(err) => {
+			error!("Unable to read content: {:?} {:?}", err, download_request);
+			Ok(match error_hresult(&err) {
+				Some(hr) => failure_response(&req, hr),
+				None => Response::generate_internal_server_error(&req),
+			})
+		}
+	}
+}
+
+/// Titles only see their own offers.
+async fn check_title(state: &ClientState, title_id: u32) -> Result<(), HResult> {
+	match state.title().await {
+		Some(title) if title.id == title_id => Ok(()),
+		_ => {
+			error!("Rejecting content request for title {:08x} from {}", title_id, state.net_name());
+			Err(E_INVALID_TITLE_ID)
+		}
+	}
+}
+
+/// The failure to report to the console, or None if it wasn't the console's
+/// fault.
+fn error_hresult(err: &ContentError) -> Option<HResult> {
+	match err {
+		ContentError::NoSuchOffer => Some(E_OFFER_NOT_FOUND),
+		ContentError::NoSuchFile => Some(E_FILE_NOT_FOUND),
+		ContentError::BadRange => Some(E_INVALID_RANGE),
+		ContentError::Io(_) => None,
+	}
+}
+
+fn failure_response(req: &Request, hr: HResult) -> Response {
+	hresult_failure_response(req, StatusCode::Forbidden403, CONTENT_TYPE, hr)
+}
+
+fn bad_request_response(req: &Request) -> Response {
+	Response::generate_error_response(req, StatusCode::BadRequest400, CONTENT_TYPE, vec![])
+}
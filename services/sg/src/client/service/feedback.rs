@@ -0,0 +1,101 @@
+use log::error;
+
+use smoltcp_user_vpn::tcp::{AcceptFn, http::{Request, Method, gen_http_accept, Response, StatusCode}};
+use xombie_feedback::FeedbackError;
+
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use xblive::service::feedback::*;
+
+use xbox_sys::account::Xuid;
+use xbox_sys::codec::Decode;
+use xbox_sys::status::HResult;
+
+use crate::client::ClientState;
+use crate::client::service::hresult_failure_response;
+use crate::client::service::unimplemented::not_found_handler;
+
+pub fn new_feedback_connection(state: Arc<ClientState>) -> AcceptFn {
+	gen_http_accept(state, Arc::new(move |state, req: Request| async move {
+		match (req.header.method, req.header.path.as_str()) {
+			(Method::Post, "/xfeedback/xfeedbacksubmit.srf") => xfeedbacksubmit_handler(state, req).await,
+			_ => not_found_handler(state, req).await,
+		}
+	}))
+}
+
+async fn xfeedbacksubmit_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let feedback_request = match FeedbackRequest::decode(req.body_bytes()) {
+		Ok((_, feedback_request)) => feedback_request,
+		Err(err) => {
+			error!("Unable to decode feedback: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_title(&state, feedback_request.title_id).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	if let Err(hr) = check_signed_in(&state, feedback_request.reporter).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	match state.ext_services.feedback.submit(
+		feedback_request.title_id,
+		feedback_request.reporter,
+		feedback_request.target,
+		feedback_request.feedback_type,
+		&feedback_request.text,
+	).await {
+		Ok(_) => Ok(Response::generate_good_response(&req, CONTENT_TYPE, vec![])),
+		Err(err) => {
+			error!("Unable to submit feedback: {:?} {:?}", err, feedback_request);
+			Ok(match error_hresult(&err) {
+				Some(hr) => failure_response(&req, hr),
+				None => Response::generate_internal_server_error(&req),
+			})
+		}
+	}
+}
+
+/// Feedback is filed from within the title being played.
+async fn check_title(state: &ClientState, title_id: u32) -> Result<(), HResult> {
+	match state.title().await {
+		Some(title) if title.id == title_id => Ok(()),
+		_ => {
+			error!("Rejecting feedback for title {:08x} from {}", title_id, state.net_name());
+			Err(E_INVALID_TITLE_ID)
+		}
+	}
+}
+
+/// Feedback is only filed by users signed in on the console.
+async fn check_signed_in(state: &ClientState, user: Xuid) -> Result<(), HResult> {
+	if state.users().await.slot_of(user).is_some() {
+		return Ok(())
+	}
+
+	error!("Rejecting feedback from {:x?}, who isn't signed in to {}", user, state.net_name());
+	Err(E_INVALID_USER)
+}
+
+/// The failure to report to the console, or None if it wasn't the console's
+/// fault.
+fn error_hresult(err: &FeedbackError) -> Option<HResult> {
+	match err {
+		FeedbackError::BadTarget => Some(E_INVALID_TARGET),
+		FeedbackError::UnknownType(_) => Some(E_INVALID_FEEDBACK_TYPE),
+		FeedbackError::TextTooLong => Some(E_TEXT_TOO_LONG),
+		FeedbackError::Store(_) => None,
+	}
+}
+
+fn failure_response(req: &Request, hr: HResult) -> Response {
+	hresult_failure_response(req, StatusCode::Forbidden403, CONTENT_TYPE, hr)
+}
+
+fn bad_request_response(req: &Request) -> Response {
+	Response::generate_error_response(req, StatusCode::BadRequest400, CONTENT_TYPE, vec![])
+}
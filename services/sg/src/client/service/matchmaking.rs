@@ -1,17 +1,19 @@
-use log::error;
+use log::{debug, error};
 
-use smoltcp_user_vpn::tcp::{AcceptFn, http::{Request, Method, gen_http_accept, Response}};
-use xombie_matchmaking::{Users, Title};
+use smoltcp_user_vpn::tcp::{AcceptFn, http::{Request, Method, gen_http_accept, Response, StatusCode}};
+use xombie_matchmaking::{SessionDeleteError, SessionUpdateError, Slots, Users, Title};
 
 use std::convert::Infallible;
 use std::sync::Arc;
 
-use xblive::{crypto::primitives::KeyId, ver::LibraryVersion};
+use xblive::crypto::primitives::KeyId;
 use xblive::service::matchmaking::*;
 
-use xbox_sys::{codec::{BufPut, Decode}, account::Xuid};
+use xbox_sys::codec::{BufPut, Decode};
+use xbox_sys::status::HResult;
 
 use crate::client::ClientState;
+use crate::client::service::hresult_failure_response;
 use crate::client::service::unimplemented::not_found_handler;
 
 pub fn new_matchmaking_connection(state: Arc<ClientState>) -> AcceptFn {
@@ -19,56 +21,61 @@ pub fn new_matchmaking_connection(state: Arc<ClientState>) -> AcceptFn {
 		match (req.header.method, req.header.path.as_str()) {
 			(Method::Post, "/xmatch/xmatchclient.srf") => xmatchclient_handler(state, req).await,
 			(Method::Post, "/xmatch/xmatchhost.srf") => xmatchhost_handler(state, req).await,
+			(Method::Post, "/xmatch/xmatchhostdelete.srf") => xmatchhostdelete_handler(state, req).await,
 			_ => not_found_handler(state, req).await,
 		}
 	}))
 }
 
-async fn xmatchclient_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
-	let (_, search_request) = Search::decode(req.body_bytes()).unwrap();
-
-	error!("TODO: Get users out of as ticket");
-	let users = Users {
-		machine: Xuid(0),
-		user: vec![Xuid(0)],
-	};
+async fn sg_users(state: &ClientState) -> Users {
+	let users = state.users().await;
 
-	error!("TODO: Validate title_id is same as sg connection");
-	let title_id = search_request.header.title_id;
+	Users {
+		machine: users.machine,
+		user: users.signed_in(),
+	}
+}
 
-	error!("TODO: Get title_version out as ticket");
-	let title_version = LibraryVersion {
-		major: 0,
-		minor: 0,
-		build: 0,
-		qfe: 0,
+async fn xmatchclient_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let search_request = match Search::decode(req.body_bytes()) {
+		Ok((_, search_request)) => search_request,
+		Err(err) => {
+			error!("Unable to decode session search: {:?}", err);
+			return Ok(failure_response(&req, HResult::E_INVALIDARG))
+		}
 	};
 
-	let title = Title {
-		id: title_id,
-		ver: title_version,
-	};
+	let users = sg_users(&state).await;
 
-	let results = match (title_id, search_request.header.procedure_index) {
-		(0x4C41000B, 1) => {
-			state.ext_services.matchmaking.search_for_sessions(
-				users,
-				title,
-				search_request.header.num_users,
-				search_request.header.flags,
-				10,
-				search_request.attributes.as_slice()
-			).await.unwrap()
+	let title = match connection_title(&state, search_request.header.title_id).await {
+		Ok(title) => title,
+		Err(hr) => {
+			error!("Rejecting search for title {:08x}: {:?}", search_request.header.title_id, hr);
+			return Ok(failure_response(&req, hr))
 		}
-		_ => {
-			error!("Unknown search request title_id, procedure_index tuple {:?}", search_request);
+	};
+
+	let search = match state.ext_services.matchmaking.search_for_sessions(
+		users,
+		state.xnaddr().await,
+		title,
+		search_request.header.procedure_index,
+		search_request.header.num_users,
+		// Invited players join through their invitation rather than a
+		// search, so searches only ever look for public slots.
+		Slots::Public,
+		search_request.attributes.as_slice()
+	).await {
+		Ok(search) => search,
+		Err(err) => {
+			error!("Unable to search for sessions: {:?} {:?}", err, search_request);
 			return Ok(Response::generate_internal_server_error(&req))
 		}
 	};
 
-	println!("search results: {:?}", results);
+	debug!("search results: {:?}", search);
 
-	let results: Vec<_> = results.iter().map(|result| {
+	let results: Vec<_> = search.results.iter().map(|result| {
 		SearchResult::generate(
 			result.session_id,
 			result.host_address,
@@ -81,7 +88,7 @@ async fn xmatchclient_handler(state: Arc<ClientState>, req: Request) -> Result<R
 		)
 	}).collect();
 
-	let results = SearchResults::generate(0, 0, results);
+	let results = SearchResults::generate(0, search.logging_threshold, results);
 
 	let mut body = vec![];
 	results.put(&mut body);
@@ -90,36 +97,35 @@ async fn xmatchclient_handler(state: Arc<ClientState>, req: Request) -> Result<R
 }
 
 async fn xmatchhost_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
-	let (_, session_request) = Session::decode(req.body_bytes()).unwrap();
-
-	error!("TODO: Get users out of as ticket");
-	let users = Users {
-		machine: Xuid(0),
-		user: vec![Xuid(0)],
+	let session_request = match Session::decode(req.body_bytes()) {
+		Ok((_, session_request)) => session_request,
+		Err(err) => {
+			error!("Unable to decode session: {:?}", err);
+			return Ok(failure_response(&req, HResult::E_INVALIDARG))
+		}
 	};
 
-	error!("TODO: Validate title_id is same as sg connection");
-	let title_id = session_request.header.title_id;
-
-	error!("TODO: Get title_version out as ticket");
-	let title_version = LibraryVersion {
-		major: 0,
-		minor: 0,
-		build: 0,
-		qfe: 0,
-	};
+	let users = sg_users(&state).await;
 
-	let title = Title {
-		id: title_id,
-		ver: title_version,
+	let title = match connection_title(&state, session_request.header.title_id).await {
+		Ok(title) => title,
+		Err(hr) => {
+			error!("Rejecting session for title {:08x}: {:?}", session_request.header.title_id, hr);
+			return Ok(failure_response(&req, hr))
+		}
 	};
 
-	error!("TOOD: validate host_address is same as sg connection");
 	let host_address = session_request.header.host_address;
 
+	if !state.is_own_xnaddr(&host_address).await {
+		error!("Rejecting session with host address {:x?} from {}", host_address, state.net_name());
+		return Ok(failure_response(&req, HResult::E_INVALIDARG))
+	}
+
 	let session_info = if session_request.header.session_id == KeyId::INVALID {
 		let created_session = match state.ext_services.matchmaking.create_session(
 			users,
+			state.connection(),
 			title,
 			host_address,
 			session_request.header.public_open,
@@ -142,6 +148,7 @@ async fn xmatchhost_handler(state: Arc<ClientState>, req: Request) -> Result<Res
 	} else {
 		let session_info = match state.ext_services.matchmaking.update_session(
 			users,
+			state.connection(),
 			title,
 			session_request.header.session_id,
 			host_address,
@@ -154,7 +161,10 @@ async fn xmatchhost_handler(state: Arc<ClientState>, req: Request) -> Result<Res
 			Ok(created_session) => created_session,
 			Err(err) => {
 				error!("Unable to update matchmaking session: {:?} {:?}", err, session_request);
-				return Ok(Response::generate_internal_server_error(&req))
+				return Ok(match update_error_hresult(&err) {
+					Some(hr) => failure_response(&req, hr),
+					None => Response::generate_internal_server_error(&req),
+				})
 			}
 		};
 
@@ -169,3 +179,69 @@ async fn xmatchhost_handler(state: Arc<ClientState>, req: Request) -> Result<Res
 
 	Ok(Response::generate_good_response(&req, CONTENT_TYPE, body))
 }
+
+async fn xmatchhostdelete_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let delete_request = match SessionDelete::decode(req.body_bytes()) {
+		Ok((_, delete_request)) => delete_request,
+		Err(err) => {
+			error!("Unable to decode session delete: {:?}", err);
+			return Ok(failure_response(&req, HResult::E_INVALIDARG))
+		}
+	};
+
+	let users = sg_users(&state).await;
+
+	let title = match connection_title(&state, delete_request.title_id).await {
+		Ok(title) => title,
+		Err(hr) => {
+			error!("Rejecting session delete for title {:08x}: {:?}", delete_request.title_id, hr);
+			return Ok(failure_response(&req, hr))
+		}
+	};
+
+	if let Err(err) = state.ext_services.matchmaking.close_session(users, title, delete_request.session_id).await {
+		error!("Unable to delete matchmaking session: {:?} {:?}", err, delete_request);
+		return Ok(match delete_error_hresult(&err) {
+			Some(hr) => failure_response(&req, hr),
+			None => Response::generate_internal_server_error(&req),
+		})
+	}
+
+	Ok(Response::generate_good_response(&req, CONTENT_TYPE, vec![]))
+}
+
+/// The title the connection is authenticated for, as long as it's the title
+/// the console is asking about.  Sessions are only ever matched against the
+/// title version the connection is running, so requests can't reach sessions
+/// for other versions of the same title.
+async fn connection_title(state: &ClientState, title_id: u32) -> Result<Title, HResult> {
+	match state.title().await {
+		Some(title) if title.id == title_id => Ok(title),
+		_ => Err(HResult::E_ACCESSDENIED),
+	}
+}
+
+/// The failure to report to the console, or None if it wasn't the console's
+/// fault.
+fn update_error_hresult(err: &SessionUpdateError) -> Option<HResult> {
+	match err {
+		SessionUpdateError::SessionIdNotFound => Some(HResult::E_INVALIDARG),
+		SessionUpdateError::TitleMismatch => Some(HResult::E_ACCESSDENIED),
+		SessionUpdateError::HostAddressMismatch => Some(HResult::E_INVALIDARG),
+		SessionUpdateError::UserMismatch => Some(HResult::E_ACCESSDENIED),
+		SessionUpdateError::Store(_) => None,
+	}
+}
+
+fn delete_error_hresult(err: &SessionDeleteError) -> Option<HResult> {
+	match err {
+		SessionDeleteError::SessionIdNotFound => Some(HResult::E_INVALIDARG),
+		SessionDeleteError::TitleMismatch => Some(HResult::E_ACCESSDENIED),
+		SessionDeleteError::UserMismatch => Some(HResult::E_ACCESSDENIED),
+		SessionDeleteError::Store(_) => None,
+	}
+}
+
+fn failure_response(req: &Request, hr: HResult) -> Response {
+	hresult_failure_response(req, StatusCode::Forbidden403, CONTENT_TYPE, hr)
+}
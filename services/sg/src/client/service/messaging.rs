@@ -0,0 +1,314 @@
+use log::error;
+
+use smoltcp_user_vpn::tcp::{AcceptFn, http::{Request, Method, gen_http_accept, Response, StatusCode}};
+use xombie_messaging::{Delivery, Message, MessagingError, NewMessage, Refusal};
+
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use xblive::service::autoupdate::DASHBOARD_TITLE_ID;
+use xblive::service::messaging::*;
+use xblive::sg::control::PulseEventKind;
+
+use xbox_sys::account::Xuid;
+use xbox_sys::codec::{BufPut, Decode};
+use xbox_sys::status::HResult;
+
+use crate::client::ClientState;
+use crate::client::service::hresult_failure_response;
+use crate::client::service::presence::notify_user;
+use crate::client::service::unimplemented::not_found_handler;
+
+pub fn new_messaging_connection(state: Arc<ClientState>) -> AcceptFn {
+	gen_http_accept(state, Arc::new(move |state, req: Request| async move {
+		match (req.header.method, req.header.path.as_str()) {
+			(Method::Post, "/xmsg/xmsgsend.srf") => xmsgsend_handler(state, req).await,
+			(Method::Post, "/xmsg/xmsgenum.srf") => xmsgenum_handler(state, req).await,
+			(Method::Post, "/xmsg/xmsgdetails.srf") => xmsgdetails_handler(state, req).await,
+			(Method::Post, "/xmsg/xmsgattachment.srf") => xmsgattachment_handler(state, req).await,
+			(Method::Post, "/xmsg/xmsgmarkread.srf") => xmsgmarkread_handler(state, req).await,
+			(Method::Post, "/xmsg/xmsgdelete.srf") => xmsgdelete_handler(state, req).await,
+			_ => not_found_handler(state, req).await,
+		}
+	}))
+}
+
+async fn xmsgsend_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let send_request = match SendMessageRequest::decode(req.body_bytes()) {
+		Ok((_, send_request)) => send_request,
+		Err(err) => {
+			error!("Unable to decode message: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_title(&state, send_request.title_id).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	if let Err(hr) = check_signed_in(&state, send_request.sender).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	let message = NewMessage {
+		title_id: send_request.title_id,
+		sender: send_request.sender,
+		flags: send_request.flags,
+		ttl: match send_request.expire_secs {
+			0 => None,
+			expire_secs => Some(Duration::from_secs(expire_secs as u64)),
+		},
+		text: send_request.text.clone(),
+		attachment: send_request.attachment.clone(),
+	};
+
+	let deliveries = match state.ext_services.messaging.send(&state.ext_services.presence, &message, &send_request.recipients).await {
+		Ok(deliveries) => deliveries,
+		Err(err) => {
+			error!("Unable to send message from {:x?}: {:?}", send_request.sender, err);
+			return Ok(error_response(&req, &err))
+		}
+	};
+
+	for delivery in &deliveries {
+		if let Ok(message_id) = delivery.result {
+			notify_message(&state, delivery.recipient, message_id, send_request.sender).await;
+		}
+	}
+
+	let reply = SendMessageReply {
+		results: deliveries.iter().map(recipient_result).collect(),
+	};
+
+	let mut body = vec![];
+	reply.put(&mut body);
+
+	Ok(Response::generate_good_response(&req, CONTENT_TYPE, body))
+}
+
+async fn xmsgenum_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let enum_request = match EnumerateMessagesRequest::decode(req.body_bytes()) {
+		Ok((_, enum_request)) => enum_request,
+		Err(err) => {
+			error!("Unable to decode message enumeration: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_title(&state, enum_request.title_id).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	if let Err(hr) = check_signed_in(&state, enum_request.user).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	let messages = match state.ext_services.messaging.inbox(enum_request.user).await {
+		Ok(messages) => messages,
+		Err(err) => {
+			error!("Unable to enumerate messages of {:x?}: {:?}", enum_request.user, err);
+			return Ok(error_response(&req, &err))
+		}
+	};
+
+	let reply = EnumerateMessagesReply {
+		messages: messages.iter().map(message_summary).collect(),
+	};
+
+	let mut body = vec![];
+	reply.put(&mut body);
+
+	Ok(Response::generate_good_response(&req, CONTENT_TYPE, body))
+}
+
+async fn xmsgdetails_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let message_request = match decode_message_request(&state, &req).await {
+		Ok(message_request) => message_request,
+		Err(response) => return Ok(response),
+	};
+
+	let message = match state.ext_services.messaging.get(message_request.user, message_request.message_id).await {
+		Ok(message) => message,
+		Err(err) => {
+			error!("Unable to get message {:?}: {:?}", message_request, err);
+			return Ok(error_response(&req, &err))
+		}
+	};
+
+	let reply = MessageDetailsReply {
+		summary: message_summary(&message),
+		text: message.text,
+	};
+
+	let mut body = vec![];
+	reply.put(&mut body);
+
+	Ok(Response::generate_good_response(&req, CONTENT_TYPE, body))
+}
+
+async fn xmsgattachment_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let message_request = match decode_message_request(&state, &req).await {
+		Ok(message_request) => message_request,
+		Err(response) => return Ok(response),
+	};
+
+	match state.ext_services.messaging.attachment(message_request.user, message_request.message_id).await {
+		Ok(attachment) => Ok(Response::generate_good_response(&req, CONTENT_TYPE, attachment)),
+		Err(err) => {
+			error!("Unable to get attachment {:?}: {:?}", message_request, err);
+			Ok(error_response(&req, &err))
+		}
+	}
+}
+
+async fn xmsgmarkread_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let message_request = match decode_message_request(&state, &req).await {
+		Ok(message_request) => message_request,
+		Err(response) => return Ok(response),
+	};
+
+	match state.ext_services.messaging.mark_read(message_request.user, message_request.message_id).await {
+		Ok(()) => Ok(Response::generate_good_response(&req, CONTENT_TYPE, vec![])),
+		Err(err) => {
+			error!("Unable to mark message read {:?}: {:?}", message_request, err);
+			Ok(error_response(&req, &err))
+		}
+	}
+}
+
+async fn xmsgdelete_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let message_request = match decode_message_request(&state, &req).await {
+		Ok(message_request) => message_request,
+		Err(response) => return Ok(response),
+	};
+
+	match state.ext_services.messaging.delete(message_request.user, message_request.message_id).await {
+		Ok(()) => Ok(Response::generate_good_response(&req, CONTENT_TYPE, vec![])),
+		Err(err) => {
+			error!("Unable to delete message {:?}: {:?}", message_request, err);
+			Ok(error_response(&req, &err))
+		}
+	}
+}
+
+/// Decode a request about one message in a user's inbox and check the user
+/// can make it, or the response to send back if not.
+async fn decode_message_request(state: &ClientState, req: &Request) -> Result<MessageRequest, Response> {
+	let message_request = match MessageRequest::decode(req.body_bytes()) {
+		Ok((_, message_request)) => message_request,
+		Err(err) => {
+			error!("Unable to decode message request: {:?}", err);
+			return Err(bad_request_response(req))
+		}
+	};
+
+	check_title(state, message_request.title_id).await
+		.map_err(|hr| failure_response(req, hr))?;
+
+	check_signed_in(state, message_request.user).await
+		.map_err(|hr| failure_response(req, hr))?;
+
+	Ok(message_request)
+}
+
+/// Let recipient's console know a message arrived, if they're online.
+async fn notify_message(state: &ClientState, recipient: Xuid, message_id: u64, sender: Xuid) {
+	let notification = MessageNotification {
+		message_id,
+		sender,
+	};
+
+	let mut data = vec![];
+	notification.put(&mut data);
+
+	notify_user(state, recipient, PulseEventKind::MESSAGE, data, None).await;
+}
+
+/// Messages are sent and read from within the title being played, or from
+/// the dashboard.
+async fn check_title(state: &ClientState, title_id: u32) -> Result<(), HResult> {
+	if title_id == DASHBOARD_TITLE_ID {
+		return Ok(())
+	}
+
+	match state.title().await {
+		Some(title) if title.id == title_id => Ok(()),
+		_ => {
+			error!("Rejecting messages for title {:08x} from {}", title_id, state.net_name());
+			Err(E_INVALID_TITLE_ID)
+		}
+	}
+}
+
+/// Users can only send messages as, and read the inbox of, a user signed in
+/// on the console.
+async fn check_signed_in(state: &ClientState, user: Xuid) -> Result<(), HResult> {
+	if state.users().await.slot_of(user).is_some() {
+		return Ok(())
+	}
+
+	error!("Rejecting messages for {:x?}, who isn't signed in to {}", user, state.net_name());
+	Err(E_INVALID_USER)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+	time.duration_since(SystemTime::UNIX_EPOCH)
+		.map(|since_epoch| since_epoch.as_secs())
+		.unwrap_or(0)
+}
+
+fn message_summary(message: &Message) -> MessageSummary {
+	MessageSummary {
+		message_id: message.id,
+		sender: message.sender,
+		title_id: message.title_id,
+		flags: message.flags,
+		sent: unix_secs(message.sent),
+		expires: unix_secs(message.expires),
+		attachment_len: message.attachment_len,
+	}
+}
+
+fn recipient_result(delivery: &Delivery) -> RecipientResult {
+	let (hr, message_id) = match delivery.result {
+		Ok(message_id) => (HResult::SUCCESS, message_id),
+		Err(Refusal::OwnAccount) => (E_INVALID_RECIPIENT, 0),
+		Err(Refusal::Blocked) => (E_BLOCKED, 0),
+		Err(Refusal::NotBuddies) => (E_NOT_BUDDIES, 0),
+		Err(Refusal::InboxFull) => (E_INBOX_FULL, 0),
+	};
+
+	RecipientResult {
+		recipient: delivery.recipient,
+		hr,
+		message_id,
+	}
+}
+
+/// The failure to report to the console, or None if it wasn't the console's
+/// fault.
+fn error_hresult(err: &MessagingError) -> Option<HResult> {
+	match err {
+		MessagingError::TooManyRecipients => Some(E_TOO_MANY_RECIPIENTS),
+		MessagingError::TextTooLong => Some(E_TEXT_TOO_LONG),
+		MessagingError::AttachmentTooLarge => Some(E_ATTACHMENT_TOO_LARGE),
+		MessagingError::NoSuchMessage => Some(E_MESSAGE_NOT_FOUND),
+		MessagingError::Store(_) | MessagingError::Presence(_) => None,
+	}
+}
+
+fn error_response(req: &Request, err: &MessagingError) -> Response {
+	match error_hresult(err) {
+		Some(hr) => failure_response(req, hr),
+		None => Response::generate_internal_server_error(req),
+	}
+}
+
+fn failure_response(req: &Request, hr: HResult) -> Response {
+	hresult_failure_response(req, StatusCode::Forbidden403, CONTENT_TYPE, hr)
+}
+
+fn bad_request_response(req: &Request) -> Response {
+	Response::generate_error_response(req, StatusCode::BadRequest400, CONTENT_TYPE, vec![])
+}
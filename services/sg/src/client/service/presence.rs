@@ -1,17 +1,24 @@
-use log::debug;
+use log::{debug, error};
 
-use std::{convert::Infallible, collections::BTreeMap, time::Duration};
+use std::{convert::Infallible, collections::BTreeMap};
 
 use smoltcp_user_vpn::tcp::{AcceptFn, http::{gen_http_accept, Method, Request, Response, ResponseHeader, StatusCode}};
 
 use std::sync::Arc;
 
 use xblive::service::presence::*;
+use xblive::sg::control::{PulseEvent, PulseEventKind};
 
 use xbox_sys::codec::{BufPut, Decode};
 use xbox_sys::status::HResult;
 
+use xbox_sys::account::Xuid;
+
+use xombie_matchmaking::Title;
+use xombie_presence::{ListError, BuddyStatus, ListVersions, PresenceUpdate};
+
 use crate::client::ClientState;
+use crate::notify::Receipt;
 
 use super::unimplemented::not_found_handler;
 
@@ -27,28 +34,105 @@ pub fn new_presence_connection(state: Arc<ClientState>) -> AcceptFn {
 }
 
 async fn xpresence_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
-	let (_, message_req) = Message::decode(req.body_bytes()).unwrap();
+	let message_req = match Message::decode(req.body_bytes()) {
+		Ok((_, message_req)) if message_req.kind.is_request() => message_req,
+		Ok((_, message_req)) => {
+			error!("Presence reply {} sent as a request by {}", message_req.header.msg_type, state.net_name());
+			return Ok(bad_request_response(&req))
+		}
+		Err(err) => {
+			error!("Unable to decode presence message from {}: {:?}", state.net_name(), err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
 	debug!("TODO: Check Content-Type");
 	debug!("TODO: Check User-Agent");
 
-	tokio::time::sleep(Duration::from_secs(1))
-		.await;
-
 	use MessageKind::*;
 	let message_reply_kind = match message_req.kind {
-		Alive{body, ref acct_name} =>
-			xpresence_alive_handler(state, &message_req.header, &body, &acct_name)
-				.await,
+		Alive{body, ref acct_name, ref nickname, ref title_stuff} =>
+			Some(xpresence_alive_handler(state, &message_req.header, &body, &acct_name, nickname, title_stuff)
+				.await),
 		Alive2{body, ref acct_name} =>
-			xpresence_alive2_handler(state, &message_req.header, &body, &acct_name)
-				.await,
-		_ => todo!("Fail on unknown message types {:x?}", message_req)
+			Some(xpresence_alive2_handler(state, &message_req.header, &body, &acct_name)
+				.await),
+		DeadUser(body) => {
+			xpresence_dead_user_handler(state, &message_req.header, &body)
+				.await;
+			None
+		}
+		ListSync(body) =>
+			Some(xpresence_list_sync_handler(state, &message_req.header, &body)
+				.await),
+		Add(body) => {
+			xpresence_add_handler(state, &message_req.header, &body)
+				.await;
+			None
+		}
+		Accept(body) => {
+			xpresence_accept_handler(state, &message_req.header, &body)
+				.await;
+			None
+		}
+		Reject(body) => {
+			xpresence_reject_handler(state, &message_req.header, &body)
+				.await;
+			None
+		}
+		Delete(body) => {
+			xpresence_delete_handler(state, &message_req.header, &body)
+				.await;
+			None
+		}
+		Block(body) => {
+			xpresence_block_handler(state, &message_req.header, &body)
+				.await;
+			None
+		}
+		Unblock(body) => {
+			xpresence_unblock_handler(state, &message_req.header, &body)
+				.await;
+			None
+		}
+		Invite{body, ref invitees} => {
+			xpresence_invite_handler(state, &message_req.header, &body, invitees)
+				.await;
+			None
+		}
+		Cancel{body, ref invitees} => {
+			xpresence_cancel_handler(state, &message_req.header, &body, invitees)
+				.await;
+			None
+		}
+		InviteAnswer(body) => {
+			xpresence_invite_answer_handler(state, &message_req.header, &body)
+				.await;
+			None
+		}
+		Peek(body) =>
+			Some(xpresence_peek_handler(state, &message_req.header, &body)
+				.await),
+		Unknown{msg_type, ref body} => {
+			error!("Unhandled presence message type {} from {}: {:02x?}", msg_type, state.net_name(), body);
+			None
+		}
+		// Replies are turned away before getting here.
+		AliveReply{..} | PeekReply{..} | Failure{..} => None,
 	};
 
-	let message_reply = Message::reply_from_kind_and_req(message_reply_kind, &message_req);
-
 	let mut reply_body = vec![];
-	message_reply.put(&mut reply_body);
+	match message_reply_kind {
+		Some(message_reply_kind) => {
+			let message_reply = Message::reply_from_kind_and_req(message_reply_kind, &message_req);
+			message_reply.put(&mut reply_body);
+		}
+		None if message_req.kind.expects_reply() => {
+			let message_reply = Message::failure_reply_to_req(HResult::E_NOTIMPL, &message_req);
+			message_reply.put(&mut reply_body);
+		}
+		None => {}
+	}
 
 	let mut headers = BTreeMap::new();
 	headers.insert("Content-Type".to_owned(), PRESENCE_CONTENT_TYPE.to_owned());
@@ -65,26 +149,434 @@ async fn xpresence_handler(state: Arc<ClientState>, req: Request) -> Result<Resp
 	})
 }
 
-async fn xpresence_alive_handler(_state: Arc<ClientState>, _header: &Header, _body: &Alive, _acct_name: &str) -> MessageKind {
-	debug!("TODO: Actually do some work here");
+fn bad_request_response(req: &Request) -> Response {
+	Response::generate_error_response(req, StatusCode::BadRequest400, PRESENCE_CONTENT_TYPE, vec![])
+}
 
-	MessageKind::AliveReply(AliveReply {
-		hr: HResult::SUCCESS,
-		buddy_list_version: 0,
-		buddies_sent: 0,
-		block_list_version: 0,
-		blocks_sent: 0,
-	})
+async fn xpresence_alive_handler(state: Arc<ClientState>, _header: &Header, body: &Alive, _acct_name: &str, nickname: &[u8], title_stuff: &[u8]) -> MessageKind {
+	let slot = match state.sign_in_user(body.user_id).await {
+		Some(slot) => slot,
+		None => return alive_reply(HResult::E_ACCESSDENIED, ListVersions::default(), vec![], vec![]),
+	};
+
+	let update = PresenceUpdate::Activity {
+		title_id: body.title_id,
+		state: body.state,
+		match_session_id: body.match_session_id,
+		nickname: nickname.to_vec(),
+		title_stuff: title_stuff.to_vec(),
+	};
+
+	let console_versions = ListVersions {
+		buddy_list: body.buddy_list_version,
+		block_list: body.block_list_version,
+	};
+
+	record_alive(&state, slot, body.user_id, console_versions, update).await
 }
 
-async fn xpresence_alive2_handler(_state: Arc<ClientState>, _header: &Header, _body: &Alive2, _acct_name: &str) -> MessageKind {
-	debug!("TODO: Actually do some work here");
+async fn xpresence_alive2_handler(state: Arc<ClientState>, _header: &Header, body: &Alive2, _acct_name: &str) -> MessageKind {
+	// Once the connection has a title, from its ticket or an earlier
+	// Alive2, an Alive2 claiming anything else isn't to be believed.
+	let title = match state.claim_title(Title { id: body.title_id, ver: body.title_version }).await {
+		Some(title) => title,
+		None => {
+			error!("Alive2 for title {:08x} on a connection already running another title", body.title_id);
+			return alive_reply(HResult::E_ACCESSDENIED, ListVersions::default(), vec![], vec![])
+		}
+	};
+
+	if !state.update_xnaddr(body.xnaddr).await {
+		return alive_reply(HResult::E_INVALIDARG, ListVersions::default(), vec![], vec![])
+	}
 
-	MessageKind::AliveReply(AliveReply {
-		hr: HResult::SUCCESS,
-		buddy_list_version: 0,
-		buddies_sent: 0,
-		block_list_version: 0,
-		blocks_sent: 0,
-	})
+	let slot = match state.sign_in_user(body.user_id).await {
+		Some(slot) => slot,
+		None => return alive_reply(HResult::E_ACCESSDENIED, ListVersions::default(), vec![], vec![]),
+	};
+
+	let update = PresenceUpdate::Connection {
+		title_id: title.id,
+		title_version: title.ver,
+		xnaddr: body.xnaddr,
+		xnkid: body.xnkid,
+	};
+
+	let console_versions = ListVersions {
+		buddy_list: body.buddy_list_version,
+		block_list: body.block_list_version,
+	};
+
+	record_alive(&state, slot, body.user_id, console_versions, update).await
+}
+
+/// Store what an Alive or Alive2 reported, and build the reply telling the
+/// console which versions of the user's lists are current.  Each list itself
+/// is only sent when the console's copy is out of date.
+async fn record_alive(state: &ClientState, slot: usize, user: Xuid, console_versions: ListVersions, update: PresenceUpdate) -> MessageKind {
+	let machine = state.users().await.machine;
+	let presence = &state.ext_services.presence;
+
+	let versions = match presence.user_alive(machine, state.connection(), Some(slot as u8), user, update).await {
+		Ok(versions) => versions,
+		Err(err) => {
+			error!("Unable to record presence for {:x?}: {:?}", user, err);
+			return alive_reply(HResult::E_FAIL, ListVersions::default(), vec![], vec![])
+		}
+	};
+
+	// Anything sent while the user was offline can be passed on now.
+	deliver_invitations(state, user).await;
+
+	list_reply(state, user, versions, Some(console_versions)).await
+}
+
+async fn xpresence_list_sync_handler(state: Arc<ClientState>, _header: &Header, body: &ListSync) -> MessageKind {
+	if !is_signed_in(&state, body.user_id).await {
+		return alive_reply(HResult::E_FAIL, ListVersions::default(), vec![], vec![])
+	}
+
+	match state.ext_services.presence.list_versions(body.user_id).await {
+		Ok(versions) => list_reply(&state, body.user_id, versions, None).await,
+		Err(err) => {
+			error!("Unable to get list versions for {:x?}: {:?}", body.user_id, err);
+			alive_reply(HResult::E_FAIL, ListVersions::default(), vec![], vec![])
+		}
+	}
+}
+
+/// An AliveReply carrying each of the user's lists that differs from the
+/// console's copy, or all of them if the console's versions aren't known.
+async fn list_reply(state: &ClientState, user: Xuid, versions: ListVersions, console_versions: Option<ListVersions>) -> MessageKind {
+	let presence = &state.ext_services.presence;
+
+	let send_buddies = console_versions.map(|console| console.buddy_list) != Some(versions.buddy_list);
+	let send_blocks = console_versions.map(|console| console.block_list) != Some(versions.block_list);
+
+	let buddies = if send_buddies {
+		match presence.buddies(user).await {
+			Ok(buddies) => buddies,
+			Err(err) => {
+				error!("Unable to get buddy list for {:x?}: {:?}", user, err);
+				return alive_reply(HResult::E_FAIL, ListVersions::default(), vec![], vec![])
+			}
+		}
+	} else {
+		vec![]
+	};
+
+	let blocks = if send_blocks {
+		match presence.blocks(user).await {
+			Ok(blocks) => blocks,
+			Err(err) => {
+				error!("Unable to get block list for {:x?}: {:?}", user, err);
+				return alive_reply(HResult::E_FAIL, ListVersions::default(), vec![], vec![])
+			}
+		}
+	} else {
+		vec![]
+	};
+
+	let buddies = buddies.into_iter()
+		.map(|buddy| ReplyBuddy {
+			buddy_id: buddy.xuid,
+			status: buddy.status.code(),
+			acct_name: buddy.gamertag,
+		})
+		.collect();
+
+	alive_reply(HResult::SUCCESS, versions, buddies, blocks)
+}
+
+fn alive_reply(hr: HResult, versions: ListVersions, buddies: Vec<ReplyBuddy>, blocks: Vec<Xuid>) -> MessageKind {
+	MessageKind::AliveReply {
+		body: AliveReply {
+			hr,
+			buddy_list_version: versions.buddy_list,
+			buddies_sent: buddies.len() as u16,
+			block_list_version: versions.block_list,
+			blocks_sent: blocks.len() as u16,
+		},
+		buddies,
+		blocks,
+	}
+}
+
+async fn xpresence_add_handler(state: Arc<ClientState>, _header: &Header, body: &BuddyRequest) {
+	if !is_signed_in(&state, body.user_id).await {
+		return
+	}
+
+	match state.ext_services.presence.add_buddy(body.user_id, body.buddy_id).await {
+		Ok(BuddyStatus::Pending) => notify_buddy_request(&state, body.user_id, body.buddy_id).await,
+		Ok(_) => {}
+		Err(err) => log_buddy_error("add", body, &err),
+	}
+}
+
+async fn xpresence_accept_handler(state: Arc<ClientState>, _header: &Header, body: &BuddyRequest) {
+	if !is_signed_in(&state, body.user_id).await {
+		return
+	}
+
+	if let Err(err) = state.ext_services.presence.accept_buddy(body.user_id, body.buddy_id).await {
+		log_buddy_error("accept", body, &err);
+	}
+}
+
+async fn xpresence_reject_handler(state: Arc<ClientState>, _header: &Header, body: &BuddyRequest) {
+	if !is_signed_in(&state, body.user_id).await {
+		return
+	}
+
+	if let Err(err) = state.ext_services.presence.reject_buddy(body.user_id, body.buddy_id).await {
+		log_buddy_error("reject", body, &err);
+	}
+}
+
+async fn xpresence_delete_handler(state: Arc<ClientState>, _header: &Header, body: &BuddyRequest) {
+	if !is_signed_in(&state, body.user_id).await {
+		return
+	}
+
+	match state.ext_services.presence.delete_buddy(body.user_id, body.buddy_id).await {
+		Ok(true) => {}
+		Ok(false) => debug!("{:x?} was not on the buddy list of {:x?}", body.buddy_id, body.user_id),
+		Err(err) => error!("Unable to delete buddy {:x?} of {:x?}: {:?}", body.buddy_id, body.user_id, err),
+	}
+}
+
+async fn xpresence_block_handler(state: Arc<ClientState>, _header: &Header, body: &BlockRequest) {
+	if !is_signed_in(&state, body.user_id).await {
+		return
+	}
+
+	match state.ext_services.presence.block_user(body.user_id, body.blocked_id).await {
+		Ok(_) => {}
+		Err(ListError::Store(err)) =>
+			error!("Unable to block {:x?} for {:x?}: {:?}", body.blocked_id, body.user_id, err),
+		Err(err) =>
+			debug!("Refusing to block {:x?} for {:x?}: {:?}", body.blocked_id, body.user_id, err),
+	}
+}
+
+async fn xpresence_unblock_handler(state: Arc<ClientState>, _header: &Header, body: &BlockRequest) {
+	if !is_signed_in(&state, body.user_id).await {
+		return
+	}
+
+	match state.ext_services.presence.unblock_user(body.user_id, body.blocked_id).await {
+		Ok(true) => {}
+		Ok(false) => debug!("{:x?} was not blocked by {:x?}", body.blocked_id, body.user_id),
+		Err(err) => error!("Unable to unblock {:x?} for {:x?}: {:?}", body.blocked_id, body.user_id, err),
+	}
+}
+
+/// List messages carry no reply, so a console can't be told it failed; it
+/// finds out from the lists it's sent on its next Alive.
+fn log_buddy_error(action: &str, body: &BuddyRequest, err: &ListError) {
+	match err {
+		ListError::Store(err) =>
+			error!("Unable to {} buddy {:x?} for {:x?}: {:?}", action, body.buddy_id, body.user_id, err),
+		err =>
+			debug!("Refusing to {} buddy {:x?} for {:x?}: {:?}", action, body.buddy_id, body.user_id, err),
+	}
+}
+
+async fn xpresence_peek_handler(state: Arc<ClientState>, _header: &Header, body: &Peek) -> MessageKind {
+	if !is_signed_in(&state, body.user_id).await {
+		return peek_reply(HResult::E_FAIL, vec![])
+	}
+
+	let peeked = match state.ext_services.presence.peek(body.user_id).await {
+		Ok(peeked) => peeked,
+		Err(err) => {
+			error!("Unable to peek buddies of {:x?}: {:?}", body.user_id, err);
+			return peek_reply(HResult::E_FAIL, vec![])
+		}
+	};
+
+	let buddies = peeked.into_iter()
+		.map(|buddy| match buddy.presence {
+			Some(presence) => PeekBuddy {
+				buddy_id: buddy.xuid,
+				state: presence.state,
+				title_id: presence.title_id,
+				match_session_id: presence.match_session_id,
+				nickname: presence.nickname,
+				title_stuff: presence.title_stuff,
+			},
+			None => PeekBuddy {
+				buddy_id: buddy.xuid,
+				state: 0,
+				title_id: 0,
+				match_session_id: 0,
+				nickname: vec![],
+				title_stuff: vec![],
+			},
+		})
+		.collect();
+
+	peek_reply(HResult::SUCCESS, buddies)
+}
+
+fn peek_reply(hr: HResult, buddies: Vec<PeekBuddy>) -> MessageKind {
+	MessageKind::PeekReply {
+		body: PeekReply {
+			hr,
+			buddies_sent: buddies.len() as u16,
+		},
+		buddies,
+	}
+}
+
+async fn xpresence_invite_handler(state: Arc<ClientState>, _header: &Header, body: &Invite, invitees: &[Xuid]) {
+	if !is_signed_in(&state, body.user_id).await {
+		return
+	}
+
+	for invitee in invitees {
+		match state.ext_services.presence.invite(body.user_id, *invitee, body.title_id, body.xnkid).await {
+			Ok(()) => deliver_invitations(&state, *invitee).await,
+			Err(ListError::Store(err)) =>
+				error!("Unable to invite {:x?} for {:x?}: {:?}", invitee, body.user_id, err),
+			Err(err) =>
+				debug!("Refusing to invite {:x?} for {:x?}: {:?}", invitee, body.user_id, err),
+		}
+	}
+}
+
+async fn xpresence_cancel_handler(state: Arc<ClientState>, _header: &Header, body: &InviteCancel, invitees: &[Xuid]) {
+	if !is_signed_in(&state, body.user_id).await {
+		return
+	}
+
+	// Invitations already passed on to an invitee's console stay there; the
+	// session going away is what tells them it's over.
+	for invitee in invitees {
+		if let Err(err) = state.ext_services.presence.remove_invitation(*invitee, body.user_id, body.xnkid).await {
+			error!("Unable to cancel invitation of {:x?} for {:x?}: {:?}", invitee, body.user_id, err);
+		}
+	}
+}
+
+async fn xpresence_invite_answer_handler(state: Arc<ClientState>, _header: &Header, body: &InviteAnswer) {
+	if !is_signed_in(&state, body.user_id).await {
+		return
+	}
+
+	debug!("{:x?} answered invitation from {:x?} into {:x?} with {}", body.user_id, body.host_id, body.xnkid, body.answer);
+
+	if let Err(err) = state.ext_services.presence.remove_invitation(body.user_id, body.host_id, body.xnkid).await {
+		error!("Unable to drop invitation of {:x?} from {:x?}: {:?}", body.user_id, body.host_id, err);
+	}
+}
+
+/// Pass every invitation held for invitee on to their console, if they're
+/// online.  Each stays held until the console acknowledges the pulse that
+/// carried it or invitee answers it, so one that doesn't make it is passed
+/// on again at invitee's next Alive.
+async fn deliver_invitations(state: &ClientState, invitee: Xuid) {
+	let presence = &state.ext_services.presence;
+
+	match presence.get(invitee).await {
+		Ok(Some(invitee_presence)) if invitee_presence.online && invitee_presence.user_index.is_some() => {}
+		Ok(_) => return,
+		Err(err) => {
+			error!("Unable to get presence of {:x?}: {:?}", invitee, err);
+			return
+		}
+	}
+
+	let invitations = match presence.invitations(invitee).await {
+		Ok(invitations) => invitations,
+		Err(err) => {
+			error!("Unable to get invitations for {:x?}: {:?}", invitee, err);
+			return
+		}
+	};
+
+	for invitation in invitations {
+		let notice = InviteNotice {
+			host_id: invitation.host,
+			xnkid: invitation.xnkid,
+			title_id: invitation.title_id,
+		};
+
+		let mut data = vec![];
+		notice.put(&mut data);
+
+		let receipt = Receipt::Invitation {
+			invitee,
+			host: invitation.host,
+			xnkid: invitation.xnkid,
+		};
+
+		if !notify_user(state, invitee, PulseEventKind::GAME_INVITE, data, Some(receipt)).await {
+			debug!("Holding invitation of {:x?} from {:x?} until their console can be told", invitee, invitation.host);
+		}
+	}
+}
+
+/// Let buddy's console know user wants to be buddies, if buddy is online.
+async fn notify_buddy_request(state: &ClientState, user: Xuid, buddy: Xuid) {
+	let mut data = vec![];
+	user.put(&mut data);
+
+	notify_user(state, buddy, PulseEventKind::BUDDY_REQUEST, data, None).await;
+}
+
+/// Queue an event for the console user is signed in to, along with what to
+/// let go of once the console acknowledges it.  Returns false if they aren't
+/// online anywhere an event can reach them.
+pub(crate) async fn notify_user(state: &ClientState, user: Xuid, kind: PulseEventKind, data: Vec<u8>, receipt: Option<Receipt>) -> bool {
+	let user_presence = match state.ext_services.presence.get(user).await {
+		Ok(Some(presence)) if presence.online => presence,
+		Ok(_) => return false,
+		Err(err) => {
+			error!("Unable to get presence of {:x?}: {:?}", user, err);
+			return false
+		}
+	};
+
+	let user_index = match user_presence.user_index {
+		Some(user_index) => user_index,
+		None => return false,
+	};
+
+	let event = PulseEvent {
+		kind,
+		user_index,
+		data,
+	};
+
+	if !state.ext_services.notifications.post(user_presence.machine, event, receipt).await {
+		debug!("{:x?} is online but their console isn't connected", user);
+		return false
+	}
+
+	true
+}
+
+/// Messages about a user are only accepted from the console they're signed
+/// in on.
+async fn is_signed_in(state: &ClientState, user: Xuid) -> bool {
+	if state.users().await.slot_of(user).is_some() {
+		return true
+	}
+
+	error!("Ignoring presence message for {:x?}, who isn't signed in to {}", user, state.net_name());
+	false
+}
+
+async fn xpresence_dead_user_handler(state: Arc<ClientState>, _header: &Header, body: &DeadUser) {
+	if state.sign_out_user(body.user_id).await.is_none() {
+		debug!("Dead user {:x?} was not signed in", body.user_id);
+	}
+
+	let machine = state.users().await.machine;
+
+	if let Err(err) = state.ext_services.presence.user_gone(machine, body.user_id).await {
+		error!("Unable to mark {:x?} offline: {:?}", body.user_id, err);
+	}
 }
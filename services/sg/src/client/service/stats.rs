@@ -0,0 +1,227 @@
+use log::error;
+
+use smoltcp_user_vpn::tcp::{AcceptFn, http::{Request, Method, gen_http_accept, Response, StatusCode}};
+use xombie_stats::{Page, StatsError};
+
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use xblive::service::stats::*;
+
+use xbox_sys::account::Xuid;
+use xbox_sys::codec::{BufPut, Decode};
+use xbox_sys::status::HResult;
+
+use crate::client::ClientState;
+use crate::client::service::hresult_failure_response;
+use crate::client::service::unimplemented::not_found_handler;
+
+pub fn new_stats_connection(state: Arc<ClientState>) -> AcceptFn {
+	gen_http_accept(state, Arc::new(move |state, req: Request| async move {
+		match (req.header.method, req.header.path.as_str()) {
+			(Method::Post, "/xstats/xstatswrite.srf") => xstatswrite_handler(state, req).await,
+			(Method::Post, "/xstats/xstatsenum.srf") => xstatsenum_handler(state, req).await,
+			(Method::Post, "/xstats/xstatsget.srf") => xstatsget_handler(state, req).await,
+			(Method::Post, "/xstats/xstatsreset.srf") => xstatsreset_handler(state, req).await,
+			_ => not_found_handler(state, req).await,
+		}
+	}))
+}
+
+async fn xstatswrite_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let write_request = match StatsWriteRequest::decode(req.body_bytes()) {
+		Ok((_, write_request)) => write_request,
+		Err(err) => {
+			error!("Unable to decode stats write: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_title(&state, write_request.title_id).await {
+		error!("Rejecting stats write for title {:08x}: {:?}", write_request.title_id, hr);
+		return Ok(failure_response(&req, hr))
+	}
+
+	for write in &write_request.writes {
+		if let Err(hr) = check_signed_in(&state, write.user_id).await {
+			return Ok(failure_response(&req, hr))
+		}
+	}
+
+	if let Err(err) = state.ext_services.stats.write(write_request.title_id, &write_request.writes).await {
+		error!("Unable to write stats: {:?} {:?}", err, write_request);
+		return Ok(error_response(&req, &err))
+	}
+
+	Ok(Response::generate_good_response(&req, CONTENT_TYPE, vec![]))
+}
+
+async fn xstatsenum_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let enum_request = match StatsEnumRequest::decode(req.body_bytes()) {
+		Ok((_, enum_request)) => enum_request,
+		Err(err) => {
+			error!("Unable to decode stats enumeration: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_title(&state, enum_request.title_id).await {
+		error!("Rejecting stats enumeration for title {:08x}: {:?}", enum_request.title_id, hr);
+		return Ok(failure_response(&req, hr))
+	}
+
+	let stats = &state.ext_services.stats;
+
+	let page = match enum_request.mode {
+		ENUM_BY_RANK => stats.read_by_rank(
+			enum_request.title_id,
+			enum_request.leaderboard_id,
+			enum_request.rank,
+			enum_request.page_size,
+			&enum_request.attribute_ids,
+		).await,
+		ENUM_AROUND_USER => stats.read_around_user(
+			enum_request.title_id,
+			enum_request.leaderboard_id,
+			enum_request.pivot_user,
+			enum_request.page_size,
+			&enum_request.attribute_ids,
+		).await,
+		mode => {
+			error!("Unknown stats enumeration mode {} from {}", mode, state.net_name());
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	match page {
+		Ok(page) => Ok(rows_response(&req, page)),
+		Err(err) => {
+			error!("Unable to enumerate stats: {:?} {:?}", err, enum_request);
+			Ok(error_response(&req, &err))
+		}
+	}
+}
+
+async fn xstatsget_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let get_request = match StatsGetRequest::decode(req.body_bytes()) {
+		Ok((_, get_request)) => get_request,
+		Err(err) => {
+			error!("Unable to decode stats get: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_title(&state, get_request.title_id).await {
+		error!("Rejecting stats get for title {:08x}: {:?}", get_request.title_id, hr);
+		return Ok(failure_response(&req, hr))
+	}
+
+	match state.ext_services.stats.read_users(
+		get_request.title_id,
+		get_request.leaderboard_id,
+		&get_request.users,
+		&get_request.attribute_ids,
+	).await {
+		Ok(page) => Ok(rows_response(&req, page)),
+		Err(err) => {
+			error!("Unable to get stats: {:?} {:?}", err, get_request);
+			Ok(error_response(&req, &err))
+		}
+	}
+}
+
+async fn xstatsreset_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let reset_request = match StatsResetRequest::decode(req.body_bytes()) {
+		Ok((_, reset_request)) => reset_request,
+		Err(err) => {
+			error!("Unable to decode stats reset: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_title(&state, reset_request.title_id).await {
+		error!("Rejecting stats reset for title {:08x}: {:?}", reset_request.title_id, hr);
+		return Ok(failure_response(&req, hr))
+	}
+
+	if let Err(hr) = check_signed_in(&state, reset_request.user_id).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	if let Err(err) = state.ext_services.stats.reset(
+		reset_request.title_id,
+		reset_request.user_id,
+		reset_request.leaderboard_id,
+	).await {
+		error!("Unable to reset stats: {:?} {:?}", err, reset_request);
+		return Ok(error_response(&req, &err))
+	}
+
+	Ok(Response::generate_good_response(&req, CONTENT_TYPE, vec![]))
+}
+
+fn rows_response(req: &Request, page: Page) -> Response {
+	let rows = page.rows.into_iter()
+		.map(|row| StatsRow {
+			user_id: row.user,
+			rank: row.rank,
+			rating: row.rating,
+			gamertag: row.gamertag.unwrap_or_default(),
+			stats: row.stats,
+		})
+		.collect();
+
+	let rows = StatsRows {
+		total_rows: page.total_rows,
+		rows,
+	};
+
+	let mut body = vec![];
+	rows.put(&mut body);
+
+	Response::generate_good_response(req, CONTENT_TYPE, body)
+}
+
+/// Titles only get at their own leaderboards.
+async fn check_title(state: &ClientState, title_id: u32) -> Result<(), HResult> {
+	match state.title().await {
+		Some(title) if title.id == title_id => Ok(()),
+		_ => Err(E_INVALID_TITLE_ID),
+	}
+}
+
+/// Stats are only written or reset for users signed in on the console.
+async fn check_signed_in(state: &ClientState, user: Xuid) -> Result<(), HResult> {
+	if state.users().await.slot_of(user).is_some() {
+		return Ok(())
+	}
+
+	error!("Rejecting stats change for {:x?}, who isn't signed in to {}", user, state.net_name());
+	Err(E_INVALID_USER)
+}
+
+/// The failure to report to the console, or None if it wasn't the console's
+/// fault.
+fn error_hresult(err: &StatsError) -> Option<HResult> {
+	match err {
+		StatsError::UnknownTitle => Some(E_INVALID_TITLE_ID),
+		StatsError::UnknownLeaderboard(_) => Some(E_INVALID_LEADERBOARD),
+		StatsError::BadAttribute(_) => Some(E_INVALID_ATTRIBUTE),
+		StatsError::Store(_) => None,
+	}
+}
+
+fn error_response(req: &Request, err: &StatsError) -> Response {
+	match error_hresult(err) {
+		Some(hr) => failure_response(req, hr),
+		None => Response::generate_internal_server_error(req),
+	}
+}
+
+fn failure_response(req: &Request, hr: HResult) -> Response {
+	hresult_failure_response(req, StatusCode::Forbidden403, CONTENT_TYPE, hr)
+}
+
+fn bad_request_response(req: &Request) -> Response {
+	Response::generate_error_response(req, StatusCode::BadRequest400, CONTENT_TYPE, vec![])
+}
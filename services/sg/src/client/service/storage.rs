@@ -0,0 +1,228 @@
+use log::error;
+
+use smoltcp_user_vpn::tcp::{AcceptFn, http::{Request, Method, gen_http_accept, Response, StatusCode}};
+use xombie_storage::{FileInfo, StorageError};
+
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use xblive::service::storage::*;
+
+use xbox_sys::account::Xuid;
+use xbox_sys::codec::{BufPut, Decode};
+use xbox_sys::status::HResult;
+
+use crate::client::ClientState;
+use crate::client::service::hresult_failure_response;
+use crate::client::service::unimplemented::not_found_handler;
+
+pub fn new_storage_connection(state: Arc<ClientState>) -> AcceptFn {
+	gen_http_accept(state, Arc::new(move |state, req: Request| async move {
+		match (req.header.method, req.header.path.as_str()) {
+			(Method::Post, "/xstorage/upload.srf") => upload_handler(state, req).await,
+			(Method::Post, "/xstorage/download.srf") => download_handler(state, req).await,
+			(Method::Post, "/xstorage/enumerate.srf") => enumerate_handler(state, req).await,
+			(Method::Post, "/xstorage/delete.srf") => delete_handler(state, req).await,
+			_ => not_found_handler(state, req).await,
+		}
+	}))
+}
+
+async fn upload_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let upload_request = match UploadRequest::decode(req.body_bytes()) {
+		Ok((_, upload_request)) => upload_request,
+		Err(err) => {
+			error!("Unable to decode storage upload: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_request(&state, upload_request.title_id, upload_request.owner).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	let file = match state.ext_services.storage.upload(
+		upload_request.title_id,
+		upload_request.owner,
+		&upload_request.path,
+		upload_request.flags,
+		&upload_request.data,
+	).await {
+		Ok(file) => file,
+		Err(err) => {
+			error!("Unable to store {} for {:x?}: {:?}", upload_request.path, upload_request.owner, err);
+			return Ok(error_response(&req, &err))
+		}
+	};
+
+	let mut body = vec![];
+	storage_file(file).put(&mut body);
+
+	Ok(Response::generate_good_response(&req, CONTENT_TYPE, body))
+}
+
+async fn download_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let file_request = match FileRequest::decode(req.body_bytes()) {
+		Ok((_, file_request)) => file_request,
+		Err(err) => {
+			error!("Unable to decode storage download: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_request(&state, file_request.title_id, file_request.user_id).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	let (file, data) = match state.ext_services.storage.download(
+		file_request.title_id,
+		file_request.user_id,
+		file_request.owner,
+		&file_request.path,
+	).await {
+		Ok(download) => download,
+		Err(err) => {
+			error!("Unable to download {} of {:x?} for {:x?}: {:?}", file_request.path, file_request.owner, file_request.user_id, err);
+			return Ok(error_response(&req, &err))
+		}
+	};
+
+	let reply = DownloadReply {
+		file: storage_file(file),
+		data,
+	};
+
+	let mut body = vec![];
+	reply.put(&mut body);
+
+	Ok(Response::generate_good_response(&req, CONTENT_TYPE, body))
+}
+
+async fn enumerate_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let enumerate_request = match EnumerateRequest::decode(req.body_bytes()) {
+		Ok((_, enumerate_request)) => enumerate_request,
+		Err(err) => {
+			error!("Unable to decode storage enumeration: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_request(&state, enumerate_request.title_id, enumerate_request.user_id).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	let listing = match state.ext_services.storage.enumerate(
+		enumerate_request.title_id,
+		enumerate_request.user_id,
+		enumerate_request.owner,
+		&enumerate_request.prefix,
+		enumerate_request.start,
+		enumerate_request.max_results,
+	).await {
+		Ok(listing) => listing,
+		Err(err) => {
+			error!("Unable to enumerate storage: {:?} {:?}", err, enumerate_request);
+			return Ok(error_response(&req, &err))
+		}
+	};
+
+	let reply = EnumerateReply {
+		total_files: listing.total_files,
+		quota_used: listing.quota_used,
+		quota: listing.quota,
+		files: listing.files.into_iter().map(storage_file).collect(),
+	};
+
+	let mut body = vec![];
+	reply.put(&mut body);
+
+	Ok(Response::generate_good_response(&req, CONTENT_TYPE, body))
+}
+
+async fn delete_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let file_request = match FileRequest::decode(req.body_bytes()) {
+		Ok((_, file_request)) => file_request,
+		Err(err) => {
+			error!("Unable to decode storage delete: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_request(&state, file_request.title_id, file_request.user_id).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	if let Err(err) = state.ext_services.storage.delete(
+		file_request.title_id,
+		file_request.user_id,
+		file_request.owner,
+		&file_request.path,
+	).await {
+		error!("Unable to delete {} of {:x?} for {:x?}: {:?}", file_request.path, file_request.owner, file_request.user_id, err);
+		return Ok(error_response(&req, &err))
+	}
+
+	Ok(Response::generate_good_response(&req, CONTENT_TYPE, vec![]))
+}
+
+/// Titles only get at their own files, and only on behalf of users signed
+/// in on the console.
+async fn check_request(state: &ClientState, title_id: u32, user: Xuid) -> Result<(), HResult> {
+	match state.title().await {
+		Some(title) if title.id == title_id => {}
+		_ => {
+			error!("Rejecting storage request for title {:08x} from {}", title_id, state.net_name());
+			return Err(E_INVALID_TITLE_ID)
+		}
+	}
+
+	if state.users().await.slot_of(user).is_none() {
+		error!("Rejecting storage request for {:x?}, who isn't signed in to {}", user, state.net_name());
+		return Err(E_ACCESS_DENIED)
+	}
+
+	Ok(())
+}
+
+fn storage_file(file: FileInfo) -> StorageFile {
+	let modified = file.modified.duration_since(SystemTime::UNIX_EPOCH)
+		.map(|since_epoch| since_epoch.as_secs())
+		.unwrap_or(0);
+
+	StorageFile {
+		owner: file.owner,
+		path: file.path,
+		flags: file.flags,
+		size: file.size as u32,
+		hash: file.hash,
+		modified,
+	}
+}
+
+/// The failure to report to the console, or None if it wasn't the console's
+/// fault.
+fn error_hresult(err: &StorageError) -> Option<HResult> {
+	match err {
+		StorageError::BadPath => Some(E_INVALID_PATH),
+		StorageError::AccessDenied => Some(E_ACCESS_DENIED),
+		StorageError::NotFound => Some(E_FILE_NOT_FOUND),
+		StorageError::QuotaExceeded => Some(E_QUOTA_EXCEEDED),
+		StorageError::Corrupt | StorageError::Blob(_) | StorageError::Store(_) => None,
+	}
+}
+
+fn error_response(req: &Request, err: &StorageError) -> Response {
+	match error_hresult(err) {
+		Some(hr) => failure_response(req, hr),
+		None => Response::generate_internal_server_error(req),
+	}
+}
+
+fn failure_response(req: &Request, hr: HResult) -> Response {
+	hresult_failure_response(req, StatusCode::Forbidden403, CONTENT_TYPE, hr)
+}
+
+fn bad_request_response(req: &Request) -> Response {
+	Response::generate_error_response(req, StatusCode::BadRequest400, CONTENT_TYPE, vec![])
+}
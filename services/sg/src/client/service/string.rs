@@ -0,0 +1,90 @@
+use log::error;
+
+use smoltcp_user_vpn::tcp::{AcceptFn, http::{Request, Method, gen_http_accept, Response, StatusCode}};
+use xombie_strings::StringError;
+
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use xblive::service::string::*;
+
+use xbox_sys::codec::{BufPut, Decode};
+use xbox_sys::status::HResult;
+
+use crate::client::ClientState;
+use crate::client::service::hresult_failure_response;
+use crate::client::service::unimplemented::not_found_handler;
+
+pub fn new_string_connection(state: Arc<ClientState>) -> AcceptFn {
+	gen_http_accept(state, Arc::new(move |state, req: Request| async move {
+		match (req.header.method, req.header.path.as_str()) {
+			(Method::Post, "/xstring/xstringlookup.srf") => xstringlookup_handler(state, req).await,
+			_ => not_found_handler(state, req).await,
+		}
+	}))
+}
+
+async fn xstringlookup_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let lookup_request = match StringLookupRequest::decode(req.body_bytes()) {
+		Ok((_, lookup_request)) => lookup_request,
+		Err(err) => {
+			error!("Unable to decode string lookup: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_title(&state, lookup_request.title_id).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	let strings = match state.ext_services.strings.lookup(
+		lookup_request.title_id,
+		lookup_request.language,
+		&lookup_request.string_ids,
+	) {
+		Ok(strings) => strings,
+		Err(err) => {
+			error!("Unable to look up strings: {:?} {:?}", err, lookup_request);
+			return Ok(failure_response(&req, error_hresult(&err)))
+		}
+	};
+
+	let reply = StringLookupReply {
+		strings: strings.into_iter().map(|string| LocalizedString {
+			string_id: string.string_id,
+			language: string.language,
+			text: string.text.to_owned(),
+		}).collect(),
+	};
+
+	let mut body = vec![];
+	reply.put(&mut body);
+
+	Ok(Response::generate_good_response(&req, CONTENT_TYPE, body))
+}
+
+/// Titles only see their own strings, and the system's.
+async fn check_title(state: &ClientState, title_id: u32) -> Result<(), HResult> {
+	match state.title().await {
+		Some(title) if title.id == title_id => Ok(()),
+		_ => {
+			error!("Rejecting string lookup for title {:08x} from {}", title_id, state.net_name());
+			Err(E_INVALID_TITLE_ID)
+		}
+	}
+}
+
+fn error_hresult(err: &StringError) -> HResult {
+	match err {
+		StringError::UnknownLanguage => E_INVALID_LANGUAGE,
+		StringError::TooManyStrings => E_TOO_MANY_STRINGS,
+	}
+}
+
+fn failure_response(req: &Request, hr: HResult) -> Response {
+	hresult_failure_response(req, StatusCode::Forbidden403, CONTENT_TYPE, hr)
+}
+
+fn bad_request_response(req: &Request) -> Response {
+	Response::generate_error_response(req, StatusCode::BadRequest400, CONTENT_TYPE, vec![])
+}
@@ -0,0 +1,417 @@
+use log::error;
+
+use smoltcp_user_vpn::tcp::{AcceptFn, http::{Request, Method, gen_http_accept, Response, StatusCode}};
+use xombie_teams::{Member, TeamError};
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use xblive::service::team::*;
+use xblive::sg::control::PulseEventKind;
+
+use xbox_sys::account::Xuid;
+use xbox_sys::codec::{BufPut, Decode};
+use xbox_sys::status::HResult;
+
+use crate::client::ClientState;
+use crate::client::service::hresult_failure_response;
+use crate::client::service::presence::notify_user;
+use crate::client::service::unimplemented::not_found_handler;
+
+pub fn new_team_connection(state: Arc<ClientState>) -> AcceptFn {
+	gen_http_accept(state, Arc::new(move |state, req: Request| async move {
+		match (req.header.method, req.header.path.as_str()) {
+			(Method::Post, "/xteam/xteamcreate.srf") => xteamcreate_handler(state, req).await,
+			(Method::Post, "/xteam/xteamenum.srf") => xteamenum_handler(state, req).await,
+			(Method::Post, "/xteam/xteamdetails.srf") => xteamdetails_handler(state, req).await,
+			(Method::Post, "/xteam/xteamprops.srf") => xteamprops_handler(state, req).await,
+			(Method::Post, "/xteam/xteaminvite.srf") => xteaminvite_handler(state, req).await,
+			(Method::Post, "/xteam/xteamaccept.srf") => xteamaccept_handler(state, req).await,
+			(Method::Post, "/xteam/xteamdecline.srf") => xteamdecline_handler(state, req).await,
+			(Method::Post, "/xteam/xteamremove.srf") => xteamremove_handler(state, req).await,
+			(Method::Post, "/xteam/xteamrole.srf") => xteamrole_handler(state, req).await,
+			(Method::Post, "/xteam/xteamdelete.srf") => xteamdelete_handler(state, req).await,
+			_ => not_found_handler(state, req).await,
+		}
+	}))
+}
+
+async fn xteamcreate_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let create_request = match CreateTeamRequest::decode(req.body_bytes()) {
+		Ok((_, create_request)) => create_request,
+		Err(err) => {
+			error!("Unable to decode team creation: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_user(&state, create_request.title_id, create_request.owner).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	let properties = property_updates(&create_request.properties);
+
+	let team_id = match state.ext_services.teams.create(create_request.title_id, create_request.owner, &create_request.name, &properties).await {
+		Ok(team_id) => team_id,
+		Err(err) => {
+			error!("Unable to create team {:?}: {:?}", create_request.name, err);
+			return Ok(error_response(&req, &err))
+		}
+	};
+
+	let mut body = vec![];
+	CreateTeamReply { team_id }.put(&mut body);
+
+	Ok(Response::generate_good_response(&req, CONTENT_TYPE, body))
+}
+
+async fn xteamenum_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let enum_request = match EnumerateTeamsRequest::decode(req.body_bytes()) {
+		Ok((_, enum_request)) => enum_request,
+		Err(err) => {
+			error!("Unable to decode team enumeration: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_user(&state, enum_request.title_id, enum_request.user).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	let teams = &state.ext_services.teams;
+
+	let memberships = match teams.teams_of(enum_request.title_id, enum_request.user).await {
+		Ok(memberships) => memberships,
+		Err(err) => {
+			error!("Unable to enumerate teams of {:x?}: {:?}", enum_request.user, err);
+			return Ok(error_response(&req, &err))
+		}
+	};
+
+	let invitations = match teams.invitations_of(enum_request.title_id, enum_request.user).await {
+		Ok(invitations) => invitations,
+		Err(err) => {
+			error!("Unable to enumerate team invitations of {:x?}: {:?}", enum_request.user, err);
+			return Ok(error_response(&req, &err))
+		}
+	};
+
+	let reply = EnumerateTeamsReply {
+		teams: memberships.into_iter()
+			.map(|membership| TeamSummary {
+				team_id: membership.team.id,
+				name: membership.team.name,
+				role: membership.role,
+				member_count: membership.member_count,
+			})
+			.collect(),
+		invitations: invitations.into_iter()
+			.map(|(invitation, team)| TeamInvitation {
+				team_id: team.id,
+				name: team.name,
+				inviter: invitation.inviter,
+				sent: unix_secs(invitation.sent),
+			})
+			.collect(),
+	};
+
+	let mut body = vec![];
+	reply.put(&mut body);
+
+	Ok(Response::generate_good_response(&req, CONTENT_TYPE, body))
+}
+
+async fn xteamdetails_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let team_request = match decode_team_request(&state, &req).await {
+		Ok(team_request) => team_request,
+		Err(response) => return Ok(response),
+	};
+
+	let (team, members) = match state.ext_services.teams.details(team_request.title_id, team_request.team_id).await {
+		Ok(details) => details,
+		Err(err) => {
+			error!("Unable to get team {:?}: {:?}", team_request, err);
+			return Ok(error_response(&req, &err))
+		}
+	};
+
+	let reply = TeamDetailsReply {
+		team_id: team.id,
+		name: team.name,
+		created: unix_secs(team.created),
+		properties: team.properties.into_iter()
+			.map(|(id, data)| TeamProperty { id, data })
+			.collect(),
+		members: members.iter().map(team_member).collect(),
+	};
+
+	let mut body = vec![];
+	reply.put(&mut body);
+
+	Ok(Response::generate_good_response(&req, CONTENT_TYPE, body))
+}
+
+async fn xteamprops_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let props_request = match SetTeamPropertiesRequest::decode(req.body_bytes()) {
+		Ok((_, props_request)) => props_request,
+		Err(err) => {
+			error!("Unable to decode team properties: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_user(&state, props_request.title_id, props_request.user).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	let updates = property_updates(&props_request.properties);
+
+	match state.ext_services.teams.set_properties(props_request.title_id, props_request.user, props_request.team_id, &updates).await {
+		Ok(()) => Ok(Response::generate_good_response(&req, CONTENT_TYPE, vec![])),
+		Err(err) => {
+			error!("Unable to set properties of team {}: {:?}", props_request.team_id, err);
+			Ok(error_response(&req, &err))
+		}
+	}
+}
+
+async fn xteaminvite_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let member_request = match decode_member_request(&state, &req).await {
+		Ok(member_request) => member_request,
+		Err(response) => return Ok(response),
+	};
+
+	if let Err(err) = state.ext_services.teams.invite(
+		member_request.title_id,
+		member_request.user,
+		member_request.team_id,
+		member_request.member,
+	).await {
+		error!("Unable to invite to team {:?}: {:?}", member_request, err);
+		return Ok(error_response(&req, &err))
+	}
+
+	notify_invitation(&state, member_request.member, member_request.team_id, member_request.user).await;
+
+	Ok(Response::generate_good_response(&req, CONTENT_TYPE, vec![]))
+}
+
+async fn xteamaccept_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let team_request = match decode_team_request(&state, &req).await {
+		Ok(team_request) => team_request,
+		Err(response) => return Ok(response),
+	};
+
+	match state.ext_services.teams.accept(team_request.title_id, team_request.user, team_request.team_id).await {
+		Ok(()) => Ok(Response::generate_good_response(&req, CONTENT_TYPE, vec![])),
+		Err(err) => {
+			error!("Unable to join team {:?}: {:?}", team_request, err);
+			Ok(error_response(&req, &err))
+		}
+	}
+}
+
+async fn xteamdecline_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let team_request = match decode_team_request(&state, &req).await {
+		Ok(team_request) => team_request,
+		Err(response) => return Ok(response),
+	};
+
+	match state.ext_services.teams.decline(team_request.title_id, team_request.user, team_request.team_id).await {
+		Ok(()) => Ok(Response::generate_good_response(&req, CONTENT_TYPE, vec![])),
+		Err(err) => {
+			error!("Unable to decline team {:?}: {:?}", team_request, err);
+			Ok(error_response(&req, &err))
+		}
+	}
+}
+
+async fn xteamremove_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let member_request = match decode_member_request(&state, &req).await {
+		Ok(member_request) => member_request,
+		Err(response) => return Ok(response),
+	};
+
+	match state.ext_services.teams.remove(
+		member_request.title_id,
+		member_request.user,
+		member_request.team_id,
+		member_request.member,
+	).await {
+		Ok(()) => Ok(Response::generate_good_response(&req, CONTENT_TYPE, vec![])),
+		Err(err) => {
+			error!("Unable to remove from team {:?}: {:?}", member_request, err);
+			Ok(error_response(&req, &err))
+		}
+	}
+}
+
+async fn xteamrole_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let role_request = match SetTeamRoleRequest::decode(req.body_bytes()) {
+		Ok((_, role_request)) => role_request,
+		Err(err) => {
+			error!("Unable to decode team role: {:?}", err);
+			return Ok(bad_request_response(&req))
+		}
+	};
+
+	if let Err(hr) = check_user(&state, role_request.title_id, role_request.user).await {
+		return Ok(failure_response(&req, hr))
+	}
+
+	match state.ext_services.teams.set_role(
+		role_request.title_id,
+		role_request.user,
+		role_request.team_id,
+		role_request.member,
+		role_request.role,
+	).await {
+		Ok(()) => Ok(Response::generate_good_response(&req, CONTENT_TYPE, vec![])),
+		Err(err) => {
+			error!("Unable to set team role {:?}: {:?}", role_request, err);
+			Ok(error_response(&req, &err))
+		}
+	}
+}
+
+async fn xteamdelete_handler(state: Arc<ClientState>, req: Request) -> Result<Response, Infallible> {
+	let team_request = match decode_team_request(&state, &req).await {
+		Ok(team_request) => team_request,
+		Err(response) => return Ok(response),
+	};
+
+	match state.ext_services.teams.delete(team_request.title_id, team_request.user, team_request.team_id).await {
+		Ok(()) => Ok(Response::generate_good_response(&req, CONTENT_TYPE, vec![])),
+		Err(err) => {
+			error!("Unable to delete team {:?}: {:?}", team_request, err);
+			Ok(error_response(&req, &err))
+		}
+	}
+}
+
+/// Decode a request about a whole team and check the user can make it, or
+/// the response to send back if not.
+async fn decode_team_request(state: &ClientState, req: &Request) -> Result<TeamRequest, Response> {
+	let team_request = match TeamRequest::decode(req.body_bytes()) {
+		Ok((_, team_request)) => team_request,
+		Err(err) => {
+			error!("Unable to decode team request: {:?}", err);
+			return Err(bad_request_response(req))
+		}
+	};
+
+	check_user(state, team_request.title_id, team_request.user).await
+		.map_err(|hr| failure_response(req, hr))?;
+
+	Ok(team_request)
+}
+
+/// Decode a request about one user of a team and check the user can make
+/// it, or the response to send back if not.
+async fn decode_member_request(state: &ClientState, req: &Request) -> Result<TeamMemberRequest, Response> {
+	let member_request = match TeamMemberRequest::decode(req.body_bytes()) {
+		Ok((_, member_request)) => member_request,
+		Err(err) => {
+			error!("Unable to decode team member request: {:?}", err);
+			return Err(bad_request_response(req))
+		}
+	};
+
+	check_user(state, member_request.title_id, member_request.user).await
+		.map_err(|hr| failure_response(req, hr))?;
+
+	Ok(member_request)
+}
+
+/// Let invitee's console know they've been invited to a team, if they're
+/// online.
+async fn notify_invitation(state: &ClientState, invitee: Xuid, team_id: u64, inviter: Xuid) {
+	let notification = TeamInviteNotification {
+		team_id,
+		inviter,
+	};
+
+	let mut data = vec![];
+	notification.put(&mut data);
+
+	notify_user(state, invitee, PulseEventKind::TEAM_INVITE, data, None).await;
+}
+
+/// Teams are only managed from within the title being played, by a user
+/// signed in on the console.
+async fn check_user(state: &ClientState, title_id: u32, user: Xuid) -> Result<(), HResult> {
+	match state.title().await {
+		Some(title) if title.id == title_id => {}
+		_ => {
+			error!("Rejecting teams for title {:08x} from {}", title_id, state.net_name());
+			return Err(E_INVALID_TITLE_ID)
+		}
+	}
+
+	if state.users().await.slot_of(user).is_none() {
+		error!("Rejecting teams for {:x?}, who isn't signed in to {}", user, state.net_name());
+		return Err(E_INVALID_USER)
+	}
+
+	Ok(())
+}
+
+fn property_updates(properties: &[TeamProperty]) -> Vec<(u32, Vec<u8>)> {
+	properties.iter()
+		.map(|property| (property.id, property.data.clone()))
+		.collect::<BTreeMap<_, _>>()
+		.into_iter()
+		.collect()
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+	time.duration_since(SystemTime::UNIX_EPOCH)
+		.map(|since_epoch| since_epoch.as_secs())
+		.unwrap_or(0)
+}
+
+fn team_member(member: &Member) -> TeamMember {
+	TeamMember {
+		xuid: member.xuid,
+		role: member.role,
+		joined: unix_secs(member.joined),
+	}
+}
+
+/// The failure to report to the console, or None if it wasn't the console's
+/// fault.
+fn error_hresult(err: &TeamError) -> Option<HResult> {
+	match err {
+		TeamError::InvalidName => Some(E_INVALID_NAME),
+		TeamError::NameTaken => Some(E_NAME_TAKEN),
+		TeamError::NoSuchTeam => Some(E_TEAM_NOT_FOUND),
+		TeamError::NotAMember => Some(E_NOT_A_MEMBER),
+		TeamError::AlreadyMember => Some(E_ALREADY_MEMBER),
+		TeamError::PermissionDenied => Some(E_PERMISSION_DENIED),
+		TeamError::NoInvitation => Some(E_NO_INVITATION),
+		TeamError::TeamFull => Some(E_TEAM_FULL),
+		TeamError::TooManyTeams => Some(E_TOO_MANY_TEAMS),
+		TeamError::TooManyProperties => Some(E_TOO_MANY_PROPERTIES),
+		TeamError::PropertyTooLarge => Some(E_PROPERTY_TOO_LARGE),
+		TeamError::InvalidRole => Some(E_INVALID_ROLE),
+		TeamError::OwnerCannotLeave => Some(E_OWNER_CANNOT_LEAVE),
+		TeamError::Store(_) => None,
+	}
+}
+
+fn error_response(req: &Request, err: &TeamError) -> Response {
+	match error_hresult(err) {
+		Some(hr) => failure_response(req, hr),
+		None => Response::generate_internal_server_error(req),
+	}
+}
+
+fn failure_response(req: &Request, hr: HResult) -> Response {
+	hresult_failure_response(req, StatusCode::Forbidden403, CONTENT_TYPE, hr)
+}
+
+fn bad_request_response(req: &Request) -> Response {
+	Response::generate_error_response(req, StatusCode::BadRequest400, CONTENT_TYPE, vec![])
+}
@@ -18,10 +18,13 @@ use xbox_sys::codec::Decode;
 use xbox_sys::crypto::SymmetricKey;
 
 use xombie::db;
-use xombie::krb::{DecryptError, SymmetricKeyCreateError, enc_key_to_symmetric_key, krb_decrypt_and_decode, AD_TYPE_SERVICE_ADDRESSES, AT_DOMAINS};
+use xombie::krb::{DecryptError, SymmetricKeyCreateError, TitleAuthData, UsersAuthData, enc_key_to_symmetric_key, krb_decrypt_and_decode, AD_TYPE_SERVICE_ADDRESSES, AD_TYPE_TITLE, AD_TYPE_USERS, AT_DOMAINS};
 
 use crate::Services;
 use crate::open_clients::OpenClients;
+use crate::user::CombinedId;
+
+use xombie_matchmaking::Title;
 
 #[derive(Debug)]
 pub enum HandleControlInitError {
@@ -30,6 +33,7 @@ pub enum HandleControlInitError {
     UnknownPacketChunkStructure(String),
     CannotDecryptTicket(DecryptError),
     UnknownTicketCName(PrincipalName),
+    DatabaseUnavailable(xombie::db::PoolError),
     CannotParseSessionKey(SymmetricKeyCreateError),
     CannotDecryptAuthenticator(DecryptError),
     AuthenticationFailed(&'static str),
@@ -37,7 +41,11 @@ pub enum HandleControlInitError {
     CannotAllocateNewSpi,
     NoAdData,
     AdDataWrongType(i32),
+    AdDataDuplicated(i32),
     ServiceAddressParseError,
+    UsersParseError,
+    TitleParseError,
+    UsersMachineMismatch(Xuid, Xuid),
 }
 
 #[derive(Debug)]
@@ -55,6 +63,8 @@ pub struct ValidatedInitPacket {
     pub ctime: KerberosTime,
     pub cusec: Microseconds,
     pub service_address: ServiceAddress,
+    pub users: CombinedId,
+    pub title: Option<Title>,
 }
 
 pub async fn process_control_init_packet(buf: Vec<u8>, peer: SocketAddr, tx_socket: Arc<UdpSocket>, services: Arc<Services>, client_table: Arc<RwLock<OpenClients>>)
@@ -127,26 +137,81 @@ async fn process_control_init(
     eprintln!("~~~TODO~~~: Validate ticket is valid");
 
     let ad_vec = enc_ticket_part.authorization_data
+        .as_ref()
         .ok_or(NoAdData)?;
 
-    if ad_vec.len() != 1 {
-        return Err(NoAdData);
-    }
-
-    if ad_vec[0].ad_type != AD_TYPE_SERVICE_ADDRESSES {
-        return Err(AdDataWrongType(ad_vec[0].ad_type))
+    let mut service_address = None;
+    let mut users_auth_data = None;
+    let mut title = None;
+
+    for ad in ad_vec.iter() {
+        match ad.ad_type {
+            AD_TYPE_SERVICE_ADDRESSES => {
+                if service_address.is_some() {
+                    return Err(AdDataDuplicated(ad.ad_type))
+                }
+
+                let (_, parsed) = ServiceAddress::decode(&ad.ad_data)
+                    .map_err(|_| ServiceAddressParseError)?;
+
+                service_address = Some(parsed);
+            }
+            AD_TYPE_USERS => {
+                if users_auth_data.is_some() {
+                    return Err(AdDataDuplicated(ad.ad_type))
+                }
+
+                // Older KDCs send an empty users entry; treat that as the
+                // machine account alone being signed in
+                users_auth_data = Some(if ad.ad_data.is_empty() {
+                    None
+                } else {
+                    Some(UsersAuthData::from_raw(&ad.ad_data)
+                        .ok_or(UsersParseError)?)
+                });
+            }
+            AD_TYPE_TITLE => {
+                if title.is_some() {
+                    return Err(AdDataDuplicated(ad.ad_type))
+                }
+
+                let title_auth_data = TitleAuthData::from_raw(&ad.ad_data)
+                    .ok_or(TitleParseError)?;
+
+                title = Some(Title {
+                    id: title_auth_data.title_id,
+                    ver: title_auth_data.title_version,
+                });
+            }
+            other => return Err(AdDataWrongType(other)),
+        }
     }
 
-    let (_, service_address) = ServiceAddress::decode(&ad_vec[0].ad_data)
-        .map_err(|_| ServiceAddressParseError)?;
+    let service_address = service_address
+        .ok_or(NoAdData)?;
 
     let (gamertag, _domain) = gamertag_from_cname(&enc_ticket_part.cname, AT_DOMAINS)
         .ok_or(UnknownTicketCName(enc_ticket_part.cname.clone()))?;
 
-    let xuid = db::get_xuid_for_gamertag(&services.pg, gamertag.clone())
+    let pg = services.pg.get()
+        .await
+        .map_err(DatabaseUnavailable)?;
+
+    let xuid = db::get_xuid_for_gamertag(&pg, gamertag.clone())
         .await
         .ok_or(UnknownTicketCName(enc_ticket_part.cname.clone()))?;
 
+    let users = match users_auth_data.flatten() {
+        Some(users_auth_data) => {
+            if users_auth_data.machine != xuid {
+                return Err(UsersMachineMismatch(users_auth_data.machine, xuid))
+            }
+
+            CombinedId::from_auth_data(&users_auth_data)
+        }
+        None => CombinedId::new(xuid),
+    };
+
     eprintln!("~~~TODO~~~: Validate xuid is machine");
     eprintln!("~~~TODO~~~: Validate that machine is not banned");
     eprintln!("~~~TODO~~~: Validate that machine is not already logged in, or wait for remote connection to close");
@@ -192,5 +257,7 @@ async fn process_control_init(
         ctime: authenticator.ctime,
         cusec: authenticator.cusec,
         service_address,
+        users,
+        title,
     })
 }
@@ -1,24 +1,48 @@
 use clap::Parser;
+use xombie_autoupdate::Updates;
+use xombie_content::Catalogue;
+use xombie_feedback::Feedback;
+use xombie_feedback::store::postgres::PostgresStore as PostgresFeedbackStore;
 use xombie_matchmaking::Matchmaking;
+use xombie_matchmaking::procedure::Procedures;
+use xombie_matchmaking::store::SessionStore;
+use xombie_matchmaking::store::memory::MemoryStore;
+use xombie_matchmaking::store::redis::RedisStore;
+use xombie_messaging::Messaging;
+use xombie_messaging::store::postgres::PostgresStore as PostgresMessageStore;
+use xombie_presence::Presence;
+use xombie_presence::store::postgres::{PostgresListStore, PostgresPresenceStore};
+use xombie_stats::Stats;
+use xombie_stats::leaderboard::Leaderboards;
+use xombie_stats::store::postgres::PostgresStore as PostgresStatsStore;
+use xombie_storage::Storage;
+use xombie_storage::quota::Quotas;
+use xombie_storage::store::fs::FsBlobStore;
+use xombie_storage::store::postgres::PostgresFileStore;
+use xombie_strings::StringTables;
+use xombie_teams::Teams;
+use xombie_teams::store::postgres::PostgresStore as PostgresTeamStore;
 
 use std::error::Error;
 use std::io;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::process::exit;
 use std::sync::Arc;
+use std::time::Duration;
 
 use tokio::net::UdpSocket;
 use tokio::signal::unix::{signal, SignalKind};
 
 use tokio::sync::RwLock;
-use tokio_postgres::Client;
 
 use xblive::sg::packet::{Header, PacketCategorizaton};
-use xombie::db::connect_db_client;
+use xombie::db::{Pool, apply_schema, db_pool};
 
 mod client;
 mod init;
 mod ip_conversion;
+mod notify;
 mod open_clients;
 mod secrets;
 mod tracer;
@@ -26,6 +50,10 @@ mod user;
 
 const MTU_SIZE: usize = 1500;
 
+const SESSION_REAP_PERIOD: Duration = Duration::from_secs(30);
+
+const MESSAGE_REAP_PERIOD: Duration = Duration::from_secs(60 * 60);
+
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
@@ -46,31 +74,209 @@ struct Args {
 
     #[clap(short, long, value_parser, default_value_t = String::from("postgres"))]
     pg_password: String,
+
+    /// Most database connections open at once, shared by every service
+    #[clap(long, value_parser, default_value_t = 16)]
+    pg_pool_size: usize,
+
+    /// Directory of per title matchmaking search procedures
+    #[clap(long, value_parser, default_value = "matchmaking")]
+    procedures_dir: PathBuf,
+
+    /// Seconds a matchmaking session lives without being updated by its host
+    #[clap(long, value_parser, default_value_t = 600)]
+    session_ttl_secs: u64,
+
+    /// Keep matchmaking sessions in this Redis instance rather than in memory
+    #[clap(long, value_parser)]
+    redis_url: Option<String>,
+
+    /// Region this SG serves; searches prefer sessions hosted in the same one
+    #[clap(long, value_parser)]
+    region: Option<String>,
+
+    /// Directory of per title leaderboard definitions
+    #[clap(long, value_parser, default_value = "stats")]
+    leaderboards_dir: PathBuf,
+
+    /// Directory user storage uploads are kept in
+    #[clap(long, value_parser, default_value = "storage")]
+    storage_dir: PathBuf,
+
+    /// TOML file of per title user storage quotas
+    #[clap(long, value_parser)]
+    storage_quotas: Option<PathBuf>,
+
+    /// Directory of downloadable content packages, one directory per title
+    #[clap(long, value_parser, default_value = "content")]
+    content_dir: PathBuf,
+
+    /// Directory of title and dashboard updates, one TOML file per title
+    #[clap(long, value_parser, default_value = "updates")]
+    updates_dir: PathBuf,
+
+    /// Tell every console its titles are current instead of offering updates
+    #[clap(long, action)]
+    no_updates: bool,
+
+    /// Directory of localized string tables, one directory per title
+    #[clap(long, value_parser, default_value = "strings")]
+    strings_dir: PathBuf,
+
+    /// Send presence, invitation and message notifications in pulses, using
+    /// event framing that hasn't been verified against a real console
+    #[clap(long, action)]
+    provisional_pulse_events: bool,
 }
 
 #[derive(Debug)]
 pub struct Services {
-    pub pg: Client,
+    pub pg: Pool,
     pub matchmaking: Matchmaking,
+    pub presence: Presence,
+    pub stats: Stats,
+    pub storage: Storage,
+    pub content: Catalogue,
+    pub updates: Updates,
+    pub strings: StringTables,
+    pub feedback: Feedback,
+    pub messaging: Messaging,
+    pub teams: Teams,
+    pub notifications: notify::Notifications,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
 
-    let pg = connect_db_client(
-        &args.pg_addr,
-        args.pg_port,
-        &args.pg_user,
-        &args.pg_password)
-        .await
-        .unwrap();
+    let pg = connect_db_pool(&args).await;
+
+    let procedures = match Procedures::load_dir(&args.procedures_dir) {
+        Ok(procedures) => procedures,
+        Err(err) => {
+            eprintln!("Unable to load matchmaking procedures from {}: {:?}", args.procedures_dir.display(), err);
+            exit(1)
+        }
+    };
+
+    println!("Loaded {} matchmaking procedures", procedures.len());
+
+    let session_ttl = Duration::from_secs(args.session_ttl_secs);
+
+    let session_store: Arc<dyn SessionStore> = match &args.redis_url {
+        Some(redis_url) => match RedisStore::connect(redis_url, session_ttl).await {
+            Ok(store) => Arc::new(store),
+            Err(err) => {
+                eprintln!("Unable to connect to redis at {}: {:?}", redis_url, err);
+                exit(1)
+            }
+        },
+        None => Arc::new(MemoryStore::new(session_ttl)),
+    };
+
+    let matchmaking = Matchmaking::new(procedures, session_store, args.region.clone());
+
+    matchmaking.spawn_reaper(SESSION_REAP_PERIOD);
+
+    let presence_store = PostgresPresenceStore::new(pg.clone());
+
+    let list_store = PostgresListStore::new(pg.clone());
+
+    let presence = Presence::new(Arc::new(presence_store), Arc::new(list_store));
+
+    let leaderboards = match Leaderboards::load_dir(&args.leaderboards_dir) {
+        Ok(leaderboards) => leaderboards,
+        Err(err) => {
+            eprintln!("Unable to load leaderboards from {}: {:?}", args.leaderboards_dir.display(), err);
+            exit(1)
+        }
+    };
+
+    println!("Loaded {} leaderboards", leaderboards.len());
+
+    let stats_store = PostgresStatsStore::new(pg.clone());
+
+    let stats = Stats::new(leaderboards, Arc::new(stats_store));
+
+    let quotas = match &args.storage_quotas {
+        Some(path) => match Quotas::load_file(path) {
+            Ok(quotas) => quotas,
+            Err(err) => {
+                eprintln!("Unable to load storage quotas from {}: {:?}", path.display(), err);
+                exit(1)
+            }
+        },
+        None => Quotas::default(),
+    };
+
+    let file_store = PostgresFileStore::new(pg.clone());
+
+    let storage = Storage::new(quotas, Arc::new(file_store), Arc::new(FsBlobStore::new(&args.storage_dir)));
+
+    let content = match Catalogue::load_dir(&args.content_dir) {
+        Ok(content) => content,
+        Err(err) => {
+            eprintln!("Unable to load content from {}: {:?}", args.content_dir.display(), err);
+            exit(1)
+        }
+    };
+
+    println!("Loaded {} content offers", content.len());
+
+    let updates = if args.no_updates {
+        println!("Not offering title updates");
+        Updates::none_required()
+    } else {
+        match Updates::load_dir(&args.updates_dir) {
+            Ok(updates) => updates,
+            Err(err) => {
+                eprintln!("Unable to load title updates from {}: {:?}", args.updates_dir.display(), err);
+                exit(1)
+            }
+        }
+    };
+
+    println!("Loaded {} title updates", updates.len());
+
+    let strings = match StringTables::load_dir(&args.strings_dir) {
+        Ok(strings) => strings,
+        Err(err) => {
+            eprintln!("Unable to load strings from {}: {:?}", args.strings_dir.display(), err);
+            exit(1)
+        }
+    };
+
+    println!("Loaded {} string tables", strings.len());
+
+    let feedback_store = PostgresFeedbackStore::new(pg.clone());
+
+    let feedback = Feedback::new(Arc::new(feedback_store));
+
+    let message_store = PostgresMessageStore::new(pg.clone());
 
-    let matchmaking = Matchmaking::new();
+    let messaging = Messaging::new(Arc::new(message_store));
+
+    messaging.spawn_reaper(MESSAGE_REAP_PERIOD);
+
+    let team_store = PostgresTeamStore::new(pg.clone());
+
+    let teams = Teams::new(Arc::new(team_store));
+
+    let notifications = notify::Notifications::new(args.provisional_pulse_events);
 
     let services = Arc::new(Services {
         pg,
         matchmaking,
+        presence,
+        stats,
+        storage,
+        content,
+        updates,
+        strings,
+        feedback,
+        messaging,
+        teams,
+        notifications,
     });
 
     let addr = format!("{}:{}", args.sg_addr, args.sg_port);
@@ -94,6 +300,44 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// The pool of database connections every service shares, with the schema
+/// brought up to date, exiting if the database can't be reached.
+async fn connect_db_pool(args: &Args) -> Pool {
+    let pool = match db_pool(&args.pg_addr, args.pg_port, &args.pg_user, &args.pg_password, args.pg_pool_size) {
+        Ok(pool) => pool,
+        Err(err) => {
+            eprintln!("Unable to set up the database pool: {:?}", err);
+            exit(1)
+        }
+    };
+
+    // Connections are only opened as they're needed, so check now that there
+    // is a database rather than on the first request.
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(err) => {
+            eprintln!("Unable to connect to the database at {}:{}: {:?}", args.pg_addr, args.pg_port, err);
+            exit(1)
+        }
+    };
+
+    match apply_schema(&mut client).await {
+        Ok(applied) => {
+            for name in applied {
+                println!("Applied schema {}", name);
+            }
+        }
+        Err(err) => {
+            eprintln!("Unable to apply the database schema: {:?}", err);
+            exit(1)
+        }
+    }
+
+    drop(client);
+
+    pool
+}
+
 async fn run(socket: UdpSocket, services: Arc<Services>) -> Result<(), io::Error> {
     simple_logger::SimpleLogger::new().init().unwrap();
 
@@ -0,0 +1,317 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+
+use log::{info, warn};
+
+use tokio::sync::{Mutex, RwLock};
+
+use xblive::crypto::primitives::KeyId;
+use xblive::sg::control::PulseEvent;
+
+use xbox_sys::account::Xuid;
+
+/// Upper bound on the events payload of a single SgToXbPulse, so that the
+/// control packet still fits comfortably within one datagram.
+const MAX_PULSE_EVENTS_LEN: usize = 1024;
+
+/// Upper bound on notifications waiting for a console to acknowledge them.
+/// Once hit the oldest notification is dropped to make room.
+const MAX_PENDING_EVENTS: usize = 64;
+
+/// Something held elsewhere until the console acknowledges the event that
+/// told it about it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Receipt {
+    /// The invitation from host into xnkid held for invitee.
+    Invitation {
+        invitee: Xuid,
+        host: Xuid,
+        xnkid: KeyId,
+    },
+}
+
+#[derive(Debug)]
+struct Pending {
+    seq: u32,
+    event: PulseEvent,
+    receipt: Option<Receipt>,
+}
+
+/// Events posted to a single SG connection that the console hasn't yet
+/// acknowledged.  Every event is assigned the next sequence number; the
+/// console acks by echoing the last sequence number it processed in the
+/// seq_ack of its XbToSgPulse.
+#[derive(Debug)]
+pub struct NotificationQueue {
+    last_seq: u32,
+    pending: VecDeque<Pending>,
+}
+
+impl NotificationQueue {
+    pub fn new() -> Self {
+        NotificationQueue {
+            last_seq: 0,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Queue event, returning its sequence number.  An event with a receipt
+    /// that's already waiting on the console isn't queued again; the
+    /// sequence number of the one waiting is returned instead.
+    pub fn post(&mut self, event: PulseEvent, receipt: Option<Receipt>) -> u32 {
+        if receipt.is_some() {
+            if let Some(pending) = self.pending.iter().find(|pending| pending.receipt == receipt) {
+                return pending.seq;
+            }
+        }
+
+        if self.pending.len() == MAX_PENDING_EVENTS {
+            if let Some(dropped) = self.pending.pop_front() {
+                warn!("Dropping unacknowledged notification {}: {:02x?}", dropped.seq, dropped.event);
+            }
+        }
+
+        self.last_seq = self.last_seq.wrapping_add(1);
+        self.pending.push_back(Pending {
+            seq: self.last_seq,
+            event,
+            receipt,
+        });
+
+        self.last_seq
+    }
+
+    /// Drop every pending event up to and including seq_ack, returning the
+    /// receipts of those that had one.  Acks that don't refer to an
+    /// outstanding event are ignored.
+    pub fn ack(&mut self, seq_ack: u32) -> Vec<Receipt> {
+        let first_seq = match self.pending.front() {
+            Some(pending) => pending.seq,
+            None => return vec![],
+        };
+
+        let acked = seq_ack.wrapping_sub(first_seq) as usize;
+        if acked >= self.pending.len() {
+            return vec![];
+        }
+
+        self.pending.drain(..=acked)
+            .filter_map(|pending| pending.receipt)
+            .collect()
+    }
+
+    /// Encode as many pending events as fit in a pulse.  Returns the sequence
+    /// number of the last event encoded along with the events payload, or
+    /// None if nothing is waiting on the console.
+    pub fn pulse_events(&self) -> Option<(u32, Vec<u8>)> {
+        let mut last_seq = None;
+        let mut events = vec![];
+
+        for pending in self.pending.iter() {
+            if events.len() + pending.event.encoded_len() > MAX_PULSE_EVENTS_LEN {
+                break;
+            }
+
+            let mut encoded = match pending.event.build() {
+                Some(encoded) => encoded,
+                None => break,
+            };
+
+            events.append(&mut encoded);
+            last_seq = Some(pending.seq);
+        }
+
+        last_seq.map(|seq| (seq, events))
+    }
+
+    /// The seq_ack and events payload of the next SgToXbPulse.  The seq_ack
+    /// is always in the queue's own sequence space: the last event sent, or
+    /// the last event posted when nothing is waiting on the console.  It
+    /// never echoes the console's seq_ack back.
+    pub fn pulse(&self) -> (u32, Vec<u8>) {
+        self.pulse_events()
+            .unwrap_or((self.last_seq, vec![]))
+    }
+}
+
+/// Registry of the notification queues for every open SG connection, keyed
+/// by the machine account of the console, that other subsystems post to.
+///
+/// The pulse event kinds and framing in xblive::sg::control are provisional,
+/// so unless pulse_events is set nothing is ever queued: every post reports
+/// the machine as unreachable and pulses keep echoing the console's seq_ack.
+#[derive(Debug)]
+pub struct Notifications {
+    pulse_events: bool,
+    queues: RwLock<BTreeMap<Xuid, Arc<Mutex<NotificationQueue>>>>,
+}
+
+impl Notifications {
+    pub fn new(pulse_events: bool) -> Self {
+        Notifications {
+            pulse_events,
+            queues: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Whether queued events are sent to consoles in their pulses.
+    pub fn pulse_events(&self) -> bool {
+        self.pulse_events
+    }
+
+    pub async fn register(&self, machine: Xuid) -> Arc<Mutex<NotificationQueue>> {
+        let queue = Arc::new(Mutex::new(NotificationQueue::new()));
+
+        let prev = self.queues.write().await.insert(machine, queue.clone());
+        if prev.is_some() {
+            info!("Replacing notification queue for {:x?}", machine);
+        }
+
+        queue
+    }
+
+    /// Remove the queue for a connection, as long as it hasn't already been
+    /// replaced by a newer connection from the same machine.
+    pub async fn unregister(&self, machine: Xuid, queue: &Arc<Mutex<NotificationQueue>>) {
+        let mut queues = self.queues.write().await;
+
+        if let Some(registered) = queues.get(&machine) {
+            if Arc::ptr_eq(registered, queue) {
+                queues.remove(&machine);
+            }
+        }
+    }
+
+    /// Queue an event for delivery on the console's next pulse, see
+    /// NotificationQueue::post.  Returns false if the machine isn't currently
+    /// connected, or pulse events are off.
+    pub async fn post(&self, machine: Xuid, event: PulseEvent, receipt: Option<Receipt>) -> bool {
+        if !self.pulse_events {
+            return false;
+        }
+
+        let queue = match self.queues.read().await.get(&machine) {
+            Some(queue) => queue.clone(),
+            None => return false,
+        };
+
+        queue.lock().await.post(event, receipt);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use xblive::sg::control::PulseEventKind;
+
+    use super::*;
+
+    fn event(user_index: u8) -> PulseEvent {
+        PulseEvent {
+            kind: PulseEventKind::GAME_INVITE,
+            user_index,
+            data: vec![user_index; 4],
+        }
+    }
+
+    #[test]
+    fn ack_drops_delivered_events() {
+        let mut queue = NotificationQueue::new();
+
+        assert_eq!(queue.pulse_events(), None);
+
+        assert_eq!(queue.post(event(0), None), 1);
+        assert_eq!(queue.post(event(1), None), 2);
+        assert_eq!(queue.post(event(2), None), 3);
+
+        let (seq, events) = queue.pulse_events().unwrap();
+        assert_eq!(seq, 3);
+        assert_eq!(PulseEvent::parse_all(&events), Some(vec![event(0), event(1), event(2)]));
+
+        queue.ack(2);
+
+        let (seq, events) = queue.pulse_events().unwrap();
+        assert_eq!(seq, 3);
+        assert_eq!(PulseEvent::parse_all(&events), Some(vec![event(2)]));
+
+        queue.ack(3);
+        assert_eq!(queue.pulse_events(), None);
+    }
+
+    #[test]
+    fn ack_ignores_unknown_sequence_numbers() {
+        let mut queue = NotificationQueue::new();
+
+        queue.post(event(0), None);
+        queue.ack(0);
+        queue.ack(0x1234_5678);
+
+        assert_eq!(queue.pulse_events().map(|(seq, _)| seq), Some(1));
+    }
+
+    #[test]
+    fn pulses_stay_in_one_sequence_space() {
+        let mut queue = NotificationQueue::new();
+
+        // Idle, whatever the console last acked.
+        queue.ack(0x1234_5678);
+        assert_eq!(queue.pulse(), (0, vec![]));
+
+        // Pending.
+        queue.post(event(0), None);
+        queue.post(event(1), None);
+        let (seq, events) = queue.pulse();
+        assert_eq!(seq, 2);
+        assert_eq!(PulseEvent::parse_all(&events), Some(vec![event(0), event(1)]));
+
+        // Acked, and idle again at the same sequence number.
+        queue.ack(2);
+        assert_eq!(queue.pulse(), (2, vec![]));
+
+        queue.post(event(2), None);
+        assert_eq!(queue.pulse().0, 3);
+    }
+
+    #[test]
+    fn receipts_come_back_once_acked() {
+        let mut queue = NotificationQueue::new();
+
+        let receipt = |host| Some(Receipt::Invitation {
+            invitee: Xuid(0x0009_0000_0000_0001),
+            host: Xuid(host),
+            xnkid: KeyId([1; 8]),
+        });
+
+        assert_eq!(queue.post(event(0), receipt(2)), 1);
+        assert_eq!(queue.post(event(1), None), 2);
+        assert_eq!(queue.post(event(2), receipt(3)), 3);
+
+        // Already waiting on the console.
+        assert_eq!(queue.post(event(0), receipt(2)), 1);
+
+        assert_eq!(queue.ack(2), vec![receipt(2).unwrap()]);
+        assert_eq!(queue.ack(2), vec![]);
+        assert_eq!(queue.ack(3), vec![receipt(3).unwrap()]);
+
+        // Sent again once the first went.
+        assert_eq!(queue.post(event(0), receipt(2)), 4);
+    }
+
+    #[tokio::test]
+    async fn nothing_is_queued_without_pulse_events() {
+        let machine = Xuid(0xFA00_0000_0000_0001);
+
+        let notifications = Notifications::new(false);
+        let queue = notifications.register(machine).await;
+
+        assert!(!notifications.post(machine, event(0), None).await);
+        assert_eq!(queue.lock().await.pulse_events(), None);
+
+        let notifications = Notifications::new(true);
+        let queue = notifications.register(machine).await;
+
+        assert!(notifications.post(machine, event(0), None).await);
+        assert_eq!(queue.lock().await.pulse_events().map(|(seq, _)| seq), Some(1));
+    }
+}
@@ -1,7 +1,101 @@
 use xbox_sys::account::Xuid;
 
+use xombie::krb::{MAX_USERS, UsersAuthData};
+
 #[derive(Clone, Copy, Debug)]
 pub struct CombinedId {
 	pub machine: Xuid,
 	pub users: [Option<Xuid>; 4],
 }
+
+impl CombinedId {
+	pub fn new(machine: Xuid) -> Self {
+		CombinedId {
+			machine,
+			users: [None; MAX_USERS],
+		}
+	}
+
+	pub fn from_auth_data(auth_data: &UsersAuthData) -> Self {
+		let mut combined = CombinedId::new(auth_data.machine);
+
+		for (slot, user) in combined.users.iter_mut().zip(auth_data.users.iter()) {
+			if *user != Xuid::INVALID {
+				*slot = Some(*user);
+			}
+		}
+
+		combined
+	}
+
+	pub fn slot_of(&self, user: Xuid) -> Option<usize> {
+		self.users.iter()
+			.position(|slot| *slot == Some(user))
+	}
+
+	pub fn signed_in(&self) -> Vec<Xuid> {
+		self.users.iter()
+			.flatten()
+			.copied()
+			.collect()
+	}
+
+	/// Place a user in the slot ticket assigned them.  Returns the user's
+	/// slot, or None if the ticket doesn't list them, in which case they
+	/// can't sign in on this connection at all.
+	pub fn sign_in(&mut self, ticket: &CombinedId, user: Xuid) -> Option<usize> {
+		let slot = ticket.slot_of(user)?;
+		self.users[slot] = Some(user);
+
+		Some(slot)
+	}
+
+	/// Returns the slot the user was signed in to, if any.
+	pub fn sign_out(&mut self, user: Xuid) -> Option<usize> {
+		let slot = self.slot_of(user)?;
+		self.users[slot] = None;
+
+		Some(slot)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const MACHINE: Xuid = Xuid(0xFA00_0000_0000_0001);
+
+	const ALICE: Xuid = Xuid(0x0009_0000_0000_0001);
+	const BOB: Xuid = Xuid(0x0009_0000_0000_0002);
+	const MALLORY: Xuid = Xuid(0x0009_0000_0000_0003);
+
+	fn ticket() -> CombinedId {
+		CombinedId {
+			machine: MACHINE,
+			users: [None, Some(ALICE), None, Some(BOB)],
+		}
+	}
+
+	#[test]
+	fn users_sign_in_to_their_ticket_slot() {
+		let ticket = ticket();
+		let mut users = CombinedId::new(MACHINE);
+
+		assert_eq!(users.sign_in(&ticket, BOB), Some(3));
+		assert_eq!(users.sign_in(&ticket, ALICE), Some(1));
+		assert_eq!(users.signed_in(), vec![ALICE, BOB]);
+
+		assert_eq!(users.sign_out(ALICE), Some(1));
+		assert_eq!(users.sign_in(&ticket, ALICE), Some(1));
+	}
+
+	#[test]
+	fn users_not_on_the_ticket_are_refused() {
+		let ticket = ticket();
+		let mut users = ticket;
+
+		assert_eq!(users.sign_in(&ticket, MALLORY), None);
+		assert_eq!(users.slot_of(MALLORY), None);
+		assert_eq!(users.sign_in(&CombinedId::new(MACHINE), ALICE), None);
+	}
+}